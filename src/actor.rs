@@ -0,0 +1,101 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::thread;
+
+use trace::{current_trace_id, in_trace_scope};
+use {Gdbm, GdbmError, StoreOutcome, StoreStats};
+
+type Command = Box<dyn FnOnce(&mut Gdbm) + Send>;
+
+/// The result of a command sent to a `GdbmWriterActor`, available once
+/// the writer thread has carried it out.
+#[derive(Debug)]
+pub struct Pending<T> {
+    result: mpsc::Receiver<Result<T, GdbmError>>,
+}
+
+impl<T> Pending<T> {
+    /// Block until the command has run and return its result. Dropping a
+    /// `Pending` instead does not cancel the command.
+    pub fn wait(self) -> Result<T, GdbmError> {
+        self.result
+            .recv()
+            .unwrap_or_else(|_| Err(GdbmError::new("command panicked or the writer thread stopped")))
+    }
+}
+
+/// Owns a writable handle on a thread of its own and runs the commands
+/// sent to it one at a time, in the order they arrive.
+///
+/// gdbm allows a single writer per database; the actor lets any number
+/// of threads submit writes through cloned senders without contending
+/// for a lock, and without waiting unless they want the result. The
+/// database is closed once every clone has been dropped and the queued
+/// commands are done.
+#[derive(Debug, Clone)]
+pub struct GdbmWriterActor {
+    commands: mpsc::Sender<Command>,
+}
+
+impl GdbmWriterActor {
+    /// Move `db` onto a new writer thread.
+    pub fn spawn(mut db: Gdbm) -> Result<GdbmWriterActor, GdbmError> {
+        let (commands, queue) = mpsc::channel::<Command>();
+        thread::Builder::new()
+            .name("gdbm-writer".to_string())
+            .spawn(move || {
+                for command in queue {
+                    // A panicking command fails its own result only
+                    let _ = panic::catch_unwind(AssertUnwindSafe(|| command(&mut db)));
+                }
+            })?;
+        Ok(GdbmWriterActor { commands })
+    }
+
+    /// Queue `f` to run against the handle on the writer thread, under the
+    /// trace id of the calling thread, see `with_trace_id`.
+    pub fn call<T, F>(&self, f: F) -> Pending<T>
+        where T: Send + 'static,
+              F: FnOnce(&mut Gdbm) -> Result<T, GdbmError> + Send + 'static
+    {
+        let (answer, result) = mpsc::sync_channel(1);
+        let trace_id = current_trace_id();
+        // If the writer is gone the command is dropped with `answer`,
+        // and `wait` reports the error.
+        let _ = self.commands.send(Box::new(move |db: &mut Gdbm| {
+            let _ = answer.send(in_trace_scope(trace_id, || f(db)));
+        }));
+        Pending { result }
+    }
+
+    /// See `Gdbm::store_checked`
+    pub fn store<K, V>(&self, key: K, content: V, replace: bool) -> Pending<StoreOutcome>
+        where K: AsRef<[u8]>,
+              V: AsRef<[u8]>
+    {
+        let key = key.as_ref().to_vec();
+        let content = content.as_ref().to_vec();
+        self.call(move |db| db.store_checked(key, content, replace))
+    }
+
+    /// See `Gdbm::store_many`
+    pub fn store_many(&self, records: Vec<(Vec<u8>, Vec<u8>)>, replace: bool) -> Pending<StoreStats> {
+        self.call(move |db| db.store_many(records, replace))
+    }
+
+    /// See `Gdbm::remove`
+    pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> Pending<Option<Vec<u8>>> {
+        let key = key.as_ref().to_vec();
+        self.call(move |db| db.remove(key))
+    }
+
+    /// See `Gdbm::delete_many`
+    pub fn delete_many(&self, keys: Vec<Vec<u8>>) -> Pending<usize> {
+        self.call(move |db| db.delete_many(keys))
+    }
+
+    /// See `Gdbm::sync`
+    pub fn sync(&self) -> Pending<()> {
+        self.call(|db| db.sync())
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+use futures_core::Stream;
+
+use trace::{current_trace_id, in_trace_scope};
+use {Cursor, Gdbm, GdbmError, Open, Page, StoreOutcome};
+
+type Job = Box<dyn FnOnce(&mut Gdbm) + Send>;
+
+type Record = (Vec<u8>, Vec<u8>);
+
+#[derive(Debug)]
+struct Slot<T> {
+    value: Option<Result<T, GdbmError>>,
+    waker: Option<Waker>,
+    /// The worker dropped the job without answering
+    abandoned: bool,
+}
+
+/// Future resolving to the result of a call made through `AsyncGdbm`.
+#[derive(Debug)]
+pub struct Reply<T> {
+    slot: Arc<Mutex<Slot<T>>>,
+}
+
+/// The worker's end of a `Reply`
+struct Answer<T> {
+    slot: Arc<Mutex<Slot<T>>>,
+}
+
+impl<T> Answer<T> {
+    fn send(self, value: Result<T, GdbmError>) {
+        self.slot.lock().unwrap_or_else(|e| e.into_inner()).value = Some(value);
+    }
+}
+
+impl<T> Drop for Answer<T> {
+    fn drop(&mut self) {
+        let mut slot = self.slot.lock().unwrap_or_else(|e| e.into_inner());
+        slot.abandoned = true;
+        if let Some(waker) = slot.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Future for Reply<T> {
+    type Output = Result<T, GdbmError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<T, GdbmError>> {
+        let mut slot = self.slot.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(value) = slot.value.take() {
+            return Poll::Ready(value);
+        }
+        if slot.abandoned {
+            return Poll::Ready(Err(GdbmError::new("gdbm call panicked or the worker thread stopped")));
+        }
+        slot.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+/// A `Gdbm` handle for async code, available with the `async` feature.
+///
+/// The handle lives on a dedicated thread and every call is sent there,
+/// so blocking gdbm calls never run on executor threads. The returned
+/// `Reply` futures work with any executor. Calls are carried out one at
+/// a time, in the order they were made. Clones share the thread, which
+/// closes the database once the last clone is dropped and the queued
+/// calls are done.
+#[derive(Debug, Clone)]
+pub struct AsyncGdbm {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl AsyncGdbm {
+    /// Open a database on a new worker thread, see `Gdbm::new`. The open
+    /// itself happens on the calling thread.
+    pub fn new(path: &Path, block_size: u32, flags: Open, mode: i32) -> Result<AsyncGdbm, GdbmError> {
+        Gdbm::new(path, block_size, flags, mode).and_then(AsyncGdbm::from_gdbm)
+    }
+
+    /// Move `db` onto a new worker thread.
+    pub fn from_gdbm(mut db: Gdbm) -> Result<AsyncGdbm, GdbmError> {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        thread::Builder::new()
+            .name("gdbm".to_string())
+            .spawn(move || {
+                for job in queue {
+                    // A panicking call fails its own reply, not the handle
+                    let _ = panic::catch_unwind(AssertUnwindSafe(|| job(&mut db)));
+                }
+            })?;
+        Ok(AsyncGdbm { jobs })
+    }
+
+    /// Run `f` against the handle on the worker thread, under the trace id
+    /// of the calling thread, see `with_trace_id`. If `f` panics the reply
+    /// resolves to an error and the handle stays usable.
+    pub fn call<T, F>(&self, f: F) -> Reply<T>
+        where T: Send + 'static,
+              F: FnOnce(&mut Gdbm) -> Result<T, GdbmError> + Send + 'static
+    {
+        let slot = Arc::new(Mutex::new(Slot {
+            value: None,
+            waker: None,
+            abandoned: false,
+        }));
+        let answer = Answer { slot: slot.clone() };
+        let trace_id = current_trace_id();
+        // If the worker is gone the job is dropped here, which drops
+        // `answer` and resolves the reply with an error.
+        let _ = self.jobs.send(Box::new(move |db: &mut Gdbm| answer.send(in_trace_scope(trace_id, || f(db)))));
+        Reply { slot }
+    }
+
+    /// See `Gdbm::fetch_data`
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Reply<Option<Vec<u8>>> {
+        let key = key.as_ref().to_vec();
+        self.call(move |db| db.fetch_data(key))
+    }
+
+    /// See `Gdbm::store_checked`
+    pub fn store<K, V>(&self, key: K, content: V, replace: bool) -> Reply<StoreOutcome>
+        where K: AsRef<[u8]>,
+              V: AsRef<[u8]>
+    {
+        let key = key.as_ref().to_vec();
+        let content = content.as_ref().to_vec();
+        self.call(move |db| db.store_checked(key, content, replace))
+    }
+
+    /// Delete the record under `key`, resolving to whether there was one.
+    pub fn delete<K: AsRef<[u8]>>(&self, key: K) -> Reply<bool> {
+        let key = key.as_ref().to_vec();
+        self.call(move |db| db.delete_bytes(&key))
+    }
+
+    /// Number of records in the database
+    pub fn len(&self) -> Reply<u64> {
+        self.call(|db| db.count())
+    }
+
+    /// See `Gdbm::sync`
+    pub fn sync(&self) -> Reply<()> {
+        self.call(|db| db.sync())
+    }
+
+    /// Stream the records of the database, see `EntriesStream`.
+    pub fn entries_stream(&self) -> EntriesStream {
+        EntriesStream {
+            db: self.clone(),
+            batch_size: STREAM_BATCH,
+            buffer: VecDeque::new(),
+            next: None,
+            pending: None,
+            done: false,
+        }
+    }
+}
+
+/// Records `EntriesStream` fetches per call to the worker by default
+const STREAM_BATCH: usize = 100;
+
+/// Asynchronous stream of the records of a database, in gdbm's traversal
+/// order, returned by `AsyncGdbm::entries_stream`.
+///
+/// Records are fetched from the worker a batch at a time with
+/// `Gdbm::page`, and the next batch is only requested once the previous
+/// one has been consumed. The stream implements `futures_core::Stream`,
+/// so the `StreamExt` combinators of `futures` apply to it;
+/// `next_entry` resolves to the next item without them.
+///
+/// Other calls may run between batches. If the last key of a batch is
+/// deleted in the meantime the stream ends with an error, as
+/// `Gdbm::iter_from` does.
+#[derive(Debug)]
+pub struct EntriesStream {
+    db: AsyncGdbm,
+    batch_size: usize,
+    buffer: VecDeque<Record>,
+    next: Option<Cursor>,
+    pending: Option<Reply<Page>>,
+    done: bool,
+}
+
+impl EntriesStream {
+    /// Fetch `records` records per call to the worker, at least 1.
+    pub fn batch_size(&mut self, records: usize) -> &mut EntriesStream {
+        self.batch_size = records.max(1);
+        self
+    }
+
+    /// Poll for the next record, None at the end of the database.
+    pub fn poll_next(&mut self, cx: &mut Context) -> Poll<Option<Result<Record, GdbmError>>> {
+        loop {
+            if let Some(record) = self.buffer.pop_front() {
+                return Poll::Ready(Some(Ok(record)));
+            }
+            if self.done {
+                return Poll::Ready(None);
+            }
+            if self.pending.is_none() {
+                let after = self.next.take();
+                let limit = self.batch_size;
+                self.pending = Some(self.db.call(move |db| db.page(after.as_ref().map(|c| c.key()), limit)));
+            }
+            let page = match self.pending.as_mut().map(|reply| Pin::new(reply).poll(cx)) {
+                Some(Poll::Ready(page)) => page,
+                _ => return Poll::Pending,
+            };
+            self.pending = None;
+            match page {
+                Ok(page) => {
+                    self.buffer.extend(page.entries);
+                    self.done = page.next.is_none();
+                    self.next = page.next;
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Poll::Ready(Some(Err(e)));
+                }
+            }
+        }
+    }
+
+    /// The next record, None at the end of the database.
+    pub fn next_entry(&mut self) -> NextEntry<'_> {
+        NextEntry { stream: self }
+    }
+}
+
+/// Future returned by `EntriesStream::next_entry`
+#[derive(Debug)]
+pub struct NextEntry<'a> {
+    stream: &'a mut EntriesStream,
+}
+
+impl Stream for EntriesStream {
+    type Item = Result<Record, GdbmError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        EntriesStream::poll_next(&mut self, cx)
+    }
+}
+
+impl<'a> Future for NextEntry<'a> {
+    type Output = Option<Result<Record, GdbmError>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        self.stream.poll_next(cx)
+    }
+}
@@ -0,0 +1,147 @@
+use std::ascii;
+use std::fmt::{self, Write as FmtWrite};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use trace::current_trace_id;
+use {Gdbm, GdbmError};
+
+/// The kind of write an `AuditRecord` is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditOp {
+    Store,
+    Delete,
+}
+
+impl fmt::Display for AuditOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AuditOp::Store => f.write_str("store"),
+            AuditOp::Delete => f.write_str("delete"),
+        }
+    }
+}
+
+/// A write made through a handle, passed to its `AuditSink`
+#[derive(Debug, Clone)]
+pub struct AuditRecord<'a> {
+    /// When the write was made
+    pub timestamp: SystemTime,
+    pub op: AuditOp,
+    pub key: &'a [u8],
+    /// Size of the value stored, 0 for a delete
+    pub value_size: usize,
+    /// The trace id the write was made under, see `with_trace_id`
+    pub trace_id: Option<&'a str>,
+}
+
+/// Receives a record of every store and delete made through a handle, set
+/// with `Gdbm::set_audit_sink`. Implemented for closures taking an
+/// `&AuditRecord`.
+pub trait AuditSink: Send + Sync {
+    /// Take note of `record`. An error is returned from the store or
+    /// delete being audited, which has already been made.
+    fn record(&self, record: &AuditRecord) -> Result<(), GdbmError>;
+}
+
+impl<F> AuditSink for F
+    where F: Fn(&AuditRecord) -> Result<(), GdbmError> + Send + Sync
+{
+    fn record(&self, record: &AuditRecord) -> Result<(), GdbmError> {
+        self(record)
+    }
+}
+
+/// The sink of `Gdbm::set_audit_sink`
+pub(crate) struct Audit(Arc<dyn AuditSink>);
+
+impl fmt::Debug for Audit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Audit")
+    }
+}
+
+/// An `AuditSink` appending a line per write to a file opened in append
+/// mode, so records already written are never overwritten. The fields of
+/// a line are separated by tabs: the time in seconds since the Unix
+/// epoch, the operation, the key escaped by `std::ascii::escape_default`,
+/// which leaves no tabs or newlines in it, the value size and, for
+/// writes made under a trace id, the trace id escaped the same way. Each
+/// line is a single write, so handles in several processes can share the
+/// file. Lines are not synced to disk.
+#[derive(Debug)]
+pub struct AuditFile {
+    file: File,
+}
+
+impl AuditFile {
+    /// Append to the file at `path`, creating it if needed
+    pub fn open(path: &Path) -> Result<AuditFile, GdbmError> {
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
+        Ok(AuditFile { file })
+    }
+
+    /// The sidecar audit file of the database at `path`: the same path
+    /// with `.audit` added
+    pub fn sidecar_path(path: &Path) -> PathBuf {
+        let mut sidecar = path.as_os_str().to_owned();
+        sidecar.push(".audit");
+        PathBuf::from(sidecar)
+    }
+}
+
+impl AuditSink for AuditFile {
+    fn record(&self, record: &AuditRecord) -> Result<(), GdbmError> {
+        let since_epoch = record.timestamp.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut line = String::new();
+        // Writing to a String cannot fail
+        let _ = write!(line, "{}.{:09}\t{}\t", since_epoch.as_secs(), since_epoch.subsec_nanos(), record.op);
+        line.extend(record.key.iter().flat_map(|&b| ascii::escape_default(b)).map(char::from));
+        let _ = write!(line, "\t{}", record.value_size);
+        if let Some(trace_id) = record.trace_id {
+            line.push('\t');
+            line.extend(trace_id.bytes().flat_map(ascii::escape_default).map(char::from));
+        }
+        line.push('\n');
+        (&self.file).write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
+
+impl Gdbm {
+    /// Pass a record of every successful store and delete made through
+    /// this handle to `sink`, or stop with None. Inserts that find the
+    /// key taken and deletes of missing keys are not recorded, nor are
+    /// writes gdbm makes itself, such as `reorganize` moving records.
+    pub fn set_audit_sink(&mut self, sink: Option<Arc<dyn AuditSink>>) {
+        self.audit = sink.map(Audit);
+    }
+
+    /// Audit to the sidecar file of the database, see
+    /// `AuditFile::sidecar_path`. Returns its path.
+    pub fn audit_to_sidecar(&mut self) -> Result<PathBuf, GdbmError> {
+        let path = AuditFile::sidecar_path(&self.name()?);
+        self.set_audit_sink(Some(Arc::new(AuditFile::open(&path)?)));
+        Ok(path)
+    }
+
+    /// Tell the audit sink, if any, about a write just made
+    pub(crate) fn audit(&self, op: AuditOp, key: &[u8], value_size: usize) -> Result<(), GdbmError> {
+        match self.audit {
+            Some(Audit(ref sink)) => {
+                let trace_id = current_trace_id();
+                sink.record(&AuditRecord {
+                    timestamp: SystemTime::now(),
+                    op,
+                    key,
+                    value_size,
+                    trace_id: trace_id.as_deref(),
+                })
+            }
+            None => Ok(()),
+        }
+    }
+}
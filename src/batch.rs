@@ -0,0 +1,273 @@
+use std::path::Path;
+
+use gdbm_sys::GDBM_SYNCMODE;
+use libc::c_int;
+
+use ffi;
+use progress::PROGRESS_INTERVAL;
+use {Gdbm, GdbmError, OpenOptions, Progress, Store, StoreOutcome};
+
+/// How many keys `Gdbm::clear` collects before deleting them
+const CLEAR_BATCH: usize = 1024;
+
+/// What `Gdbm::store_many` did with the records it was given
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StoreStats {
+    /// Records stored under keys that had no record yet
+    pub inserted: u64,
+    /// Records that overwrote an existing record
+    pub replaced: u64,
+    /// Records not stored because the key existed and `replace` was off
+    pub skipped: u64,
+}
+
+#[derive(Debug)]
+enum BatchOp {
+    Store(Vec<u8>, Vec<u8>),
+    Delete(Vec<u8>),
+}
+
+/// Stores and deletes collected in memory and applied together with
+/// `commit`, with one sync at the end: a cheap group commit. Returned by
+/// `Gdbm::write_batch`. Nothing reaches the database before `commit`;
+/// dropping the batch discards it like `discard` does.
+///
+/// The operations are applied in the order they were added, but not
+/// atomically: if one fails, those before it stay applied.
+#[derive(Debug)]
+pub struct WriteBatch<'a> {
+    db: &'a Gdbm,
+    ops: Vec<BatchOp>,
+}
+
+impl<'a> WriteBatch<'a> {
+    /// Store `value` under `key`, replacing any record
+    pub fn put<K, V>(&mut self, key: K, value: V) -> &mut WriteBatch<'a>
+        where K: AsRef<[u8]>,
+              V: AsRef<[u8]>
+    {
+        self.ops.push(BatchOp::Store(key.as_ref().to_vec(), value.as_ref().to_vec()));
+        self
+    }
+
+    /// Delete the record under `key`, if there is one
+    pub fn delete<K: AsRef<[u8]>>(&mut self, key: K) -> &mut WriteBatch<'a> {
+        self.ops.push(BatchOp::Delete(key.as_ref().to_vec()));
+        self
+    }
+
+    /// Number of operations collected
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// Whether no operations have been collected
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Apply the operations and sync once. Stops at the first error; the
+    /// operations applied up to then stand and are synced.
+    pub fn commit(self) -> Result<(), GdbmError> {
+        let db = self.db;
+        db.with_deferred_sync(|| {
+            for op in self.ops {
+                match op {
+                    BatchOp::Store(key, value) => {
+                        db.store_bytes(&key, &value, Store::REPLACE)?;
+                    }
+                    BatchOp::Delete(key) => {
+                        db.delete_bytes(&key)?;
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Drop the operations without applying any
+    pub fn discard(self) {}
+}
+
+impl Gdbm {
+    /// Open the database at `path` with `options` and store every
+    /// `(key, value)` pair from `records`, replacing existing records and
+    /// syncing once at the end. `options` must allow writing.
+    pub fn create_from_iter<I, K, V>(path: &Path, records: I, options: &OpenOptions) -> Result<Gdbm, GdbmError>
+        where I: IntoIterator<Item = (K, V)>,
+              K: AsRef<[u8]>,
+              V: AsRef<[u8]>
+    {
+        let db = options.open(path)?;
+        db.store_many(records, true)?;
+        Ok(db)
+    }
+
+    /// Start an empty `WriteBatch` against this database
+    pub fn write_batch(&self) -> WriteBatch<'_> {
+        WriteBatch {
+            db: self,
+            ops: Vec::new(),
+        }
+    }
+
+    /// Fetch the records under all of `keys`, in order, as `fetch_data`
+    /// would. The result vector is allocated once up front and each value
+    /// is copied once, out of gdbm's buffer.
+    pub fn fetch_many<I>(&self, keys: I) -> Result<Vec<Option<Vec<u8>>>, GdbmError>
+        where I: IntoIterator,
+              I::Item: AsRef<[u8]>
+    {
+        let keys = keys.into_iter();
+        let mut values = Vec::with_capacity(keys.size_hint().0);
+        for key in keys {
+            values.push(self.fetch_bytes(key.as_ref())?);
+        }
+        Ok(values)
+    }
+
+    /// Store every `(key, value)` pair from `records`, with the same
+    /// `replace` semantics as `store_checked`, and sync exactly once at
+    /// the end, even if the database was opened with `SYNC`.
+    ///
+    /// Stops at the first error; records stored up to that point stay
+    /// stored and are synced.
+    pub fn store_many<I, K, V>(&self, records: I, replace: bool) -> Result<StoreStats, GdbmError>
+        where I: IntoIterator<Item = (K, V)>,
+              K: AsRef<[u8]>,
+              V: AsRef<[u8]>
+    {
+        self.store_many_impl(records, replace, None)
+    }
+
+    /// `store_many`, calling `progress` every `PROGRESS_INTERVAL` records
+    /// and once at the end.
+    pub fn store_many_with_progress<I, K, V, F>(&self, records: I, replace: bool, mut progress: F) -> Result<StoreStats, GdbmError>
+        where I: IntoIterator<Item = (K, V)>,
+              K: AsRef<[u8]>,
+              V: AsRef<[u8]>,
+              F: FnMut(Progress)
+    {
+        self.store_many_impl(records, replace, Some(&mut progress))
+    }
+
+    fn store_many_impl<I, K, V>(&self,
+                                records: I,
+                                replace: bool,
+                                mut progress: Option<&mut dyn FnMut(Progress)>)
+                                -> Result<StoreStats, GdbmError>
+        where I: IntoIterator<Item = (K, V)>,
+              K: AsRef<[u8]>,
+              V: AsRef<[u8]>
+    {
+        self.with_deferred_sync(|| {
+            let mut stats = StoreStats::default();
+            let mut done = 0;
+            for (key, value) in records {
+                match self.store_checked(key, value, replace)? {
+                    StoreOutcome::Inserted => stats.inserted += 1,
+                    StoreOutcome::Replaced => stats.replaced += 1,
+                    StoreOutcome::AlreadyExists => stats.skipped += 1,
+                }
+                done += 1;
+                if let Some(ref mut progress) = progress {
+                    if done % PROGRESS_INTERVAL == 0 {
+                        progress(Progress { records: done, bytes: 0 });
+                    }
+                }
+            }
+            if let Some(ref mut progress) = progress {
+                progress(Progress { records: done, bytes: 0 });
+            }
+            Ok(stats)
+        })
+    }
+
+    /// Delete the records under all of `keys` and sync once at the end.
+    /// Returns how many of the keys had a record.
+    ///
+    /// Stops at the first error; deletions made up to that point stand
+    /// and are synced.
+    pub fn delete_many<I>(&self, keys: I) -> Result<usize, GdbmError>
+        where I: IntoIterator,
+              I::Item: AsRef<[u8]>
+    {
+        self.with_deferred_sync(|| {
+            let mut deleted = 0;
+            for key in keys {
+                if self.delete_bytes(key.as_ref())? {
+                    deleted += 1;
+                }
+            }
+            Ok(deleted)
+        })
+    }
+
+    /// Delete every record in the database and sync once at the end.
+    ///
+    /// The crate's bookkeeping records, `SCHEMA_VERSION_KEY` and
+    /// `SEED_MARKER_KEY`, are kept: a cleared database stays at its schema
+    /// version and is not seeded again. Blob chunks are deleted with the
+    /// other records.
+    ///
+    /// gdbm does not guarantee a traversal survives deletions, so keys
+    /// are collected `CLEAR_BATCH` at a time and the walk restarted after
+    /// each batch is deleted. The file does not shrink.
+    pub fn clear(&self) -> Result<(), GdbmError> {
+        self.with_deferred_sync(|| {
+            loop {
+                let mut keys = Vec::with_capacity(CLEAR_BATCH);
+                let mut next = self.first_key_bytes()?;
+                while let Some(key) = next {
+                    if keys.len() == CLEAR_BATCH {
+                        break;
+                    }
+                    next = self.next_key_bytes(&key)?;
+                    keys.push(key);
+                }
+                if keys.is_empty() {
+                    return Ok(());
+                }
+                for key in &keys {
+                    self.delete_bytes(key)?;
+                }
+            }
+        })
+    }
+
+    /// Run `f` with gdbm's synchronous mode switched off, then restore
+    /// it and sync once, whether or not `f` succeeded.
+    pub(crate) fn with_deferred_sync<R, F>(&self, f: F) -> Result<R, GdbmError>
+        where F: FnOnce() -> Result<R, GdbmError>
+    {
+        let sync_mode: c_int = self.getopt(ffi::GDBM_GETSYNCMODE, 0)?;
+        if sync_mode != 0 {
+            self.setopt(GDBM_SYNCMODE as c_int, 0 as c_int)?;
+        }
+        let result = f();
+        let restored = if sync_mode != 0 {
+            self.setopt(GDBM_SYNCMODE as c_int, sync_mode)
+        } else {
+            Ok(())
+        };
+        let synced = self.sync();
+        let result = result?;
+        restored?;
+        synced?;
+        Ok(result)
+    }
+}
+
+/// Stores each pair with `store_many(records, true)`.
+///
+/// # Panics
+///
+/// `Extend` has no way to report errors, so this panics if a record
+/// cannot be stored. Use `store_many` to handle the error instead.
+impl<K: AsRef<[u8]>, V: AsRef<[u8]>> Extend<(K, V)> for Gdbm {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, records: I) {
+        if let Err(e) = self.store_many(records, true) {
+            panic!("storing records failed: {}", e);
+        }
+    }
+}
@@ -0,0 +1,299 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+use {Gdbm, GdbmError, StoreOutcome, StoreStats};
+
+const DB_HASHMAGIC: u32 = 0x0006_1561;
+/// Hash format versions of Berkeley DB 3.0 up to 18.1
+const HASH_VERSIONS: [u32; 4] = [6, 7, 8, 9];
+/// Metadata flag: every page carries a checksum after its header
+const DBMETA_CHKSUM: u8 = 0x01;
+
+// Page types
+const P_HASH_UNSORTED: u8 = 2;
+const P_OVERFLOW: u8 = 7;
+const P_HASH: u8 = 13;
+
+// Item types on hash pages
+const H_KEYDATA: u8 = 1;
+const H_DUPLICATE: u8 = 2;
+const H_OFFPAGE: u8 = 3;
+const H_OFFDUP: u8 = 4;
+
+const PAGE_HEADER_LEN: usize = 26;
+const CHECKSUM_LEN: usize = 20;
+const META_LEN: usize = 72;
+
+type Record = (Vec<u8>, Vec<u8>);
+
+/// What `Gdbm::import_bdb_hash` does with a key the database already has,
+/// or that the Berkeley DB file holds more than once
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnConflict {
+    /// Stop the import with an error
+    Fail,
+    /// Keep the record already stored
+    Skip,
+    /// Overwrite it
+    Replace,
+}
+
+/// A Berkeley DB hash database, read without libdb, as written by
+/// Berkeley DB 3.0 and later: `db_load -t hash`, Postfix `hash:` maps,
+/// Apache `dbmmanage` and the like. The older 1.85 format, encrypted
+/// databases and sorted off-page duplicates are not supported.
+#[derive(Debug)]
+pub struct BdbHashFile {
+    file: File,
+    page_size: usize,
+    big_endian: bool,
+    /// Bytes before a page's item offsets or overflow data
+    overhead: usize,
+    last_page: u32,
+}
+
+/// Iterator over the records of a `BdbHashFile`, returned by
+/// `BdbHashFile::iter`. A key with duplicates yields one record per value.
+#[derive(Debug)]
+pub struct BdbRecords<'a> {
+    db: &'a BdbHashFile,
+    next_page: u32,
+    page: Vec<u8>,
+    entries: usize,
+    next_entry: usize,
+    pending: VecDeque<Record>,
+    failed: bool,
+}
+
+fn corrupt(what: &str) -> GdbmError {
+    GdbmError::new(format!("corrupt Berkeley DB file: {}", what))
+}
+
+impl BdbHashFile {
+    /// Open the Berkeley DB hash database at `path`
+    pub fn open(path: &Path) -> Result<BdbHashFile, GdbmError> {
+        let file = File::open(path)?;
+        let mut meta = [0; META_LEN];
+        file.read_exact_at(&mut meta, 0)
+            .map_err(|_| GdbmError::new("not a Berkeley DB hash database"))?;
+        let magic = [meta[12], meta[13], meta[14], meta[15]];
+        let big_endian = if u32::from_le_bytes(magic) == DB_HASHMAGIC {
+            false
+        } else if u32::from_be_bytes(magic) == DB_HASHMAGIC {
+            true
+        } else {
+            return Err(GdbmError::new("not a Berkeley DB hash database"));
+        };
+        let mut db = BdbHashFile {
+            file,
+            page_size: 0,
+            big_endian,
+            overhead: PAGE_HEADER_LEN,
+            last_page: 0,
+        };
+        let version = db.u32_at(&meta, 16);
+        if !HASH_VERSIONS.contains(&version) {
+            return Err(GdbmError::new(format!("unsupported Berkeley DB hash version {}", version)));
+        }
+        if meta[24] != 0 {
+            return Err(GdbmError::new("encrypted Berkeley DB databases are not supported"));
+        }
+        db.page_size = db.u32_at(&meta, 20) as usize;
+        if !db.page_size.is_power_of_two() || db.page_size < 512 || db.page_size > 65536 {
+            return Err(corrupt("bad page size"));
+        }
+        if meta[26] & DBMETA_CHKSUM != 0 {
+            db.overhead += CHECKSUM_LEN;
+        }
+        db.last_page = db.u32_at(&meta, 32);
+        Ok(db)
+    }
+
+    /// Iterate over the records, page by page
+    pub fn iter(&self) -> BdbRecords<'_> {
+        BdbRecords {
+            db: self,
+            next_page: 1,
+            page: vec![0; self.page_size],
+            entries: 0,
+            next_entry: 0,
+            pending: VecDeque::new(),
+            failed: false,
+        }
+    }
+
+    fn u16_at(&self, bytes: &[u8], pos: usize) -> u16 {
+        let field = [bytes[pos], bytes[pos + 1]];
+        if self.big_endian {
+            u16::from_be_bytes(field)
+        } else {
+            u16::from_le_bytes(field)
+        }
+    }
+
+    fn u32_at(&self, bytes: &[u8], pos: usize) -> u32 {
+        let field = [bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]];
+        if self.big_endian {
+            u32::from_be_bytes(field)
+        } else {
+            u32::from_le_bytes(field)
+        }
+    }
+
+    fn read_page(&self, pgno: u32, page: &mut [u8]) -> Result<(), GdbmError> {
+        if pgno == 0 || pgno > self.last_page {
+            return Err(corrupt("page number out of range"));
+        }
+        self.file.read_exact_at(page, pgno as u64 * self.page_size as u64)?;
+        Ok(())
+    }
+
+    /// Item `index` of a hash page. Items are packed from the end of the
+    /// page downwards, so each ends where the one before it starts.
+    fn item<'p>(&self, page: &'p [u8], index: usize) -> Result<&'p [u8], GdbmError> {
+        let offset_at = |i: usize| self.u16_at(page, self.overhead + 2 * i) as usize;
+        let start = offset_at(index);
+        let end = if index == 0 { self.page_size } else { offset_at(index - 1) };
+        if start >= end || end > self.page_size || start < self.overhead {
+            return Err(corrupt("bad item offset"));
+        }
+        Ok(&page[start..end])
+    }
+
+    /// Read a chain of overflow pages holding `len` bytes
+    fn read_overflow(&self, mut pgno: u32, len: usize) -> Result<Vec<u8>, GdbmError> {
+        let mut data = Vec::with_capacity(len.min(1 << 24));
+        let mut page = vec![0; self.page_size];
+        while pgno != 0 {
+            self.read_page(pgno, &mut page)?;
+            if page[25] != P_OVERFLOW {
+                return Err(corrupt("overflow chain leads to a page of another type"));
+            }
+            let used = self.u16_at(&page, 22) as usize;
+            if self.overhead + used > self.page_size || data.len() + used > len {
+                return Err(corrupt("overflow page is too long"));
+            }
+            data.extend_from_slice(&page[self.overhead..self.overhead + used]);
+            pgno = self.u32_at(&page, 16);
+        }
+        if data.len() != len {
+            return Err(corrupt("overflow chain is too short"));
+        }
+        Ok(data)
+    }
+
+    /// The values an item holds: one, or several for on-page duplicates
+    fn values(&self, item: &[u8]) -> Result<Vec<Vec<u8>>, GdbmError> {
+        match item[0] {
+            H_KEYDATA => Ok(vec![item[1..].to_vec()]),
+            H_OFFPAGE if item.len() >= 12 => {
+                let pgno = self.u32_at(item, 4);
+                let len = self.u32_at(item, 8) as usize;
+                Ok(vec![self.read_overflow(pgno, len)?])
+            }
+            // Each duplicate is framed by its length on both sides
+            H_DUPLICATE => {
+                let mut values = Vec::new();
+                let mut rest = &item[1..];
+                while !rest.is_empty() {
+                    if rest.len() < 2 {
+                        return Err(corrupt("bad duplicate"));
+                    }
+                    let len = self.u16_at(rest, 0) as usize;
+                    if rest.len() < len + 4 {
+                        return Err(corrupt("bad duplicate"));
+                    }
+                    values.push(rest[2..2 + len].to_vec());
+                    rest = &rest[len + 4..];
+                }
+                Ok(values)
+            }
+            H_OFFDUP => Err(GdbmError::new("off-page duplicates are not supported")),
+            _ => Err(corrupt("unknown item type")),
+        }
+    }
+
+    /// The records of the pair of items starting at `index`
+    fn pair(&self, page: &[u8], index: usize) -> Result<Vec<Record>, GdbmError> {
+        let mut keys = self.values(self.item(page, index)?)?;
+        if keys.len() != 1 {
+            return Err(corrupt("key with duplicates"));
+        }
+        let key = keys.remove(0);
+        let values = self.values(self.item(page, index + 1)?)?;
+        Ok(values.into_iter().map(|value| (key.clone(), value)).collect())
+    }
+}
+
+impl<'a> BdbRecords<'a> {
+    fn advance(&mut self) -> Result<Option<Record>, GdbmError> {
+        loop {
+            if let Some(record) = self.pending.pop_front() {
+                return Ok(Some(record));
+            }
+            if self.next_entry + 1 < self.entries {
+                let pair = self.db.pair(&self.page, self.next_entry)?;
+                self.pending.extend(pair);
+                self.next_entry += 2;
+                continue;
+            }
+            // Scan pages in file order rather than following the bucket
+            // chains; every hash page belongs to exactly one chain
+            if self.next_page > self.db.last_page {
+                return Ok(None);
+            }
+            let pgno = self.next_page;
+            self.next_page += 1;
+            self.db.read_page(pgno, &mut self.page)?;
+            self.next_entry = 0;
+            self.entries = match self.page[25] {
+                P_HASH | P_HASH_UNSORTED => self.db.u16_at(&self.page, 20) as usize,
+                _ => 0,
+            };
+            if self.db.overhead + 2 * self.entries > self.db.page_size {
+                return Err(corrupt("too many items on a page"));
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for BdbRecords<'a> {
+    type Item = Result<Record, GdbmError>;
+
+    fn next(&mut self) -> Option<Result<Record, GdbmError>> {
+        if self.failed {
+            return None;
+        }
+        let next = self.advance().transpose();
+        self.failed = matches!(next, Some(Err(_)));
+        next
+    }
+}
+
+impl Gdbm {
+    /// Copy the records of the Berkeley DB hash database at `path` into
+    /// this one, syncing once at the end. See `BdbHashFile` for the files
+    /// it reads. Stops at the first error; the records stored up to then
+    /// stay stored.
+    pub fn import_bdb_hash(&self, path: &Path, on_conflict: OnConflict) -> Result<StoreStats, GdbmError> {
+        let source = BdbHashFile::open(path)?;
+        self.with_deferred_sync(|| {
+            let mut stats = StoreStats::default();
+            for record in source.iter() {
+                let (key, value) = record?;
+                match self.store_checked(&key, value, on_conflict == OnConflict::Replace)? {
+                    StoreOutcome::Inserted => stats.inserted += 1,
+                    StoreOutcome::Replaced => stats.replaced += 1,
+                    StoreOutcome::AlreadyExists if on_conflict == OnConflict::Skip => stats.skipped += 1,
+                    StoreOutcome::AlreadyExists => {
+                        return Err(GdbmError::new(format!("key {:?} already exists",
+                                                          String::from_utf8_lossy(&key))));
+                    }
+                }
+            }
+            Ok(stats)
+        })
+    }
+}
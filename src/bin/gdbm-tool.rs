@@ -0,0 +1,430 @@
+//! Inspect and edit gdbm databases from the command line.
+//!
+//! Keys and values are taken and printed as raw bytes; values are
+//! followed by a newline.
+
+extern crate gdbm;
+
+use std::env;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::process;
+
+use gdbm::{ChecksummedGdbm, CsvOptions, DbFormat, Difference, DumpFormat, ForeignGdbmFile, Gdbm, GdbmError, ImportFlag, KvStore, Open, Quoting};
+
+const USAGE: &str = "usage: gdbm-tool <command> <db> [<args>]
+
+commands:
+    get <db> <key>            print the value stored under <key>
+    put <db> <key> <value>    store <value> under <key>, creating <db> if needed
+    del <db> <key>            delete <key>
+    keys <db>                 print every key, one per line
+    count <db>                print the number of records
+    check <db>                check the header, directory, buckets and free
+                              lists, then read every record, reporting any
+                              that cannot be read
+    dump <db> [--format ascii|binary|csv|tsv] <out>
+                              write a dump of <db> to <out>, or stdout for -,
+                              in the format of gdbm_dump (ascii by default)
+                              or as two-column CSV or TSV
+    load <db> [--replace] [--format csv|tsv] <dump>
+                              add the records of <dump>, or stdin for -,
+                              to <db>; --replace overwrites existing keys.
+                              Without --format, <dump> is a gdbm_dump file
+    diff <db-a> <db-b>        list the keys that differ: - only in <db-a>,
+                              + only in <db-b>, ~ different values;
+                              exits with 1 if there are any
+    convert --to numsync|standard <db>
+                              rewrite <db> in the given format; numsync needs
+                              gdbm 1.21 or later to open
+    scrub <db>                verify the checksum of every value written
+                              through ChecksummedGdbm, listing the damaged ones
+    reorganize <db>           rebuild <db> without the space of deleted and
+                              replaced records
+
+CSV and TSV options, for dump and load:
+    --delimiter <byte>        separate the columns with <byte>
+    --quote necessary|always|never
+                              when to quote fields; TSV is never quoted
+    --base64                  base64 encode the values
+    --header                  write, or skip, a header line";
+
+/// Why the tool stopped, and so which exit code it returns
+#[derive(Debug)]
+enum Failure {
+    /// The command line made no sense: exit code 2
+    Usage(String),
+    /// The command failed: exit code 1
+    Error(String),
+}
+
+impl From<GdbmError> for Failure {
+    fn from(err: GdbmError) -> Failure {
+        Failure::Error(err.to_string())
+    }
+}
+
+impl From<io::Error> for Failure {
+    fn from(err: io::Error) -> Failure {
+        Failure::Error(err.to_string())
+    }
+}
+
+fn open(path: &OsString, flags: Open) -> Result<Gdbm, Failure> {
+    Gdbm::new(Path::new(path), 0, flags, 0o666)
+        .map_err(|err| Failure::Error(format!("{}: {}", Path::new(path).display(), err)))
+}
+
+/// The arguments of a command that takes exactly `names`
+fn operands<'a>(command: &str, args: &'a [OsString], names: &[&str]) -> Result<&'a [OsString], Failure> {
+    if args.len() != names.len() {
+        return Err(Failure::Usage(format!("{} takes {}", command, names.join(" "))));
+    }
+    Ok(args)
+}
+
+/// Remove `--name <value>` from `args`, returning the value
+fn take_option(args: &mut Vec<OsString>, name: &str) -> Result<Option<OsString>, Failure> {
+    match args.iter().position(|arg| arg == name) {
+        Some(i) if i + 1 < args.len() => {
+            let value = args.remove(i + 1);
+            args.remove(i);
+            Ok(Some(value))
+        }
+        Some(_) => Err(Failure::Usage(format!("{} needs a value", name))),
+        None => Ok(None),
+    }
+}
+
+/// Remove `name` from `args`, returning whether it was there
+fn take_flag(args: &mut Vec<OsString>, name: &str) -> bool {
+    let len = args.len();
+    args.retain(|arg| arg != name);
+    args.len() != len
+}
+
+fn get(args: &[OsString]) -> Result<(), Failure> {
+    let args = operands("get", args, &["<db>", "<key>"])?;
+    let db = open(&args[0], Open::READER)?;
+    match db.fetch_data(args[1].as_bytes())? {
+        Some(value) => {
+            let mut out = io::stdout();
+            out.write_all(&value)?;
+            out.write_all(b"\n")?;
+            Ok(())
+        }
+        None => Err(Failure::Error("no such key".to_string())),
+    }
+}
+
+fn put(args: &[OsString]) -> Result<(), Failure> {
+    let args = operands("put", args, &["<db>", "<key>", "<value>"])?;
+    let db = open(&args[0], Open::WRCREAT)?;
+    db.store_checked(args[1].as_bytes(), args[2].as_bytes(), true)?;
+    Ok(())
+}
+
+fn del(args: &[OsString]) -> Result<(), Failure> {
+    let args = operands("del", args, &["<db>", "<key>"])?;
+    let db = open(&args[0], Open::WRITER)?;
+    match db.remove(args[1].as_bytes())? {
+        Some(_) => Ok(()),
+        None => Err(Failure::Error("no such key".to_string())),
+    }
+}
+
+fn keys(args: &[OsString]) -> Result<(), Failure> {
+    let args = operands("keys", args, &["<db>"])?;
+    let db = open(&args[0], Open::READER)?;
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
+    for key in db.keys() {
+        out.write_all(&key?)?;
+        out.write_all(b"\n")?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn count(args: &[OsString]) -> Result<(), Failure> {
+    let args = operands("count", args, &["<db>"])?;
+    let db = open(&args[0], Open::READER)?;
+    println!("{}", KvStore::len(&db)?);
+    Ok(())
+}
+
+/// Printable form of a key, with bytes outside of ASCII escaped
+fn show(bytes: &[u8]) -> String {
+    bytes.iter().flat_map(|&byte| ::std::ascii::escape_default(byte)).map(char::from).collect()
+}
+
+fn check(args: &[OsString]) -> Result<(), Failure> {
+    let args = operands("check", args, &["<db>"])?;
+    let mut problems = 0u64;
+    match ForeignGdbmFile::open(Path::new(&args[0])) {
+        Ok(file) => {
+            for problem in file.check() {
+                println!("{}", problem);
+                problems += 1;
+            }
+        }
+        Err(GdbmError::IoError(err)) => return Err(Failure::Error(format!("{}: {}", Path::new(&args[0]).display(), err))),
+        Err(err) => {
+            println!("cannot read the header and directory: {}", err);
+            problems += 1;
+        }
+    }
+    let db = open(&args[0], Open::READER)?;
+    let mut records = 0u64;
+    let mut next = db.first_key();
+    loop {
+        let key = match next {
+            Ok(Some(key)) => key,
+            Ok(None) => break,
+            Err(err) => {
+                // Without the next key the walk cannot go on
+                println!("cannot get the key after record {}: {}", records, err);
+                problems += 1;
+                break;
+            }
+        };
+        records += 1;
+        match db.fetch_data(&key) {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                println!("key \"{}\" is listed but cannot be found", show(&key));
+                problems += 1;
+            }
+            Err(err) => {
+                println!("cannot read \"{}\": {}", show(&key), err);
+                problems += 1;
+            }
+        }
+        next = db.next_key(&key);
+    }
+    match KvStore::len(&db) {
+        Ok(count) if count != records => {
+            println!("the database counts {} records but {} were found", count, records);
+            problems += 1;
+        }
+        Ok(_) => {}
+        Err(err) => {
+            println!("cannot count the records: {}", err);
+            problems += 1;
+        }
+    }
+    println!("{} records checked, {} problems", records, problems);
+    if problems > 0 {
+        return Err(Failure::Error(format!("{} is damaged", Path::new(&args[0]).display())));
+    }
+    Ok(())
+}
+
+/// The CSV options given by the flags in `args` for `--format csv|tsv`
+fn csv_options(format: &OsString, args: &mut Vec<OsString>) -> Result<CsvOptions, Failure> {
+    let mut options = if format == "tsv" {
+        CsvOptions::tsv()
+    } else {
+        CsvOptions::new()
+    };
+    if let Some(delimiter) = take_option(args, "--delimiter")? {
+        match delimiter.as_bytes() {
+            &[delimiter] => options.delimiter(delimiter),
+            _ => return Err(Failure::Usage("--delimiter takes a single byte".to_string())),
+        };
+    }
+    match take_option(args, "--quote")? {
+        None => {}
+        Some(ref quote) if quote == "necessary" => {
+            options.quoting(Quoting::Necessary);
+        }
+        Some(ref quote) if quote == "always" => {
+            options.quoting(Quoting::Always);
+        }
+        Some(ref quote) if quote == "never" => {
+            options.quoting(Quoting::Never);
+        }
+        Some(quote) => return Err(Failure::Usage(format!("unknown quoting {}", quote.to_string_lossy()))),
+    }
+    options.base64_values(take_flag(args, "--base64"));
+    options.header(take_flag(args, "--header"));
+    Ok(options)
+}
+
+fn is_csv(format: &Option<OsString>) -> bool {
+    format.as_ref().is_some_and(|format| format == "csv" || format == "tsv")
+}
+
+fn dump(args: &[OsString]) -> Result<(), Failure> {
+    let mut args = args.to_vec();
+    let format = take_option(&mut args, "--format")?;
+    if is_csv(&format) {
+        let options = csv_options(format.as_ref().unwrap(), &mut args)?;
+        let args = operands("dump", &args, &["<db>", "<out>"])?;
+        let db = open(&args[0], Open::READER)?;
+        if args[1] == "-" {
+            let stdout = io::stdout();
+            db.export_csv(&mut stdout.lock(), &options)?;
+        } else {
+            db.export_csv(&mut File::create(&args[1])?, &options)?;
+        }
+        return Ok(());
+    }
+    let format = match format {
+        None => DumpFormat::Ascii,
+        Some(ref format) if format == "ascii" => DumpFormat::Ascii,
+        Some(ref format) if format == "binary" => DumpFormat::Binary,
+        Some(format) => return Err(Failure::Usage(format!("unknown dump format {}", format.to_string_lossy()))),
+    };
+    let args = operands("dump", &args, &["<db>", "<out>"])?;
+    let db = open(&args[0], Open::READER)?;
+    if args[1] == "-" {
+        let stdout = io::stdout();
+        db.export_to_writer(&mut stdout.lock(), format)?;
+    } else {
+        db.export_to_path(Path::new(&args[1]), format, 0o666)?;
+    }
+    Ok(())
+}
+
+fn load(args: &[OsString]) -> Result<(), Failure> {
+    let mut args = args.to_vec();
+    let flag = if take_flag(&mut args, "--replace") {
+        ImportFlag::Replace
+    } else {
+        ImportFlag::Insert
+    };
+    let format = take_option(&mut args, "--format")?;
+    let csv = if is_csv(&format) {
+        let mut options = csv_options(format.as_ref().unwrap(), &mut args)?;
+        options.import_flag(flag);
+        Some(options)
+    } else if let Some(format) = format {
+        return Err(Failure::Usage(format!("unknown load format {}", format.to_string_lossy())));
+    } else {
+        None
+    };
+    let args = operands("load", &args, &["<db>", "<dump>"])?;
+    let db = open(&args[0], Open::WRCREAT)?;
+    if args[1] == "-" {
+        let stdin = io::stdin();
+        match csv {
+            Some(options) => db.import_csv(&mut stdin.lock(), &options)?,
+            None => db.import_from_reader(&mut stdin.lock(), flag)?,
+        };
+    } else {
+        // Open it here to report a missing file as such, not as a
+        // parse error
+        let mut file = File::open(&args[1])
+            .map_err(|err| Failure::Error(format!("{}: {}", Path::new(&args[1]).display(), err)))?;
+        match csv {
+            Some(options) => db.import_csv(&mut file, &options)?,
+            None => db.import_from_path(Path::new(&args[1]), flag)?,
+        };
+    }
+    Ok(())
+}
+
+fn diff(args: &[OsString]) -> Result<(), Failure> {
+    let args = operands("diff", args, &["<db-a>", "<db-b>"])?;
+    let a = open(&args[0], Open::READER)?;
+    let b = open(&args[1], Open::READER)?;
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
+    let mut differences = 0u64;
+    for difference in gdbm::diff(&a, &b) {
+        let difference = difference?;
+        let marker = match difference {
+            Difference::OnlyInA { .. } => '-',
+            Difference::OnlyInB { .. } => '+',
+            Difference::Changed { .. } => '~',
+        };
+        writeln!(out, "{} {}", marker, show(difference.key()))?;
+        differences += 1;
+    }
+    out.flush()?;
+    if differences > 0 {
+        return Err(Failure::Error(format!("{} keys differ", differences)));
+    }
+    Ok(())
+}
+
+fn convert(args: &[OsString]) -> Result<(), Failure> {
+    let mut args = args.to_vec();
+    let format = match take_option(&mut args, "--to")? {
+        Some(ref format) if format == "numsync" => DbFormat::Numsync,
+        Some(ref format) if format == "standard" => DbFormat::Standard,
+        Some(format) => return Err(Failure::Usage(format!("unknown database format {}", format.to_string_lossy()))),
+        None => return Err(Failure::Usage("convert needs --to numsync|standard".to_string())),
+    };
+    let args = operands("convert", &args, &["<db>"])?;
+    let db = open(&args[0], Open::WRITER)?;
+    db.convert(format)?;
+    Ok(())
+}
+
+fn scrub(args: &[OsString]) -> Result<(), Failure> {
+    let args = operands("scrub", args, &["<db>"])?;
+    let db = ChecksummedGdbm::new(open(&args[0], Open::READER)?);
+    let damaged = db.scrub()?;
+    for key in &damaged {
+        println!("value of \"{}\" does not match its checksum", show(key));
+    }
+    println!("{} damaged values", damaged.len());
+    if !damaged.is_empty() {
+        return Err(Failure::Error(format!("{} is damaged", Path::new(&args[0]).display())));
+    }
+    Ok(())
+}
+
+fn reorganize(args: &[OsString]) -> Result<(), Failure> {
+    let args = operands("reorganize", args, &["<db>"])?;
+    let db = open(&args[0], Open::WRITER)?;
+    db.reorganize()?;
+    Ok(())
+}
+
+fn run(args: &[OsString]) -> Result<(), Failure> {
+    let command = match args.first() {
+        Some(command) => command.to_string_lossy(),
+        None => return Err(Failure::Usage("no command given".to_string())),
+    };
+    let args = &args[1..];
+    match &*command {
+        "get" => get(args),
+        "put" => put(args),
+        "del" => del(args),
+        "keys" => keys(args),
+        "count" => count(args),
+        "check" => check(args),
+        "dump" => dump(args),
+        "load" => load(args),
+        "diff" => diff(args),
+        "convert" => convert(args),
+        "scrub" => scrub(args),
+        "reorganize" => reorganize(args),
+        "help" | "-h" | "--help" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        other => Err(Failure::Usage(format!("unknown command {}", other))),
+    }
+}
+
+fn main() {
+    let args: Vec<OsString> = env::args_os().skip(1).collect();
+    match run(&args) {
+        Ok(()) => {}
+        Err(Failure::Usage(message)) => {
+            eprintln!("gdbm-tool: {}\n\n{}", message, USAGE);
+            process::exit(2);
+        }
+        Err(Failure::Error(message)) => {
+            eprintln!("gdbm-tool: {}", message);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use std::io::{self, Read, Write};
+
+use {Gdbm, GdbmError, Store};
+
+/// Start of the manifest record a blob's own key holds
+const MANIFEST_MAGIC: &[u8] = b"\0gdbm-rs:blob\0";
+
+/// Start of the keys of the records holding blob chunks
+pub(crate) const CHUNK_KEY_PREFIX: &[u8] = b"\0gdbm-rs:blob-chunk:";
+
+/// Bytes of blob data per chunk record
+pub const BLOB_CHUNK_SIZE: usize = 1 << 20;
+
+/// What the key of a blob holds: where its chunks are and how long it is
+#[derive(Debug, Clone, Copy)]
+struct Manifest {
+    /// Bumped on every rewrite, so that the chunks of a blob being
+    /// rewritten do not overwrite those of the current version
+    generation: u64,
+    len: u64,
+    chunk_size: u64,
+}
+
+impl Manifest {
+    fn encode(&self) -> Vec<u8> {
+        let mut out = MANIFEST_MAGIC.to_vec();
+        out.extend_from_slice(&self.generation.to_le_bytes());
+        out.extend_from_slice(&self.len.to_le_bytes());
+        out.extend_from_slice(&self.chunk_size.to_le_bytes());
+        out
+    }
+
+    fn decode(stored: &[u8]) -> Option<Manifest> {
+        if stored.len() != MANIFEST_MAGIC.len() + 24 || !stored.starts_with(MANIFEST_MAGIC) {
+            return None;
+        }
+        let field = |i: usize| {
+            let mut bytes = [0; 8];
+            let start = MANIFEST_MAGIC.len() + 8 * i;
+            bytes.copy_from_slice(&stored[start..start + 8]);
+            u64::from_le_bytes(bytes)
+        };
+        Some(Manifest {
+            generation: field(0),
+            len: field(1),
+            chunk_size: field(2),
+        })
+    }
+
+    fn chunks(&self) -> u64 {
+        self.len.div_ceil(self.chunk_size.max(1))
+    }
+}
+
+fn chunk_key(key: &[u8], generation: u64, index: u64) -> Vec<u8> {
+    let mut chunk_key = CHUNK_KEY_PREFIX.to_vec();
+    chunk_key.extend_from_slice(&generation.to_be_bytes());
+    chunk_key.extend_from_slice(&index.to_be_bytes());
+    chunk_key.extend_from_slice(key);
+    chunk_key
+}
+
+fn io_error(err: GdbmError) -> io::Error {
+    match err {
+        GdbmError::IoError(err) => err,
+        other => io::Error::other(other),
+    }
+}
+
+/// Writes a blob in chunks, returned by `Gdbm::blob_writer`.
+///
+/// The blob replaces whatever the key held once `finish` succeeds;
+/// readers see the old value until then. A writer dropped without
+/// `finish`, after a failed write or a panic say, is discarded: the
+/// chunks it wrote are deleted and the key keeps its old value.
+#[derive(Debug)]
+pub struct BlobWriter<'a> {
+    db: &'a Gdbm,
+    key: Vec<u8>,
+    previous: Option<Manifest>,
+    generation: u64,
+    buffer: Vec<u8>,
+    chunks: u64,
+    len: u64,
+    finished: bool,
+}
+
+/// Reads a blob chunk by chunk, returned by `Gdbm::blob_reader`
+#[derive(Debug)]
+pub struct BlobReader<'a> {
+    db: &'a Gdbm,
+    key: Vec<u8>,
+    manifest: Manifest,
+    chunk: Vec<u8>,
+    pos_in_chunk: usize,
+    next_chunk: u64,
+}
+
+impl Gdbm {
+    /// Start writing a blob under `key`, for values too big to build in
+    /// memory. The data is split over records of `BLOB_CHUNK_SIZE` bytes,
+    /// and `key` itself holds a small manifest pointing at them.
+    pub fn blob_writer<K: AsRef<[u8]>>(&self, key: K) -> Result<BlobWriter<'_>, GdbmError> {
+        let key = key.as_ref().to_vec();
+        let previous = self.fetch_data(&key)?.and_then(|stored| Manifest::decode(&stored));
+        let generation = previous.map_or(0, |manifest| manifest.generation.wrapping_add(1));
+        Ok(BlobWriter {
+            db: self,
+            key,
+            previous,
+            generation,
+            buffer: Vec::new(),
+            chunks: 0,
+            len: 0,
+            finished: false,
+        })
+    }
+
+    /// Open the blob stored under `key`. None if the key has no record;
+    /// an error if its record is not a blob.
+    pub fn blob_reader<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<BlobReader<'_>>, GdbmError> {
+        let key = key.as_ref().to_vec();
+        let manifest = match self.fetch_data(&key)? {
+            Some(stored) => Manifest::decode(&stored).ok_or_else(|| GdbmError::new("record is not a blob"))?,
+            None => return Ok(None),
+        };
+        Ok(Some(BlobReader {
+            db: self,
+            key,
+            manifest,
+            chunk: Vec::new(),
+            pos_in_chunk: 0,
+            next_chunk: 0,
+        }))
+    }
+
+    /// Delete the blob stored under `key` and all its chunks. Returns
+    /// false if the key has no record; fails if its record is not a blob.
+    pub fn remove_blob<K: AsRef<[u8]>>(&self, key: K) -> Result<bool, GdbmError> {
+        let key = key.as_ref();
+        let manifest = match self.fetch_data(key)? {
+            Some(stored) => Manifest::decode(&stored).ok_or_else(|| GdbmError::new("record is not a blob"))?,
+            None => return Ok(false),
+        };
+        self.remove(key)?;
+        self.delete_many((0..manifest.chunks()).map(|i| chunk_key(key, manifest.generation, i)))?;
+        Ok(true)
+    }
+}
+
+impl<'a> BlobWriter<'a> {
+    fn write_chunk(&mut self) -> Result<(), GdbmError> {
+        let chunk_key = chunk_key(&self.key, self.generation, self.chunks);
+        self.db.store_bytes(&chunk_key, &self.buffer, Store::REPLACE)?;
+        self.buffer.clear();
+        self.chunks += 1;
+        Ok(())
+    }
+
+    /// Store the last chunk and the manifest, then delete the chunks of
+    /// the blob this one replaces. Returns the length of the blob. If it
+    /// fails the key keeps its old value.
+    pub fn finish(mut self) -> Result<u64, GdbmError> {
+        self.finished = true;
+        if let Err(err) = self.commit() {
+            let _ = self.remove_chunks();
+            return Err(err);
+        }
+        Ok(self.len)
+    }
+
+    /// Delete the chunks written so far, leaving the key's old value
+    pub fn discard(mut self) -> Result<(), GdbmError> {
+        self.finished = true;
+        self.remove_chunks()
+    }
+
+    fn remove_chunks(&mut self) -> Result<(), GdbmError> {
+        self.db.delete_many((0..self.chunks).map(|i| chunk_key(&self.key, self.generation, i)))?;
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<(), GdbmError> {
+        if !self.buffer.is_empty() {
+            self.write_chunk()?;
+        }
+        let manifest = Manifest {
+            generation: self.generation,
+            len: self.len,
+            chunk_size: BLOB_CHUNK_SIZE as u64,
+        };
+        self.db.store_bytes(&self.key, &manifest.encode(), Store::REPLACE)?;
+        if let Some(previous) = self.previous {
+            // The new blob is in place; stale chunks only waste space
+            let _ = self.db.delete_many((0..previous.chunks()).map(|i| chunk_key(&self.key, previous.generation, i)));
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Write for BlobWriter<'a> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        // Take at most a chunk's worth, to not buffer more than a chunk
+        let len = data.len().min(BLOB_CHUNK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&data[..len]);
+        self.len += len as u64;
+        if self.buffer.len() == BLOB_CHUNK_SIZE {
+            self.write_chunk().map_err(io_error)?;
+        }
+        Ok(len)
+    }
+
+    /// Does nothing: a partial chunk is only written by `finish`
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Discards an unfinished writer, ignoring errors; call `discard` to see
+/// them.
+impl<'a> Drop for BlobWriter<'a> {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.remove_chunks();
+        }
+    }
+}
+
+impl<'a> BlobReader<'a> {
+    /// Length of the blob in bytes
+    pub fn len(&self) -> u64 {
+        self.manifest.len
+    }
+
+    /// True for a blob of no bytes
+    pub fn is_empty(&self) -> bool {
+        self.manifest.len == 0
+    }
+}
+
+impl<'a> Read for BlobReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos_in_chunk == self.chunk.len() {
+            if self.next_chunk == self.manifest.chunks() {
+                return Ok(0);
+            }
+            let chunk_key = chunk_key(&self.key, self.manifest.generation, self.next_chunk);
+            self.chunk = self.db
+                .fetch_data(&chunk_key)
+                .map_err(io_error)?
+                .ok_or_else(|| io::Error::other("blob chunk is missing; was the blob rewritten or removed?"))?;
+            self.pos_in_chunk = 0;
+            self.next_chunk += 1;
+        }
+        let len = buf.len().min(self.chunk.len() - self.pos_in_chunk);
+        buf[..len].copy_from_slice(&self.chunk[self.pos_in_chunk..self.pos_in_chunk + len]);
+        self.pos_in_chunk += len;
+        Ok(len)
+    }
+}
@@ -0,0 +1,165 @@
+use {Gdbm, GdbmError, Keys, StoreOutcome};
+
+/// Ends a bucket's name in the stored keys, so that bucket "user" does
+/// not see the keys of bucket "users"
+const BUCKET_SEPARATOR: u8 = 0;
+
+/// A namespace inside a database, returned by `Gdbm::bucket`.
+///
+/// A key `k` of bucket `name` is stored as `name`, a NUL byte, then `k`.
+/// Iterating over a bucket still walks every key of the database, since
+/// gdbm keeps no order to jump to the prefix with.
+#[derive(Debug, Clone)]
+pub struct Bucket<'a> {
+    db: &'a Gdbm,
+    prefix: Vec<u8>,
+}
+
+/// Iterator over the keys of a bucket, without the bucket prefix,
+/// returned by `Bucket::keys`
+#[derive(Debug)]
+pub struct BucketKeys<'a> {
+    keys: Keys<'a>,
+    prefix: Vec<u8>,
+}
+
+/// Iterator over the records of a bucket, with keys stripped of the
+/// bucket prefix, returned by `Bucket::iter`. Only the values of the
+/// bucket's own records are read.
+#[derive(Debug)]
+pub struct BucketIter<'a> {
+    db: &'a Gdbm,
+    keys: BucketKeys<'a>,
+}
+
+impl Gdbm {
+    /// A handle to the bucket called `name`. Buckets need no creating;
+    /// one exists as long as it has records. The name may not contain a
+    /// NUL byte.
+    pub fn bucket(&self, name: &str) -> Result<Bucket<'_>, GdbmError> {
+        if name.as_bytes().contains(&BUCKET_SEPARATOR) {
+            return Err(GdbmError::new("bucket names may not contain a NUL byte"));
+        }
+        let mut prefix = name.as_bytes().to_vec();
+        prefix.push(BUCKET_SEPARATOR);
+        Ok(Bucket { db: self, prefix })
+    }
+}
+
+impl<'a> Bucket<'a> {
+    /// The bucket's name
+    pub fn name(&self) -> &str {
+        let name = &self.prefix[..self.prefix.len() - 1];
+        ::std::str::from_utf8(name).expect("bucket names are created from a str")
+    }
+
+    /// The key `key` is stored under in the database
+    pub fn full_key<K: AsRef<[u8]>>(&self, key: K) -> Vec<u8> {
+        let mut full = self.prefix.clone();
+        full.extend_from_slice(key.as_ref());
+        full
+    }
+
+    /// See `Gdbm::fetch_data`
+    pub fn fetch_data<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Vec<u8>>, GdbmError> {
+        self.db.fetch_data(self.full_key(key))
+    }
+
+    /// See `Gdbm::store_checked`
+    pub fn store_checked<K, V>(&self, key: K, content: V, replace: bool) -> Result<StoreOutcome, GdbmError>
+        where K: AsRef<[u8]>,
+              V: AsRef<[u8]>
+    {
+        self.db.store_checked(self.full_key(key), content, replace)
+    }
+
+    /// See `Gdbm::insert`
+    pub fn insert<K, V>(&self, key: K, value: V) -> Result<Option<Vec<u8>>, GdbmError>
+        where K: AsRef<[u8]>,
+              V: AsRef<[u8]>
+    {
+        self.db.insert(self.full_key(key), value)
+    }
+
+    /// See `Gdbm::remove`
+    pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Vec<u8>>, GdbmError> {
+        self.db.remove(self.full_key(key))
+    }
+
+    /// Number of records in the bucket
+    pub fn len(&self) -> Result<usize, GdbmError> {
+        self.db.count_prefix(&self.prefix)
+    }
+
+    /// True if the bucket has no records
+    pub fn is_empty(&self) -> Result<bool, GdbmError> {
+        Ok(self.keys().next().transpose()?.is_none())
+    }
+
+    /// Delete every record in the bucket, returning how many there were
+    pub fn clear(&self) -> Result<usize, GdbmError> {
+        self.db.delete_prefix(&self.prefix)
+    }
+
+    /// Iterate over the keys of the bucket
+    pub fn keys(&self) -> BucketKeys<'a> {
+        BucketKeys {
+            keys: self.db.keys(),
+            prefix: self.prefix.clone(),
+        }
+    }
+
+    /// Iterate over the records of the bucket
+    pub fn iter(&self) -> BucketIter<'a> {
+        BucketIter {
+            db: self.db,
+            keys: self.keys(),
+        }
+    }
+}
+
+impl<'a> Iterator for BucketKeys<'a> {
+    type Item = Result<Vec<u8>, GdbmError>;
+
+    fn next(&mut self) -> Option<Result<Vec<u8>, GdbmError>> {
+        loop {
+            match self.keys.next()? {
+                Ok(key) => {
+                    if key.starts_with(&self.prefix) {
+                        return Some(Ok(key[self.prefix.len()..].to_vec()));
+                    }
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.keys.size_hint().1)
+    }
+}
+
+impl<'a> Iterator for BucketIter<'a> {
+    type Item = Result<(Vec<u8>, Vec<u8>), GdbmError>;
+
+    fn next(&mut self) -> Option<Result<(Vec<u8>, Vec<u8>), GdbmError>> {
+        loop {
+            let key = match self.keys.next()? {
+                Ok(key) => key,
+                Err(e) => return Some(Err(e)),
+            };
+            let mut full = self.keys.prefix.clone();
+            full.extend_from_slice(&key);
+            match self.db.fetch_data(&full) {
+                Ok(Some(value)) => return Some(Ok((key, value))),
+                // Deleted since the key was read
+                Ok(None) => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.keys.size_hint()
+    }
+}
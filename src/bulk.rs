@@ -0,0 +1,237 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::ops::Deref;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+
+use gdbm_sys::{gdbm_reorganize, GDBM_CACHESIZE, GDBM_SYNCMODE};
+use libc::{self, c_int};
+
+use ffi;
+use migrations::copy_reserved;
+use progress::PROGRESS_INTERVAL;
+use {Gdbm, GdbmError, Open, Progress, Store};
+
+/// Buckets `Gdbm::bulk_load` keeps in the cache unless told otherwise
+pub const BULK_LOAD_CACHE_SIZE: usize = 4096;
+
+/// How `Gdbm::bulk_load_with` sets the database up for a large import
+#[derive(Debug, Clone)]
+pub struct BulkLoadOptions {
+    cache_size: usize,
+    preallocate: Option<u64>,
+    reorganize: bool,
+}
+
+impl Default for BulkLoadOptions {
+    fn default() -> BulkLoadOptions {
+        BulkLoadOptions {
+            cache_size: BULK_LOAD_CACHE_SIZE,
+            preallocate: None,
+            reorganize: false,
+        }
+    }
+}
+
+impl BulkLoadOptions {
+    /// No preallocation and no reorganize, `BULK_LOAD_CACHE_SIZE` buckets
+    /// of cache.
+    pub fn new() -> BulkLoadOptions {
+        BulkLoadOptions::default()
+    }
+
+    /// Number of buckets to cache during the load
+    pub fn cache_size(&mut self, buckets: usize) -> &mut BulkLoadOptions {
+        self.cache_size = buckets;
+        self
+    }
+
+    /// Reserve disk space for a file of `bytes` before the load starts;
+    /// see `Gdbm::preallocate`
+    pub fn preallocate(&mut self, bytes: u64) -> &mut BulkLoadOptions {
+        self.preallocate = Some(bytes);
+        self
+    }
+
+    /// Reorganize the database when the load ends, reclaiming the space
+    /// left by records that were replaced or deleted during it
+    pub fn reorganize(&mut self, reorganize: bool) -> &mut BulkLoadOptions {
+        self.reorganize = reorganize;
+        self
+    }
+}
+
+/// A database set up for a large import, returned by `Gdbm::bulk_load`.
+/// Store through it as through the `Gdbm` it derefs to.
+///
+/// Ending the load restores the sync mode and cache size, syncs once and
+/// reorganizes if asked to. Call `finish` to see errors doing that;
+/// dropping the guard ends the load and ignores them. Nothing stored
+/// during the load is safely on disk before it ends.
+#[derive(Debug)]
+pub struct BulkLoad<'a> {
+    db: &'a Gdbm,
+    sync_mode: c_int,
+    cache_size: usize,
+    cache_auto: Option<c_int>,
+    reorganize: bool,
+    finished: bool,
+}
+
+impl Gdbm {
+    /// Start a bulk load with the default `BulkLoadOptions`
+    pub fn bulk_load(&self) -> Result<BulkLoad<'_>, GdbmError> {
+        self.bulk_load_with(&BulkLoadOptions::new())
+    }
+
+    /// Start a bulk load: switch off synchronous mode, enlarge the bucket
+    /// cache and preallocate the file as `options` say.
+    pub fn bulk_load_with(&self, options: &BulkLoadOptions) -> Result<BulkLoad<'_>, GdbmError> {
+        if let Some(bytes) = options.preallocate {
+            self.preallocate(bytes)?;
+        }
+        let load = BulkLoad {
+            db: self,
+            sync_mode: self.getopt(ffi::GDBM_GETSYNCMODE, 0)?,
+            cache_size: self.getopt(ffi::GDBM_GETCACHESIZE, 0)?,
+            // Setting the cache size turns automatic sizing off
+            cache_auto: self.getopt(ffi::GDBM_GETCACHEAUTO, 0).ok(),
+            reorganize: options.reorganize,
+            finished: false,
+        };
+        self.setopt(GDBM_SYNCMODE as c_int, 0 as c_int)?;
+        self.setopt(GDBM_CACHESIZE as c_int, options.cache_size.max(load.cache_size))?;
+        Ok(load)
+    }
+
+    /// Rebuild the database file without the space of deleted and
+    /// replaced records. Needs a writer and takes as long as copying the
+    /// database.
+    pub fn reorganize(&self) -> Result<(), GdbmError> {
+        let result = unsafe { gdbm_reorganize(self.handle()?) };
+        if result != 0 {
+            return Err(self.last_error());
+        }
+        Ok(())
+    }
+
+    /// `reorganize`, calling `progress` every `PROGRESS_INTERVAL` records
+    /// and once more at the end. `bytes` is always 0.
+    ///
+    /// gdbm reorganizes without saying how far it has got, so this does
+    /// the same work itself: it copies the records into a new file next to
+    /// the database, named after it with `.reorganize` appended and with
+    /// its block size and permissions, renames that over the database and
+    /// reopens the handle on it. Other handles keep the old file, as they
+    /// do with `reorganize`. Fails if the new file already exists; if the
+    /// copy fails it is removed and the database is left as it was.
+    pub fn reorganize_with_progress<F>(&mut self, mut progress: F) -> Result<(), GdbmError>
+        where F: FnMut(Progress)
+    {
+        let info = self.info()?;
+        if info.flags.bits & Open::NEWDB.bits == Open::READER.bits {
+            return Err(GdbmError::new("Reader can't reorganize"));
+        }
+        let mut dest = OsString::from(&info.name);
+        dest.push(".reorganize");
+        let dest = PathBuf::from(dest);
+        if fs::symlink_metadata(&dest).is_ok() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "reorganize file already exists").into());
+        }
+        let mode = fs::metadata(&info.name)?.permissions().mode() & 0o7777;
+        let copy = Gdbm::new(&dest, info.block_size, Open::NEWDB, mode as i32)?;
+        let copied = copy.with_deferred_sync(|| {
+            let mut records = 0;
+            for record in self.iter() {
+                let (key, value) = record?;
+                copy.store_bytes(&key, &value, Store::INSERT)?;
+                records += 1;
+                if records % PROGRESS_INTERVAL == 0 {
+                    progress(Progress { records, bytes: 0 });
+                }
+            }
+            copy_reserved(self, &copy)?;
+            Ok(records)
+        });
+        drop(copy);
+        let renamed = copied.and_then(|records| {
+            fs::rename(&dest, &info.name)?;
+            Ok(records)
+        });
+        let records = match renamed {
+            Ok(records) => records,
+            Err(err) => {
+                let _ = fs::remove_file(&dest);
+                return Err(err);
+            }
+        };
+        self.reopen()?;
+        progress(Progress { records, bytes: 0 });
+        Ok(())
+    }
+
+    /// Reserve disk space for the first `bytes` of the database file, so
+    /// a large import does not grow it block by block and leave it
+    /// fragmented on disk. The file keeps its size, so gdbm sees the same
+    /// database; only the blocks are allocated. Fails with the OS error
+    /// if the file system cannot preallocate, and on systems other than
+    /// Linux, where `posix_fallocate` would change the size.
+    #[cfg(target_os = "linux")]
+    pub fn preallocate(&self, bytes: u64) -> Result<(), GdbmError> {
+        self.handle()?;
+        let result = unsafe { libc::fallocate(self.as_raw_fd(), libc::FALLOC_FL_KEEP_SIZE, 0, bytes as libc::off_t) };
+        if result != 0 {
+            return Err(::std::io::Error::last_os_error().into());
+        }
+        Ok(())
+    }
+
+    /// See the Linux version
+    #[cfg(not(target_os = "linux"))]
+    pub fn preallocate(&self, _bytes: u64) -> Result<(), GdbmError> {
+        Err(GdbmError::new("preallocation is only supported on Linux"))
+    }
+}
+
+impl<'a> BulkLoad<'a> {
+    /// End the load, reporting the first error restoring the settings,
+    /// syncing or reorganizing
+    pub fn finish(mut self) -> Result<(), GdbmError> {
+        self.end()
+    }
+
+    fn end(&mut self) -> Result<(), GdbmError> {
+        self.finished = true;
+        let sync_mode = self.db.setopt(GDBM_SYNCMODE as c_int, self.sync_mode);
+        let cache_size = self.db.setopt(GDBM_CACHESIZE as c_int, self.cache_size);
+        if let Some(auto) = self.cache_auto {
+            let _ = self.db.setopt(ffi::GDBM_SETCACHEAUTO, auto);
+        }
+        let synced = self.db.sync();
+        sync_mode?;
+        cache_size?;
+        synced?;
+        if self.reorganize {
+            self.db.reorganize()?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Deref for BulkLoad<'a> {
+    type Target = Gdbm;
+
+    fn deref(&self) -> &Gdbm {
+        self.db
+    }
+}
+
+impl<'a> Drop for BulkLoad<'a> {
+    fn drop(&mut self) {
+        if !self.finished {
+            let _ = self.end();
+        }
+    }
+}
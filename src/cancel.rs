@@ -0,0 +1,74 @@
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use GdbmError;
+
+/// A flag for stopping long-running operations from another thread, such
+/// as a Ctrl-C handler. Clones share the flag.
+///
+/// Operations that accept a token check it between units of work and
+/// fail with `GdbmError::Cancelled` once it is set. Work done up to that
+/// point is not rolled back.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// A token that has not been cancelled
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Ask the operations watching this token to stop.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// True once `cancel` has been called on this token or a clone
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// `Err(GdbmError::Cancelled)` once the token is cancelled
+    pub(crate) fn check(&self) -> Result<(), GdbmError> {
+        if self.is_cancelled() {
+            return Err(GdbmError::Cancelled);
+        }
+        Ok(())
+    }
+}
+
+/// `io::copy`, checking `cancel` before every buffer and passing the
+/// running total of bytes copied to `progress` after it.
+pub(crate) fn copy<R, W>(reader: &mut R,
+                         writer: &mut W,
+                         cancel: Option<&CancellationToken>,
+                         mut progress: Option<&mut dyn FnMut(u64)>)
+                         -> Result<u64, GdbmError>
+    where R: Read + ?Sized,
+          W: Write + ?Sized
+{
+    if cancel.is_none() && progress.is_none() {
+        return Ok(io::copy(reader, writer)?);
+    }
+    let mut buf = [0; 8192];
+    let mut copied = 0;
+    loop {
+        if let Some(cancel) = cancel {
+            cancel.check()?;
+        }
+        let len = match reader.read(&mut buf) {
+            Ok(0) => return Ok(copied),
+            Ok(len) => len,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        writer.write_all(&buf[..len])?;
+        copied += len as u64;
+        if let Some(ref mut progress) = progress {
+            progress(copied);
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use stats::{WriteAmplification, WriteCounters};
+use {Gdbm, GdbmError, Iter, StoreOutcome};
+
+/// Bytes of checksum after every value
+const CHECKSUM_LEN: usize = 4;
+
+fn append_checksum(value: &[u8]) -> Vec<u8> {
+    let mut stored = Vec::with_capacity(value.len() + CHECKSUM_LEN);
+    stored.extend_from_slice(value);
+    stored.extend_from_slice(&crc32fast::hash(value).to_le_bytes());
+    stored
+}
+
+/// Check and strip the checksum of the value stored under `key`
+fn verify(key: &[u8], mut stored: Vec<u8>) -> Result<Vec<u8>, GdbmError> {
+    if stored.len() < CHECKSUM_LEN {
+        return Err(GdbmError::new("value is too short to hold a checksum"));
+    }
+    let split = stored.len() - CHECKSUM_LEN;
+    let mut expected = [0; CHECKSUM_LEN];
+    expected.copy_from_slice(&stored[split..]);
+    let expected = u32::from_le_bytes(expected);
+    stored.truncate(split);
+    let actual = crc32fast::hash(&stored);
+    if actual != expected {
+        return Err(GdbmError::ChecksumMismatch {
+            key: key.to_vec(),
+            expected,
+            actual,
+        });
+    }
+    Ok(stored)
+}
+
+/// A database that stores a CRC-32 after every value and checks it on
+/// every read, to catch values damaged on disk.
+///
+/// A damaged value is reported as `GdbmError::ChecksumMismatch`; `scrub`
+/// finds all of them. gdbm's own structures are not covered, and values
+/// written without the wrapper read as damaged.
+#[derive(Debug)]
+pub struct ChecksummedGdbm {
+    db: Gdbm,
+    writes: WriteCounters,
+}
+
+/// Iterator over the records of a `ChecksummedGdbm`, returned by
+/// `ChecksummedGdbm::iter`
+#[derive(Debug)]
+pub struct ChecksummedIter<'a> {
+    iter: Iter<'a>,
+}
+
+impl ChecksummedGdbm {
+    /// Wrap `db`
+    pub fn new(db: Gdbm) -> ChecksummedGdbm {
+        ChecksummedGdbm {
+            db,
+            writes: WriteCounters::default(),
+        }
+    }
+
+    /// The underlying handle, for operations on the stored bytes
+    pub fn as_gdbm(&self) -> &Gdbm {
+        &self.db
+    }
+
+    /// Unwrap the underlying handle
+    pub fn into_inner(self) -> Gdbm {
+        self.db
+    }
+
+    /// See `Gdbm::fetch_data`
+    pub fn fetch_data<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Vec<u8>>, GdbmError> {
+        let key = key.as_ref();
+        self.db.fetch_data(key)?.map(|stored| verify(key, stored)).transpose()
+    }
+
+    /// See `Gdbm::store_checked`
+    pub fn store_checked<K, V>(&self, key: K, content: V, replace: bool) -> Result<StoreOutcome, GdbmError>
+        where K: AsRef<[u8]>,
+              V: AsRef<[u8]>
+    {
+        let (key, content) = (key.as_ref(), content.as_ref());
+        let stored = append_checksum(content);
+        let outcome = self.db.store_checked(key, &stored, replace)?;
+        if outcome != StoreOutcome::AlreadyExists {
+            self.writes.add(key.len() + content.len(), key.len() + stored.len());
+        }
+        Ok(outcome)
+    }
+
+    /// See `Gdbm::insert`. A damaged old value is replaced all the same,
+    /// and reported as an error afterwards.
+    pub fn insert<K, V>(&self, key: K, value: V) -> Result<Option<Vec<u8>>, GdbmError>
+        where K: AsRef<[u8]>,
+              V: AsRef<[u8]>
+    {
+        let (key, value) = (key.as_ref(), value.as_ref());
+        let stored = append_checksum(value);
+        let old = self.db.insert(key, &stored)?;
+        self.writes.add(key.len() + value.len(), key.len() + stored.len());
+        old.map(|old| verify(key, old)).transpose()
+    }
+
+    /// See `Gdbm::remove`. A damaged value is removed all the same, and
+    /// reported as an error afterwards.
+    pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Vec<u8>>, GdbmError> {
+        let key = key.as_ref();
+        self.db.remove(key)?.map(|old| verify(key, old)).transpose()
+    }
+
+    /// The bytes the checksums have added to the writes made since the
+    /// wrapper was created or `reset_write_amplification` was last called
+    pub fn write_amplification(&self) -> WriteAmplification {
+        self.writes.snapshot()
+    }
+
+    /// Set the write amplification counts back to zero
+    pub fn reset_write_amplification(&self) {
+        self.writes.reset();
+    }
+
+    /// Iterate over the records, checking every value
+    pub fn iter(&self) -> ChecksummedIter<'_> {
+        ChecksummedIter { iter: self.db.iter() }
+    }
+
+    /// Check every value, returning the keys of the damaged ones
+    pub fn scrub(&self) -> Result<Vec<Vec<u8>>, GdbmError> {
+        let mut damaged = Vec::new();
+        for record in self.db.iter() {
+            let (key, stored) = record?;
+            if verify(&key, stored).is_err() {
+                damaged.push(key);
+            }
+        }
+        Ok(damaged)
+    }
+}
+
+impl<'a> Iterator for ChecksummedIter<'a> {
+    type Item = Result<(Vec<u8>, Vec<u8>), GdbmError>;
+
+    fn next(&mut self) -> Option<Result<(Vec<u8>, Vec<u8>), GdbmError>> {
+        Some(self.iter.next()?.and_then(|(key, stored)| {
+            let value = verify(&key, stored)?;
+            Ok((key, value))
+        }))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
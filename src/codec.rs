@@ -0,0 +1,109 @@
+use bincode;
+#[cfg(feature = "cbor")]
+use ciborium;
+#[cfg(feature = "msgpack")]
+use rmp_serde;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+#[cfg(feature = "json")]
+use serde_json;
+
+use GdbmError;
+
+/// How a `TypedGdbm` turns keys and values of type `T` into bytes and
+/// back. Codecs are types without values; pick one with the third type
+/// parameter of `TypedGdbm`.
+pub trait Codec<T> {
+    /// The stored form of `value`
+    fn encode(value: &T) -> Result<Vec<u8>, GdbmError>;
+
+    /// Read back a value stored with `encode`
+    fn decode(bytes: &[u8]) -> Result<T, GdbmError>;
+}
+
+/// The default codec, bincode with its default options: fixed-width
+/// little endian integers and a `u64` length before strings and
+/// sequences
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Bincode;
+
+impl<T: Serialize + DeserializeOwned> Codec<T> for Bincode {
+    fn encode(value: &T) -> Result<Vec<u8>, GdbmError> {
+        bincode::serialize(value).map_err(|err| GdbmError::new(format!("bincode encoding failed: {}", err)))
+    }
+
+    fn decode(bytes: &[u8]) -> Result<T, GdbmError> {
+        bincode::deserialize(bytes).map_err(|err| GdbmError::new(format!("bincode decoding failed: {}", err)))
+    }
+}
+
+/// A codec storing keys and values as UTF-8 JSON text with serde_json,
+/// readable from any language. Nesting deeper than 128 levels is refused
+/// when decoding.
+#[cfg(feature = "json")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl<T: Serialize + DeserializeOwned> Codec<T> for Json {
+    fn encode(value: &T) -> Result<Vec<u8>, GdbmError> {
+        serde_json::to_vec(value).map_err(|err| GdbmError::new(format!("JSON encoding failed: {}", err)))
+    }
+
+    fn decode(bytes: &[u8]) -> Result<T, GdbmError> {
+        serde_json::from_slice(bytes).map_err(|err| GdbmError::new(format!("JSON decoding failed: {}", err)))
+    }
+}
+
+/// A codec storing keys and values as CBOR (RFC 8949) with ciborium.
+/// Nesting deeper than ciborium's recursion limit is refused when
+/// decoding.
+#[cfg(feature = "cbor")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Cbor;
+
+#[cfg(feature = "cbor")]
+impl<T: Serialize + DeserializeOwned> Codec<T> for Cbor {
+    fn encode(value: &T) -> Result<Vec<u8>, GdbmError> {
+        let mut out = Vec::new();
+        ciborium::into_writer(value, &mut out).map_err(|err| GdbmError::new(format!("CBOR encoding failed: {}", err)))?;
+        Ok(out)
+    }
+
+    fn decode(mut bytes: &[u8]) -> Result<T, GdbmError> {
+        let value = ciborium::from_reader(&mut bytes)
+            .map_err(|err| GdbmError::new(format!("CBOR decoding failed: {}", err)))?;
+        trailing(bytes)?;
+        Ok(value)
+    }
+}
+
+/// A codec storing keys and values as MessagePack with rmp-serde.
+/// Structs are written as maps keyed by field name, so that consumers
+/// in other languages can read them.
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl<T: Serialize + DeserializeOwned> Codec<T> for MessagePack {
+    fn encode(value: &T) -> Result<Vec<u8>, GdbmError> {
+        rmp_serde::to_vec_named(value).map_err(|err| GdbmError::new(format!("MessagePack encoding failed: {}", err)))
+    }
+
+    fn decode(mut bytes: &[u8]) -> Result<T, GdbmError> {
+        let value = rmp_serde::from_read(&mut bytes)
+            .map_err(|err| GdbmError::new(format!("MessagePack decoding failed: {}", err)))?;
+        trailing(bytes)?;
+        Ok(value)
+    }
+}
+
+/// Refuse bytes left over after a value read from a stream format
+#[cfg(any(feature = "cbor", feature = "msgpack"))]
+fn trailing(rest: &[u8]) -> Result<(), GdbmError> {
+    if !rest.is_empty() {
+        return Err(GdbmError::new(format!("{} trailing byte(s) after encoded value", rest.len())));
+    }
+    Ok(())
+}
@@ -0,0 +1,41 @@
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+use migrations::copy_reserved;
+use {Gdbm, GdbmError, Open, Store};
+
+impl Gdbm {
+    /// Write the live records into a new database at `dest`, which must
+    /// not exist yet, and return a writable handle to it.
+    ///
+    /// The copy has no dead space and lets gdbm pick the block size, which
+    /// matches the file system's preferred I/O size. It gets the source
+    /// file's permissions, and the bookkeeping records such as the schema
+    /// version. This is the safe way to compact a database in
+    /// use: copy, check, then rename the copy over the original and
+    /// reopen. If the copy fails the partial file is removed.
+    pub fn copy_compacted(&self, dest: &Path) -> Result<Gdbm, GdbmError> {
+        if fs::symlink_metadata(dest).is_ok() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "destination already exists").into());
+        }
+        let mode = fs::metadata(self.name()?)?.permissions().mode() & 0o7777;
+        let copy = Gdbm::new(dest, 0, Open::NEWDB, mode as i32)?;
+        let copied = copy.with_deferred_sync(|| {
+            for record in self.iter() {
+                let (key, value) = record?;
+                copy.store_bytes(&key, &value, Store::INSERT)?;
+            }
+            copy_reserved(self, &copy)
+        });
+        match copied {
+            Ok(()) => Ok(copy),
+            Err(err) => {
+                drop(copy);
+                let _ = fs::remove_file(dest);
+                Err(err)
+            }
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use lz4_flex::block;
+
+use stats::{WriteAmplification, WriteCounters};
+use {Gdbm, GdbmError, Iter, StoreOutcome};
+
+/// Start of every value written by `CompressedGdbm`; the byte after it
+/// says whether the rest is compressed
+const HEADER_MAGIC: &[u8] = b"\0lz4";
+
+/// Header byte for a value stored as is, used for values that start with
+/// `HEADER_MAGIC` but are not worth compressing
+const STORED_RAW: u8 = 0;
+
+/// Header byte for an LZ4 block, preceded by the u32 little endian
+/// length of the uncompressed value
+const STORED_LZ4: u8 = 1;
+
+/// Values shorter than this are stored uncompressed by default
+pub const DEFAULT_MIN_COMPRESS_SIZE: usize = 64;
+
+fn corrupt() -> GdbmError {
+    GdbmError::new("corrupt compressed value")
+}
+
+/// Decode an LZ4 block that must expand to exactly `len` bytes
+fn lz4_decompress(input: &[u8], len: usize) -> Result<Vec<u8>, GdbmError> {
+    // A byte of input expands to at most 255 bytes of output, so a bogus
+    // length cannot make this allocate much more than the value is worth
+    if len > input.len().saturating_mul(255) {
+        return Err(corrupt());
+    }
+    match block::decompress(input, len) {
+        Ok(out) if out.len() == len => Ok(out),
+        _ => Err(corrupt()),
+    }
+}
+
+/// The stored form of `value`
+fn pack(value: &[u8], min_size: usize) -> Vec<u8> {
+    if value.len() >= min_size && value.len() <= u32::MAX as usize {
+        let compressed = block::compress(value);
+        if compressed.len() + HEADER_MAGIC.len() + 5 < value.len() {
+            let mut stored = Vec::with_capacity(compressed.len() + HEADER_MAGIC.len() + 5);
+            stored.extend_from_slice(HEADER_MAGIC);
+            stored.push(STORED_LZ4);
+            stored.extend_from_slice(&(value.len() as u32).to_le_bytes());
+            stored.extend_from_slice(&compressed);
+            return stored;
+        }
+    }
+    if value.starts_with(HEADER_MAGIC) {
+        let mut stored = Vec::with_capacity(value.len() + HEADER_MAGIC.len() + 1);
+        stored.extend_from_slice(HEADER_MAGIC);
+        stored.push(STORED_RAW);
+        stored.extend_from_slice(value);
+        stored
+    } else {
+        value.to_vec()
+    }
+}
+
+/// The value a stored record holds
+fn unpack(stored: Vec<u8>) -> Result<Vec<u8>, GdbmError> {
+    if !stored.starts_with(HEADER_MAGIC) {
+        return Ok(stored);
+    }
+    let body = &stored[HEADER_MAGIC.len()..];
+    match body.first() {
+        Some(&STORED_RAW) => Ok(body[1..].to_vec()),
+        Some(&STORED_LZ4) if body.len() >= 5 => {
+            let mut len = [0; 4];
+            len.copy_from_slice(&body[1..5]);
+            lz4_decompress(&body[5..], u32::from_le_bytes(len) as usize)
+        }
+        _ => Err(corrupt()),
+    }
+}
+
+/// A database whose values are compressed with LZ4 on store and
+/// decompressed on fetch.
+///
+/// Compressed values start with a short header. Values without one, such
+/// as those written before compression was turned on, read back as they
+/// are, so an existing database can be wrapped without converting it.
+/// The exception is a plain value that happens to start with the bytes
+/// `\0lz4`, which this wrapper would misread. Values that are short, or
+/// that do not get smaller, are stored uncompressed.
+#[derive(Debug)]
+pub struct CompressedGdbm {
+    db: Gdbm,
+    min_size: usize,
+    writes: WriteCounters,
+}
+
+/// Iterator over the records of a `CompressedGdbm`, returned by
+/// `CompressedGdbm::iter`
+#[derive(Debug)]
+pub struct CompressedIter<'a> {
+    iter: Iter<'a>,
+}
+
+impl CompressedGdbm {
+    /// Wrap `db`, compressing values of `DEFAULT_MIN_COMPRESS_SIZE` bytes
+    /// or more
+    pub fn new(db: Gdbm) -> CompressedGdbm {
+        CompressedGdbm {
+            db,
+            min_size: DEFAULT_MIN_COMPRESS_SIZE,
+            writes: WriteCounters::default(),
+        }
+    }
+
+    /// Only try to compress values of at least `min_size` bytes
+    pub fn set_min_size(&mut self, min_size: usize) {
+        self.min_size = min_size;
+    }
+
+    /// The underlying handle, for operations on the stored bytes
+    pub fn as_gdbm(&self) -> &Gdbm {
+        &self.db
+    }
+
+    /// Unwrap the underlying handle
+    pub fn into_inner(self) -> Gdbm {
+        self.db
+    }
+
+    /// The bytes compression has saved, or added, on the writes made since
+    /// the wrapper was created or `reset_write_amplification` was last
+    /// called
+    pub fn write_amplification(&self) -> WriteAmplification {
+        self.writes.snapshot()
+    }
+
+    /// Set the write amplification counts back to zero
+    pub fn reset_write_amplification(&self) {
+        self.writes.reset();
+    }
+
+    /// See `Gdbm::fetch_data`
+    pub fn fetch_data<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Vec<u8>>, GdbmError> {
+        self.db.fetch_data(key)?.map(unpack).transpose()
+    }
+
+    /// See `Gdbm::store_checked`
+    pub fn store_checked<K, V>(&self, key: K, content: V, replace: bool) -> Result<StoreOutcome, GdbmError>
+        where K: AsRef<[u8]>,
+              V: AsRef<[u8]>
+    {
+        let (key, content) = (key.as_ref(), content.as_ref());
+        let stored = pack(content, self.min_size);
+        let outcome = self.db.store_checked(key, &stored, replace)?;
+        if outcome != StoreOutcome::AlreadyExists {
+            self.writes.add(key.len() + content.len(), key.len() + stored.len());
+        }
+        Ok(outcome)
+    }
+
+    /// See `Gdbm::insert`
+    pub fn insert<K, V>(&self, key: K, value: V) -> Result<Option<Vec<u8>>, GdbmError>
+        where K: AsRef<[u8]>,
+              V: AsRef<[u8]>
+    {
+        let (key, value) = (key.as_ref(), value.as_ref());
+        let stored = pack(value, self.min_size);
+        let old = self.db.insert(key, &stored)?;
+        self.writes.add(key.len() + value.len(), key.len() + stored.len());
+        old.map(unpack).transpose()
+    }
+
+    /// See `Gdbm::remove`
+    pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Vec<u8>>, GdbmError> {
+        self.db.remove(key)?.map(unpack).transpose()
+    }
+
+    /// Iterate over the records, decompressing the values
+    pub fn iter(&self) -> CompressedIter<'_> {
+        CompressedIter { iter: self.db.iter() }
+    }
+}
+
+impl<'a> Iterator for CompressedIter<'a> {
+    type Item = Result<(Vec<u8>, Vec<u8>), GdbmError>;
+
+    fn next(&mut self) -> Option<Result<(Vec<u8>, Vec<u8>), GdbmError>> {
+        Some(self.iter.next()?.and_then(|(key, stored)| Ok((key, unpack(stored)?))))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
@@ -0,0 +1,58 @@
+use std::mem;
+
+use libc::{self, c_int};
+
+use ffi::{self, GdbmConvert};
+use {Gdbm, GdbmError};
+
+/// On-disk format of a database file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DbFormat {
+    /// The format every gdbm release can read
+    Standard,
+    /// The extended format of gdbm 1.21 and later, which counts syncs so
+    /// that crash tolerance can pick the most recent snapshot. Older
+    /// releases cannot open it.
+    Numsync,
+}
+
+/// Look `gdbm_convert` up at run time, so that linking against a gdbm
+/// older than 1.21 keeps working for everything else
+fn gdbm_convert() -> Result<GdbmConvert, GdbmError> {
+    let symbol = unsafe { libc::dlsym(libc::RTLD_DEFAULT, b"gdbm_convert\0".as_ptr() as *const _) };
+    if symbol.is_null() {
+        return Err(GdbmError::new("converting between formats needs gdbm 1.21 or later"));
+    }
+    Ok(unsafe { mem::transmute::<*mut libc::c_void, GdbmConvert>(symbol) })
+}
+
+impl Gdbm {
+    /// The format of the database file. Always `Standard` before gdbm
+    /// 1.21, which only has the one.
+    pub fn format(&self) -> Result<DbFormat, GdbmError> {
+        if gdbm_convert().is_err() {
+            return Ok(DbFormat::Standard);
+        }
+        let format: c_int = self.getopt(ffi::GDBM_GETDBFORMAT, 0)?;
+        Ok(if format & ffi::GDBM_NUMSYNC != 0 {
+            DbFormat::Numsync
+        } else {
+            DbFormat::Standard
+        })
+    }
+
+    /// Rewrite the database file in `format`, in place. The handle must be
+    /// open for writing. Converting to the format the file is already in
+    /// does nothing.
+    pub fn convert(&self, format: DbFormat) -> Result<(), GdbmError> {
+        let convert = gdbm_convert()?;
+        let flag = match format {
+            DbFormat::Standard => 0,
+            DbFormat::Numsync => ffi::GDBM_NUMSYNC,
+        };
+        if unsafe { convert(self.handle()?, flag) } != 0 {
+            return Err(self.last_error());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,262 @@
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+
+use {ExportMap, Gdbm, GdbmError, ImportFlag};
+
+/// When `Gdbm::export_csv` puts a field in double quotes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quoting {
+    /// Only fields holding the delimiter, a quote or a line break
+    Necessary,
+    /// Every field
+    Always,
+    /// No field. Exporting a field that holds the delimiter or a line break
+    /// fails, and importing treats quotes as ordinary characters, as TSV
+    /// files expect.
+    Never,
+}
+
+/// How `Gdbm::export_csv` writes and `Gdbm::import_csv` reads records,
+/// one per line, the key in the first column and the value in the second.
+#[derive(Debug, Clone)]
+pub struct CsvOptions {
+    delimiter: u8,
+    quoting: Quoting,
+    base64_values: bool,
+    header: bool,
+    flag: ImportFlag,
+}
+
+impl Default for CsvOptions {
+    fn default() -> CsvOptions {
+        CsvOptions {
+            delimiter: b',',
+            quoting: Quoting::Necessary,
+            base64_values: false,
+            header: false,
+            flag: ImportFlag::Insert,
+        }
+    }
+}
+
+impl CsvOptions {
+    /// Comma separated, quoted where necessary, no header line, values as
+    /// they are, and importing fails on keys that already exist.
+    pub fn new() -> CsvOptions {
+        CsvOptions::default()
+    }
+
+    /// Tab separated without quoting, as TSV files are
+    pub fn tsv() -> CsvOptions {
+        CsvOptions {
+            delimiter: b'\t',
+            quoting: Quoting::Never,
+            ..CsvOptions::default()
+        }
+    }
+
+    /// Separate the columns with `delimiter`
+    pub fn delimiter(&mut self, delimiter: u8) -> &mut CsvOptions {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// When to quote fields
+    pub fn quoting(&mut self, quoting: Quoting) -> &mut CsvOptions {
+        self.quoting = quoting;
+        self
+    }
+
+    /// Write values as base64 and decode them on import, for binary values
+    /// that other tools would mangle
+    pub fn base64_values(&mut self, base64_values: bool) -> &mut CsvOptions {
+        self.base64_values = base64_values;
+        self
+    }
+
+    /// Write a `key,value` header line on export, and skip the first line
+    /// on import
+    pub fn header(&mut self, header: bool) -> &mut CsvOptions {
+        self.header = header;
+        self
+    }
+
+    /// What importing does with keys that already exist
+    pub fn import_flag(&mut self, flag: ImportFlag) -> &mut CsvOptions {
+        self.flag = flag;
+        self
+    }
+
+    fn write_field(&self, out: &mut Vec<u8>, field: &[u8]) -> Result<(), GdbmError> {
+        let delimiter = self.delimiter;
+        let breaks_line = |c: &u8| *c == delimiter || *c == b'\n' || *c == b'\r';
+        let quote = match self.quoting {
+            Quoting::Always => true,
+            Quoting::Necessary => field.iter().any(|c| breaks_line(c) || *c == b'"'),
+            Quoting::Never if field.iter().any(breaks_line) => {
+                return Err(GdbmError::new("field holds the delimiter or a line break and quoting is off"));
+            }
+            Quoting::Never => false,
+        };
+        if quote {
+            out.push(b'"');
+            for &c in field {
+                if c == b'"' {
+                    out.push(b'"');
+                }
+                out.push(c);
+            }
+            out.push(b'"');
+        } else {
+            out.extend_from_slice(field);
+        }
+        Ok(())
+    }
+
+    fn write_row(&self, out: &mut Vec<u8>, key: &[u8], value: &[u8]) -> Result<(), GdbmError> {
+        out.clear();
+        self.write_field(out, key)?;
+        out.push(self.delimiter);
+        self.write_field(out, value)?;
+        out.push(b'\n');
+        Ok(())
+    }
+
+    /// Split a line, without its line break, into fields. None if it ends
+    /// inside a quoted field, which then goes on on the next line.
+    fn parse_row(&self, row: &[u8]) -> Result<Option<Vec<Vec<u8>>>, &'static str> {
+        let mut fields = Vec::new();
+        let mut pos = 0;
+        loop {
+            let mut field = Vec::new();
+            if self.quoting != Quoting::Never && row.get(pos) == Some(&b'"') {
+                pos += 1;
+                loop {
+                    match row.get(pos) {
+                        None => return Ok(None),
+                        Some(&b'"') if row.get(pos + 1) == Some(&b'"') => {
+                            field.push(b'"');
+                            pos += 2;
+                        }
+                        Some(&b'"') => {
+                            pos += 1;
+                            break;
+                        }
+                        Some(&c) => {
+                            field.push(c);
+                            pos += 1;
+                        }
+                    }
+                }
+                fields.push(field);
+                match row.get(pos) {
+                    None => return Ok(Some(fields)),
+                    Some(&c) if c == self.delimiter => pos += 1,
+                    Some(_) => return Err("unexpected character after a quoted field"),
+                }
+            } else {
+                let end = row[pos..].iter().position(|&c| c == self.delimiter).map_or(row.len(), |i| pos + i);
+                field.extend_from_slice(&row[pos..end]);
+                fields.push(field);
+                if end == row.len() {
+                    return Ok(Some(fields));
+                }
+                pos = end + 1;
+            }
+        }
+    }
+}
+
+fn trim_line_break(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+impl Gdbm {
+    /// Write every record to `writer` as a CSV line. Returns the number of
+    /// records written.
+    pub fn export_csv<W: Write>(&self, writer: &mut W, options: &CsvOptions) -> Result<u64, GdbmError> {
+        self.export_csv_mapped(writer, options, &mut ExportMap::new())
+    }
+
+    /// `export_csv`, passing every record through `map` before it is
+    /// written. Base64 encoding applies to the mapped value.
+    pub fn export_csv_mapped<W: Write>(&self,
+                                       writer: &mut W,
+                                       options: &CsvOptions,
+                                       map: &mut ExportMap)
+                                       -> Result<u64, GdbmError> {
+        let mut out = BufWriter::new(writer);
+        let mut row = Vec::new();
+        if options.header {
+            options.write_row(&mut row, b"key", b"value")?;
+            out.write_all(&row)?;
+        }
+        let mut records = 0;
+        for record in self.iter() {
+            let (key, value) = record?;
+            let (key, value) = map.apply(key, value);
+            if options.base64_values {
+                options.write_row(&mut row, &key, BASE64.encode(&value).as_bytes())?;
+            } else {
+                options.write_row(&mut row, &key, &value)?;
+            }
+            out.write_all(&row)?;
+            records += 1;
+        }
+        out.flush()?;
+        Ok(records)
+    }
+
+    /// Store the records of the CSV lines read from `reader`. Every line
+    /// must have exactly two fields; empty lines are skipped. A malformed
+    /// line is reported as `GdbmError::ImportError` with its line number.
+    ///
+    /// Returns the number of records stored. The records before a failed
+    /// line stay in the database.
+    pub fn import_csv<R: Read>(&self, reader: &mut R, options: &CsvOptions) -> Result<u64, GdbmError> {
+        let mut input = BufReader::new(reader);
+        let mut buffer = Vec::new();
+        let mut line = 0;
+        let mut records = 0;
+        let flag = options.flag.to_store();
+        self.with_deferred_sync(|| loop {
+            buffer.clear();
+            if input.read_until(b'\n', &mut buffer)? == 0 {
+                return Ok(records);
+            }
+            line += 1;
+            let start = line;
+            let error = |message: &str| GdbmError::ImportError {
+                line: start,
+                message: message.to_string(),
+            };
+            let fields = loop {
+                if let Some(fields) = options.parse_row(trim_line_break(&buffer)).map_err(error)? {
+                    break fields;
+                }
+                if input.read_until(b'\n', &mut buffer)? == 0 {
+                    return Err(error("quoted field is not closed"));
+                }
+                line += 1;
+            };
+            if (start == 1 && options.header) || fields == [Vec::<u8>::new()] {
+                continue;
+            }
+            if fields.len() != 2 {
+                return Err(error(&format!("expected 2 fields, found {}", fields.len())));
+            }
+            let value = if options.base64_values {
+                BASE64.decode(&fields[1]).map_err(|_| error("value is not valid base64"))?
+            } else {
+                fields[1].clone()
+            };
+            if !self.store_bytes(&fields[0], &value, flag)? {
+                return Err(error("key already exists"));
+            }
+            records += 1;
+        })
+    }
+}
@@ -0,0 +1,177 @@
+use std::fmt;
+
+use {Gdbm, GdbmError};
+
+/// A position in gdbm's traversal order: the last key seen.
+///
+/// A cursor can be turned into a token, kept by a client between
+/// requests, and turned back into a cursor to continue iterating with
+/// `Gdbm::iter_from`. It stays valid as long as its key has a record.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Cursor {
+    key: Vec<u8>,
+}
+
+impl Cursor {
+    /// A cursor positioned at `key`
+    pub fn new<K: Into<Vec<u8>>>(key: K) -> Cursor {
+        Cursor { key: key.into() }
+    }
+
+    /// The key the cursor is positioned at
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// Encode the cursor as lowercase hex, safe to put in a URL.
+    pub fn to_token(&self) -> String {
+        self.to_string()
+    }
+
+    /// Decode a token made by `to_token`.
+    pub fn from_token(token: &str) -> Result<Cursor, GdbmError> {
+        let token = token.as_bytes();
+        if !token.len().is_multiple_of(2) {
+            return Err(GdbmError::new("cursor token has an odd length"));
+        }
+        let mut key = Vec::with_capacity(token.len() / 2);
+        for pair in token.chunks(2) {
+            match (hex_value(pair[0]), hex_value(pair[1])) {
+                (Some(high), Some(low)) => key.push(high << 4 | low),
+                _ => return Err(GdbmError::new("cursor token is not hex")),
+            }
+        }
+        Ok(Cursor { key })
+    }
+}
+
+fn hex_value(digit: u8) -> Option<u8> {
+    match digit {
+        b'0'..=b'9' => Some(digit - b'0'),
+        b'a'..=b'f' => Some(digit - b'a' + 10),
+        b'A'..=b'F' => Some(digit - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Displays as the token
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in &self.key {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// One page of records, returned by `Gdbm::page`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page {
+    /// The `(key, value)` records on this page
+    pub entries: Vec<(Vec<u8>, Vec<u8>)>,
+    /// Where the next page starts, None if this is the last page
+    pub next: Option<Cursor>,
+}
+
+/// Iterator over the records after a cursor, in gdbm's traversal order,
+/// returned by `Gdbm::iter_from`.
+///
+/// The caveats of `Iter` apply; the number of remaining records is not
+/// known up front.
+#[derive(Debug)]
+pub struct IterFrom<'a> {
+    db: &'a Gdbm,
+    last: Option<Vec<u8>>,
+}
+
+impl Gdbm {
+    /// Continue iterating over the records after `cursor`.
+    ///
+    /// gdbm finds the next key from the cursor's key, so this fails if
+    /// that key no longer has a record; the caller has to start over.
+    pub fn iter_from(&self, cursor: &Cursor) -> Result<IterFrom<'_>, GdbmError> {
+        if !self.contains(&cursor.key)? {
+            return Err(GdbmError::new("cursor key no longer exists"));
+        }
+        Ok(IterFrom {
+            db: self,
+            last: Some(cursor.key.clone()),
+        })
+    }
+}
+
+impl Gdbm {
+    /// Read up to `limit` records following `after`, or from the start of
+    /// the traversal if it is None. Pass the key of `next` as `after` to
+    /// fetch the following page.
+    ///
+    /// Fails if `limit` is 0, or if `after` no longer has a record (see
+    /// `iter_from`).
+    pub fn page(&self, after: Option<&[u8]>, limit: usize) -> Result<Page, GdbmError> {
+        if limit == 0 {
+            return Err(GdbmError::new("page limit must be at least 1"));
+        }
+        let mut entries = Vec::with_capacity(limit);
+        let next = match after {
+            Some(key) => {
+                let mut iter = self.iter_from(&Cursor::new(key))?;
+                for record in iter.by_ref().take(limit) {
+                    entries.push(record?);
+                }
+                iter.cursor()
+            }
+            None => {
+                let mut iter = self.iter();
+                for record in iter.by_ref().take(limit) {
+                    entries.push(record?);
+                }
+                iter.cursor()
+            }
+        };
+        // A full page may still be the last; only hand out a cursor if
+        // gdbm has another key after it.
+        let next = match next {
+            Some(ref cursor) if entries.len() == limit && self.next_key_bytes(cursor.key())?.is_some() => next,
+            _ => None,
+        };
+        Ok(Page { entries, next })
+    }
+}
+
+impl<'a> IterFrom<'a> {
+    /// A cursor at the record most recently returned, or at the starting
+    /// cursor if there was none yet. None once iteration has ended.
+    pub fn cursor(&self) -> Option<Cursor> {
+        self.last.as_ref().map(|key| Cursor::new(key.clone()))
+    }
+}
+
+impl<'a> Iterator for IterFrom<'a> {
+    type Item = Result<(Vec<u8>, Vec<u8>), GdbmError>;
+
+    fn next(&mut self) -> Option<Result<(Vec<u8>, Vec<u8>), GdbmError>> {
+        loop {
+            let next = self.db.next_key_bytes(self.last.as_ref()?);
+            let key = match next {
+                Ok(Some(key)) => key,
+                Ok(None) => {
+                    self.last = None;
+                    return None;
+                }
+                Err(e) => {
+                    self.last = None;
+                    return Some(Err(e));
+                }
+            };
+            self.last = Some(key.clone());
+            match self.db.fetch_bytes(&key) {
+                Ok(Some(value)) => return Some(Ok((key, value))),
+                Ok(None) => continue,
+                Err(e) => {
+                    self.last = None;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
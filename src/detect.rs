@@ -0,0 +1,91 @@
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::Read;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use libc::off_t;
+
+use {DbFormat, GdbmError};
+
+// gdbm magic numbers, as found in the first four bytes in the byte order
+// of the host that wrote the file
+const GDBM_OMAGIC: u32 = 0x1357_9ace;
+const GDBM_MAGIC32: u32 = 0x1357_9acd;
+const GDBM_MAGIC64: u32 = 0x1357_9acf;
+const GDBM_NUMSYNC_MAGIC32: u32 = 0x1357_9ad0;
+const GDBM_NUMSYNC_MAGIC64: u32 = 0x1357_9ad1;
+
+// Berkeley DB magic numbers: at offset 12 in the metadata page from 2.0
+// on, at offset 0 in 1.85
+const BDB_MAGICS: [u32; 4] = [0x0005_3162, 0x0006_1561, 0x0004_2253, 0x0007_4582];
+
+/// What kind of database a file holds, as reported by `detect_format`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileFormat {
+    /// A gdbm database this host can open
+    Gdbm(DbFormat),
+    /// A gdbm database written on a host with a different byte order or
+    /// `off_t` size, which gdbm here cannot open
+    ForeignGdbm,
+    /// An ndbm database, a `.dir` and `.pag` pair of files
+    Ndbm,
+    /// A Berkeley DB database of any access method
+    BerkeleyDb,
+    /// Anything else, including an empty file
+    Unknown,
+}
+
+/// `path` with `extension` appended, keeping any extension it has
+fn with_suffix(path: &Path, extension: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+/// The format of a gdbm file starting with `magic`, read in the host's
+/// byte order
+fn gdbm_format(magic: u32) -> Option<FileFormat> {
+    native_gdbm_format(magic).or_else(|| native_gdbm_format(magic.swap_bytes()).map(|_| FileFormat::ForeignGdbm))
+}
+
+fn native_gdbm_format(magic: u32) -> Option<FileFormat> {
+    let native_offsets = mem::size_of::<off_t>() == 8;
+    match magic {
+        GDBM_OMAGIC => Some(FileFormat::Gdbm(DbFormat::Standard)),
+        GDBM_MAGIC64 if native_offsets => Some(FileFormat::Gdbm(DbFormat::Standard)),
+        GDBM_MAGIC32 if !native_offsets => Some(FileFormat::Gdbm(DbFormat::Standard)),
+        GDBM_NUMSYNC_MAGIC64 if native_offsets => Some(FileFormat::Gdbm(DbFormat::Numsync)),
+        GDBM_NUMSYNC_MAGIC32 if !native_offsets => Some(FileFormat::Gdbm(DbFormat::Numsync)),
+        GDBM_MAGIC32 | GDBM_MAGIC64 | GDBM_NUMSYNC_MAGIC32 | GDBM_NUMSYNC_MAGIC64 => Some(FileFormat::ForeignGdbm),
+        _ => None,
+    }
+}
+
+fn is_bdb_magic(bytes: &[u8]) -> bool {
+    let magic = [bytes[0], bytes[1], bytes[2], bytes[3]];
+    BDB_MAGICS.contains(&u32::from_le_bytes(magic)) || BDB_MAGICS.contains(&u32::from_be_bytes(magic))
+}
+
+/// Find out what kind of database the file at `path` holds, from its
+/// magic numbers, without opening it with any database library: the
+/// `whichdb` of this crate. For an ndbm database pass the name without
+/// `.dir` or `.pag`. Fails if `path` does not exist.
+pub fn detect_format(path: &Path) -> Result<FileFormat, GdbmError> {
+    if with_suffix(path, ".dir").is_file() && with_suffix(path, ".pag").is_file() {
+        return Ok(FileFormat::Ndbm);
+    }
+    let mut header = Vec::with_capacity(16);
+    File::open(path)?.take(16).read_to_end(&mut header)?;
+    if header.len() < 4 {
+        return Ok(FileFormat::Unknown);
+    }
+    let magic = u32::from_ne_bytes([header[0], header[1], header[2], header[3]]);
+    if let Some(format) = gdbm_format(magic) {
+        return Ok(format);
+    }
+    if is_bdb_magic(&header[..4]) || (header.len() >= 16 && is_bdb_magic(&header[12..16])) {
+        return Ok(FileFormat::BerkeleyDb);
+    }
+    Ok(FileFormat::Unknown)
+}
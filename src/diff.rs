@@ -0,0 +1,105 @@
+use {Gdbm, GdbmError, Iter, Keys};
+
+/// A record that differs between two databases, yielded by `Diff`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    /// The key is only in the first database
+    OnlyInA { key: Vec<u8>, value: Vec<u8> },
+    /// The key is only in the second database
+    OnlyInB { key: Vec<u8>, value: Vec<u8> },
+    /// The key is in both, with different values
+    Changed { key: Vec<u8>, a: Vec<u8>, b: Vec<u8> },
+}
+
+impl Difference {
+    /// The key the difference is about
+    pub fn key(&self) -> &[u8] {
+        match *self {
+            Difference::OnlyInA { ref key, .. } |
+            Difference::OnlyInB { ref key, .. } |
+            Difference::Changed { ref key, .. } => key,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Pass<'a> {
+    /// Walking the records of A, looking each up in B
+    A(Iter<'a>),
+    /// Walking the keys of B, looking each up in A
+    B(Keys<'a>),
+    Done,
+}
+
+/// Iterator over the differences between two databases, returned by
+/// `diff`. Walks the first database, then the keys of the second, looking
+/// every key up in the other one, so memory use does not grow with the
+/// size of the databases. Iteration stops after the first error.
+#[derive(Debug)]
+pub struct Diff<'a> {
+    a: &'a Gdbm,
+    b: &'a Gdbm,
+    pass: Pass<'a>,
+}
+
+/// The records that differ between `a` and `b`, to check that a copy or
+/// a migration produced what it should have. Differences come in
+/// traversal order: all keys of `a` first, then those only in `b`.
+pub fn diff<'a>(a: &'a Gdbm, b: &'a Gdbm) -> Diff<'a> {
+    Diff {
+        a,
+        b,
+        pass: Pass::A(a.iter()),
+    }
+}
+
+impl<'a> Diff<'a> {
+    fn advance(&mut self) -> Result<Option<Difference>, GdbmError> {
+        loop {
+            match self.pass {
+                Pass::A(ref mut records) => {
+                    let (key, value) = match records.next() {
+                        Some(record) => record?,
+                        None => {
+                            self.pass = Pass::B(self.b.keys());
+                            continue;
+                        }
+                    };
+                    match self.b.fetch_bytes(&key)? {
+                        None => return Ok(Some(Difference::OnlyInA { key, value })),
+                        Some(ref other) if *other == value => {}
+                        Some(other) => return Ok(Some(Difference::Changed { key, a: value, b: other })),
+                    }
+                }
+                Pass::B(ref mut keys) => {
+                    let key = match keys.next() {
+                        Some(key) => key?,
+                        None => {
+                            self.pass = Pass::Done;
+                            continue;
+                        }
+                    };
+                    if !self.a.contains(&key)? {
+                        // Skipped if it vanished since its key was read
+                        if let Some(value) = self.b.fetch_bytes(&key)? {
+                            return Ok(Some(Difference::OnlyInB { key, value }));
+                        }
+                    }
+                }
+                Pass::Done => return Ok(None),
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Diff<'a> {
+    type Item = Result<Difference, GdbmError>;
+
+    fn next(&mut self) -> Option<Result<Difference, GdbmError>> {
+        let next = self.advance().transpose();
+        if let Some(Err(_)) = next {
+            self.pass = Pass::Done;
+        }
+        next
+    }
+}
@@ -0,0 +1,474 @@
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::path::Path;
+use std::thread;
+
+use gdbm_sys::{gdbm_version, GDBM_FILE};
+use libc::{self, c_char, c_int, c_ulong, FILE};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+
+use cancel::{self, CancellationToken};
+use ffi::{self, gdbm_dump, gdbm_dump_to_file, gdbm_load, gdbm_load_from_file};
+use {get_error, Gdbm, GdbmError, Open, Progress, Store};
+
+/// File format written by `Gdbm::export_to_path`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// Portable text format, as produced by `gdbm_dump`
+    Ascii,
+    /// Binary flat file format, as produced by `gdbm_export`
+    Binary,
+}
+
+impl DumpFormat {
+    fn to_c(self) -> c_int {
+        match self {
+            DumpFormat::Ascii => ffi::GDBM_DUMP_FMT_ASCII,
+            DumpFormat::Binary => ffi::GDBM_DUMP_FMT_BINARY,
+        }
+    }
+}
+
+/// How `Gdbm::import_from_path` treats keys that already exist
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFlag {
+    /// Keep the existing record and fail the import
+    Insert,
+    /// Overwrite the existing record with the one from the dump
+    Replace,
+}
+
+impl ImportFlag {
+    pub(crate) fn to_store(self) -> Store {
+        match self {
+            ImportFlag::Insert => Store::INSERT,
+            ImportFlag::Replace => Store::REPLACE,
+        }
+    }
+
+    fn to_c(self) -> c_int {
+        self.to_store().bits as c_int
+    }
+}
+
+type MapFn<'a> = dyn FnMut(Vec<u8>) -> Vec<u8> + 'a;
+
+/// Rewrites keys and values on their way out of `export_to_writer_mapped`,
+/// `export_csv_mapped` and `export_jsonl_mapped`, so that a conversion
+/// such as stripping NULs or changing a key prefix happens during the
+/// export rather than in a second pass over the dump.
+///
+/// ```no_run
+/// # use gdbm::{DumpFormat, ExportMap, Gdbm, Open};
+/// # use std::path::Path;
+/// # let db = Gdbm::new(Path::new("example.db"), 0, Open::READER, 0o600).unwrap();
+/// let mut map = ExportMap::new();
+/// map.map_key(|key| [&b"v2/"[..], key.strip_prefix(b"v1/").unwrap_or(&key)].concat())
+///    .map_value(|mut value| {
+///        value.retain(|&c| c != 0);
+///        value
+///    });
+/// db.export_to_writer_mapped(&mut std::io::stdout(), DumpFormat::Ascii, &mut map).unwrap();
+/// ```
+#[derive(Default)]
+pub struct ExportMap<'a> {
+    key: Option<Box<MapFn<'a>>>,
+    value: Option<Box<MapFn<'a>>>,
+}
+
+impl<'a> fmt::Debug for ExportMap<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ExportMap")
+            .field("key", &self.key.is_some())
+            .field("value", &self.value.is_some())
+            .finish()
+    }
+}
+
+impl<'a> ExportMap<'a> {
+    /// Export the records as they are
+    pub fn new() -> ExportMap<'a> {
+        ExportMap::default()
+    }
+
+    /// Export each key as `map_key` returns it. Keys that map to the same
+    /// bytes end up as duplicates in the export.
+    pub fn map_key<F>(&mut self, map_key: F) -> &mut ExportMap<'a>
+        where F: FnMut(Vec<u8>) -> Vec<u8> + 'a
+    {
+        self.key = Some(Box::new(map_key));
+        self
+    }
+
+    /// Export each value as `map_value` returns it
+    pub fn map_value<F>(&mut self, map_value: F) -> &mut ExportMap<'a>
+        where F: FnMut(Vec<u8>) -> Vec<u8> + 'a
+    {
+        self.value = Some(Box::new(map_value));
+        self
+    }
+
+    pub(crate) fn apply(&mut self, key: Vec<u8>, value: Vec<u8>) -> (Vec<u8>, Vec<u8>) {
+        let key = match self.key {
+            Some(ref mut map_key) => map_key(key),
+            None => key,
+        };
+        let value = match self.value {
+            Some(ref mut map_value) => map_value(value),
+            None => value,
+        };
+        (key, value)
+    }
+}
+
+/// Write a record in gdbm's ASCII dump format: a `#:len=` line, then the
+/// bytes in base64, 76 characters to a line and no line at all when
+/// empty
+fn write_ascii_field<W: Write>(out: &mut W, bytes: &[u8]) -> io::Result<()> {
+    writeln!(out, "#:len={}", bytes.len())?;
+    for line in BASE64.encode(bytes).as_bytes().chunks(76) {
+        out.write_all(line)?;
+        out.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Write a record in gdbm's binary flat file format. gdbm stores the
+/// length as `htonl(len)` widened to a C `unsigned long` in native byte
+/// order, and reads it back the same way.
+fn write_binary_field<W: Write>(out: &mut W, bytes: &[u8]) -> Result<(), GdbmError> {
+    if bytes.len() > c_int::MAX as usize {
+        return Err(GdbmError::new("record is too large for a gdbm dump"));
+    }
+    out.write_all(&c_ulong::from((bytes.len() as u32).to_be()).to_ne_bytes())?;
+    out.write_all(bytes)?;
+    Ok(())
+}
+
+const LOAD_META_FLAGS: c_int = ffi::GDBM_META_MASK_MODE | ffi::GDBM_META_MASK_OWNER;
+
+/// Build the error for a failed gdbm_load. Must be called on the thread
+/// that made the gdbm call, gdbm_errno is thread local.
+fn import_error(line: c_ulong) -> GdbmError {
+    // c_ulong is only 32 bits wide on some targets
+    #[allow(clippy::unnecessary_cast)]
+    let line = line as u64;
+    GdbmError::ImportError {
+        line,
+        message: get_error(),
+    }
+}
+
+/// The database handle and stdio stream, moved to the helper thread of
+/// a streaming dump or load. The calling thread does not touch either
+/// while the helper runs.
+struct SendHandle(GDBM_FILE);
+struct SendStream(*mut FILE);
+
+unsafe impl Send for SendHandle {}
+unsafe impl Send for SendStream {}
+
+/// Create a pipe, returning the read and write ends.
+fn pipe() -> io::Result<(RawFd, RawFd)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok((fds[0], fds[1]))
+}
+
+/// Wrap a pipe end in a stdio stream for gdbm. On failure the
+/// descriptor is closed.
+fn fdopen(fd: RawFd, mode: &'static [u8]) -> io::Result<*mut FILE> {
+    let stream = unsafe { libc::fdopen(fd, mode.as_ptr() as *const c_char) };
+    if stream.is_null() {
+        let err = io::Error::last_os_error();
+        unsafe { libc::close(fd) };
+        return Err(err);
+    }
+    Ok(stream)
+}
+
+impl Gdbm {
+    /// Dump the database to a flat file at `path` that `gdbm_load` and
+    /// gdbmtool can read back. An existing file at `path` is overwritten,
+    /// otherwise it is created with permissions `mode`. gdbm dumps every
+    /// record, the crate's bookkeeping records included, so a database
+    /// loaded from the dump keeps its schema version and seed marker.
+    pub fn export_to_path(&self, path: &Path, format: DumpFormat, mode: i32) -> Result<(), GdbmError> {
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        let result = unsafe {
+            gdbm_dump(self.handle()?, path.as_ptr(), format.to_c(), Open::NEWDB.bits as c_int, mode)
+        };
+        if result != 0 {
+            return Err(self.last_error());
+        }
+        Ok(())
+    }
+
+    /// Load the records of a dump file produced by `export_to_path`,
+    /// `gdbm_dump` or `gdbm_export` into this database. Both the ASCII and
+    /// the binary format are accepted.
+    ///
+    /// Returns the number of records that were added to the database.
+    /// Records that replaced an existing key are not included. A parse
+    /// error is reported as `GdbmError::ImportError` with the line at
+    /// which it occurred.
+    pub fn import_from_path(&self, path: &Path, flag: ImportFlag) -> Result<u64, GdbmError> {
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        let before = self.count()?;
+        let mut line = 0;
+        // gdbm_load only opens a new database if handed a NULL handle,
+        // ours is left as it is.
+        let mut handle = self.handle()?;
+        let result = unsafe {
+            gdbm_load(&mut handle, path.as_ptr(), flag.to_c(), LOAD_META_FLAGS, &mut line)
+        };
+        if result != 0 {
+            return Err(import_error(line));
+        }
+        Ok(self.count()?.saturating_sub(before))
+    }
+
+    /// `export_to_path`, calling `progress` as
+    /// `export_to_writer_with_progress` does. The dump is streamed into
+    /// the file rather than written by gdbm directly, which gives the same
+    /// file.
+    pub fn export_to_path_with_progress<F>(&self, path: &Path, format: DumpFormat, mode: i32, progress: F) -> Result<(), GdbmError>
+        where F: FnMut(Progress)
+    {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(mode as u32)
+            .open(path)?;
+        self.export_to_writer_with_progress(&mut file, format, progress)
+    }
+
+    /// `import_from_path`, calling `progress` as
+    /// `import_from_reader_with_progress` does, with the bytes of the file
+    /// read so far.
+    pub fn import_from_path_with_progress<F>(&self, path: &Path, flag: ImportFlag, progress: F) -> Result<u64, GdbmError>
+        where F: FnMut(Progress)
+    {
+        let mut file = File::open(path)?;
+        self.import_from_reader_with_progress(&mut file, flag, progress)
+    }
+
+    /// Stream a dump of the database into `writer`, in the same format
+    /// `export_to_path` writes, without going through the filesystem.
+    ///
+    /// gdbm writes the dump into a pipe from a helper thread while the
+    /// calling thread copies it to `writer`.
+    pub fn export_to_writer<W: Write>(&self, writer: &mut W, format: DumpFormat) -> Result<(), GdbmError> {
+        self.export_to_writer_impl(writer, format, None, None)
+    }
+
+    /// `export_to_writer`, stopping with `GdbmError::Cancelled` once
+    /// `cancel` is set. `writer` is left with a partial dump.
+    pub fn export_to_writer_cancellable<W: Write>(&self,
+                                                  writer: &mut W,
+                                                  format: DumpFormat,
+                                                  cancel: &CancellationToken)
+                                                  -> Result<(), GdbmError> {
+        self.export_to_writer_impl(writer, format, Some(cancel), None)
+    }
+
+    /// `export_to_writer`, calling `progress` with the bytes written so
+    /// far as the dump is copied, and once more at the end with the
+    /// number of records. gdbm writes the dump without saying how many
+    /// records it has written, so until that last call `records` is 0 and
+    /// only `bytes` moves.
+    pub fn export_to_writer_with_progress<W, F>(&self, writer: &mut W, format: DumpFormat, mut progress: F) -> Result<(), GdbmError>
+        where W: Write,
+              F: FnMut(Progress)
+    {
+        let mut bytes = 0;
+        self.export_to_writer_impl(writer,
+                                   format,
+                                   None,
+                                   Some(&mut |copied| {
+                                       bytes = copied;
+                                       progress(Progress { records: 0, bytes: copied });
+                                   }))?;
+        progress(Progress {
+            records: self.count()?,
+            bytes,
+        });
+        Ok(())
+    }
+
+    /// `export_to_writer`, passing every record through `map` first.
+    ///
+    /// gdbm cannot rewrite records while it dumps them, so the dump is
+    /// written here instead, in the same formats: `import_from_reader` and
+    /// `gdbm_load` read it back. The ASCII header leaves out the file
+    /// name, owner and mode of the database, and like `iter` the dump
+    /// leaves out the bookkeeping records. Returns the number of records
+    /// written.
+    pub fn export_to_writer_mapped<W: Write>(&self,
+                                             writer: &mut W,
+                                             format: DumpFormat,
+                                             map: &mut ExportMap)
+                                             -> Result<u64, GdbmError> {
+        let version = unsafe { CStr::from_ptr(gdbm_version) }.to_string_lossy().into_owned();
+        let mut out = BufWriter::new(writer);
+        match format {
+            DumpFormat::Ascii => {
+                writeln!(out, "# GDBM dump file created by {}", version)?;
+                out.write_all(b"#:version=1.1\n#:format=standard\n# End of header\n")?;
+            }
+            DumpFormat::Binary => {
+                write!(out,
+                       "!\r\n! GDBM FLAT FILE DUMP -- THIS IS NOT A TEXT FILE\r\n! {}\r\n!\r\n",
+                       version)?;
+            }
+        }
+        let mut records = 0;
+        for record in self.iter() {
+            let (key, value) = record?;
+            let (key, value) = map.apply(key, value);
+            match format {
+                DumpFormat::Ascii => {
+                    write_ascii_field(&mut out, &key)?;
+                    write_ascii_field(&mut out, &value)?;
+                }
+                DumpFormat::Binary => {
+                    write_binary_field(&mut out, &key)?;
+                    write_binary_field(&mut out, &value)?;
+                }
+            }
+            records += 1;
+        }
+        if format == DumpFormat::Ascii {
+            writeln!(out, "#:count={}\n# End of data", records)?;
+        }
+        out.flush()?;
+        Ok(records)
+    }
+
+    fn export_to_writer_impl<W: Write>(&self,
+                                       writer: &mut W,
+                                       format: DumpFormat,
+                                       cancel: Option<&CancellationToken>,
+                                       progress: Option<&mut dyn FnMut(u64)>)
+                                       -> Result<(), GdbmError> {
+        let (read_fd, write_fd) = pipe()?;
+        let mut reader = unsafe { File::from_raw_fd(read_fd) };
+        let stream = fdopen(write_fd, b"w\0")?;
+        let handle = SendHandle(self.handle()?);
+        let stream = SendStream(stream);
+        thread::scope(|scope| -> Result<(), GdbmError> {
+            let dumper = scope.spawn(move || {
+                let stream = stream.0;
+                let result = unsafe { gdbm_dump_to_file(handle.0, stream, format.to_c()) };
+                let error = if result != 0 { Some(get_error()) } else { None };
+                // Closing the write end is what ends the copy below
+                let closed = unsafe { libc::fclose(stream) };
+                match error {
+                    Some(error) => Err(GdbmError::new(error)),
+                    None if closed != 0 => Err(io::Error::last_os_error().into()),
+                    None => Ok(()),
+                }
+            });
+            let copied = cancel::copy(&mut reader, writer, cancel, progress);
+            // If the copy failed this unblocks the dumper with EPIPE
+            drop(reader);
+            let dumped = dumper.join().expect("dump thread panicked");
+            if let Err(GdbmError::Cancelled) = copied {
+                return copied.map(|_| ());
+            }
+            dumped?;
+            copied?;
+            Ok(())
+        })
+    }
+
+    /// Load a dump streamed from `reader`, in either of the formats
+    /// `import_from_path` accepts, without going through the filesystem.
+    ///
+    /// Returns the number of records added, as `import_from_path` does.
+    pub fn import_from_reader<R: Read>(&self, reader: &mut R, flag: ImportFlag) -> Result<u64, GdbmError> {
+        self.import_from_reader_impl(reader, flag, None, None)
+    }
+
+    /// `import_from_reader`, stopping with `GdbmError::Cancelled` once
+    /// `cancel` is set. The records loaded up to then stay in the
+    /// database.
+    pub fn import_from_reader_cancellable<R: Read>(&self,
+                                                   reader: &mut R,
+                                                   flag: ImportFlag,
+                                                   cancel: &CancellationToken)
+                                                   -> Result<u64, GdbmError> {
+        self.import_from_reader_impl(reader, flag, Some(cancel), None)
+    }
+
+    /// `import_from_reader`, calling `progress` with the bytes read so far
+    /// as the dump is copied, and once more at the end with the number of
+    /// records added. As with `export_to_writer_with_progress`, `records`
+    /// is 0 until that last call.
+    pub fn import_from_reader_with_progress<R, F>(&self, reader: &mut R, flag: ImportFlag, mut progress: F) -> Result<u64, GdbmError>
+        where R: Read,
+              F: FnMut(Progress)
+    {
+        let mut bytes = 0;
+        let records = self.import_from_reader_impl(reader,
+                                                   flag,
+                                                   None,
+                                                   Some(&mut |copied| {
+                                                       bytes = copied;
+                                                       progress(Progress { records: 0, bytes: copied });
+                                                   }))?;
+        progress(Progress { records, bytes });
+        Ok(records)
+    }
+
+    fn import_from_reader_impl<R: Read>(&self,
+                                        reader: &mut R,
+                                        flag: ImportFlag,
+                                        cancel: Option<&CancellationToken>,
+                                        progress: Option<&mut dyn FnMut(u64)>)
+                                        -> Result<u64, GdbmError> {
+        let before = self.count()?;
+        let (read_fd, write_fd) = pipe()?;
+        let mut writer = unsafe { File::from_raw_fd(write_fd) };
+        let stream = SendStream(fdopen(read_fd, b"r\0")?);
+        let handle = SendHandle(self.handle()?);
+        thread::scope(|scope| -> Result<(), GdbmError> {
+            let loader = scope.spawn(move || {
+                let mut handle = handle;
+                let stream = stream.0;
+                let mut line = 0;
+                let result = unsafe {
+                    gdbm_load_from_file(&mut handle.0, stream, flag.to_c(), LOAD_META_FLAGS, &mut line)
+                };
+                let error = if result != 0 { Some(import_error(line)) } else { None };
+                unsafe { libc::fclose(stream) };
+                match error {
+                    Some(error) => Err(error),
+                    None => Ok(()),
+                }
+            });
+            let copied = cancel::copy(reader, &mut writer, cancel, progress);
+            // Closing the write end signals the end of the dump
+            drop(writer);
+            let loaded = loader.join().expect("load thread panicked");
+            // The loader may have choked on the truncated dump
+            if let Err(GdbmError::Cancelled) = copied {
+                return copied.map(|_| ());
+            }
+            // A load error explains a failed copy (EPIPE), so it wins
+            loaded?;
+            copied?;
+            Ok(())
+        })?;
+        Ok(self.count()?.saturating_sub(before))
+    }
+}
@@ -0,0 +1,305 @@
+use std::io;
+
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use zeroize::{Zeroize, Zeroizing};
+
+use stats::{WriteAmplification, WriteCounters};
+use {Gdbm, GdbmError, Iter, StoreOutcome};
+
+/// Format byte at the start of every encrypted value
+const FORMAT_XCHACHA20_POLY1305: u8 = 1;
+const KEY_ID_LEN: usize = 4;
+const NONCE_LEN: usize = 24;
+const TAG_LEN: usize = 16;
+const HEADER_LEN: usize = 1 + KEY_ID_LEN + NONCE_LEN;
+
+/// Supplies the 256 bit keys an `EncryptedGdbm` encrypts with.
+///
+/// Every value records the id of the key it was encrypted with, so keys
+/// can be rotated: switch `current_key_id` to a new key and keep
+/// returning the old ones from `key` until every value has been
+/// rewritten.
+pub trait KeyProvider {
+    /// Id of the key new values are encrypted with
+    fn current_key_id(&self) -> u32;
+
+    /// The key with id `id`, wiped from memory once it has been used
+    fn key(&self, id: u32) -> Result<Zeroizing<[u8; 32]>, GdbmError>;
+}
+
+/// A single fixed key, with id 0, wiped from memory when dropped
+#[derive(Clone)]
+pub struct StaticKey {
+    key: [u8; 32],
+}
+
+impl StaticKey {
+    /// Encrypt everything with `key`
+    pub fn new(key: [u8; 32]) -> StaticKey {
+        StaticKey { key }
+    }
+}
+
+impl ::std::fmt::Debug for StaticKey {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        f.write_str("StaticKey")
+    }
+}
+
+impl Drop for StaticKey {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+impl KeyProvider for StaticKey {
+    fn current_key_id(&self) -> u32 {
+        0
+    }
+
+    fn key(&self, id: u32) -> Result<Zeroizing<[u8; 32]>, GdbmError> {
+        if id == 0 {
+            Ok(Zeroizing::new(self.key))
+        } else {
+            Err(GdbmError::new(format!("unknown encryption key id {}", id)))
+        }
+    }
+}
+
+fn le32(bytes: &[u8]) -> u32 {
+    let mut word = [0; 4];
+    word.copy_from_slice(&bytes[..4]);
+    u32::from_le_bytes(word)
+}
+
+fn seal(key: &[u8; 32], nonce: &[u8; NONCE_LEN], aad: &[u8], plaintext: &[u8], out: &mut Vec<u8>) -> Result<(), GdbmError> {
+    let cipher = XChaCha20Poly1305::new(key.into());
+    let sealed = cipher.encrypt(XNonce::from_slice(nonce), Payload { msg: plaintext, aad })
+        .map_err(|_| GdbmError::new("value is too large to encrypt"))?;
+    out.extend_from_slice(&sealed);
+    Ok(())
+}
+
+fn open(key: &[u8; 32], nonce: &[u8; NONCE_LEN], aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>, GdbmError> {
+    if sealed.len() < TAG_LEN {
+        return Err(GdbmError::new("encrypted value is truncated"));
+    }
+    let cipher = XChaCha20Poly1305::new(key.into());
+    cipher.decrypt(XNonce::from_slice(nonce), Payload { msg: sealed, aad })
+        .map_err(|_| GdbmError::new("encrypted value failed authentication (wrong key or tampered data)"))
+}
+
+/// A database whose values are encrypted with XChaCha20-Poly1305.
+///
+/// Each value is stored as a format byte, the id of the key used, a
+/// random 24 byte nonce, the ciphertext and a 16 byte tag. The record's
+/// key is authenticated along with the value, so a value copied to
+/// another key fails to decrypt, as does one that has been altered.
+///
+/// Keys are stored in the clear, as are the number of records and the
+/// length of each value. Encrypting keys is deliberately not supported:
+/// lookups need a key to encrypt the same way every time, and a
+/// deterministic scheme such as AES-SIV is more than this wrapper should
+/// carry. Store a keyed hash of a sensitive key instead, if lookups by
+/// exact key are all that is needed.
+#[derive(Debug)]
+pub struct EncryptedGdbm<P> {
+    db: Gdbm,
+    keys: P,
+    writes: WriteCounters,
+}
+
+/// Iterator over the records of an `EncryptedGdbm`, returned by
+/// `EncryptedGdbm::iter`
+#[derive(Debug)]
+pub struct EncryptedIter<'a, P: 'a> {
+    iter: Iter<'a>,
+    keys: &'a P,
+}
+
+fn decrypt<P: KeyProvider>(keys: &P, key: &[u8], stored: &[u8]) -> Result<Vec<u8>, GdbmError> {
+    if stored.len() < HEADER_LEN || stored[0] != FORMAT_XCHACHA20_POLY1305 {
+        return Err(GdbmError::new("value is not encrypted"));
+    }
+    let key_id = &stored[1..1 + KEY_ID_LEN];
+    let mut nonce = [0; NONCE_LEN];
+    nonce.copy_from_slice(&stored[1 + KEY_ID_LEN..HEADER_LEN]);
+    let mut aad = key_id.to_vec();
+    aad.extend_from_slice(key);
+    open(&*keys.key(le32(key_id))?, &nonce, &aad, &stored[HEADER_LEN..])
+}
+
+impl<P: KeyProvider> EncryptedGdbm<P> {
+    /// Wrap `db`, encrypting with keys from `keys`
+    pub fn new(db: Gdbm, keys: P) -> EncryptedGdbm<P> {
+        EncryptedGdbm {
+            db,
+            keys,
+            writes: WriteCounters::default(),
+        }
+    }
+
+    /// The underlying handle, for operations on the stored bytes
+    pub fn as_gdbm(&self) -> &Gdbm {
+        &self.db
+    }
+
+    /// Unwrap the underlying handle
+    pub fn into_inner(self) -> Gdbm {
+        self.db
+    }
+
+    fn encrypt(&self, key: &[u8], value: &[u8]) -> Result<Vec<u8>, GdbmError> {
+        let key_id = self.keys.current_key_id().to_le_bytes();
+        let mut nonce = [0; NONCE_LEN];
+        getrandom::getrandom(&mut nonce).map_err(io::Error::from)?;
+        let mut stored = Vec::with_capacity(HEADER_LEN + value.len() + TAG_LEN);
+        stored.push(FORMAT_XCHACHA20_POLY1305);
+        stored.extend_from_slice(&key_id);
+        stored.extend_from_slice(&nonce);
+        let mut aad = key_id.to_vec();
+        aad.extend_from_slice(key);
+        seal(&*self.keys.key(self.keys.current_key_id())?, &nonce, &aad, value, &mut stored)?;
+        Ok(stored)
+    }
+
+    /// The bytes the encryption header and tag have added to the writes
+    /// made since the wrapper was created or `reset_write_amplification`
+    /// was last called. `reencrypt` is not counted.
+    pub fn write_amplification(&self) -> WriteAmplification {
+        self.writes.snapshot()
+    }
+
+    /// Set the write amplification counts back to zero
+    pub fn reset_write_amplification(&self) {
+        self.writes.reset();
+    }
+
+    /// See `Gdbm::fetch_data`
+    pub fn fetch_data<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Vec<u8>>, GdbmError> {
+        let key = key.as_ref();
+        match self.db.fetch_data(key)? {
+            Some(stored) => Ok(Some(decrypt(&self.keys, key, &stored)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// See `Gdbm::store_checked`
+    pub fn store_checked<K, V>(&self, key: K, content: V, replace: bool) -> Result<StoreOutcome, GdbmError>
+        where K: AsRef<[u8]>,
+              V: AsRef<[u8]>
+    {
+        let (key, content) = (key.as_ref(), content.as_ref());
+        let stored = self.encrypt(key, content)?;
+        let outcome = self.db.store_checked(key, &stored, replace)?;
+        if outcome != StoreOutcome::AlreadyExists {
+            self.writes.add(key.len() + content.len(), key.len() + stored.len());
+        }
+        Ok(outcome)
+    }
+
+    /// See `Gdbm::insert`
+    pub fn insert<K, V>(&self, key: K, value: V) -> Result<Option<Vec<u8>>, GdbmError>
+        where K: AsRef<[u8]>,
+              V: AsRef<[u8]>
+    {
+        let (key, value) = (key.as_ref(), value.as_ref());
+        let stored = self.encrypt(key, value)?;
+        let old = self.db.insert(key, &stored)?;
+        self.writes.add(key.len() + value.len(), key.len() + stored.len());
+        match old {
+            Some(old) => Ok(Some(decrypt(&self.keys, key, &old)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// See `Gdbm::remove`
+    pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Vec<u8>>, GdbmError> {
+        let key = key.as_ref();
+        match self.db.remove(key)? {
+            Some(old) => Ok(Some(decrypt(&self.keys, key, &old)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Re-encrypt every value not already under the current key, for key
+    /// rotation. Returns the number of values rewritten.
+    pub fn reencrypt(&self) -> Result<u64, GdbmError> {
+        let current = self.keys.current_key_id();
+        let mut rewritten = 0;
+        self.db.with_deferred_sync(|| {
+            for key in self.db.keys_stable()? {
+                let stored = match self.db.fetch_data(&key)? {
+                    Some(stored) => stored,
+                    None => continue,
+                };
+                if stored.len() >= HEADER_LEN && le32(&stored[1..]) == current {
+                    continue;
+                }
+                let value = decrypt(&self.keys, &key, &stored)?;
+                self.db.insert(&key, self.encrypt(&key, &value)?)?;
+                rewritten += 1;
+            }
+            Ok(())
+        })?;
+        Ok(rewritten)
+    }
+
+    /// Iterate over the records, decrypting the values
+    pub fn iter(&self) -> EncryptedIter<'_, P> {
+        EncryptedIter {
+            iter: self.db.iter(),
+            keys: &self.keys,
+        }
+    }
+}
+
+impl<'a, P: KeyProvider> Iterator for EncryptedIter<'a, P> {
+    type Item = Result<(Vec<u8>, Vec<u8>), GdbmError>;
+
+    fn next(&mut self) -> Option<Result<(Vec<u8>, Vec<u8>), GdbmError>> {
+        Some(self.iter.next()?.and_then(|(key, stored)| {
+            let value = decrypt(self.keys, &key, &stored)?;
+            Ok((key, value))
+        }))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{open, seal};
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect()
+    }
+
+    // draft-irtf-cfrg-xchacha-03 A.3.1, pinning the construction values
+    // are stored with
+    #[test]
+    fn xchacha20_poly1305_vector() {
+        let mut key = [0; 32];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = 0x80 + i as u8;
+        }
+        let mut nonce = [0; 24];
+        nonce.copy_from_slice(&hex("404142434445464748494a4b4c4d4e4f5051525354555657"));
+        let aad = hex("50515253c0c1c2c3c4c5c6c7");
+        let plaintext: &[u8] = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for \
+                                 the future, sunscreen would be it.";
+        let mut sealed = Vec::new();
+        seal(&key, &nonce, &aad, plaintext, &mut sealed).unwrap();
+        assert_eq!(sealed,
+                   hex("bd6d179d3e83d43b9576579493c0e939572a1700252bfaccbed2902c21396cbb\
+                        731c7f1b0b4aa6440bf3a82f4eda7e39ae64c6708c54c216cb96b72e1213b452\
+                        2f8c9ba40db5d945b11b69b982c1bb9e3f3fac2bc369488f76b2383565d3fff9\
+                        21f9664c97637da9768812f615c68b13b52e\
+                        c0875924c1c7987947deafd8780acf49"));
+        assert_eq!(open(&key, &nonce, &aad, &sealed).unwrap(), plaintext);
+        assert!(open(&key, &nonce, &aad[1..], &sealed).is_err());
+    }
+}
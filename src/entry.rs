@@ -0,0 +1,126 @@
+use {Gdbm, GdbmError, Store};
+
+/// A view into a single record of the database, which may or may not
+/// exist, returned by `Gdbm::entry`.
+///
+/// The record is read when the entry is created; modifications are
+/// written back to the database immediately.
+#[derive(Debug)]
+pub enum Entry<'a> {
+    Occupied(OccupiedEntry<'a>),
+    Vacant(VacantEntry<'a>),
+}
+
+/// An entry for a key that has a record
+#[derive(Debug)]
+pub struct OccupiedEntry<'a> {
+    db: &'a Gdbm,
+    key: Vec<u8>,
+    value: Vec<u8>,
+}
+
+/// An entry for a key that has no record
+#[derive(Debug)]
+pub struct VacantEntry<'a> {
+    db: &'a Gdbm,
+    key: Vec<u8>,
+}
+
+impl Gdbm {
+    /// Look up `key` for in-place manipulation, like `HashMap::entry`.
+    pub fn entry<K: AsRef<[u8]>>(&self, key: K) -> Result<Entry<'_>, GdbmError> {
+        let key = key.as_ref().to_vec();
+        Ok(match self.fetch_bytes(&key)? {
+            Some(value) => Entry::Occupied(OccupiedEntry { db: self, key, value }),
+            None => Entry::Vacant(VacantEntry { db: self, key }),
+        })
+    }
+}
+
+impl<'a> Entry<'a> {
+    /// The key this entry refers to
+    pub fn key(&self) -> &[u8] {
+        match *self {
+            Entry::Occupied(ref entry) => entry.key(),
+            Entry::Vacant(ref entry) => entry.key(),
+        }
+    }
+
+    /// Store `default` if the entry is vacant. Returns the value the
+    /// record now has.
+    pub fn or_insert<V: AsRef<[u8]>>(self, default: V) -> Result<Vec<u8>, GdbmError> {
+        match self {
+            Entry::Occupied(entry) => Ok(entry.into_value()),
+            Entry::Vacant(entry) => entry.insert(default),
+        }
+    }
+
+    /// Store the result of `default` if the entry is vacant. Returns the
+    /// value the record now has.
+    pub fn or_insert_with<V, F>(self, default: F) -> Result<Vec<u8>, GdbmError>
+        where V: AsRef<[u8]>,
+              F: FnOnce() -> V
+    {
+        match self {
+            Entry::Occupied(entry) => Ok(entry.into_value()),
+            Entry::Vacant(entry) => entry.insert(default()),
+        }
+    }
+
+    /// If the entry is occupied, let `f` modify the value and store the
+    /// result.
+    pub fn and_modify<F: FnOnce(&mut Vec<u8>)>(self, f: F) -> Result<Entry<'a>, GdbmError> {
+        match self {
+            Entry::Occupied(mut entry) => {
+                f(&mut entry.value);
+                entry.db.store_bytes(&entry.key, &entry.value, Store::REPLACE)?;
+                Ok(Entry::Occupied(entry))
+            }
+            Entry::Vacant(entry) => Ok(Entry::Vacant(entry)),
+        }
+    }
+}
+
+impl<'a> OccupiedEntry<'a> {
+    /// The key this entry refers to
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// The value of the record, as read when the entry was created
+    pub fn get(&self) -> &[u8] {
+        &self.value
+    }
+
+    /// Take ownership of the value
+    pub fn into_value(self) -> Vec<u8> {
+        self.value
+    }
+
+    /// Overwrite the record, returning the old value.
+    pub fn insert<V: AsRef<[u8]>>(&mut self, value: V) -> Result<Vec<u8>, GdbmError> {
+        let value = value.as_ref();
+        self.db.store_bytes(&self.key, value, Store::REPLACE)?;
+        Ok(::std::mem::replace(&mut self.value, value.to_vec()))
+    }
+
+    /// Delete the record, returning its value.
+    pub fn remove(self) -> Result<Vec<u8>, GdbmError> {
+        self.db.delete_bytes(&self.key)?;
+        Ok(self.value)
+    }
+}
+
+impl<'a> VacantEntry<'a> {
+    /// The key this entry refers to
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    /// Store `value` under the entry's key, returning it.
+    pub fn insert<V: AsRef<[u8]>>(self, value: V) -> Result<Vec<u8>, GdbmError> {
+        let value = value.as_ref();
+        self.db.store_bytes(&self.key, value, Store::REPLACE)?;
+        Ok(value.to_vec())
+    }
+}
@@ -0,0 +1,57 @@
+// Bindings for the parts of the libgdbm API that gdbm-sys does not cover.
+
+use gdbm_sys::GDBM_FILE;
+use libc::{c_char, c_int, c_ulong, c_ulonglong, FILE};
+
+// gdbm_setopt options
+pub const GDBM_GETFLAGS: c_int = 8;
+pub const GDBM_GETMMAP: c_int = 9;
+pub const GDBM_GETCACHESIZE: c_int = 10;
+pub const GDBM_GETSYNCMODE: c_int = 11;
+pub const GDBM_GETMAXMAPSIZE: c_int = 14;
+pub const GDBM_GETDBNAME: c_int = 15;
+pub const GDBM_GETBLOCKSIZE: c_int = 16;
+pub const GDBM_GETDBFORMAT: c_int = 17;
+pub const GDBM_GETCACHEAUTO: c_int = 20;
+pub const GDBM_SETCACHEAUTO: c_int = 21;
+
+// gdbm_convert formats, also an open flag
+pub const GDBM_NUMSYNC: c_int = 0x2000;
+
+/// gdbm_convert, only present in gdbm 1.21 and later
+pub type GdbmConvert = unsafe extern "C" fn(dbf: GDBM_FILE, flag: c_int) -> c_int;
+
+// gdbm_dump formats
+pub const GDBM_DUMP_FMT_BINARY: c_int = 0;
+pub const GDBM_DUMP_FMT_ASCII: c_int = 1;
+
+// gdbm_load metadata flags
+pub const GDBM_META_MASK_MODE: c_int = 0x01;
+pub const GDBM_META_MASK_OWNER: c_int = 0x02;
+
+extern "C" {
+    pub fn gdbm_count(dbf: GDBM_FILE, pcount: *mut c_ulonglong) -> c_int;
+    pub fn gdbm_dump(dbf: GDBM_FILE,
+                     filename: *const c_char,
+                     format: c_int,
+                     open_flags: c_int,
+                     mode: c_int)
+                     -> c_int;
+    pub fn gdbm_dump_to_file(dbf: GDBM_FILE, fp: *mut FILE, format: c_int) -> c_int;
+    pub fn gdbm_load(pdbf: *mut GDBM_FILE,
+                     filename: *const c_char,
+                     replace: c_int,
+                     meta_flags: c_int,
+                     line: *mut c_ulong)
+                     -> c_int;
+    pub fn gdbm_load_from_file(pdbf: *mut GDBM_FILE,
+                               fp: *mut FILE,
+                               replace: c_int,
+                               meta_flags: c_int,
+                               line: *mut c_ulong)
+                               -> c_int;
+    pub fn gdbm_needs_recovery(dbf: GDBM_FILE) -> c_int;
+    // gdbm-sys binds the pre 1.17 prototype, which returned nothing
+    pub fn gdbm_sync(dbf: GDBM_FILE) -> c_int;
+    pub fn gdbm_version_cmp(a: *const c_int, b: *const c_int) -> c_int;
+}
@@ -0,0 +1,500 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::fs::{FileExt, PermissionsExt};
+use std::path::Path;
+
+use {Gdbm, GdbmError, Open, Store};
+
+const GDBM_OMAGIC: u32 = 0x1357_9ace;
+const GDBM_MAGIC32: u32 = 0x1357_9acd;
+const GDBM_MAGIC64: u32 = 0x1357_9acf;
+const GDBM_NUMSYNC_MAGIC32: u32 = 0x1357_9ad0;
+const GDBM_NUMSYNC_MAGIC64: u32 = 0x1357_9ad1;
+
+/// Number of free-space entries at the start of every bucket
+const BUCKET_AVAIL: usize = 6;
+
+/// Bytes of the file header `Layout::read_header` needs
+pub(crate) const HEADER_LEN: usize = 40;
+
+type Record = (Vec<u8>, Vec<u8>);
+
+pub(crate) fn corrupt(what: &str) -> GdbmError {
+    GdbmError::new(format!("corrupt gdbm file: {}", what))
+}
+
+/// How a gdbm file lays out its structures: in the byte order and with
+/// the `off_t` of the host that wrote it, with `off_t` aligned to its
+/// size, as every common ABI does except i386 with 64-bit file offsets
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Layout {
+    big_endian: bool,
+    /// Size of `off_t`, 4 or 8
+    offset_size: usize,
+    numsync: bool,
+}
+
+/// The header fields that lead to the records
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Header {
+    pub dir: u64,
+    pub dir_size: usize,
+    pub dir_bits: u32,
+    pub bucket_size: usize,
+    pub bucket_elems: usize,
+}
+
+/// A bucket element in use
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Element {
+    #[cfg_attr(not(feature = "mmap"), allow(dead_code))]
+    pub hash: u32,
+    pub pointer: u64,
+    pub key_size: usize,
+    pub data_size: usize,
+}
+
+impl Layout {
+    /// The layout and header of the file starting with `header`, at
+    /// least `HEADER_LEN` bytes
+    pub(crate) fn read_header(header: &[u8]) -> Result<(Layout, Header), GdbmError> {
+        let magic = [header[0], header[1], header[2], header[3]];
+        let (big_endian, magic) = if is_magic(u32::from_le_bytes(magic)) {
+            (false, u32::from_le_bytes(magic))
+        } else if is_magic(u32::from_be_bytes(magic)) {
+            (true, u32::from_be_bytes(magic))
+        } else {
+            return Err(GdbmError::new("not a gdbm database"));
+        };
+        let sizes: &[usize] = match magic {
+            GDBM_MAGIC32 | GDBM_NUMSYNC_MAGIC32 => &[4],
+            GDBM_MAGIC64 | GDBM_NUMSYNC_MAGIC64 => &[8],
+            // The old magic does not tell; only one size gives a
+            // directory as large as its number of bits says
+            _ => &[8, 4],
+        };
+        for &offset_size in sizes {
+            let layout = Layout {
+                big_endian,
+                offset_size,
+                numsync: magic == GDBM_NUMSYNC_MAGIC32 || magic == GDBM_NUMSYNC_MAGIC64,
+            };
+            // The directory offset follows the magic and block size
+            let dir_size = layout.int_at(header, 8 + offset_size) as u64;
+            let dir_bits = layout.int_at(header, 12 + offset_size);
+            if dir_bits >= 32 || dir_size != (1u64 << dir_bits) * offset_size as u64 {
+                continue;
+            }
+            let parsed = Header {
+                dir: layout.offset_at(header, 8),
+                dir_size: dir_size as usize,
+                dir_bits,
+                bucket_size: layout.int_at(header, 16 + offset_size) as usize,
+                bucket_elems: layout.int_at(header, 20 + offset_size) as usize,
+            };
+            if parsed.bucket_elems == 0 ||
+               parsed.bucket_size < layout.bucket_header_len() + parsed.bucket_elems * layout.element_size() {
+                return Err(corrupt("bad bucket size"));
+            }
+            return Ok((layout, parsed));
+        }
+        Err(corrupt("bad directory size"))
+    }
+
+    pub(crate) fn offset_size(&self) -> usize {
+        self.offset_size
+    }
+
+    pub(crate) fn int_at(&self, bytes: &[u8], pos: usize) -> u32 {
+        let field = [bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]];
+        if self.big_endian {
+            u32::from_be_bytes(field)
+        } else {
+            u32::from_le_bytes(field)
+        }
+    }
+
+    pub(crate) fn offset_at(&self, bytes: &[u8], pos: usize) -> u64 {
+        if self.offset_size == 4 {
+            return self.int_at(bytes, pos) as u64;
+        }
+        let mut field = [0; 8];
+        field.copy_from_slice(&bytes[pos..pos + 8]);
+        if self.big_endian {
+            u64::from_be_bytes(field)
+        } else {
+            u64::from_le_bytes(field)
+        }
+    }
+
+    /// Fields before a bucket's first element: the free-space count and
+    /// entries, the bucket bits and the element count
+    pub(crate) fn bucket_header_len(&self) -> usize {
+        self.offset_size + BUCKET_AVAIL * 2 * self.offset_size + 8
+    }
+
+    /// An element is its hash, the first four key bytes, the offset of
+    /// the record and the sizes of key and value
+    fn element_size(&self) -> usize {
+        16 + self.offset_size
+    }
+
+    /// Element `index` of `bucket`, None if the slot is empty
+    pub(crate) fn element(&self, bucket: &[u8], index: usize) -> Option<Element> {
+        let element = &bucket[self.bucket_header_len() + index * self.element_size()..];
+        let hash = self.int_at(element, 0);
+        if hash == u32::MAX {
+            return None;
+        }
+        Some(Element {
+            hash,
+            pointer: self.offset_at(element, 8),
+            key_size: self.int_at(element, 8 + self.offset_size) as usize,
+            data_size: self.int_at(element, 12 + self.offset_size) as usize,
+        })
+    }
+
+    /// The size and address of each entry of a free list
+    fn avail(&self, entries: &[u8], count: usize) -> Vec<(u64, u64)> {
+        entries
+            .chunks(2 * self.offset_size)
+            .take(count)
+            .map(|elem| (self.int_at(elem, 0) as u64, self.offset_at(elem, self.offset_size)))
+            .collect()
+    }
+
+    /// The free list at the start of `bucket`
+    fn bucket_avail(&self, bucket: &[u8]) -> Vec<(u64, u64)> {
+        let count = (self.int_at(bucket, 0) as usize).min(BUCKET_AVAIL);
+        self.avail(&bucket[self.offset_size..], count)
+    }
+
+    /// The bucket bits and element count of `bucket`
+    fn bucket_counts(&self, bucket: &[u8]) -> (u32, usize) {
+        let bits = self.offset_size + BUCKET_AVAIL * 2 * self.offset_size;
+        (self.int_at(bucket, bits), self.int_at(bucket, bits + 4) as usize)
+    }
+
+    /// Where the header's free list starts: after the header fields and,
+    /// in the numsync format, the extension header
+    fn free_list_start(&self) -> u64 {
+        let start = 8 + 4 * 4 + 2 * self.offset_size as u64;
+        if self.numsync {
+            start + 32
+        } else {
+            start
+        }
+    }
+}
+
+/// A gdbm database read without libgdbm, whatever the byte order and
+/// `off_t` size of the host that wrote it. This is what reads the files
+/// `Gdbm::new` rejects with `GdbmError::ForeignFormat`; see
+/// `Gdbm::convert_offsets`.
+///
+/// Assumes the writer aligned `off_t` to its size, as every common ABI
+/// does except i386 built with 64-bit file offsets.
+#[derive(Debug)]
+pub struct ForeignGdbmFile {
+    file: File,
+    len: u64,
+    layout: Layout,
+    header: Header,
+    /// Addresses of the buckets, each once, in directory order
+    buckets: Vec<u64>,
+}
+
+/// Iterator over the records of a `ForeignGdbmFile`, returned by
+/// `ForeignGdbmFile::iter`. Iteration stops after the first error.
+#[derive(Debug)]
+pub struct ForeignRecords<'a> {
+    db: &'a ForeignGdbmFile,
+    next_bucket: usize,
+    pending: Vec<Record>,
+    failed: bool,
+}
+
+/// The addresses in a directory, each once, in directory order
+pub(crate) fn unique_buckets(layout: &Layout, directory: &[u8]) -> Vec<u64> {
+    let mut seen = HashSet::new();
+    directory
+        .chunks(layout.offset_size())
+        .map(|entry| layout.offset_at(entry, 0))
+        .filter(|&address| seen.insert(address))
+        .collect()
+}
+
+impl ForeignGdbmFile {
+    /// Open the gdbm database at `path` for reading
+    pub fn open(path: &Path) -> Result<ForeignGdbmFile, GdbmError> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let mut header = [0; HEADER_LEN];
+        file.read_exact_at(&mut header, 0).map_err(|_| GdbmError::new("not a gdbm database"))?;
+        let (layout, header) = Layout::read_header(&header)?;
+        let mut db = ForeignGdbmFile {
+            file,
+            len,
+            layout,
+            header,
+            buckets: Vec::new(),
+        };
+        let mut directory = vec![0; header.dir_size];
+        db.read_exact_at(&mut directory, header.dir)?;
+        db.buckets = unique_buckets(&layout, &directory);
+        Ok(db)
+    }
+
+    /// Iterate over the records, bucket by bucket
+    pub fn iter(&self) -> ForeignRecords<'_> {
+        ForeignRecords {
+            db: self,
+            next_bucket: 0,
+            pending: Vec::new(),
+            failed: false,
+        }
+    }
+
+    /// The size and address of each entry of the header's free list and
+    /// the blocks it continues in
+    fn header_avail(&self) -> Result<Vec<(u64, u64)>, GdbmError> {
+        let layout = &self.layout;
+        let avail_elem = 2 * layout.offset_size;
+        let mut address = layout.free_list_start();
+        let mut avail = Vec::new();
+        let mut seen = HashSet::new();
+        while address != 0 {
+            if !seen.insert(address) {
+                return Err(corrupt("free list blocks form a loop"));
+            }
+            let table = 8 + layout.offset_size;
+            let mut block = vec![0; table];
+            self.read_exact_at(&mut block, address)?;
+            let size = layout.int_at(&block, 0) as usize;
+            let count = layout.int_at(&block, 4) as usize;
+            if count > size || (table + size * avail_elem) as u64 > self.len {
+                return Err(corrupt("bad free list block"));
+            }
+            let next = layout.offset_at(&block, 8);
+            block.resize(table + count * avail_elem, 0);
+            self.read_exact_at(&mut block[table..], address + table as u64)?;
+            avail.extend(layout.avail(&block[table..], count));
+            address = next;
+        }
+        Ok(avail)
+    }
+
+    /// Bytes on gdbm's free lists: the header's list with the blocks it
+    /// continues in, and the list of every bucket
+    pub(crate) fn free_bytes(&self) -> Result<u64, GdbmError> {
+        let mut free = self.header_avail()?.iter().map(|&(size, _)| size).sum::<u64>();
+        let mut bucket = vec![0; self.layout.bucket_header_len()];
+        for &address in &self.buckets {
+            self.read_exact_at(&mut bucket, address)?;
+            free += self.layout.bucket_avail(&bucket).iter().map(|&(size, _)| size).sum::<u64>();
+        }
+        Ok(free)
+    }
+
+    /// Check the structure of the file: that the directory splits into
+    /// whole runs of entries per bucket, that each bucket's counts agree
+    /// with its elements and that its records hash to it, and that every
+    /// record and free list entry lies within the file. The header is
+    /// checked by `open`. Returns a description of each problem found,
+    /// none if the file is sound.
+    ///
+    /// This reads only gdbm's structures, not the records themselves;
+    /// read every record to be sure they can all be fetched.
+    pub fn check(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        match self.header_avail() {
+            Ok(avail) => self.check_avail("the header", &avail, &mut problems),
+            Err(err) => problems.push(format!("cannot read the free list: {}", err)),
+        }
+        let mut directory = vec![0; self.header.dir_size];
+        if let Err(err) = self.read_exact_at(&mut directory, self.header.dir) {
+            problems.push(format!("cannot read the directory: {}", err));
+            return problems;
+        }
+        let layout = &self.layout;
+        let entries: Vec<u64> = directory.chunks(layout.offset_size).map(|entry| layout.offset_at(entry, 0)).collect();
+        let dir_bits = self.header.dir_bits;
+        let mut bucket = vec![0; self.header.bucket_size];
+        let mut seen = HashSet::new();
+        let mut i = 0;
+        while i < entries.len() {
+            let address = entries[i];
+            if let Err(err) = self.read_exact_at(&mut bucket, address) {
+                problems.push(format!("cannot read bucket {}: {}", address, err));
+                i += entries[i..].iter().take_while(|&&entry| entry == address).count();
+                continue;
+            }
+            let (bits, count) = layout.bucket_counts(&bucket);
+            if bits > dir_bits {
+                problems.push(format!("bucket {} has {} bits, more than the directory's {}", address, bits, dir_bits));
+                i += entries[i..].iter().take_while(|&&entry| entry == address).count();
+                continue;
+            }
+            // A bucket of `bits` bits is listed by the run of entries
+            // sharing their first `bits` bits
+            let run = 1usize << (dir_bits - bits);
+            if i % run != 0 || entries[i..].len() < run || entries[i..i + run].iter().any(|&entry| entry != address) {
+                problems.push(format!("directory entry {} does not start a run of {} entries for bucket {}", i, run, address));
+                i += 1;
+                continue;
+            }
+            if !seen.insert(address) {
+                problems.push(format!("bucket {} is listed by more than one run of directory entries", address));
+            }
+            self.check_bucket(&bucket, address, (i >> (dir_bits - bits)) as u32, bits, count, &mut problems);
+            i += run;
+        }
+        problems
+    }
+
+    /// Check a bucket whose records' hashes start with the `bits` bits of
+    /// `prefix`
+    fn check_bucket(&self, bucket: &[u8], address: u64, prefix: u32, bits: u32, count: usize, problems: &mut Vec<String>) {
+        let layout = &self.layout;
+        if layout.int_at(bucket, 0) as usize > BUCKET_AVAIL {
+            problems.push(format!("bucket {} lists {} free entries, more than {}", address, layout.int_at(bucket, 0), BUCKET_AVAIL));
+        }
+        self.check_avail(&format!("bucket {}", address), &layout.bucket_avail(bucket), problems);
+        if count > self.header.bucket_elems {
+            problems.push(format!("bucket {} counts {} elements, more than the {} it holds", address, count, self.header.bucket_elems));
+        }
+        let mut found = 0;
+        for element in (0..self.header.bucket_elems).filter_map(|i| layout.element(bucket, i)) {
+            found += 1;
+            // gdbm hashes to 31 bits and indexes the directory by the top ones
+            if element.hash >> (31 - bits) != prefix {
+                problems.push(format!("bucket {} holds a record with hash {:08x}, which belongs elsewhere", address, element.hash));
+            }
+            let end = element.pointer.saturating_add((element.key_size + element.data_size) as u64);
+            if end > self.len {
+                problems.push(format!("bucket {} has a record at {} extending past the end of the file", address, element.pointer));
+            }
+        }
+        if found != count {
+            problems.push(format!("bucket {} counts {} elements but holds {}", address, count, found));
+        }
+    }
+
+    fn check_avail(&self, what: &str, avail: &[(u64, u64)], problems: &mut Vec<String>) {
+        for &(size, address) in avail {
+            if address.saturating_add(size) > self.len {
+                problems.push(format!("free space of {} bytes at {} listed in {} extends past the end of the file", size, address, what));
+            }
+        }
+    }
+
+    /// Bytes of the keys and values of the records
+    pub(crate) fn live_bytes(&self) -> Result<u64, GdbmError> {
+        let mut bucket = vec![0; self.header.bucket_size];
+        let mut live = 0u64;
+        for &address in &self.buckets {
+            self.read_exact_at(&mut bucket, address)?;
+            live += (0..self.header.bucket_elems)
+                .filter_map(|i| self.layout.element(&bucket, i))
+                .map(|element| (element.key_size + element.data_size) as u64)
+                .sum::<u64>();
+        }
+        Ok(live)
+    }
+
+    /// Size of the file when it was opened
+    pub(crate) fn len(&self) -> u64 {
+        self.len
+    }
+
+    fn read_exact_at(&self, buf: &mut [u8], address: u64) -> Result<(), GdbmError> {
+        if address.saturating_add(buf.len() as u64) > self.len {
+            return Err(corrupt("block extends past the end of the file"));
+        }
+        self.file.read_exact_at(buf, address)?;
+        Ok(())
+    }
+
+    fn read_bucket(&self, address: u64) -> Result<Vec<Record>, GdbmError> {
+        let mut bucket = vec![0; self.header.bucket_size];
+        self.read_exact_at(&mut bucket, address)?;
+        let mut records = Vec::new();
+        for i in 0..self.header.bucket_elems {
+            let element = match self.layout.element(&bucket, i) {
+                Some(element) => element,
+                None => continue,
+            };
+            let mut record = vec![0; element.key_size + element.data_size];
+            self.read_exact_at(&mut record, element.pointer)?;
+            let value = record.split_off(element.key_size);
+            records.push((record, value));
+        }
+        Ok(records)
+    }
+}
+
+fn is_magic(magic: u32) -> bool {
+    [GDBM_OMAGIC, GDBM_MAGIC32, GDBM_MAGIC64, GDBM_NUMSYNC_MAGIC32, GDBM_NUMSYNC_MAGIC64].contains(&magic)
+}
+
+impl<'a> ForeignRecords<'a> {
+    fn advance(&mut self) -> Result<Option<Record>, GdbmError> {
+        loop {
+            if let Some(record) = self.pending.pop() {
+                return Ok(Some(record));
+            }
+            let address = match self.db.buckets.get(self.next_bucket) {
+                Some(&address) => address,
+                None => return Ok(None),
+            };
+            self.next_bucket += 1;
+            self.pending = self.db.read_bucket(address)?;
+            self.pending.reverse();
+        }
+    }
+}
+
+impl<'a> Iterator for ForeignRecords<'a> {
+    type Item = Result<Record, GdbmError>;
+
+    fn next(&mut self) -> Option<Result<Record, GdbmError>> {
+        if self.failed {
+            return None;
+        }
+        let next = self.advance().transpose();
+        self.failed = matches!(next, Some(Err(_)));
+        next
+    }
+}
+
+impl Gdbm {
+    /// Salvage the gdbm database at `src`, written on a host with a
+    /// different byte order or `off_t` size, by copying its records into
+    /// a new native database at `dest`, which must not exist yet. Returns
+    /// a writable handle to the copy, which gets the permissions of `src`.
+    /// If the copy fails the partial file is removed.
+    pub fn convert_offsets(src: &Path, dest: &Path) -> Result<Gdbm, GdbmError> {
+        let source = ForeignGdbmFile::open(src)?;
+        if fs::symlink_metadata(dest).is_ok() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "destination already exists").into());
+        }
+        let mode = fs::metadata(src)?.permissions().mode() & 0o7777;
+        let copy = Gdbm::new(dest, 0, Open::NEWDB, mode as i32)?;
+        let copied = copy.with_deferred_sync(|| {
+            for record in source.iter() {
+                let (key, value) = record?;
+                copy.store_bytes(&key, &value, Store::REPLACE)?;
+            }
+            Ok(())
+        });
+        match copied {
+            Ok(()) => Ok(copy),
+            Err(err) => {
+                drop(copy);
+                let _ = fs::remove_file(dest);
+                Err(err)
+            }
+        }
+    }
+}
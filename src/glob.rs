@@ -0,0 +1,121 @@
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use globset::{self, GlobBuilder, GlobMatcher};
+#[cfg(feature = "regex")]
+use regex::bytes::Regex;
+
+use {Gdbm, GdbmError, Keys};
+
+/// A shell-style pattern matched against raw key bytes.
+///
+/// `*` matches any run of bytes, `?` any single byte, `[abc]` and
+/// `[a-z]` one byte from the set (`[!...]` or `[^...]` negates it),
+/// `{a,b}` either alternative and `\` makes the next byte literal. Unlike
+/// a shell, `*` also matches `/`. Matching is done by `globset`.
+#[derive(Debug, Clone)]
+pub struct Glob {
+    glob: globset::Glob,
+    matcher: GlobMatcher,
+}
+
+impl Glob {
+    /// Compile `pattern`. Fails on an unterminated `[` or `{`, or a
+    /// trailing `\`.
+    pub fn new(pattern: &str) -> Result<Glob, GdbmError> {
+        let glob = GlobBuilder::new(pattern)
+            .backslash_escape(true)
+            .build()
+            .map_err(|e| GdbmError::new(e.to_string()))?;
+        Ok(Glob {
+            matcher: glob.compile_matcher(),
+            glob,
+        })
+    }
+
+    /// True if the whole of `key` matches the pattern
+    pub fn matches(&self, key: &[u8]) -> bool {
+        // globset matches paths, which on Unix are any bytes
+        self.matcher.is_match(Path::new(OsStr::from_bytes(key)))
+    }
+}
+
+impl PartialEq for Glob {
+    fn eq(&self, other: &Glob) -> bool {
+        self.glob == other.glob
+    }
+}
+
+impl Eq for Glob {}
+
+/// What `KeysMatching` matches keys against
+#[derive(Debug)]
+enum Pattern {
+    Glob(Glob),
+    #[cfg(feature = "regex")]
+    Regex(Regex),
+}
+
+impl Pattern {
+    fn matches(&self, key: &[u8]) -> bool {
+        match *self {
+            Pattern::Glob(ref glob) => glob.matches(key),
+            #[cfg(feature = "regex")]
+            Pattern::Regex(ref regex) => regex.is_match(key),
+        }
+    }
+}
+
+/// Iterator over the keys matching a pattern, returned by
+/// `Gdbm::keys_matching` and `Gdbm::keys_matching_regex`.
+#[derive(Debug)]
+pub struct KeysMatching<'a> {
+    keys: Keys<'a>,
+    pattern: Pattern,
+}
+
+impl Gdbm {
+    /// Iterate over the keys matching the glob `pattern`, such as
+    /// `session:*:expired`. Every key is still read from the file, but
+    /// only the matching ones are handed out.
+    pub fn keys_matching(&self, pattern: &str) -> Result<KeysMatching<'_>, GdbmError> {
+        Ok(KeysMatching {
+            keys: self.keys(),
+            pattern: Pattern::Glob(Glob::new(pattern)?),
+        })
+    }
+
+    /// Iterate over the keys the regular expression `pattern` matches,
+    /// as `regex::bytes::Regex::is_match` does: anywhere in the key
+    /// unless anchored with `^` and `$`. The pattern is matched against
+    /// raw bytes, so `(?-u)` lets it match keys that are not UTF-8.
+    #[cfg(feature = "regex")]
+    pub fn keys_matching_regex(&self, pattern: &str) -> Result<KeysMatching<'_>, GdbmError> {
+        Ok(KeysMatching {
+            keys: self.keys(),
+            pattern: Pattern::Regex(Regex::new(pattern).map_err(|e| GdbmError::new(e.to_string()))?),
+        })
+    }
+}
+
+impl<'a> Iterator for KeysMatching<'a> {
+    type Item = Result<Vec<u8>, GdbmError>;
+
+    fn next(&mut self) -> Option<Result<Vec<u8>, GdbmError>> {
+        loop {
+            match self.keys.next()? {
+                Ok(key) => {
+                    if self.pattern.matches(&key) {
+                        return Some(Ok(key));
+                    }
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.keys.size_hint().1)
+    }
+}
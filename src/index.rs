@@ -0,0 +1,204 @@
+use std::fmt;
+use std::sync::Arc;
+
+use stats::{WriteAmplification, WriteCounters};
+use {Gdbm, GdbmError};
+
+type Record = (Vec<u8>, Vec<u8>);
+
+type ExtractFn = dyn Fn(&[u8], &[u8]) -> Vec<Vec<u8>> + Send + Sync;
+
+#[derive(Clone)]
+struct Extract(Arc<ExtractFn>);
+
+impl fmt::Debug for Extract {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Extract")
+    }
+}
+
+/// Lookups by something other than the primary key, kept in a separate
+/// index database.
+///
+/// An extractor function maps each record to the index keys it should be
+/// found under (none, one or several). The index database maps every
+/// index key to the primary keys carrying it. Write to the primary
+/// database through `store` and `remove` to keep the index current; after
+/// writes that bypassed it, or a crash between the two databases being
+/// updated, `rebuild` brings it back in line.
+#[derive(Debug)]
+pub struct SecondaryIndex {
+    index: Gdbm,
+    extract: Extract,
+    writes: WriteCounters,
+}
+
+/// The stored form of a list of primary keys: for each key in sorted
+/// order, its length as a u32 little endian, then its bytes
+fn encode_keys(keys: &[Vec<u8>]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for key in keys {
+        out.extend_from_slice(&(key.len() as u32).to_le_bytes());
+        out.extend_from_slice(key);
+    }
+    out
+}
+
+fn decode_keys(mut data: &[u8]) -> Result<Vec<Vec<u8>>, GdbmError> {
+    let mut keys = Vec::new();
+    while !data.is_empty() {
+        if data.len() < 4 {
+            return Err(GdbmError::new("corrupt secondary index entry"));
+        }
+        let mut len = [0; 4];
+        len.copy_from_slice(&data[..4]);
+        let len = u32::from_le_bytes(len) as usize;
+        if data.len() - 4 < len {
+            return Err(GdbmError::new("corrupt secondary index entry"));
+        }
+        keys.push(data[4..4 + len].to_vec());
+        data = &data[4 + len..];
+    }
+    Ok(keys)
+}
+
+impl SecondaryIndex {
+    /// Keep the index in `index`, a writable handle to its own database,
+    /// using `extract` to find the index keys of a record from its key and
+    /// value.
+    pub fn new<F>(index: Gdbm, extract: F) -> SecondaryIndex
+        where F: Fn(&[u8], &[u8]) -> Vec<Vec<u8>> + Send + Sync + 'static
+    {
+        SecondaryIndex {
+            index,
+            extract: Extract(Arc::new(extract)),
+            writes: WriteCounters::default(),
+        }
+    }
+
+    /// The index database
+    pub fn as_gdbm(&self) -> &Gdbm {
+        &self.index
+    }
+
+    /// The primary keys of the records found under `index_key`, sorted
+    pub fn lookup<K: AsRef<[u8]>>(&self, index_key: K) -> Result<Vec<Vec<u8>>, GdbmError> {
+        match self.index.fetch_data(index_key)? {
+            Some(data) => decode_keys(&data),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// The records of `db` found under `index_key`
+    pub fn get<K: AsRef<[u8]>>(&self, db: &Gdbm, index_key: K) -> Result<Vec<Record>, GdbmError> {
+        let mut records = Vec::new();
+        for key in self.lookup(index_key)? {
+            if let Some(value) = db.fetch_data(&key)? {
+                records.push((key, value));
+            }
+        }
+        Ok(records)
+    }
+
+    /// `Gdbm::insert` on `db`, then update the index for the old and new
+    /// values. Returns the value replaced.
+    pub fn store<K, V>(&self, db: &Gdbm, key: K, value: V) -> Result<Option<Vec<u8>>, GdbmError>
+        where K: AsRef<[u8]>,
+              V: AsRef<[u8]>
+    {
+        let (key, value) = (key.as_ref(), value.as_ref());
+        let old = db.insert(key, value)?;
+        let mut removed = match old {
+            Some(ref old) => (self.extract.0)(key, old),
+            None => Vec::new(),
+        };
+        let added = (self.extract.0)(key, value);
+        removed.retain(|index_key| !added.contains(index_key));
+        let mut stored = key.len() + value.len();
+        for index_key in removed {
+            stored += self.unlink(&index_key, key)?;
+        }
+        for index_key in added {
+            stored += self.link(&index_key, key)?;
+        }
+        self.writes.add(key.len() + value.len(), stored);
+        Ok(old)
+    }
+
+    /// `Gdbm::remove` on `db`, then drop the record from the index.
+    /// Returns the value removed.
+    pub fn remove<K: AsRef<[u8]>>(&self, db: &Gdbm, key: K) -> Result<Option<Vec<u8>>, GdbmError> {
+        let key = key.as_ref();
+        let old = db.remove(key)?;
+        if let Some(ref old) = old {
+            let mut stored = 0;
+            for index_key in (self.extract.0)(key, old) {
+                stored += self.unlink(&index_key, key)?;
+            }
+            self.writes.add(0, stored);
+        }
+        Ok(old)
+    }
+
+    /// What keeping the index current has cost: the bytes `store` and
+    /// `remove` wrote to `db` and to the index, against the keys and values
+    /// stored, since the index was created or `reset_write_amplification`
+    /// was last called. `rebuild` is not counted.
+    pub fn write_amplification(&self) -> WriteAmplification {
+        self.writes.snapshot()
+    }
+
+    /// Set the write amplification counts back to zero
+    pub fn reset_write_amplification(&self) {
+        self.writes.reset();
+    }
+
+    /// Throw the index away and index every record of `db` again.
+    /// Returns the number of records indexed.
+    pub fn rebuild(&self, db: &Gdbm) -> Result<u64, GdbmError> {
+        self.index.clear()?;
+        let mut records = 0;
+        self.index.with_deferred_sync(|| {
+            for record in db.iter() {
+                let (key, value) = record?;
+                for index_key in (self.extract.0)(&key, &value) {
+                    self.link(&index_key, &key)?;
+                }
+                records += 1;
+            }
+            Ok(())
+        })?;
+        Ok(records)
+    }
+
+    /// Add `key` to the entry of `index_key`, returning the bytes stored
+    fn link(&self, index_key: &[u8], key: &[u8]) -> Result<usize, GdbmError> {
+        let mut keys = self.lookup(index_key)?;
+        match keys.binary_search_by(|k| k.as_slice().cmp(key)) {
+            Ok(_) => Ok(0),
+            Err(pos) => {
+                keys.insert(pos, key.to_vec());
+                let entry = encode_keys(&keys);
+                self.index.insert(index_key, &entry)?;
+                Ok(index_key.len() + entry.len())
+            }
+        }
+    }
+
+    /// Drop `key` from the entry of `index_key`, returning the bytes
+    /// stored
+    fn unlink(&self, index_key: &[u8], key: &[u8]) -> Result<usize, GdbmError> {
+        let mut keys = self.lookup(index_key)?;
+        if let Ok(pos) = keys.binary_search_by(|k| k.as_slice().cmp(key)) {
+            keys.remove(pos);
+            if keys.is_empty() {
+                self.index.remove(index_key)?;
+            } else {
+                let entry = encode_keys(&keys);
+                self.index.insert(index_key, &entry)?;
+                return Ok(index_key.len() + entry.len());
+            }
+        }
+        Ok(0)
+    }
+}
@@ -0,0 +1,259 @@
+use std::mem;
+use std::slice;
+use std::vec;
+
+use gdbm_sys::{datum, gdbm_errno_location, gdbm_firstkey, gdbm_nextkey, GDBM_ITEM_NOT_FOUND, GDBM_NO_ERROR};
+use libc::{c_uint, c_void, free};
+
+use migrations::is_reserved_key;
+use {clear_error, CancellationToken, Cursor, Gdbm, GdbmError};
+
+/// A key gdbm allocated, freed on drop
+struct KeyBuffer(datum);
+
+impl Drop for KeyBuffer {
+    fn drop(&mut self) {
+        unsafe { free(self.0.dptr as *mut c_void) };
+    }
+}
+
+#[derive(Debug)]
+enum Position {
+    Start,
+    After(Vec<u8>),
+    /// An error to yield next, after which iteration ends
+    Failed(GdbmError),
+    End,
+}
+
+/// Iterator over the keys of a database, in gdbm's traversal order,
+/// returned by `Gdbm::keys`.
+///
+/// The length is the record count taken with gdbm_count when the
+/// iterator was created. Storing or deleting records while iterating may
+/// cause keys to be skipped or repeated, and the length to be wrong.
+/// Iteration stops after the first error.
+#[derive(Debug)]
+pub struct Keys<'a> {
+    db: &'a Gdbm,
+    position: Position,
+    remaining: usize,
+    cancel: Option<CancellationToken>,
+}
+
+/// Iterator over the records of a database, in gdbm's traversal order,
+/// returned by `Gdbm::iter`.
+///
+/// The same length and caveats as for `Keys` apply. A key whose record
+/// disappears between reading the key and its value is skipped.
+#[derive(Debug)]
+pub struct Iter<'a> {
+    keys: Keys<'a>,
+}
+
+/// Snapshot of the keys of a database, returned by `Gdbm::keys_stable`.
+///
+/// Unlike `Keys`, this is unaffected by changes to the database, so the
+/// records can be deleted or replaced while iterating.
+#[derive(Debug, Clone)]
+pub struct StableKeys {
+    keys: vec::IntoIter<Vec<u8>>,
+}
+
+impl Gdbm {
+    /// Iterate over the keys of the database
+    pub fn keys(&self) -> Keys<'_> {
+        let (position, remaining) = match self.count() {
+            Ok(count) => (Position::Start, count as usize),
+            Err(e) => (Position::Failed(e), 1),
+        };
+        Keys {
+            db: self,
+            position,
+            remaining,
+            cancel: None,
+        }
+    }
+
+    /// Collect every key up front and iterate over the snapshot, so that
+    /// deleting or storing records during the loop is safe:
+    ///
+    /// ```no_run
+    /// # fn prune(db: &gdbm::Gdbm) -> Result<(), gdbm::GdbmError> {
+    /// for key in db.keys_stable()? {
+    ///     if key.starts_with(b"tmp:") {
+    ///         db.remove(&key)?;
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// Keys stored after the snapshot is taken are not visited, and keys
+    /// deleted after it still are. Memory use grows with the number of
+    /// keys.
+    pub fn keys_stable(&self) -> Result<StableKeys, GdbmError> {
+        let keys = self.keys().collect::<Result<Vec<_>, _>>()?;
+        Ok(StableKeys { keys: keys.into_iter() })
+    }
+
+    /// Iterate over the `(key, value)` records of the database
+    pub fn iter(&self) -> Iter<'_> {
+        Iter { keys: self.keys() }
+    }
+
+    /// Call `f` with every key, in gdbm's traversal order, without
+    /// copying any: each key is lent straight from the buffer gdbm
+    /// returned it in, which is freed as soon as the next key is read.
+    /// For key-only scans where the per-key `Vec` of `keys` shows up in
+    /// profiles. The caveats of `Keys` about changing the database while
+    /// iterating apply. Stops at the first error.
+    pub fn for_each_key<F: FnMut(&[u8])>(&self, mut f: F) -> Result<(), GdbmError> {
+        let handle = self.handle()?;
+        clear_error();
+        let mut key = KeyBuffer(unsafe { gdbm_firstkey(handle) });
+        loop {
+            if key.0.dptr.is_null() {
+                return match unsafe { *gdbm_errno_location() } as c_uint {
+                    GDBM_NO_ERROR | GDBM_ITEM_NOT_FOUND => Ok(()),
+                    _ => Err(self.last_error()),
+                };
+            }
+            if key.0.dsize < 0 {
+                return Err(GdbmError::new("key has negative size"));
+            }
+            let bytes = unsafe { slice::from_raw_parts(key.0.dptr as *const u8, key.0.dsize as usize) };
+            if !is_reserved_key(bytes) {
+                f(bytes);
+            }
+            clear_error();
+            key = KeyBuffer(unsafe { gdbm_nextkey(handle, key.0) });
+        }
+    }
+}
+
+impl<'a> Keys<'a> {
+    /// Check `cancel` before every key; once it is set the iterator
+    /// yields `GdbmError::Cancelled` and ends.
+    pub fn with_cancellation(mut self, cancel: &CancellationToken) -> Keys<'a> {
+        self.cancel = Some(cancel.clone());
+        self
+    }
+
+    /// A cursor at the key most recently returned, None before the first
+    /// key and once iteration has ended.
+    pub fn cursor(&self) -> Option<Cursor> {
+        match self.position {
+            Position::After(ref key) => Some(Cursor::new(key.clone())),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> Iter<'a> {
+    /// Check `cancel` before every record, see `Keys::with_cancellation`.
+    pub fn with_cancellation(self, cancel: &CancellationToken) -> Iter<'a> {
+        Iter { keys: self.keys.with_cancellation(cancel) }
+    }
+
+    /// A cursor at the record most recently returned, see `Keys::cursor`.
+    pub fn cursor(&self) -> Option<Cursor> {
+        self.keys.cursor()
+    }
+}
+
+impl<'a> Iterator for Keys<'a> {
+    type Item = Result<Vec<u8>, GdbmError>;
+
+    fn next(&mut self) -> Option<Result<Vec<u8>, GdbmError>> {
+        if let Some(ref cancel) = self.cancel {
+            if cancel.is_cancelled() {
+                if let Position::End = self.position {
+                    return None;
+                }
+                self.position = Position::Failed(GdbmError::Cancelled);
+            }
+        }
+        let next = match mem::replace(&mut self.position, Position::End) {
+            Position::Start => self.db.first_key_bytes(),
+            Position::After(key) => self.db.next_key_bytes(&key),
+            Position::Failed(e) => Err(e),
+            Position::End => return None,
+        };
+        match next {
+            Ok(Some(key)) => {
+                self.position = Position::After(key.clone());
+                self.remaining = self.remaining.saturating_sub(1);
+                Some(Ok(key))
+            }
+            Ok(None) => {
+                self.remaining = 0;
+                None
+            }
+            Err(e) => {
+                self.remaining = 0;
+                Some(Err(e))
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<'a> ExactSizeIterator for Keys<'a> {}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = Result<(Vec<u8>, Vec<u8>), GdbmError>;
+
+    fn next(&mut self) -> Option<Result<(Vec<u8>, Vec<u8>), GdbmError>> {
+        loop {
+            let key = match self.keys.next()? {
+                Ok(key) => key,
+                Err(e) => return Some(Err(e)),
+            };
+            match self.keys.db.fetch_bytes(&key) {
+                Ok(Some(value)) => return Some(Ok((key, value))),
+                Ok(None) => continue,
+                Err(e) => {
+                    self.keys.position = Position::End;
+                    self.keys.remaining = 0;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.keys.size_hint()
+    }
+}
+
+impl<'a> ExactSizeIterator for Iter<'a> {}
+
+impl Iterator for StableKeys {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        self.keys.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.keys.size_hint()
+    }
+}
+
+impl ExactSizeIterator for StableKeys {}
+
+/// `for record in &db` walks the records as `Gdbm::iter` does. Each item
+/// is a `Result`, so the loop body typically starts with
+/// `let (key, value) = record?;`.
+impl<'a> IntoIterator for &'a Gdbm {
+    type Item = Result<(Vec<u8>, Vec<u8>), GdbmError>;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
@@ -0,0 +1,97 @@
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::str;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde_json::{self, Map, Value};
+
+use {ExportMap, Gdbm, GdbmError, ImportFlag};
+
+/// Add `"name":"..."` to `object`, or `"name_base64":"..."` for bytes
+/// that are not UTF-8
+fn write_member(object: &mut Map<String, Value>, name: &str, bytes: &[u8]) {
+    match str::from_utf8(bytes) {
+        Ok(text) => object.insert(name.to_string(), Value::String(text.to_string())),
+        Err(_) => object.insert(format!("{}_base64", name), Value::String(BASE64.encode(bytes))),
+    };
+}
+
+/// The bytes of member `name` of a line's object, from either `name` or
+/// `name_base64`
+fn read_member(object: &Map<String, Value>, name: &str) -> Result<Vec<u8>, String> {
+    let base64_name = format!("{}_base64", name);
+    match (object.get(name), object.get(&base64_name)) {
+        (Some(Value::String(text)), _) => Ok(text.clone().into_bytes()),
+        (Some(_), _) => Err(format!("{} is not a string", name)),
+        (None, Some(Value::String(text))) => {
+            BASE64.decode(text.as_bytes()).map_err(|_| format!("{} is not valid base64", base64_name))
+        }
+        (None, Some(_)) => Err(format!("{} is not a string", base64_name)),
+        (None, None) => Err(format!("missing {}", name)),
+    }
+}
+
+impl Gdbm {
+    /// Write every record to `writer` as JSON Lines: one
+    /// `{"key":...,"value":...}` object per line. A key or value that is
+    /// not UTF-8 is written base64 encoded, as `key_base64` or
+    /// `value_base64` instead. Returns the number of records written.
+    pub fn export_jsonl<W: Write>(&self, writer: &mut W) -> Result<u64, GdbmError> {
+        self.export_jsonl_mapped(writer, &mut ExportMap::new())
+    }
+
+    /// `export_jsonl`, passing every record through `map` before it is
+    /// written. Whether a member is base64 encoded depends on the mapped
+    /// bytes.
+    pub fn export_jsonl_mapped<W: Write>(&self, writer: &mut W, map: &mut ExportMap) -> Result<u64, GdbmError> {
+        let mut out = BufWriter::new(writer);
+        let mut records = 0;
+        for record in self.iter() {
+            let (key, value) = record?;
+            let (key, value) = map.apply(key, value);
+            // Members are kept in name order, so "key" comes first
+            let mut object = Map::new();
+            write_member(&mut object, "key", &key);
+            write_member(&mut object, "value", &value);
+            serde_json::to_writer(&mut out, &object).map_err(io::Error::from)?;
+            out.write_all(b"\n")?;
+            records += 1;
+        }
+        out.flush()?;
+        Ok(records)
+    }
+
+    /// Store the records of JSON Lines read from `reader`, in the form
+    /// `export_jsonl` writes. Other members of the objects are ignored and
+    /// blank lines are skipped. A malformed line is reported as
+    /// `GdbmError::ImportError` with its line number.
+    ///
+    /// Returns the number of records stored. The records before a failed
+    /// line stay in the database.
+    pub fn import_jsonl<R: Read>(&self, reader: &mut R, flag: ImportFlag) -> Result<u64, GdbmError> {
+        let input = BufReader::new(reader);
+        let flag = flag.to_store();
+        self.with_deferred_sync(|| {
+            let mut records = 0;
+            for (i, text) in input.lines().enumerate() {
+                let line = i as u64 + 1;
+                let error = |message: String| GdbmError::ImportError { line, message };
+                let text = text?;
+                if text.trim().is_empty() {
+                    continue;
+                }
+                let object = match serde_json::from_str(&text).map_err(|err| error(err.to_string()))? {
+                    Value::Object(object) => object,
+                    _ => return Err(error("not a JSON object".to_string())),
+                };
+                let key = read_member(&object, "key").map_err(error)?;
+                let value = read_member(&object, "value").map_err(error)?;
+                if !self.store_bytes(&key, &value, flag)? {
+                    return Err(error("key already exists".to_string()));
+                }
+                records += 1;
+            }
+            Ok(records)
+        })
+    }
+}
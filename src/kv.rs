@@ -0,0 +1,54 @@
+use {Gdbm, GdbmError};
+
+/// Iterator over the records of a `KvStore`
+pub type KvIter<'a> = Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>), GdbmError>> + 'a>;
+
+/// The basic operations of a key-value store, so that application code
+/// can be written against either a `Gdbm` or, in unit tests, the
+/// in-memory `testing::MemStore`.
+///
+/// The trait is object safe: take a `&dyn KvStore` to choose the store
+/// at run time.
+pub trait KvStore {
+    /// The value stored under `key`, if any
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, GdbmError>;
+
+    /// Store `value` under `key`, returning the value it replaced
+    fn insert(&self, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>, GdbmError>;
+
+    /// Delete `key`, returning the value it held
+    fn remove(&self, key: &[u8]) -> Result<Option<Vec<u8>>, GdbmError>;
+
+    /// Every record, in no particular order
+    fn iter(&self) -> KvIter<'_>;
+
+    /// Number of records
+    fn len(&self) -> Result<u64, GdbmError>;
+
+    /// True if the store holds no records
+    fn is_empty(&self) -> Result<bool, GdbmError> {
+        Ok(self.len()? == 0)
+    }
+}
+
+impl KvStore for Gdbm {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, GdbmError> {
+        self.fetch_data(key)
+    }
+
+    fn insert(&self, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>, GdbmError> {
+        Gdbm::insert(self, key, value)
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<Option<Vec<u8>>, GdbmError> {
+        Gdbm::remove(self, key)
+    }
+
+    fn iter(&self) -> KvIter<'_> {
+        Box::new(Gdbm::iter(self))
+    }
+
+    fn len(&self) -> Result<u64, GdbmError> {
+        self.count()
+    }
+}
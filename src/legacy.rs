@@ -0,0 +1,96 @@
+//! The original `Gdbm` API, kept signature for signature so existing code
+//! can move to a newer release of the crate by changing an import:
+//!
+//! ```no_run
+//! use gdbm::legacy::Gdbm;
+//! ```
+//!
+//! Each method is a thin adapter over `gdbm::Gdbm`, which is reachable
+//! through `Deref` so code can migrate one call at a time.
+
+use std::ops::{Deref, DerefMut};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+
+use {GdbmError, Open};
+
+/// A database handle with the original method signatures
+#[derive(Debug)]
+pub struct Gdbm {
+    inner: ::Gdbm,
+}
+
+impl Gdbm {
+    /// Open a DBM with location.
+    /// mode (see http://www.manpagez.com/man/2/chmod,
+    /// and http://www.manpagez.com/man/2/open), which is used if the file is created).
+    pub fn new(path: &Path, block_size: u32, flags: Open, mode: i32) -> Result<Gdbm, GdbmError> {
+        ::Gdbm::new(path, block_size, flags, mode).map(|inner| Gdbm { inner })
+    }
+
+    /// Store a record in the database.
+    ///
+    /// If `replace` is `false`, and the key already exists in the
+    /// database, the record is not stored and `false` is returned.
+    /// Otherwise `true` is returned.
+    pub fn store(&self, key: &str, content: &String, replace: bool) -> Result<bool, GdbmError> {
+        self.inner.store(key, content, replace)
+    }
+
+    /// Retrieve a key from the database
+    pub fn fetch(&self, key: &str) -> Result<String, GdbmError> {
+        self.inner.fetch(key)
+    }
+
+    /// Retrieve the record stored under `key` as raw bytes
+    pub fn fetch_data<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Vec<u8>>, GdbmError> {
+        self.inner.fetch_data(key)
+    }
+
+    /// Delete a key and value from the database
+    pub fn delete(&self, key: &str) -> bool {
+        self.inner.delete(key)
+    }
+
+    /// Flush pending changes to disk. Errors are ignored, as they always
+    /// were; use `gdbm::Gdbm::sync` to see them.
+    pub fn sync(&self) {
+        let _ = self.inner.sync();
+    }
+
+    /// Check to see if a key exists in the database
+    pub fn exists(&self, key: &str) -> Result<bool, GdbmError> {
+        self.inner.exists(key)
+    }
+
+    /// Unwrap the handle to use the current API
+    pub fn into_inner(self) -> ::Gdbm {
+        self.inner
+    }
+}
+
+impl From<::Gdbm> for Gdbm {
+    fn from(inner: ::Gdbm) -> Gdbm {
+        Gdbm { inner }
+    }
+}
+
+impl Deref for Gdbm {
+    type Target = ::Gdbm;
+
+    fn deref(&self) -> &::Gdbm {
+        &self.inner
+    }
+}
+
+impl DerefMut for Gdbm {
+    fn deref_mut(&mut self) -> &mut ::Gdbm {
+        &mut self.inner
+    }
+}
+
+impl AsRawFd for Gdbm {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
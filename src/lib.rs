@@ -1,21 +1,193 @@
+extern crate base64;
 #[macro_use]
 extern crate bitflags;
+#[cfg(feature = "typed")]
+extern crate bincode;
+#[cfg(feature = "encryption")]
+extern crate chacha20poly1305;
+#[cfg(feature = "cbor")]
+extern crate ciborium;
+extern crate crc32fast;
+#[cfg(feature = "async")]
+extern crate futures_core;
 extern crate gdbm_sys;
+extern crate globset;
+#[cfg(feature = "encryption")]
+extern crate getrandom;
 extern crate libc;
+#[cfg(feature = "compression")]
+extern crate lz4_flex;
+#[cfg(feature = "metrics")]
+extern crate metrics;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(feature = "regex")]
+extern crate regex;
+#[cfg(feature = "msgpack")]
+extern crate rmp_serde;
+#[cfg(feature = "sqlite")]
+extern crate rusqlite;
+#[cfg(feature = "typed")]
+extern crate serde;
+#[cfg(feature = "json")]
+#[macro_use]
+extern crate serde_json;
+#[cfg(feature = "hash")]
+extern crate sha2;
+#[cfg(feature = "encryption")]
+extern crate zeroize;
+
+mod actor;
+#[cfg(feature = "async")]
+mod async_gdbm;
+mod audit;
+mod batch;
+#[cfg(feature = "bdb")]
+mod bdb;
+mod blob;
+mod bucket;
+mod bulk;
+mod cancel;
+mod checksum;
+#[cfg(feature = "typed")]
+mod codec;
+mod compact;
+#[cfg(feature = "compression")]
+mod compress;
+mod convert;
+mod csv;
+mod cursor;
+mod detect;
+mod diff;
+mod dump;
+#[cfg(feature = "encryption")]
+mod encrypt;
+mod entry;
+mod ffi;
+mod foreign;
+mod glob;
+mod index;
+mod iter;
+#[cfg(feature = "json")]
+mod jsonl;
+mod kv;
+pub mod legacy;
+mod lock;
+mod map;
+mod merge;
+mod migrations;
+#[cfg(feature = "mmap")]
+mod mmap;
+mod model;
+mod op_stats;
+mod options;
+mod parallel;
+mod pool;
+mod prefix;
+mod progress;
+#[cfg(feature = "prometheus")]
+pub mod prometheus;
+mod registry;
+mod reorganize;
+#[cfg(feature = "json")]
+mod serializable;
+mod shared;
+mod sort;
+mod sorted_dump;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+pub mod stats;
+pub mod testing;
+mod ttl;
+mod trace;
+mod tune;
+#[cfg(feature = "typed")]
+mod typed;
+mod writeback;
+
+pub use actor::{GdbmWriterActor, Pending};
+#[cfg(feature = "async")]
+pub use async_gdbm::{AsyncGdbm, EntriesStream, NextEntry, Reply};
+pub use audit::{AuditFile, AuditOp, AuditRecord, AuditSink};
+pub use batch::{StoreStats, WriteBatch};
+#[cfg(feature = "bdb")]
+pub use bdb::{BdbHashFile, BdbRecords, OnConflict};
+pub use blob::{BlobReader, BlobWriter, BLOB_CHUNK_SIZE};
+pub use bucket::{Bucket, BucketIter, BucketKeys};
+pub use bulk::{BulkLoad, BulkLoadOptions, BULK_LOAD_CACHE_SIZE};
+pub use cancel::CancellationToken;
+pub use checksum::{ChecksummedGdbm, ChecksummedIter};
+#[cfg(feature = "typed")]
+pub use codec::{Bincode, Codec};
+#[cfg(feature = "cbor")]
+pub use codec::Cbor;
+#[cfg(feature = "json")]
+pub use codec::Json;
+#[cfg(feature = "msgpack")]
+pub use codec::MessagePack;
+#[cfg(feature = "compression")]
+pub use compress::{CompressedGdbm, CompressedIter, DEFAULT_MIN_COMPRESS_SIZE};
+pub use convert::DbFormat;
+pub use csv::{CsvOptions, Quoting};
+pub use cursor::{Cursor, IterFrom, Page};
+pub use detect::{detect_format, FileFormat};
+pub use diff::{diff, Diff, Difference};
+pub use dump::{DumpFormat, ExportMap, ImportFlag};
+#[cfg(feature = "encryption")]
+pub use encrypt::{EncryptedGdbm, EncryptedIter, KeyProvider, StaticKey};
+#[cfg(feature = "encryption")]
+pub use zeroize::Zeroizing;
+pub use entry::{Entry, OccupiedEntry, VacantEntry};
+pub use foreign::{ForeignGdbmFile, ForeignRecords};
+pub use glob::{Glob, KeysMatching};
+pub use index::SecondaryIndex;
+pub use iter::{Iter, Keys, StableKeys};
+pub use kv::{KvIter, KvStore};
+pub use lock::FileLock;
+pub use merge::{ConflictPolicy, Resolve};
+pub use migrations::{MigrationReport, Migrations, SCHEMA_VERSION_KEY};
+#[cfg(feature = "mmap")]
+pub use mmap::{GdbmReaderPure, PureEntries, PureKeys};
+pub use model::ModelReport;
+pub use op_stats::OpStats;
+pub use options::{OpenOptions, SEED_MARKER_KEY};
+pub use progress::{Progress, PROGRESS_INTERVAL};
+pub use pool::{GdbmPool, PooledGdbm};
+pub use reorganize::AutoReorganize;
+#[cfg(feature = "json")]
+pub use serializable::{BytesFormat, Serializable};
+pub use shared::{SharedGdbm, SyncPolicy};
+pub use sort::{SortOptions, SortedEntries};
+pub use sorted_dump::SORTED_DUMP_VERSION;
+pub use trace::{current_trace_id, with_trace_id};
+pub use ttl::{Sweeper, TtlGdbm};
+pub use tune::{recommended_block_size, ValueProfile};
+#[cfg(feature = "typed")]
+pub use typed::{TypedGdbm, TypedIter};
+pub use writeback::{FlushPolicy, WriteBackGdbm};
 
+use std::cmp::Ordering;
 use std::error::Error as StdError;
-use std::io::Error;
+use std::fs;
+use std::io::{Error, ErrorKind};
 use std::fmt;
-use std::ffi::{CStr, CString, IntoStringError, NulError};
+use std::ffi::{CStr, CString, IntoStringError, NulError, OsStr};
+use std::mem;
 use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
 use std::os::unix::io::{AsRawFd, RawFd};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::Mutex;
 use std::str::Utf8Error;
 use std::string::FromUtf8Error;
 
-use libc::{c_uint, c_void, free};
+use libc::{c_char, c_int, c_uint, c_void, free};
 
 use gdbm_sys::*;
+use ffi::{gdbm_count, gdbm_sync, gdbm_version_cmp};
+use migrations::{is_reserved_key, RESERVED_KEYS};
+use op_stats::Op;
 
 /// Custom error handling for the library
 #[derive(Debug)]
@@ -26,11 +198,57 @@ pub enum GdbmError {
     Error(String),
     IoError(Error),
     IntoStringError(IntoStringError),
+    /// Loading a dump file failed. `line` is the offending line of an
+    /// ASCII dump, or 0 if gdbm could not attribute the error to a line.
+    ImportError { line: u64, message: String },
+    /// A key or value is larger than gdbm can store, or than the limit
+    /// set with `Gdbm::set_max_value_size`.
+    TooLarge { what: &'static str, size: usize, limit: usize },
+    /// The database could not be opened because another handle holds its
+    /// lock, after `attempts` tries (see `OpenOptions::retry`).
+    LockContended { attempts: u32, message: String },
+    /// The operation was stopped through a `CancellationToken`
+    Cancelled,
+    /// A value read through `ChecksummedGdbm` does not match the checksum
+    /// stored with it
+    ChecksumMismatch { key: Vec<u8>, expected: u32, actual: u32 },
+    /// The file is a gdbm database written on a host with a different
+    /// byte order or `off_t` size, which gdbm cannot open here. See
+    /// `Gdbm::convert_offsets`.
+    ForeignFormat { path: PathBuf },
 }
 
 impl fmt::Display for GdbmError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self)
+        match *self {
+            GdbmError::FromUtf8Error(ref err) => write!(f, "{}", err.utf8_error()),
+            GdbmError::Utf8Error(ref err) => write!(f, "{}", err),
+            GdbmError::NulError(ref err) => write!(f, "{}", err),
+            GdbmError::Error(ref err) => write!(f, "{}", err),
+            GdbmError::IoError(ref err) => write!(f, "{}", err),
+            GdbmError::IntoStringError(ref err) => write!(f, "{}", err),
+            GdbmError::ImportError { line, ref message } => write!(f, "line {}: {}", line, message),
+            GdbmError::TooLarge { what, size, limit } => {
+                write!(f, "{} of {} bytes exceeds the limit of {} bytes", what, size, limit)
+            }
+            GdbmError::LockContended { attempts, ref message } => {
+                write!(f, "{} (gave up after {} attempt(s))", message, attempts)
+            }
+            GdbmError::Cancelled => write!(f, "operation cancelled"),
+            GdbmError::ChecksumMismatch { ref key, expected, actual } => {
+                write!(f,
+                       "checksum mismatch for key {:?}: stored {:08x}, computed {:08x}",
+                       String::from_utf8_lossy(key),
+                       expected,
+                       actual)
+            }
+            GdbmError::ForeignFormat { ref path } => {
+                write!(f,
+                       "{} was written on a host with a different byte order or offset size; \
+                        convert it with Gdbm::convert_offsets",
+                       path.display())
+            }
+        }
     }
 }
 
@@ -43,6 +261,12 @@ impl StdError for GdbmError {
             GdbmError::Error(ref _e) => "gdbm error",
             GdbmError::IoError(ref _e) => "I/O error",
             GdbmError::IntoStringError(ref _e) => "error",
+            GdbmError::ImportError { .. } => "dump import error",
+            GdbmError::TooLarge { .. } => "data too large",
+            GdbmError::LockContended { .. } => "database locked",
+            GdbmError::Cancelled => "operation cancelled",
+            GdbmError::ChecksumMismatch { .. } => "checksum mismatch",
+            GdbmError::ForeignFormat { .. } => "foreign database format",
         }
     }
     fn cause(&self) -> Option<&dyn StdError> {
@@ -53,6 +277,12 @@ impl StdError for GdbmError {
             GdbmError::Error(_) => None,
             GdbmError::IoError(ref e) => e.source(),
             GdbmError::IntoStringError(ref e) => e.source(),
+            GdbmError::ImportError { .. } => None,
+            GdbmError::TooLarge { .. } => None,
+            GdbmError::LockContended { .. } => None,
+            GdbmError::Cancelled => None,
+            GdbmError::ChecksumMismatch { .. } => None,
+            GdbmError::ForeignFormat { .. } => None,
         }
     }
 }
@@ -61,18 +291,6 @@ impl GdbmError {
     fn new(err: impl Into<String>) -> GdbmError {
         GdbmError::Error(err.into())
     }
-
-    /// Convert a GdbmError into a String representation.
-    pub fn to_string(&self) -> String {
-        match *self {
-            GdbmError::FromUtf8Error(ref err) => err.utf8_error().to_string(),
-            GdbmError::Utf8Error(ref err) => err.to_string(),
-            GdbmError::NulError(ref err) => err.to_string(),
-            GdbmError::Error(ref err) => err.to_string(),
-            GdbmError::IoError(ref err) => err.to_string(),
-            GdbmError::IntoStringError(ref err) => err.to_string(),
-        }
-    }
 }
 
 impl From<NulError> for GdbmError {
@@ -106,14 +324,84 @@ fn get_error() -> String {
     unsafe {
         let error_ptr = gdbm_strerror(*gdbm_errno_location());
         let err_string = CStr::from_ptr(error_ptr);
-        return err_string.to_string_lossy().into_owned();
+        err_string.to_string_lossy().into_owned()
     }
 }
 
-fn datum(what: &str, data: impl AsRef<[u8]>) -> Result<datum, GdbmError> {
+type FatalHandler = Box<dyn Fn(&str) + Send + Sync>;
+
+static FATAL_HANDLER: Mutex<Option<FatalHandler>> = Mutex::new(None);
+
+/// Register a closure that receives the messages of gdbm's fatal errors,
+/// for logging them.
+///
+/// A fatal error is one that leaves the database needing recovery, such
+/// as a failed read or write or a corrupt bucket. The handler is called
+/// with gdbm's message when a call on any handle fails with one, and the
+/// error is returned from the call as usual; later calls on that handle
+/// fail with "database needs recovery" and call the handler again.
+///
+/// This is not gdbm's own fatal function: gdbm exits the process once
+/// that returns, so the crate never installs one and gdbm reports fatal
+/// errors through the failing call instead. The handler is process wide.
+pub fn set_fatal_handler<F>(handler: F)
+    where F: Fn(&str) + Send + Sync + 'static
+{
+    let mut guard = FATAL_HANDLER.lock().unwrap_or_else(|e| e.into_inner());
+    *guard = Some(Box::new(handler));
+}
+
+/// Remove the handler registered with `set_fatal_handler`.
+pub fn clear_fatal_handler() {
+    let mut guard = FATAL_HANDLER.lock().unwrap_or_else(|e| e.into_inner());
+    *guard = None;
+}
+
+/// The gdbm error code of the last failed call on this thread
+fn error_code() -> gdbm_error {
+    unsafe { *gdbm_errno_location() }
+}
+
+/// Reset gdbm_errno, for calls where only errno tells "not found" and
+/// "failed" apart.
+fn clear_error() {
+    unsafe {
+        *gdbm_errno_location() = GDBM_NO_ERROR as gdbm_error;
+    }
+}
+
+/// Copy a datum returned by gdbm into a Vec and free gdbm's buffer.
+/// A NULL datum means the item was not found, unless gdbm_errno reports
+/// a different error, which is reported as an error of `db`.
+unsafe fn take_datum(db: &Gdbm, content: datum) -> Result<Option<Vec<u8>>, GdbmError> {
+    if content.dptr.is_null() {
+        return match *gdbm_errno_location() as c_uint {
+            GDBM_NO_ERROR | GDBM_ITEM_NOT_FOUND => Ok(None),
+            _ => Err(db.last_error()),
+        };
+    }
+    let data = if content.dsize < 0 {
+        Err(GdbmError::new("content has negative size"))
+    } else {
+        let ptr = content.dptr as *const u8;
+        Ok(Some(std::slice::from_raw_parts(ptr, content.dsize as usize).to_vec()))
+    };
+    // Free the malloc'd content that the library gave us
+    free(content.dptr as *mut c_void);
+    data
+}
+
+/// The largest key or value gdbm can represent in a datum
+pub const MAX_DATUM_SIZE: usize = i32::MAX as usize;
+
+fn datum(what: &'static str, data: impl AsRef<[u8]>) -> Result<datum, GdbmError> {
     let data = data.as_ref();
-    if data.len() > i32::MAX as usize {
-        return Err(GdbmError::new(format!("{} too large", what)));
+    if data.len() > MAX_DATUM_SIZE {
+        return Err(GdbmError::TooLarge {
+            what,
+            size: data.len(),
+            limit: MAX_DATUM_SIZE,
+        });
     }
     // Note that we cast data.as_ptr(), which is a *const u8, to
     // a *mut i8. This is an artefact of the gdbm C interface where
@@ -125,6 +413,25 @@ fn datum(what: &str, data: impl AsRef<[u8]>) -> Result<datum, GdbmError> {
     })
 }
 
+/// Version of the libgdbm in use as `(major, minor, patch)`, parsed from
+/// the `gdbm_version` string, e.g. "GDBM version 1.23. 04/02/2022".
+pub fn version() -> (u32, u32, u32) {
+    let version = unsafe { CStr::from_ptr(gdbm_version) }.to_string_lossy();
+    let numbers = version.split_whitespace()
+        .skip_while(|word| *word != "version")
+        .nth(1)
+        .unwrap_or("");
+    let mut parts = numbers.split('.').map(|part| part.parse::<u32>().unwrap_or(0));
+    (parts.next().unwrap_or(0), parts.next().unwrap_or(0), parts.next().unwrap_or(0))
+}
+
+/// Compare two `(major, minor, patch)` versions using `gdbm_version_cmp`.
+pub fn version_cmp(a: (u32, u32, u32), b: (u32, u32, u32)) -> Ordering {
+    let a = [a.0 as c_int, a.1 as c_int, a.2 as c_int];
+    let b = [b.0 as c_int, b.1 as c_int, b.2 as c_int];
+    unsafe { gdbm_version_cmp(a.as_ptr(), b.as_ptr()) }.cmp(&0)
+}
+
 bitflags! {
     pub struct Open: c_uint {
         /// Read only database access
@@ -143,6 +450,17 @@ bitflags! {
     }
 }
 
+/// What `Gdbm::store_checked` did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreOutcome {
+    /// There was no record under the key, one was added
+    Inserted,
+    /// The existing record under the key was overwritten
+    Replaced,
+    /// A record already existed under the key and was left untouched
+    AlreadyExists,
+}
+
 bitflags! {
     struct Store: c_uint {
         const INSERT  = 0;
@@ -150,15 +468,34 @@ bitflags! {
     }
 }
 
+/// How an open database handle is configured, as reported by gdbm itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DbInfo {
+    /// Path of the database file
+    pub name: PathBuf,
+    /// Block size the database was created with
+    pub block_size: u32,
+    /// Number of buckets held in the bucket cache
+    pub cache_size: usize,
+    /// Whether the file is accessed through a memory mapping
+    pub mmap: bool,
+    /// Upper limit on the size of the memory mapped region
+    pub max_map_size: usize,
+    /// Flags the database was opened with
+    pub flags: Open,
+}
+
 #[derive(Debug)]
 pub struct Gdbm {
-    db_handle: GDBM_FILE, /* int gdbm_export (GDBM_FILE, const char *, int, int);
-                           * int gdbm_export_to_file (GDBM_FILE dbf, FILE *fp);
-                           * int gdbm_import (GDBM_FILE, const char *, int);
-                           * int gdbm_import_from_file (GDBM_FILE dbf, FILE *fp, int flag);
-                           * int gdbm_count (GDBM_FILE dbf, gdbm_count_t *pcount);
-                           * int gdbm_version_cmp (int const a[], int const b[]);
-                           * */
+    db_handle: GDBM_FILE,
+    max_value_size: usize,
+    /// Held while open, if opened with `OpenOptions::exclusive_in_process`
+    writer_claim: Option<registry::WriterClaim>,
+    /// What to reopen, after `reopen` failed and left the handle closed
+    closed_info: Option<DbInfo>,
+    auto_reorganize: Option<reorganize::AutoReorganizeState>,
+    ops: op_stats::OpCounters,
+    audit: Option<audit::Audit>,
 }
 
 // Safety: Gdbm does have thread-local data, but it's only used to set
@@ -180,9 +517,14 @@ impl Drop for Gdbm {
 
 /// With locking disabled (if gdbm_open was called with ‘GDBM_NOLOCK’), the user may want
 /// to perform their own file locking on the database file in order to prevent multiple
-/// writers operating on the same file simultaneously.
+/// writers operating on the same file simultaneously. `Gdbm::lock_exclusive` and
+/// `Gdbm::lock_shared` do this with flock.
 impl AsRawFd for Gdbm {
     fn as_raw_fd(&self) -> RawFd {
+        if self.db_handle.is_null() {
+            // Closed by a failed `reopen`
+            return -1;
+        }
         unsafe {
             gdbm_fdesc(self.db_handle) as RawFd
         }
@@ -194,17 +536,29 @@ impl Gdbm {
     /// mode (see http://www.manpagez.com/man/2/chmod,
     /// and http://www.manpagez.com/man/2/open), which is used if the file is created).
     pub fn new(path: &Path, block_size: u32, flags: Open, mode: i32) -> Result<Gdbm, GdbmError> {
-        let path = CString::new(path.as_os_str().as_bytes())?;
+        let c_path = CString::new(path.as_os_str().as_bytes())?;
         unsafe {
-            let db_ptr = gdbm_open(path.as_ptr() as *mut i8,
+            let db_ptr = gdbm_open(c_path.as_ptr() as *mut i8,
                                    block_size as i32,
                                    flags.bits as i32,
                                    mode,
                                    None);
             if db_ptr.is_null() {
+                // Only reads the file, so gdbm_errno stays as gdbm_open left it
+                if let Ok(FileFormat::ForeignGdbm) = detect_format(path) {
+                    return Err(GdbmError::ForeignFormat { path: path.to_path_buf() });
+                }
                 return Err(GdbmError::new("gdbm_open failed".to_string()));
             }
-            Ok(Gdbm { db_handle: db_ptr })
+            Ok(Gdbm {
+                db_handle: db_ptr,
+                max_value_size: MAX_DATUM_SIZE,
+                writer_claim: None,
+                closed_info: None,
+                auto_reorganize: None,
+                ops: op_stats::OpCounters::default(),
+                audit: None,
+            })
         }
     }
 
@@ -214,114 +568,597 @@ impl Gdbm {
     /// database, the record is not stored and `false` is returned.
     /// Otherwise `true` is returned.
     pub fn store(&self, key: &str, content: &String, replace: bool) -> Result<bool, GdbmError> {
-        let key_datum = datum("key", key)?;
-        let content_datum = datum("content", content)?;
         let flag = if replace { Store::REPLACE } else { Store::INSERT };
-        let result = unsafe {
-            gdbm_store(self.db_handle, key_datum, content_datum, flag.bits as i32)
-        };
-        if result < 0 {
-            return Err(GdbmError::new(get_error()));
+        self.store_bytes(key.as_bytes(), content.as_bytes(), flag)
+    }
+
+    /// Store a record in the database and report what happened to any
+    /// record that already existed under `key`.
+    ///
+    /// With `replace` set an existing record is overwritten and
+    /// `StoreOutcome::Replaced` returned, otherwise it is left alone and
+    /// `StoreOutcome::AlreadyExists` returned.
+    pub fn store_checked<K, V>(&self, key: K, content: V, replace: bool) -> Result<StoreOutcome, GdbmError>
+        where K: AsRef<[u8]>,
+              V: AsRef<[u8]>
+    {
+        let key = key.as_ref();
+        if !replace {
+            if self.store_bytes(key, content.as_ref(), Store::INSERT)? {
+                return Ok(StoreOutcome::Inserted);
+            }
+            return Ok(StoreOutcome::AlreadyExists);
+        }
+        let existed = self.contains(key)?;
+        self.store_bytes(key, content.as_ref(), Store::REPLACE)?;
+        if existed {
+            Ok(StoreOutcome::Replaced)
+        } else {
+            Ok(StoreOutcome::Inserted)
         }
-        Ok(result == 0)
+    }
+
+    /// Refuse to store values larger than `limit` bytes, reporting them
+    /// as `GdbmError::TooLarge`. The limit cannot be raised beyond
+    /// `MAX_DATUM_SIZE`, which is also the default.
+    pub fn set_max_value_size(&mut self, limit: usize) {
+        self.max_value_size = limit.min(MAX_DATUM_SIZE);
+    }
+
+    /// The largest value this handle accepts, see `set_max_value_size`.
+    pub fn max_value_size(&self) -> usize {
+        self.max_value_size
+    }
+
+    /// Returns false if `flag` is INSERT and the key already exists.
+    fn store_bytes(&self, key: &[u8], content: &[u8], flag: Store) -> Result<bool, GdbmError> {
+        self.ops.count(Op::Store, || {
+            if content.len() > self.max_value_size {
+                return Err(GdbmError::TooLarge {
+                    what: "content",
+                    size: content.len(),
+                    limit: self.max_value_size,
+                });
+            }
+            let key_datum = datum("key", key)?;
+            let content_datum = datum("content", content)?;
+            let result = unsafe {
+                gdbm_store(self.handle()?, key_datum, content_datum, flag.bits as i32)
+            };
+            if result < 0 {
+                return Err(self.last_error());
+            }
+            if result == 0 {
+                self.wrote();
+                #[cfg(feature = "metrics")]
+                self.ops.value_size(Op::Store, content.len());
+                self.audit(AuditOp::Store, key, content.len())?;
+            }
+            Ok(result == 0)
+        })
     }
 
     /// Retrieve a key from the database
     pub fn fetch(&self, key: &str) -> Result<String, GdbmError> {
-        // datum gdbm_fetch(dbf, key);
-        let key_datum = datum("key", key)?;
-        unsafe {
-            let content = gdbm_fetch(self.db_handle, key_datum);
-            if content.dptr.is_null() {
-                return Err(GdbmError::new(get_error()));
-            } else if content.dsize < 0 {
-                return Err(GdbmError::new("content has negative size"));
-            } else {
-                // handle the data as an utf8 encoded string slice
-                // that may or may not be terminated by a \0 byte.
-                let ptr = content.dptr as *const u8;
-                let len = content.dsize as usize;
-                let mut slice = std::slice::from_raw_parts(ptr, len);
-                if len > 0 && slice[len - 1] == 0 {
-                    slice = &slice[0..len-1];
+        let content = self.with_fetched(key.as_bytes(), |content| {
+            // handle the data as an utf8 encoded string slice
+            // that may or may not be terminated by a \0 byte.
+            content.map(|slice| match slice.split_last() {
+                Some((&0, rest)) => std::str::from_utf8(rest).map(str::to_string),
+                _ => std::str::from_utf8(slice).map(str::to_string),
+            })
+        })?;
+        match content {
+            Some(content) => Ok(content?),
+            // gdbm_errno still says why there was no record
+            None => Err(GdbmError::new(get_error())),
+        }
+    }
+
+    /// Retrieve the record stored under `key` as raw bytes, or None if
+    /// there is no such key. Unlike `fetch` the data is returned exactly
+    /// as stored.
+    pub fn fetch_data<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Vec<u8>>, GdbmError> {
+        self.fetch_bytes(key.as_ref())
+    }
+
+    /// Store `value` under `key`, replacing any existing record, and
+    /// return the value that was replaced.
+    ///
+    /// This is a fetch followed by a store. gdbm's file lock keeps other
+    /// processes from writing in between, but a database opened with
+    /// `NOLOCK` has no such protection unless the caller locks it.
+    pub fn insert<K, V>(&self, key: K, value: V) -> Result<Option<Vec<u8>>, GdbmError>
+        where K: AsRef<[u8]>,
+              V: AsRef<[u8]>
+    {
+        let key = key.as_ref();
+        let old = self.fetch_bytes(key)?;
+        self.store_bytes(key, value.as_ref(), Store::REPLACE)?;
+        Ok(old)
+    }
+
+    /// Delete the record under `key` and return its value, or None if
+    /// there was no such record.
+    ///
+    /// The same locking caveats as for `insert` apply.
+    pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Vec<u8>>, GdbmError> {
+        let key = key.as_ref();
+        let old = self.fetch_bytes(key)?;
+        if old.is_some() {
+            self.delete_bytes(key)?;
+        }
+        Ok(old)
+    }
+
+    /// Read the record under `key`, pass it to `f` and write back what
+    /// `f` returns: `Some` stores the new value, `None` deletes the
+    /// record. Returns the previous value.
+    ///
+    /// The same locking caveats as for `insert` apply.
+    pub fn fetch_update<K, F>(&self, key: K, f: F) -> Result<Option<Vec<u8>>, GdbmError>
+        where K: AsRef<[u8]>,
+              F: FnOnce(Option<&[u8]>) -> Option<Vec<u8>>
+    {
+        let key = key.as_ref();
+        let old = self.fetch_bytes(key)?;
+        match f(old.as_ref().map(|v| &v[..])) {
+            Some(new) => {
+                self.store_bytes(key, &new, Store::REPLACE)?;
+            }
+            None => {
+                if old.is_some() {
+                    self.delete_bytes(key)?;
                 }
-                let res = match std::str::from_utf8(slice) {
-                    Ok(s) => Ok(s.to_string()),
-                    Err(e) => Err(e.into()),
-                };
+            }
+        }
+        Ok(old)
+    }
+
+    /// Replace the record under `key` with `new` if its current value is
+    /// `expected`. `None` stands for "no record" on both sides, so this
+    /// can also create or delete records conditionally.
+    ///
+    /// Returns `Ok(Ok(()))` if the swap happened and `Ok(Err(current))`
+    /// with the actual value if it did not. The check and the write are
+    /// only atomic against other processes while the database is locked;
+    /// with `NOLOCK` the callers must hold an external lock.
+    pub fn compare_and_swap<K>(&self,
+                               key: K,
+                               expected: Option<&[u8]>,
+                               new: Option<&[u8]>)
+                               -> Result<Result<(), Option<Vec<u8>>>, GdbmError>
+        where K: AsRef<[u8]>
+    {
+        let key = key.as_ref();
+        let current = self.fetch_bytes(key)?;
+        if current.as_ref().map(|v| &v[..]) != expected {
+            return Ok(Err(current));
+        }
+        match new {
+            Some(new) => {
+                self.store_bytes(key, new, Store::REPLACE)?;
+            }
+            None => {
+                if current.is_some() {
+                    self.delete_bytes(key)?;
+                }
+            }
+        }
+        Ok(Ok(()))
+    }
+
+    /// Append `data` to the record under `key`, creating it if needed,
+    /// and return the new length of the value.
+    ///
+    /// The existing value is copied once, straight out of gdbm's buffer
+    /// into the buffer that gets stored.
+    pub fn append<K, V>(&self, key: K, data: V) -> Result<usize, GdbmError>
+        where K: AsRef<[u8]>,
+              V: AsRef<[u8]>
+    {
+        let key = key.as_ref();
+        let data = data.as_ref();
+        let value = self.with_fetched(key, |old| {
+            let old = old.unwrap_or(&[]);
+            let mut value = Vec::with_capacity(old.len() + data.len());
+            value.extend_from_slice(old);
+            value.extend_from_slice(data);
+            value
+        })?;
+        self.store_bytes(key, &value, Store::REPLACE)?;
+        Ok(value.len())
+    }
+
+    /// Copy the record under `src` to `dst`.
+    ///
+    /// If `dst` already has a record it is only replaced when `overwrite`
+    /// is set; otherwise nothing is written and `false` is returned. A
+    /// missing `src` record is an error.
+    pub fn copy_key<K1, K2>(&self, src: K1, dst: K2, overwrite: bool) -> Result<bool, GdbmError>
+        where K1: AsRef<[u8]>,
+              K2: AsRef<[u8]>
+    {
+        let value = self.fetch_bytes(src.as_ref())?
+            .ok_or_else(|| GdbmError::new("no record under the source key"))?;
+        let flag = if overwrite { Store::REPLACE } else { Store::INSERT };
+        self.store_bytes(dst.as_ref(), &value, flag)
+    }
 
-                // Free the malloc'd content that the library gave us
-                // Rust will manage this memory
+    /// Move the record under `old` to `new`, with the same rules for an
+    /// existing `new` record as `copy_key`.
+    ///
+    /// The record is written under `new` before `old` is deleted, so an
+    /// interruption can leave both keys behind but never neither. Renaming
+    /// a key to itself changes nothing and, as `new` already exists,
+    /// returns `overwrite`.
+    pub fn rename_key<K1, K2>(&self, old: K1, new: K2, overwrite: bool) -> Result<bool, GdbmError>
+        where K1: AsRef<[u8]>,
+              K2: AsRef<[u8]>
+    {
+        let (old, new) = (old.as_ref(), new.as_ref());
+        if old == new {
+            if !self.contains(old)? {
+                return Err(GdbmError::new("no record under the source key"));
+            }
+            return Ok(overwrite);
+        }
+        if !self.copy_key(old, new, overwrite)? {
+            return Ok(false);
+        }
+        self.delete_bytes(old)?;
+        Ok(true)
+    }
+
+    /// Add `delta` to the counter stored under `key` and return the new
+    /// value. A missing record counts as 0.
+    ///
+    /// Counters are stored as 8 byte little-endian `i64` values; any other
+    /// value under `key`, or an overflow, is an error and leaves the
+    /// record unchanged.
+    pub fn incr<K: AsRef<[u8]>>(&self, key: K, delta: i64) -> Result<i64, GdbmError> {
+        let key = key.as_ref();
+        let current = self.with_fetched(key, |value| match value {
+            None => Ok(0),
+            Some(bytes) if bytes.len() == 8 => {
+                let mut counter = [0; 8];
+                counter.copy_from_slice(bytes);
+                Ok(i64::from_le_bytes(counter))
+            }
+            Some(bytes) => {
+                Err(GdbmError::new(format!("value of {} bytes is not a counter", bytes.len())))
+            }
+        })??;
+        let new = current.checked_add(delta)
+            .ok_or_else(|| GdbmError::new("counter overflow"))?;
+        self.store_bytes(key, &new.to_le_bytes(), Store::REPLACE)?;
+        Ok(new)
+    }
+
+    /// Subtract `delta` from the counter stored under `key`, see `incr`.
+    pub fn decr<K: AsRef<[u8]>>(&self, key: K, delta: i64) -> Result<i64, GdbmError> {
+        let delta = delta.checked_neg()
+            .ok_or_else(|| GdbmError::new("counter overflow"))?;
+        self.incr(key, delta)
+    }
+
+    /// Run `f` on the record under `key` while it is still in the buffer
+    /// gdbm returned, avoiding a copy when the caller only needs to look.
+    fn with_fetched<R, F>(&self, key: &[u8], f: F) -> Result<R, GdbmError>
+        where F: FnOnce(Option<&[u8]>) -> R
+    {
+        self.ops.count(Op::Fetch, || {
+            let key_datum = datum("key", key)?;
+            clear_error();
+            unsafe {
+                let content = gdbm_fetch(self.handle()?, key_datum);
+                if content.dptr.is_null() {
+                    return match *gdbm_errno_location() as c_uint {
+                        GDBM_NO_ERROR | GDBM_ITEM_NOT_FOUND => {
+                            self.ops.miss();
+                            Ok(f(None))
+                        }
+                        _ => Err(self.last_error()),
+                    };
+                }
+                let result = if content.dsize < 0 {
+                    Err(GdbmError::new("content has negative size"))
+                } else {
+                    #[cfg(feature = "metrics")]
+                    self.ops.value_size(Op::Fetch, content.dsize as usize);
+                    let ptr = content.dptr as *const u8;
+                    Ok(f(Some(std::slice::from_raw_parts(ptr, content.dsize as usize))))
+                };
                 free(content.dptr as *mut c_void);
+                result
+            }
+        })
+    }
 
-                return res;
+    /// Retrieve a record as raw bytes, None if there is no such key.
+    fn fetch_bytes(&self, key: &[u8]) -> Result<Option<Vec<u8>>, GdbmError> {
+        self.with_fetched(key, |value| value.map(|v| v.to_vec()))
+    }
+
+    /// First key in gdbm's traversal order, None if the database is empty.
+    ///
+    /// Together with `next_key` this is the traversal `keys` is built on,
+    /// for walks the iterators don't cover. The order is that of gdbm's
+    /// hash table and changes as records are stored and deleted. The
+    /// crate's bookkeeping records, `SCHEMA_VERSION_KEY` and
+    /// `SEED_MARKER_KEY`, are skipped here as in every iterator and count.
+    pub fn first_key(&self) -> Result<Option<Vec<u8>>, GdbmError> {
+        self.first_key_bytes()
+    }
+
+    /// Key following `after` in gdbm's traversal order, None at the end.
+    /// Returns None as well if `after` has no record.
+    pub fn next_key<K: AsRef<[u8]>>(&self, after: K) -> Result<Option<Vec<u8>>, GdbmError> {
+        self.next_key_bytes(after.as_ref())
+    }
+
+    /// First key in gdbm's traversal order, skipping the crate's
+    /// bookkeeping records.
+    fn first_key_bytes(&self) -> Result<Option<Vec<u8>>, GdbmError> {
+        clear_error();
+        let first = unsafe { take_datum(self, gdbm_firstkey(self.handle()?)) }?;
+        self.skip_reserved(first)
+    }
+
+    /// Key following `key` in gdbm's traversal order, None at the end,
+    /// skipping the crate's bookkeeping records.
+    fn next_key_bytes(&self, key: &[u8]) -> Result<Option<Vec<u8>>, GdbmError> {
+        let next = self.raw_next_key(key)?;
+        self.skip_reserved(next)
+    }
+
+    fn raw_next_key(&self, key: &[u8]) -> Result<Option<Vec<u8>>, GdbmError> {
+        let key_datum = datum("key", key)?;
+        clear_error();
+        unsafe { take_datum(self, gdbm_nextkey(self.handle()?, key_datum)) }
+    }
+
+    /// `key`, or the first key after it that is not a bookkeeping record
+    fn skip_reserved(&self, mut key: Option<Vec<u8>>) -> Result<Option<Vec<u8>>, GdbmError> {
+        while let Some(current) = key {
+            if !is_reserved_key(&current) {
+                return Ok(Some(current));
             }
+            key = self.raw_next_key(&current)?;
         }
+        Ok(None)
     }
 
     /// Delete a key and value from the database
     pub fn delete(&self, key: &str) -> bool {
-        let key_datum = match datum("key", key) {
-            Ok(d) => d,
-            Err(_) => return false,
-        };
-        unsafe {
-            let result = gdbm_delete(self.db_handle, key_datum);
-            if result == -1 { false } else { true }
-        }
+        self.delete_bytes(key.as_bytes()).unwrap_or(false)
     }
-    // TODO: Make an iterator out of this to hide the datum handling
-    // pub fn firstkey(&self, key: &str) -> Result<String, GdbmError> {
-    // unsafe {
-    // let content = gdbm_firstkey(self.db_handle);
-    // if content.dptr.is_null() {
-    // return Err(GdbmError::new(get_error()));
-    // } else {
-    // let c_string = CStr::from_ptr(content.dptr);
-    // let data = c_string.to_str()?.to_string();
-    // Free the malloc'd content that the library gave us
-    // Rust will manage this memory
-    // free(content.dptr as *mut c_void);
-    //
-    // return Ok(data);
-    // }
-    // }
-    // }
-    // pub fn nextkey(&self, key: &str) -> Result<String, GdbmError> {
-    // unsafe {
-    // datum gdbm_nextkey(dbf, key);
-    //
-    // }
-    // }
-    //
-    // int gdbm_reorganize(dbf);
-    pub fn sync(&self) {
-        unsafe {
-            gdbm_sync(self.db_handle);
+
+    /// Delete a record, returning false if there was no such key.
+    fn delete_bytes(&self, key: &[u8]) -> Result<bool, GdbmError> {
+        self.ops.count(Op::Delete, || {
+            let key_datum = datum("key", key)?;
+            clear_error();
+            if unsafe { gdbm_delete(self.handle()?, key_datum) } == 0 {
+                self.wrote();
+                self.audit(AuditOp::Delete, key, 0)?;
+                return Ok(true);
+            }
+            match unsafe { *gdbm_errno_location() } as c_uint {
+                GDBM_ITEM_NOT_FOUND => {
+                    self.ops.miss();
+                    Ok(false)
+                }
+                _ => Err(self.last_error()),
+            }
+        })
+    }
+
+    /// Flush all pending changes to disk.
+    ///
+    /// Errors from writing or syncing the file (ENOSPC, EIO, ...) are
+    /// returned rather than ignored.
+    pub fn sync(&self) -> Result<(), GdbmError> {
+        let result = unsafe { gdbm_sync(self.handle()?) };
+        if result != 0 {
+            return Err(self.last_error());
         }
+        Ok(())
     }
 
     /// Check to see if a key exists in the database
     pub fn exists(&self, key: &str) -> Result<bool, GdbmError> {
+        self.contains(key.as_bytes())
+    }
+
+    fn contains(&self, key: &[u8]) -> Result<bool, GdbmError> {
         let key_datum = datum("key", key)?;
+        // gdbm_exists returns 0 both for a missing key and on error,
+        // so errno has to be looked at, and must not be stale.
+        clear_error();
         unsafe {
-            let result = gdbm_exists(self.db_handle, key_datum);
-            if result == 0 {
-                Ok(true)
-            } else {
-                if *gdbm_errno_location() == 0 {
-                    return Ok(true);
-                } else {
-                    return Err(GdbmError::new(get_error()));
+            if gdbm_exists(self.handle()?, key_datum) != 0 {
+                return Ok(true);
+            }
+            match *gdbm_errno_location() as c_uint {
+                GDBM_NO_ERROR | GDBM_ITEM_NOT_FOUND => Ok(false),
+                _ => Err(self.last_error()),
+            }
+        }
+    }
+
+    /// Number of records in the database, not counting the crate's
+    /// bookkeeping records
+    fn count(&self) -> Result<u64, GdbmError> {
+        let mut count = 0;
+        let result = unsafe { gdbm_count(self.handle()?, &mut count) };
+        if result != 0 {
+            return Err(self.last_error());
+        }
+        for key in &RESERVED_KEYS {
+            if self.contains(key)? {
+                count = count.saturating_sub(1);
+            }
+        }
+        Ok(count)
+    }
+
+    /// Report how this handle is configured: file name, block size,
+    /// cache and mmap settings and the flags it was opened with.
+    pub fn info(&self) -> Result<DbInfo, GdbmError> {
+        let name = self.name()?;
+        let block_size: c_int = self.getopt(ffi::GDBM_GETBLOCKSIZE, 0)?;
+        let cache_size: usize = self.getopt(ffi::GDBM_GETCACHESIZE, 0)?;
+        let mmap: c_int = self.getopt(ffi::GDBM_GETMMAP, 0)?;
+        let max_map_size: usize = self.getopt(ffi::GDBM_GETMAXMAPSIZE, 0)?;
+        let flags: c_int = self.getopt(ffi::GDBM_GETFLAGS, 0)?;
+        Ok(DbInfo {
+            name,
+            block_size: block_size as u32,
+            cache_size,
+            mmap: mmap != 0,
+            max_map_size,
+            flags: Open::from_bits_truncate(flags as c_uint),
+        })
+    }
+
+    /// The error of the last failed gdbm call on this handle. If it left
+    /// the database needing recovery, the `set_fatal_handler` handler is
+    /// told first.
+    fn last_error(&self) -> GdbmError {
+        let message = get_error();
+        if !self.db_handle.is_null() && unsafe { ffi::gdbm_needs_recovery(self.db_handle) } != 0 {
+            let guard = FATAL_HANDLER.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(ref handler) = *guard {
+                handler(&message);
+            }
+        }
+        GdbmError::new(message)
+    }
+
+    /// The gdbm handle, unless a failed `reopen` left the database closed
+    fn handle(&self) -> Result<GDBM_FILE, GdbmError> {
+        if self.db_handle.is_null() {
+            return Err(GdbmError::new("database is closed"));
+        }
+        Ok(self.db_handle)
+    }
+
+    /// Close the database and open the same path again, with the same
+    /// flags and limits, for instance after `is_stale` reports that the
+    /// file was replaced or after a fatal error. A database created with
+    /// `NEWDB` or `WRCREAT` is reopened as `WRITER`. Cache and sync
+    /// settings changed after the open are not carried over.
+    ///
+    /// If the database is opened for writing and is still the same file,
+    /// the new handle cannot be opened while the old one holds the lock,
+    /// so the old one is closed first. If the open then fails the handle
+    /// stays closed and every call on it returns an error until a later
+    /// `reopen` succeeds. If the path is locked by another handle, because
+    /// the file was replaced and someone else opened the new one, the
+    /// reopen fails with `GdbmError::LockContended`. In every other case
+    /// a failed reopen leaves the old handle open.
+    pub fn reopen(&mut self) -> Result<(), GdbmError> {
+        let info = match self.closed_info.take() {
+            Some(info) => info,
+            None => self.info()?,
+        };
+        let access = Open::NEWDB.bits;
+        let mut flags = info.flags;
+        if flags.bits & access != Open::READER.bits {
+            flags = Open::from_bits_truncate((flags.bits & !access) | Open::WRITER.bits);
+        }
+        let mut reopened = match Gdbm::new(&info.name, 0, flags, 0) {
+            Ok(db) => db,
+            Err(err) => {
+                let code = error_code() as u32;
+                if code != GDBM_CANT_BE_WRITER && code != GDBM_CANT_BE_READER {
+                    return Err(err);
+                }
+                // Only our own lock is worth closing the handle for
+                if self.db_handle.is_null() || self.is_stale()? {
+                    return Err(GdbmError::LockContended {
+                        attempts: 1,
+                        message: err.to_string(),
+                    });
+                }
+                unsafe {
+                    gdbm_close(self.db_handle);
+                }
+                self.db_handle = ptr::null_mut();
+                match Gdbm::new(&info.name, 0, flags, 0) {
+                    Ok(db) => db,
+                    Err(err) => {
+                        self.closed_info = Some(info);
+                        return Err(err);
+                    }
                 }
             }
+        };
+        // The old handle, if still open, is closed when `reopened` drops
+        mem::swap(&mut self.db_handle, &mut reopened.db_handle);
+        Ok(())
+    }
+
+    /// The path the database was opened with
+    fn name(&self) -> Result<PathBuf, GdbmError> {
+        unsafe {
+            let name_ptr: *mut c_char = self.getopt(ffi::GDBM_GETDBNAME, ptr::null_mut())?;
+            let name = PathBuf::from(OsStr::from_bytes(CStr::from_ptr(name_ptr).to_bytes()));
+            // GDBM_GETDBNAME hands us a malloc'd copy of the name
+            free(name_ptr as *mut c_void);
+            Ok(name)
+        }
+    }
+
+    /// True if the path the database was opened with no longer leads to
+    /// the open file, because the file was deleted or another file was
+    /// renamed into its place. The handle keeps reading the old file;
+    /// reopen the path to see the new one.
+    ///
+    /// A relative path is looked up from the current directory, so this
+    /// is only meaningful if the directory has not changed since the
+    /// open.
+    pub fn is_stale(&self) -> Result<bool, GdbmError> {
+        let mut open: libc::stat = unsafe { mem::zeroed() };
+        if unsafe { libc::fstat(self.as_raw_fd(), &mut open) } != 0 {
+            return Err(Error::last_os_error().into());
+        }
+        let current = match fs::metadata(self.name()?) {
+            Ok(metadata) => metadata,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(true),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(current.dev() != open.st_dev as u64 || current.ino() != open.st_ino as u64)
+    }
+
+    /// Set a gdbm option. `value` must have the type gdbm expects for
+    /// `option`.
+    fn setopt<T>(&self, option: c_int, mut value: T) -> Result<(), GdbmError> {
+        let result = unsafe {
+            gdbm_setopt(self.handle()?,
+                        option,
+                        &mut value as *mut T as *mut c_int,
+                        mem::size_of::<T>() as c_int)
+        };
+        if result != 0 {
+            return Err(self.last_error());
+        }
+        Ok(())
+    }
+
+    /// Query a gdbm option. `value` is the initial value of the buffer
+    /// gdbm writes into and must have the type gdbm expects for `option`.
+    fn getopt<T>(&self, option: c_int, mut value: T) -> Result<T, GdbmError> {
+        let result = unsafe {
+            gdbm_setopt(self.handle()?,
+                        option,
+                        &mut value as *mut T as *mut c_int,
+                        mem::size_of::<T>() as c_int)
+        };
+        if result != 0 {
+            return Err(self.last_error());
         }
+        Ok(value)
     }
-    // pub fn setopt(&self, key: &str) -> Result<(), GdbmError> {
-    // unsafe {
-    // int gdbm_setopt(dbf, option, value, size);
-    //
-    // }
-    // }
-    //
 }
@@ -0,0 +1,93 @@
+use std::io;
+use std::ops::Deref;
+use std::os::unix::io::AsRawFd;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libc::{flock, EINTR, EWOULDBLOCK, LOCK_EX, LOCK_NB, LOCK_SH, LOCK_UN};
+
+use {Gdbm, GdbmError, Open};
+
+/// Longest pause between attempts to take a contended lock
+const MAX_LOCK_BACKOFF: Duration = Duration::from_millis(50);
+
+/// An advisory lock on a `NOLOCK` database, released on drop.
+///
+/// Returned by `Gdbm::lock_exclusive` and `Gdbm::lock_shared`. The guard
+/// dereferences to the handle, so code that must only write under the
+/// lock can take a `&FileLock` instead of a `&Gdbm`.
+#[derive(Debug)]
+pub struct FileLock<'a> {
+    db: &'a Gdbm,
+    exclusive: bool,
+}
+
+impl Gdbm {
+    /// Take an exclusive flock on the database file, for writing. Waits
+    /// up to `timeout` while other holders release it.
+    ///
+    /// Only for databases opened with `NOLOCK`: gdbm's own locking uses
+    /// the same file descriptor, and unlocking it here would drop gdbm's
+    /// lock too. The lock only excludes other processes, or other handles
+    /// to the same file, that take it as well.
+    pub fn lock_exclusive(&self, timeout: Duration) -> Result<FileLock<'_>, GdbmError> {
+        self.file_lock(LOCK_EX, timeout)
+    }
+
+    /// Take a shared flock on the database file, for reading. Otherwise
+    /// the same as `lock_exclusive`.
+    pub fn lock_shared(&self, timeout: Duration) -> Result<FileLock<'_>, GdbmError> {
+        self.file_lock(LOCK_SH, timeout)
+    }
+
+    fn file_lock(&self, operation: i32, timeout: Duration) -> Result<FileLock<'_>, GdbmError> {
+        if !self.info()?.flags.contains(Open::NOLOCK) {
+            return Err(GdbmError::new("file locks need a database opened with NOLOCK"));
+        }
+        let deadline = Instant::now() + timeout;
+        let mut backoff = Duration::from_millis(1);
+        loop {
+            if unsafe { flock(self.as_raw_fd(), operation | LOCK_NB) } == 0 {
+                return Ok(FileLock {
+                    db: self,
+                    exclusive: operation == LOCK_EX,
+                });
+            }
+            let err = io::Error::last_os_error();
+            match err.raw_os_error() {
+                Some(EWOULDBLOCK) => {}
+                Some(EINTR) => continue,
+                _ => return Err(err.into()),
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(GdbmError::new("timed out waiting for the database file lock"));
+            }
+            thread::sleep(backoff.min(deadline - now));
+            backoff = (backoff * 2).min(MAX_LOCK_BACKOFF);
+        }
+    }
+}
+
+impl<'a> FileLock<'a> {
+    /// True for a lock taken with `lock_exclusive`
+    pub fn is_exclusive(&self) -> bool {
+        self.exclusive
+    }
+}
+
+impl<'a> Deref for FileLock<'a> {
+    type Target = Gdbm;
+
+    fn deref(&self) -> &Gdbm {
+        self.db
+    }
+}
+
+impl<'a> Drop for FileLock<'a> {
+    fn drop(&mut self) {
+        unsafe {
+            flock(self.db.as_raw_fd(), LOCK_UN);
+        }
+    }
+}
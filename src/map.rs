@@ -0,0 +1,39 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+use {Gdbm, GdbmError, OpenOptions};
+
+type Map = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// In-memory snapshots of a database, for tests and small databases.
+///
+/// These hold every key and value in memory at once, roughly the size of
+/// the database file, so they are no way to process a large database;
+/// iterate over it instead.
+impl Gdbm {
+    /// Every record of the database, ordered by key
+    pub fn to_map(&self) -> Result<Map, GdbmError> {
+        self.iter().collect()
+    }
+
+    /// Every record of the database, in a `HashMap`
+    pub fn to_hash_map(&self) -> Result<HashMap<Vec<u8>, Vec<u8>>, GdbmError> {
+        self.iter().collect()
+    }
+
+    /// Open the database at `path` with `options`, which must allow
+    /// writing, and make its contents exactly the records of `map`: any
+    /// records it already had are deleted first. Takes a reference to a
+    /// `BTreeMap` or `HashMap` with byte-like keys and values, or any other
+    /// source of pairs.
+    pub fn from_map<M, K, V>(path: &Path, map: M, options: &OpenOptions) -> Result<Gdbm, GdbmError>
+        where M: IntoIterator<Item = (K, V)>,
+              K: AsRef<[u8]>,
+              V: AsRef<[u8]>
+    {
+        let db = options.open(path)?;
+        db.clear()?;
+        db.store_many(map, true)?;
+        Ok(db)
+    }
+}
@@ -0,0 +1,81 @@
+use std::fmt;
+use std::sync::Arc;
+
+use {Gdbm, GdbmError, Store, StoreStats};
+
+type ResolveFn = dyn Fn(&[u8], &[u8], &[u8]) -> Option<Vec<u8>> + Send + Sync;
+
+/// The function of a `ConflictPolicy::Callback`
+#[derive(Clone)]
+pub struct Resolve(Arc<ResolveFn>);
+
+impl fmt::Debug for Resolve {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Resolve")
+    }
+}
+
+/// What `Gdbm::merge_from` does with a key both databases hold with
+/// different values
+#[derive(Debug, Clone)]
+pub enum ConflictPolicy {
+    /// Keep the record of the database merged into
+    KeepMine,
+    /// Overwrite it with the record of the other database
+    TakeTheirs,
+    /// Decide per key; built with `ConflictPolicy::callback`
+    Callback(Resolve),
+}
+
+impl ConflictPolicy {
+    /// Call `resolve` with the key, our value and theirs, and store the
+    /// value it returns, or keep ours if it returns None
+    pub fn callback<F>(resolve: F) -> ConflictPolicy
+        where F: Fn(&[u8], &[u8], &[u8]) -> Option<Vec<u8>> + Send + Sync + 'static
+    {
+        ConflictPolicy::Callback(Resolve(Arc::new(resolve)))
+    }
+}
+
+impl Gdbm {
+    /// Copy every record of `other` into this database, settling keys
+    /// both hold with `policy`, and sync once at the end.
+    ///
+    /// In the returned counts, `replaced` are the records overwritten and
+    /// `skipped` the conflicts where ours was kept. Keys both databases
+    /// hold with the same value count as skipped and never reach a
+    /// callback. Stops at the first error; records merged up to then stay.
+    pub fn merge_from(&self, other: &Gdbm, policy: ConflictPolicy) -> Result<StoreStats, GdbmError> {
+        self.with_deferred_sync(|| {
+            let mut stats = StoreStats::default();
+            for record in other.iter() {
+                let (key, theirs) = record?;
+                let mine = match self.fetch_bytes(&key)? {
+                    Some(mine) => mine,
+                    None => {
+                        self.store_bytes(&key, &theirs, Store::INSERT)?;
+                        stats.inserted += 1;
+                        continue;
+                    }
+                };
+                let merged = if mine == theirs {
+                    None
+                } else {
+                    match policy {
+                        ConflictPolicy::KeepMine => None,
+                        ConflictPolicy::TakeTheirs => Some(theirs),
+                        ConflictPolicy::Callback(Resolve(ref resolve)) => resolve(&key, &mine, &theirs),
+                    }
+                };
+                match merged {
+                    Some(ref value) if *value != mine => {
+                        self.store_bytes(&key, value, Store::REPLACE)?;
+                        stats.replaced += 1;
+                    }
+                    _ => stats.skipped += 1,
+                }
+            }
+            Ok(stats)
+        })
+    }
+}
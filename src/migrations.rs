@@ -0,0 +1,213 @@
+use std::fmt;
+use std::str;
+use std::sync::Arc;
+
+use blob::CHUNK_KEY_PREFIX;
+use options::SEED_MARKER_KEY;
+use progress::PROGRESS_INTERVAL;
+use {Gdbm, GdbmError, Progress, Store};
+
+/// Key of the record holding the schema version, see `Migrations`
+pub const SCHEMA_VERSION_KEY: &[u8] = b"\0gdbm-rs:schema-version";
+
+/// Keys starting with this are the crate's own bookkeeping records and
+/// are never passed to a migration
+const RESERVED_KEY_PREFIX: &[u8] = b"\0gdbm-rs:";
+
+/// The bookkeeping records the crate writes under `RESERVED_KEY_PREFIX`
+pub(crate) const RESERVED_KEYS: [&[u8]; 2] = [SCHEMA_VERSION_KEY, SEED_MARKER_KEY];
+
+/// Whether `key` is one of the crate's bookkeeping records, which
+/// iteration, counting and `clear` leave out. Blob chunks share the
+/// prefix but hold the data of the blobs, so they are not.
+pub(crate) fn is_reserved_key(key: &[u8]) -> bool {
+    key.starts_with(RESERVED_KEY_PREFIX) && !key.starts_with(CHUNK_KEY_PREFIX)
+}
+
+/// Copy the bookkeeping records `from` has into `to`, for copies of a
+/// whole database, which iterating over `from` would leave them out of
+pub(crate) fn copy_reserved(from: &Gdbm, to: &Gdbm) -> Result<(), GdbmError> {
+    for key in RESERVED_KEYS.iter() {
+        if let Some(value) = from.fetch_data(key)? {
+            to.store_bytes(key, &value, Store::REPLACE)?;
+        }
+    }
+    Ok(())
+}
+
+type MigrateFn = dyn Fn(&[u8], Vec<u8>) -> Result<Option<Vec<u8>>, GdbmError> + Send + Sync;
+
+#[derive(Clone)]
+struct Migration(Arc<MigrateFn>);
+
+impl fmt::Debug for Migration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Migration")
+    }
+}
+
+/// An ordered list of value transforms that bring a database from any
+/// older schema version up to the latest.
+///
+/// The version is a number stored under `SCHEMA_VERSION_KEY`; a database
+/// without one is at version 0. The first migration added takes records
+/// from version 0 to 1, the second from 1 to 2, and so on. Run them with
+/// `run`, or on every writable open with `OpenOptions::migrations`.
+///
+/// All pending migrations are applied to a record in turn before it is
+/// written back, in a single pass over the database, and the new version
+/// is stored at the end. The pass is not atomic: if it is interrupted the
+/// database holds a mix of old and new records under the old version, so
+/// take a backup first (for example with `export_to_path`).
+#[derive(Debug, Clone, Default)]
+pub struct Migrations {
+    steps: Vec<Migration>,
+}
+
+/// What `Migrations::run` did, or would do for a dry run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MigrationReport {
+    /// Schema version before the run
+    pub from: u64,
+    /// Schema version after the run
+    pub to: u64,
+    /// Records whose value was rewritten
+    pub changed: u64,
+    /// Records the migrations deleted
+    pub deleted: u64,
+    /// Records left as they were
+    pub unchanged: u64,
+}
+
+impl Gdbm {
+    /// The schema version stored by `Migrations`, 0 if there is none
+    pub fn schema_version(&self) -> Result<u64, GdbmError> {
+        match self.fetch_data(SCHEMA_VERSION_KEY)? {
+            Some(version) => {
+                str::from_utf8(&version)?
+                    .parse()
+                    .map_err(|_| GdbmError::new("invalid schema version record"))
+            }
+            None => Ok(0),
+        }
+    }
+}
+
+impl Migrations {
+    /// No migrations; the latest version is 0
+    pub fn new() -> Migrations {
+        Migrations::default()
+    }
+
+    /// Add the migration from version `latest()` to the next. `migrate`
+    /// gets each key and its value at the previous version and returns
+    /// the new value, or None to delete the record.
+    pub fn add<F>(&mut self, migrate: F) -> &mut Migrations
+        where F: Fn(&[u8], Vec<u8>) -> Result<Option<Vec<u8>>, GdbmError> + Send + Sync + 'static
+    {
+        self.steps.push(Migration(Arc::new(migrate)));
+        self
+    }
+
+    /// The version the migrations bring a database to
+    pub fn latest(&self) -> u64 {
+        self.steps.len() as u64
+    }
+
+    /// Bring `db` up to the latest version. Does nothing for a database
+    /// that is already there, and fails for one at a newer version than
+    /// these migrations know about.
+    pub fn run(&self, db: &Gdbm) -> Result<MigrationReport, GdbmError> {
+        self.run_impl(db, false, None)
+    }
+
+    /// Work out what `run` would do, without writing anything
+    pub fn dry_run(&self, db: &Gdbm) -> Result<MigrationReport, GdbmError> {
+        self.run_impl(db, true, None)
+    }
+
+    /// `run`, or `dry_run` if `dry_run` is set, calling `progress` every
+    /// `PROGRESS_INTERVAL` records and once at the end.
+    pub fn run_with_progress<F>(&self, db: &Gdbm, dry_run: bool, mut progress: F) -> Result<MigrationReport, GdbmError>
+        where F: FnMut(Progress)
+    {
+        self.run_impl(db, dry_run, Some(&mut progress))
+    }
+
+    fn run_impl(&self,
+                db: &Gdbm,
+                dry_run: bool,
+                mut progress: Option<&mut dyn FnMut(Progress)>)
+                -> Result<MigrationReport, GdbmError> {
+        let from = db.schema_version()?;
+        let to = self.latest();
+        if from > to {
+            return Err(GdbmError::new(format!("database schema version {} is newer than the latest known version {}",
+                                              from,
+                                              to)));
+        }
+        let mut report = MigrationReport {
+            from,
+            to,
+            ..MigrationReport::default()
+        };
+        if from == to {
+            return Ok(report);
+        }
+        let pending = &self.steps[from as usize..];
+        let mut migrate_all = || {
+            let mut done = 0;
+            // Rewriting records during a traversal can make gdbm skip keys
+            for key in db.keys_stable()? {
+                if key.starts_with(RESERVED_KEY_PREFIX) {
+                    continue;
+                }
+                let old = match db.fetch_data(&key)? {
+                    Some(old) => old,
+                    None => continue,
+                };
+                let mut value = Some(old.clone());
+                for Migration(migrate) in pending {
+                    value = match value {
+                        Some(value) => migrate(&key, value)?,
+                        None => break,
+                    };
+                }
+                match value {
+                    None => {
+                        report.deleted += 1;
+                        if !dry_run {
+                            db.remove(&key)?;
+                        }
+                    }
+                    Some(ref new) if *new == old => report.unchanged += 1,
+                    Some(new) => {
+                        report.changed += 1;
+                        if !dry_run {
+                            db.store_bytes(&key, &new, Store::REPLACE)?;
+                        }
+                    }
+                }
+                done += 1;
+                if let Some(ref mut progress) = progress {
+                    if done % PROGRESS_INTERVAL == 0 {
+                        progress(Progress { records: done, bytes: 0 });
+                    }
+                }
+            }
+            if let Some(ref mut progress) = progress {
+                progress(Progress { records: done, bytes: 0 });
+            }
+            if !dry_run {
+                db.store_bytes(SCHEMA_VERSION_KEY, to.to_string().as_bytes(), Store::REPLACE)?;
+            }
+            Ok(())
+        };
+        if dry_run {
+            migrate_all()?;
+        } else {
+            db.with_deferred_sync(migrate_all)?;
+        }
+        Ok(report)
+    }
+}
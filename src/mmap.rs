@@ -0,0 +1,242 @@
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+
+use libc::{self, c_char, c_void, flock, EWOULDBLOCK, LOCK_NB, LOCK_SH, MAP_FAILED, MAP_SHARED, PROT_READ};
+
+use foreign::{corrupt, unique_buckets, Element, Header, Layout, HEADER_LEN};
+use migrations::is_reserved_key;
+use GdbmError;
+
+/// gdbm's bucket directory is indexed by the top bits of a 31 bit hash
+const GDBM_HASH_BITS: u32 = 31;
+
+/// A key and value borrowed from the map
+type Entry<'a> = (&'a [u8], &'a [u8]);
+
+/// gdbm's key hash. Key bytes are widened as C `char`, so the hash of a
+/// key with bytes above 0x7f differs between hosts where `char` is signed
+/// and where it is not, just as in gdbm.
+fn gdbm_hash(key: &[u8]) -> u32 {
+    let mut value = 0x238f_13afu32.wrapping_mul(key.len() as u32);
+    for (index, &byte) in key.iter().enumerate() {
+        let widened = (byte as c_char as i32) << (index * 5 % 24);
+        value = value.wrapping_add(widened as u32) & 0x7fff_ffff;
+    }
+    1_103_515_243u32.wrapping_mul(value).wrapping_add(12345) & 0x7fff_ffff
+}
+
+/// A read-only memory map of a whole file, unmapped on drop
+#[derive(Debug)]
+struct Map {
+    ptr: *const u8,
+    len: usize,
+}
+
+impl Map {
+    fn new(file: &File, len: usize) -> Result<Map, GdbmError> {
+        let ptr = unsafe { libc::mmap(ptr::null_mut(), len, PROT_READ, MAP_SHARED, file.as_raw_fd(), 0) };
+        if ptr == MAP_FAILED {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(Map {
+            ptr: ptr as *const u8,
+            len,
+        })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+
+    /// `len` bytes of the file at `address`
+    fn slice(&self, address: u64, len: usize) -> Result<&[u8], GdbmError> {
+        let start = address as usize;
+        match start.checked_add(len) {
+            Some(end) if address <= self.len as u64 && end <= self.len => Ok(&self.bytes()[start..end]),
+            _ => Err(corrupt("block extends past the end of the file")),
+        }
+    }
+}
+
+impl Drop for Map {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut c_void, self.len);
+        }
+    }
+}
+
+/// A read-only gdbm database parsed straight from a memory map of the
+/// file, without libgdbm: lookups hash the key and read the bucket in
+/// place, and keys and values are borrowed from the map rather than
+/// copied. Reads any byte order and `off_t` size, like `ForeignGdbmFile`,
+/// though lookups only work in files written by a host whose C `char` has
+/// the same signedness.
+///
+/// Holds a shared flock on the file while open, as a gdbm reader does, so
+/// gdbm writers cannot open it meanwhile and opening fails while one has
+/// it open. Records written by a gdbm handle in this process that was
+/// opened with `NOLOCK` are not seen, and truncating the file under the
+/// map crashes the process.
+#[derive(Debug)]
+pub struct GdbmReaderPure {
+    map: Map,
+    /// Keeps the flock
+    _file: File,
+    layout: Layout,
+    header: Header,
+    /// Bucket address per directory entry
+    directory: Vec<u64>,
+    /// Each bucket address once, in directory order
+    buckets: Vec<u64>,
+}
+
+// Safety: the map is read-only and owned by the reader
+unsafe impl Send for GdbmReaderPure {}
+unsafe impl Sync for GdbmReaderPure {}
+
+/// Iterator over the records of a `GdbmReaderPure`, borrowed from the
+/// map, returned by `GdbmReaderPure::entries`. Iteration stops after the
+/// first error.
+#[derive(Debug)]
+pub struct PureEntries<'a> {
+    db: &'a GdbmReaderPure,
+    next_bucket: usize,
+    next_element: usize,
+    failed: bool,
+}
+
+/// Iterator over the keys of a `GdbmReaderPure`, returned by
+/// `GdbmReaderPure::keys`
+#[derive(Debug)]
+pub struct PureKeys<'a> {
+    entries: PureEntries<'a>,
+}
+
+impl GdbmReaderPure {
+    /// Map the gdbm database at `path` for reading
+    pub fn open(path: &Path) -> Result<GdbmReaderPure, GdbmError> {
+        let file = File::open(path)?;
+        if unsafe { flock(file.as_raw_fd(), LOCK_SH | LOCK_NB) } != 0 {
+            let err = io::Error::last_os_error();
+            if err.raw_os_error() == Some(EWOULDBLOCK) {
+                return Err(GdbmError::new("database is open for writing"));
+            }
+            return Err(err.into());
+        }
+        let len = file.metadata()?.len() as usize;
+        if len < HEADER_LEN {
+            return Err(GdbmError::new("not a gdbm database"));
+        }
+        let map = Map::new(&file, len)?;
+        let (layout, header) = Layout::read_header(&map.bytes()[..HEADER_LEN])?;
+        let (directory, buckets) = {
+            let entries = map.slice(header.dir, header.dir_size)?;
+            let directory = entries.chunks(layout.offset_size()).map(|entry| layout.offset_at(entry, 0)).collect();
+            (directory, unique_buckets(&layout, entries))
+        };
+        Ok(GdbmReaderPure {
+            map,
+            _file: file,
+            layout,
+            header,
+            directory,
+            buckets,
+        })
+    }
+
+    fn bucket(&self, address: u64) -> Result<&[u8], GdbmError> {
+        self.map.slice(address, self.header.bucket_size)
+    }
+
+    fn record(&self, element: &Element) -> Result<Entry<'_>, GdbmError> {
+        let record = self.map.slice(element.pointer, element.key_size + element.data_size)?;
+        Ok(record.split_at(element.key_size))
+    }
+
+    /// The value stored under `key`, borrowed from the map
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<&[u8]>, GdbmError> {
+        let key = key.as_ref();
+        let hash = gdbm_hash(key);
+        let index = (hash >> (GDBM_HASH_BITS - self.header.dir_bits)) as usize;
+        let address = *self.directory.get(index).ok_or_else(|| corrupt("directory is too small"))?;
+        let bucket = self.bucket(address)?;
+        // Open addressing from the home slot, as gdbm stores them
+        let home = hash as usize % self.header.bucket_elems;
+        for probe in 0..self.header.bucket_elems {
+            let element = match self.layout.element(bucket, (home + probe) % self.header.bucket_elems) {
+                Some(element) => element,
+                None => return Ok(None),
+            };
+            if element.hash == hash && element.key_size == key.len() {
+                let (stored, value) = self.record(&element)?;
+                if stored == key {
+                    return Ok(Some(value));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Iterate over the keys, bucket by bucket
+    pub fn keys(&self) -> PureKeys<'_> {
+        PureKeys { entries: self.entries() }
+    }
+
+    /// Iterate over the records, bucket by bucket
+    pub fn entries(&self) -> PureEntries<'_> {
+        PureEntries {
+            db: self,
+            next_bucket: 0,
+            next_element: 0,
+            failed: false,
+        }
+    }
+}
+
+impl<'a> PureEntries<'a> {
+    fn advance(&mut self) -> Result<Option<Entry<'a>>, GdbmError> {
+        let db = self.db;
+        while let Some(&address) = db.buckets.get(self.next_bucket) {
+            let bucket = db.bucket(address)?;
+            while self.next_element < db.header.bucket_elems {
+                let element = db.layout.element(bucket, self.next_element);
+                self.next_element += 1;
+                if let Some(element) = element {
+                    let record = db.record(&element)?;
+                    if !is_reserved_key(record.0) {
+                        return Ok(Some(record));
+                    }
+                }
+            }
+            self.next_bucket += 1;
+            self.next_element = 0;
+        }
+        Ok(None)
+    }
+}
+
+impl<'a> Iterator for PureEntries<'a> {
+    type Item = Result<Entry<'a>, GdbmError>;
+
+    fn next(&mut self) -> Option<Result<Entry<'a>, GdbmError>> {
+        if self.failed {
+            return None;
+        }
+        let next = self.advance().transpose();
+        self.failed = matches!(next, Some(Err(_)));
+        next
+    }
+}
+
+impl<'a> Iterator for PureKeys<'a> {
+    type Item = Result<&'a [u8], GdbmError>;
+
+    fn next(&mut self) -> Option<Result<&'a [u8], GdbmError>> {
+        self.entries.next().map(|entry| entry.map(|(key, _)| key))
+    }
+}
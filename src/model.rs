@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use {Gdbm, GdbmError};
+
+/// How many keys of each kind of mismatch a `ModelReport` keeps. The
+/// counts always cover every mismatch.
+const REPORT_SAMPLE: usize = 10;
+
+/// Differences between a database and a reference `HashMap`, as found by
+/// `Gdbm::compare_to_model`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ModelReport {
+    /// Number of keys present in the database but not the model
+    pub only_in_db: u64,
+    /// Number of keys present in the model but not the database
+    pub only_in_model: u64,
+    /// Number of keys present in both with different values
+    pub different: u64,
+    /// The first few keys that are only in the database
+    pub only_in_db_keys: Vec<Vec<u8>>,
+    /// The first few keys that are only in the model
+    pub only_in_model_keys: Vec<Vec<u8>>,
+    /// The first few keys whose values differ
+    pub different_keys: Vec<Vec<u8>>,
+}
+
+impl ModelReport {
+    /// True if the database and the model hold exactly the same records
+    pub fn is_match(&self) -> bool {
+        self.only_in_db == 0 && self.only_in_model == 0 && self.different == 0
+    }
+}
+
+fn note(count: &mut u64, sample: &mut Vec<Vec<u8>>, key: &[u8]) {
+    *count += 1;
+    if sample.len() < REPORT_SAMPLE {
+        sample.push(key.to_vec());
+    }
+}
+
+struct Keys<'a>(&'a [Vec<u8>]);
+
+impl<'a> fmt::Display for Keys<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for key in self.0 {
+            write!(f, "\n    \"")?;
+            for byte in key.iter().flat_map(|b| ::std::ascii::escape_default(*b)) {
+                write!(f, "{}", byte as char)?;
+            }
+            write!(f, "\"")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ModelReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_match() {
+            return write!(f, "database matches the model");
+        }
+        write!(f, "database does not match the model:")?;
+        if self.only_in_db > 0 {
+            write!(f, "\n  {} key(s) only in the database:{}", self.only_in_db, Keys(&self.only_in_db_keys))?;
+        }
+        if self.only_in_model > 0 {
+            write!(f, "\n  {} key(s) only in the model:{}", self.only_in_model, Keys(&self.only_in_model_keys))?;
+        }
+        if self.different > 0 {
+            write!(f, "\n  {} key(s) with different values:{}", self.different, Keys(&self.different_keys))?;
+        }
+        Ok(())
+    }
+}
+
+impl Gdbm {
+    /// Compare the contents of the database with `model` in both
+    /// directions.
+    ///
+    /// The database is walked once and each record looked up in the
+    /// model; the model is then only walked if some of its keys were not
+    /// seen. Apart from the model, memory use is bounded by the size of
+    /// a single record plus the sample of keys kept in the report.
+    pub fn compare_to_model(&self, model: &HashMap<Vec<u8>, Vec<u8>>) -> Result<ModelReport, GdbmError> {
+        let mut report = ModelReport::default();
+        let mut seen = 0;
+        let mut next = self.first_key_bytes()?;
+        while let Some(key) = next {
+            let value = self.fetch_bytes(&key)?;
+            match model.get(&key) {
+                None => note(&mut report.only_in_db, &mut report.only_in_db_keys, &key),
+                Some(expected) => {
+                    seen += 1;
+                    if value.as_ref() != Some(expected) {
+                        note(&mut report.different, &mut report.different_keys, &key);
+                    }
+                }
+            }
+            next = self.next_key_bytes(&key)?;
+        }
+        if seen < model.len() {
+            for key in model.keys() {
+                if !self.contains(key)? {
+                    note(&mut report.only_in_model, &mut report.only_in_model_keys, key);
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    /// Panic with a description of the differences unless the database
+    /// holds exactly the records in `model`. Meant for test suites that
+    /// mirror their mutations into a `HashMap`.
+    pub fn assert_matches_model(&self, model: &HashMap<Vec<u8>, Vec<u8>>) {
+        match self.compare_to_model(model) {
+            Ok(ref report) if report.is_match() => {}
+            Ok(report) => panic!("{}", report),
+            Err(e) => panic!("comparing database to model failed: {}", e),
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "metrics")]
+use std::time::Instant;
+
+use {Gdbm, GdbmError};
+
+/// Counts of the operations a handle has made, returned by
+/// `Gdbm::op_stats`.
+///
+/// These count the lookups, stores and deletes the handle makes in gdbm,
+/// whatever method they come from: `insert` is a fetch and a store, and
+/// iterating over the records fetches each one.
+///
+/// With the `metrics` feature the same counts are also reported through
+/// the `metrics` facade, to whatever recorder the application installed:
+/// the counters `gdbm_fetches_total`, `gdbm_stores_total`,
+/// `gdbm_deletes_total`, `gdbm_misses_total` and `gdbm_errors_total`
+/// (labelled with the `op` that failed), the histogram
+/// `gdbm_value_size_bytes` of the values fetched and stored (labelled with
+/// the `op`), and the histogram `gdbm_fetch_duration_seconds`. Unlike
+/// `OpStats` these add up across every handle in the process.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OpStats {
+    /// Records looked up, found or not
+    pub fetches: u64,
+    /// Records stored, including inserts that found the key taken
+    pub stores: u64,
+    /// Records deleted, found or not
+    pub deletes: u64,
+    /// Fetches and deletes that found no record
+    pub misses: u64,
+    /// Fetches, stores and deletes that failed
+    pub errors: u64,
+}
+
+/// A kind of operation `OpCounters::count` counts
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Op {
+    Fetch,
+    Store,
+    Delete,
+}
+
+#[cfg(feature = "metrics")]
+impl Op {
+    fn label(self) -> &'static str {
+        match self {
+            Op::Fetch => "fetch",
+            Op::Store => "store",
+            Op::Delete => "delete",
+        }
+    }
+
+    fn counter(self) -> &'static str {
+        match self {
+            Op::Fetch => "gdbm_fetches_total",
+            Op::Store => "gdbm_stores_total",
+            Op::Delete => "gdbm_deletes_total",
+        }
+    }
+}
+
+/// The counters behind `OpStats`. Relaxed atomics: each count is exact,
+/// but a snapshot taken while another thread works may be mid-operation.
+#[derive(Debug, Default)]
+pub(crate) struct OpCounters {
+    fetches: AtomicU64,
+    stores: AtomicU64,
+    deletes: AtomicU64,
+    misses: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl OpCounters {
+    /// Count an operation of kind `op`, run by `body`, and its failure
+    pub(crate) fn count<T, F>(&self, op: Op, body: F) -> Result<T, GdbmError>
+        where F: FnOnce() -> Result<T, GdbmError>
+    {
+        let counter = match op {
+            Op::Fetch => &self.fetches,
+            Op::Store => &self.stores,
+            Op::Delete => &self.deletes,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        let started = Instant::now();
+        let result = body();
+        if result.is_err() {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        #[cfg(feature = "metrics")]
+        {
+            metrics::counter!(op.counter()).increment(1);
+            if result.is_err() {
+                metrics::counter!("gdbm_errors_total", "op" => op.label()).increment(1);
+            }
+            if let Op::Fetch = op {
+                metrics::histogram!("gdbm_fetch_duration_seconds").record(started.elapsed());
+            }
+        }
+        result
+    }
+
+    /// Count a fetch or delete that found no record
+    pub(crate) fn miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::counter!("gdbm_misses_total").increment(1);
+    }
+
+    /// Report the size of a value fetched or stored by `op`
+    #[cfg(feature = "metrics")]
+    pub(crate) fn value_size(&self, op: Op, size: usize) {
+        metrics::histogram!("gdbm_value_size_bytes", "op" => op.label()).record(size as f64);
+    }
+
+    fn snapshot(&self) -> OpStats {
+        OpStats {
+            fetches: self.fetches.load(Ordering::Relaxed),
+            stores: self.stores.load(Ordering::Relaxed),
+            deletes: self.deletes.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Gdbm {
+    /// The operations this handle has made since it was opened or
+    /// `reset_op_stats` was last called
+    pub fn op_stats(&self) -> OpStats {
+        self.ops.snapshot()
+    }
+
+    /// Set the operation counts back to zero
+    pub fn reset_op_stats(&self) {
+        for counter in &[&self.ops.fetches, &self.ops.stores, &self.ops.deletes, &self.ops.misses, &self.ops.errors] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
@@ -0,0 +1,314 @@
+use std::fmt;
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use gdbm_sys::{GDBM_CACHESIZE, GDBM_CANT_BE_READER, GDBM_CANT_BE_WRITER};
+use libc::c_int;
+
+use ffi;
+use migrations::SCHEMA_VERSION_KEY;
+use registry;
+use tune::ValueProfile;
+use {error_code, get_error, Gdbm, GdbmError, Migrations, Open, Store, MAX_DATUM_SIZE};
+
+/// Key of the record `OpenOptions::seed_if_empty` leaves behind once a
+/// database has been seeded. The leading NUL keeps it out of the way of
+/// textual keys, and like the schema version it is left out of
+/// iteration and counting.
+pub const SEED_MARKER_KEY: &[u8] = b"\0gdbm-rs:seeded";
+
+type SeedFn = dyn Fn(&Gdbm) -> Result<(), GdbmError> + Send + Sync;
+
+#[derive(Clone)]
+struct Seed(Arc<SeedFn>);
+
+impl fmt::Debug for Seed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Seed")
+    }
+}
+
+/// Builder for opening a database with more control than `Gdbm::new`.
+///
+/// Besides the usual gdbm_open parameters it can run a number of checks
+/// against the path before gdbm touches it, so that a database is never
+/// created over, or opened from, the wrong file. The checks run before
+/// gdbm_open and are not atomic with respect to it.
+#[derive(Debug, Clone)]
+pub struct OpenOptions {
+    block_size: u32,
+    flags: Open,
+    mode: i32,
+    max_value_size: usize,
+    refuse_if_exists: bool,
+    require_regular_file: bool,
+    require_owner: Option<u32>,
+    max_existing_size: Option<u64>,
+    seed: Option<Seed>,
+    migrations: Option<Migrations>,
+    retry_attempts: u32,
+    retry_backoff: Duration,
+    exclusive_in_process: bool,
+    profile: Option<ValueProfile>,
+}
+
+impl Default for OpenOptions {
+    fn default() -> OpenOptions {
+        OpenOptions::new()
+    }
+}
+
+impl OpenOptions {
+    /// Read only access, default block size and mode 0644, no checks.
+    pub fn new() -> OpenOptions {
+        OpenOptions {
+            block_size: 0,
+            flags: Open::READER,
+            mode: 0o644,
+            max_value_size: MAX_DATUM_SIZE,
+            refuse_if_exists: false,
+            require_regular_file: false,
+            require_owner: None,
+            max_existing_size: None,
+            seed: None,
+            migrations: None,
+            retry_attempts: 0,
+            retry_backoff: Duration::from_millis(0),
+            exclusive_in_process: false,
+            profile: None,
+        }
+    }
+
+    /// Block size passed to gdbm_open. 0 lets gdbm choose.
+    pub fn block_size(&mut self, block_size: u32) -> &mut OpenOptions {
+        self.block_size = block_size;
+        self
+    }
+
+    /// Flags passed to gdbm_open
+    pub fn flags(&mut self, flags: Open) -> &mut OpenOptions {
+        self.flags = flags;
+        self
+    }
+
+    /// Permissions used if the file is created
+    pub fn mode(&mut self, mode: i32) -> &mut OpenOptions {
+        self.mode = mode;
+        self
+    }
+
+    /// Largest value the handle will store, see `Gdbm::set_max_value_size`.
+    pub fn max_value_size(&mut self, limit: usize) -> &mut OpenOptions {
+        self.max_value_size = limit;
+        self
+    }
+
+    /// Fail if anything already exists at the path.
+    pub fn refuse_if_exists(&mut self, refuse: bool) -> &mut OpenOptions {
+        self.refuse_if_exists = refuse;
+        self
+    }
+
+    /// Fail if the path exists but is not a regular file.
+    pub fn require_regular_file(&mut self, require: bool) -> &mut OpenOptions {
+        self.require_regular_file = require;
+        self
+    }
+
+    /// Fail if the path exists but is not owned by `uid`.
+    pub fn require_owner(&mut self, uid: u32) -> &mut OpenOptions {
+        self.require_owner = Some(uid);
+        self
+    }
+
+    /// Fail if the path exists and is larger than `bytes`.
+    pub fn max_existing_size(&mut self, bytes: u64) -> &mut OpenOptions {
+        self.max_existing_size = Some(bytes);
+        self
+    }
+
+    /// Populate a newly created database with `seed` on open.
+    ///
+    /// `seed` runs when a writable open finds the database without a seed
+    /// marker and without records, bookkeeping records such as the schema
+    /// version aside. Once it succeeds the `SEED_MARKER_KEY`
+    /// record is stored and the database synced, so the database is
+    /// seeded exactly once even if it is later emptied again. If `seed`
+    /// fails the records it stored are deleted, no marker is written and
+    /// the open fails with its error, so the next open seeds again.
+    pub fn seed_if_empty<F>(&mut self, seed: F) -> &mut OpenOptions
+        where F: Fn(&Gdbm) -> Result<(), GdbmError> + Send + Sync + 'static
+    {
+        self.seed = Some(Seed(Arc::new(seed)));
+        self
+    }
+
+    /// Bring the database up to the latest version of `migrations` on a
+    /// writable open, after any seeding. Seeded databases are marked as
+    /// at the latest version without migrating. A read-only open fails if
+    /// the database is not at the latest version.
+    pub fn migrations(&mut self, migrations: &Migrations) -> &mut OpenOptions {
+        self.migrations = Some(migrations.clone());
+        self
+    }
+
+    /// If another handle holds the database's lock, try again up to
+    /// `attempts` more times, sleeping `backoff` before the first retry
+    /// and doubling it each time. Opens that fail for any other reason
+    /// are not retried.
+    pub fn retry(&mut self, attempts: u32, backoff: Duration) -> &mut OpenOptions {
+        self.retry_attempts = attempts;
+        self.retry_backoff = backoff;
+        self
+    }
+
+    /// Refuse a writable open of a database that is already open for
+    /// writing in this process. Handles are told apart by canonical path
+    /// and only handles opened with this option are tracked, so every
+    /// component sharing the file has to set it. Unlike gdbm's own
+    /// locking this also works with `NOLOCK`.
+    pub fn exclusive_in_process(&mut self, exclusive: bool) -> &mut OpenOptions {
+        self.exclusive_in_process = exclusive;
+        self
+    }
+
+    /// Pick the block size and cache size for records like `profile`
+    /// describes, see `recommended_block_size`. The block size only
+    /// applies when the database is created, and an explicit
+    /// `block_size` takes precedence. The cache is sized after opening,
+    /// from the database's actual block size, if `profile` says how many
+    /// records to expect.
+    pub fn tune_for(&mut self, profile: &ValueProfile) -> &mut OpenOptions {
+        self.profile = Some(profile.clone());
+        self
+    }
+
+    /// Run the configured checks and open the database at `path`.
+    ///
+    /// Fails with `GdbmError::LockContended` if the database stays locked
+    /// by another handle.
+    pub fn open(&self, path: &Path) -> Result<Gdbm, GdbmError> {
+        self.check(path)?;
+        let claim = if self.exclusive_in_process && self.writable() {
+            Some(registry::claim(path)?)
+        } else {
+            None
+        };
+        let mut db = self.open_with_retry(path)?;
+        db.writer_claim = claim;
+        db.set_max_value_size(self.max_value_size);
+        if let Some(ref profile) = self.profile {
+            let block_size: c_int = db.getopt(ffi::GDBM_GETBLOCKSIZE, 0)?;
+            if let Some(cache_size) = profile.cache_size(block_size as u32) {
+                db.setopt(GDBM_CACHESIZE as c_int, cache_size)?;
+            }
+        }
+        let mut seeded = false;
+        if let Some(Seed(ref seed)) = self.seed {
+            if self.writable() && !db.contains(SEED_MARKER_KEY)? && db.count()? == 0 {
+                if let Err(e) = seed(&db).and_then(|()| db.store_bytes(SEED_MARKER_KEY, b"", Store::REPLACE)) {
+                    // The database was empty, so this deletes just what the
+                    // seed wrote and the next open seeds again
+                    let _ = db.clear();
+                    return Err(e);
+                }
+                if let Some(ref migrations) = self.migrations {
+                    // Seed data is written in the latest format
+                    db.store_bytes(SCHEMA_VERSION_KEY, migrations.latest().to_string().as_bytes(), Store::REPLACE)?;
+                }
+                db.sync()?;
+                seeded = true;
+            }
+        }
+        if let Some(ref migrations) = self.migrations {
+            if self.writable() {
+                if !seeded {
+                    migrations.run(&db)?;
+                }
+            } else {
+                let version = db.schema_version()?;
+                if version != migrations.latest() {
+                    return Err(GdbmError::new(format!("database is at schema version {}, not {}; open it for writing to migrate",
+                                                      version,
+                                                      migrations.latest())));
+                }
+            }
+        }
+        Ok(db)
+    }
+
+    fn open_with_retry(&self, path: &Path) -> Result<Gdbm, GdbmError> {
+        let mut backoff = self.retry_backoff;
+        let mut attempts = 1;
+        let block_size = match self.profile {
+            Some(ref profile) if self.block_size == 0 => profile.block_size(path),
+            _ => self.block_size,
+        };
+        loop {
+            let err = match Gdbm::new(path, block_size, self.flags, self.mode) {
+                Ok(db) => return Ok(db),
+                Err(err) => err,
+            };
+            let code = error_code() as u32;
+            if code != GDBM_CANT_BE_WRITER && code != GDBM_CANT_BE_READER {
+                return Err(err);
+            }
+            if attempts > self.retry_attempts {
+                return Err(GdbmError::LockContended {
+                    attempts,
+                    message: get_error(),
+                });
+            }
+            thread::sleep(backoff);
+            backoff = backoff.checked_mul(2).unwrap_or(backoff);
+            attempts += 1;
+        }
+    }
+
+    fn writable(&self) -> bool {
+        // READER is 0, every other access mode in the low bits can write
+        (self.flags.bits & Open::NEWDB.bits) != Open::READER.bits
+    }
+
+    fn check(&self, path: &Path) -> Result<(), GdbmError> {
+        let metadata = match fs::symlink_metadata(path) {
+            Ok(metadata) => metadata,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        if self.refuse_if_exists {
+            return Err(GdbmError::new(format!("{} already exists", path.display())));
+        }
+        // The remaining checks apply to whatever a symlink points at
+        let metadata = if metadata.file_type().is_symlink() {
+            fs::metadata(path)?
+        } else {
+            metadata
+        };
+        if self.require_regular_file && !metadata.is_file() {
+            return Err(GdbmError::new(format!("{} is not a regular file", path.display())));
+        }
+        if let Some(uid) = self.require_owner {
+            if metadata.uid() != uid {
+                return Err(GdbmError::new(format!("{} is owned by uid {}, expected {}",
+                                                  path.display(),
+                                                  metadata.uid(),
+                                                  uid)));
+            }
+        }
+        if let Some(max) = self.max_existing_size {
+            if metadata.len() > max {
+                return Err(GdbmError::new(format!("{} is {} bytes, more than the allowed {}",
+                                                  path.display(),
+                                                  metadata.len(),
+                                                  max)));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+use std::sync::mpsc;
+#[cfg(feature = "rayon")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use {Gdbm, GdbmError};
+
+type Chunk = Vec<(Vec<u8>, Vec<u8>)>;
+
+impl Gdbm {
+    /// Call `f` on every record, spread over `threads` worker threads.
+    ///
+    /// The records are read sequentially on the calling thread, as gdbm
+    /// requires, and handed to the workers `chunk_size` at a time. This
+    /// pays off when `f` is expensive compared to reading a record. At
+    /// most two chunks per worker are buffered, so a slow `f` slows the
+    /// read down rather than letting memory grow. The order in which
+    /// records reach `f` is unspecified.
+    ///
+    /// Stops reading at the first error; chunks already handed out are
+    /// still processed. If `f` panics the panic is propagated once the
+    /// workers have finished.
+    ///
+    /// With the `rayon` feature, `par_entries` does the same on the rayon
+    /// thread pool instead of threads of its own.
+    pub fn par_for_each<F>(&self, chunk_size: usize, threads: usize, f: F) -> Result<(), GdbmError>
+        where F: Fn(&[u8], &[u8]) + Sync
+    {
+        let chunk_size = chunk_size.max(1);
+        let threads = threads.max(1);
+        let (chunks, queue) = mpsc::sync_channel::<Chunk>(threads * 2);
+        // Only the workers hold the receiving end, so once they are all
+        // gone, panicked or not, sending fails instead of blocking
+        let queue = Arc::new(Mutex::new(queue));
+        let f = &f;
+        thread::scope(|scope| {
+            for _ in 0..threads {
+                let queue = queue.clone();
+                scope.spawn(move || {
+                    loop {
+                        // Hold the lock only while taking a chunk
+                        let chunk = match queue.lock().unwrap_or_else(|e| e.into_inner()).recv() {
+                            Ok(chunk) => chunk,
+                            Err(_) => return,
+                        };
+                        for (key, value) in &chunk {
+                            f(key, value);
+                        }
+                    }
+                });
+            }
+            drop(queue);
+            // Dropping `chunks` on the way out, error or not, is what
+            // lets the workers finish
+            let chunks = chunks;
+            let mut chunk = Vec::with_capacity(chunk_size);
+            for record in self.iter() {
+                chunk.push(record?);
+                if chunk.len() == chunk_size {
+                    let full = ::std::mem::replace(&mut chunk, Vec::with_capacity(chunk_size));
+                    if chunks.send(full).is_err() {
+                        // Every worker is gone, one of them panicked
+                        return Ok(());
+                    }
+                }
+            }
+            if !chunk.is_empty() {
+                let _ = chunks.send(chunk);
+            }
+            Ok(())
+        })
+    }
+
+    /// Call `f` on every record, on the rayon thread pool.
+    ///
+    /// As with `par_for_each` the records are read sequentially on the
+    /// calling thread and handed out `chunk_size` at a time, here as rayon
+    /// tasks, so `f` runs on the global pool, or on the pool of
+    /// `ThreadPool::install` when called within it. When twice as many chunks as the pool has threads are
+    /// waiting, the calling thread processes the next chunk itself rather
+    /// than read further, which bounds the memory held without blocking a
+    /// pool thread. The order in which records reach `f` is unspecified.
+    ///
+    /// Stops reading at the first error; chunks already handed out are
+    /// still processed. If `f` panics the panic is propagated once the
+    /// other chunks have been processed.
+    #[cfg(feature = "rayon")]
+    pub fn par_entries<F>(&self, chunk_size: usize, f: F) -> Result<(), GdbmError>
+        where F: Fn(&[u8], &[u8]) + Sync
+    {
+        let chunk_size = chunk_size.max(1);
+        let limit = rayon::current_num_threads() * 2;
+        let waiting = AtomicUsize::new(0);
+        let (f, waiting) = (&f, &waiting);
+        let process = move |chunk: Chunk| {
+            for (key, value) in &chunk {
+                f(key, value);
+            }
+        };
+        // in_place_scope keeps the reading on this thread, which the
+        // handle cannot leave
+        rayon::in_place_scope(|scope| {
+            let mut chunk = Vec::with_capacity(chunk_size);
+            for record in self.iter() {
+                chunk.push(record?);
+                if chunk.len() == chunk_size {
+                    let full = ::std::mem::replace(&mut chunk, Vec::with_capacity(chunk_size));
+                    if waiting.load(Ordering::Acquire) >= limit {
+                        process(full);
+                    } else {
+                        waiting.fetch_add(1, Ordering::AcqRel);
+                        scope.spawn(move |_| {
+                            waiting.fetch_sub(1, Ordering::AcqRel);
+                            process(full);
+                        });
+                    }
+                }
+            }
+            process(chunk);
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,175 @@
+use std::fmt;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use {Gdbm, GdbmError, OpenOptions};
+
+type HealthCheckFn = dyn Fn(&Gdbm) -> bool + Send + Sync;
+
+#[derive(Clone)]
+struct HealthCheck(Arc<HealthCheckFn>);
+
+impl fmt::Debug for HealthCheck {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("HealthCheck")
+    }
+}
+
+#[derive(Debug)]
+struct PoolState {
+    idle: Vec<Gdbm>,
+    /// Handles in existence, idle or checked out, plus those being opened
+    open: usize,
+}
+
+/// A pool of handles to one database, meant for read-only handles that
+/// worker threads check out in turn.
+///
+/// Handles are opened on demand, up to `max_size`, and returned to the
+/// pool when the `PooledGdbm` guard is dropped. Open the pool with
+/// `READER` flags: gdbm refuses a second writer, and readers cannot open
+/// while a writer holds the database.
+#[derive(Debug)]
+pub struct GdbmPool {
+    path: PathBuf,
+    options: OpenOptions,
+    max_size: usize,
+    health_check: Option<HealthCheck>,
+    state: Mutex<PoolState>,
+    returned: Condvar,
+}
+
+/// A handle checked out of a `GdbmPool`, returned to it on drop
+#[derive(Debug)]
+pub struct PooledGdbm<'a> {
+    pool: &'a GdbmPool,
+    db: Option<Gdbm>,
+}
+
+impl GdbmPool {
+    /// A pool of at most `max_size` handles (at least 1) to `path`,
+    /// opened with `options`. No handle is opened yet.
+    pub fn new(path: &Path, options: &OpenOptions, max_size: usize) -> GdbmPool {
+        GdbmPool {
+            path: path.to_path_buf(),
+            options: options.clone(),
+            max_size: max_size.max(1),
+            health_check: None,
+            state: Mutex::new(PoolState {
+                idle: Vec::new(),
+                open: 0,
+            }),
+            returned: Condvar::new(),
+        }
+    }
+
+    /// Run `check` on an idle handle before handing it out; a handle that
+    /// fails it is closed and another one used instead.
+    pub fn health_check<F>(&mut self, check: F) -> &mut GdbmPool
+        where F: Fn(&Gdbm) -> bool + Send + Sync + 'static
+    {
+        self.health_check = Some(HealthCheck(Arc::new(check)));
+        self
+    }
+
+    /// The most handles the pool will open
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Number of open handles, idle or checked out
+    pub fn open_count(&self) -> usize {
+        self.lock().open
+    }
+
+    /// Number of open handles waiting in the pool
+    pub fn idle_count(&self) -> usize {
+        self.lock().idle.len()
+    }
+
+    /// Check out a handle, waiting for one to be returned if `max_size`
+    /// are in use.
+    pub fn get(&self) -> Result<PooledGdbm<'_>, GdbmError> {
+        self.checkout(None)
+    }
+
+    /// Like `get`, but fail if no handle becomes available within
+    /// `timeout`.
+    pub fn get_timeout(&self, timeout: Duration) -> Result<PooledGdbm<'_>, GdbmError> {
+        self.checkout(Some(Instant::now() + timeout))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PoolState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn checkout(&self, deadline: Option<Instant>) -> Result<PooledGdbm<'_>, GdbmError> {
+        let mut state = self.lock();
+        loop {
+            if let Some(db) = state.idle.pop() {
+                let healthy = match self.health_check {
+                    Some(HealthCheck(ref check)) => check(&db),
+                    None => true,
+                };
+                if healthy {
+                    return Ok(PooledGdbm {
+                        pool: self,
+                        db: Some(db),
+                    });
+                }
+                state.open -= 1;
+                continue;
+            }
+            if state.open < self.max_size {
+                // Open outside the lock, holding a slot for the handle
+                state.open += 1;
+                drop(state);
+                return match self.options.open(&self.path) {
+                    Ok(db) => {
+                        Ok(PooledGdbm {
+                            pool: self,
+                            db: Some(db),
+                        })
+                    }
+                    Err(e) => {
+                        self.lock().open -= 1;
+                        self.returned.notify_one();
+                        Err(e)
+                    }
+                };
+            }
+            state = match deadline {
+                None => self.returned.wait(state).unwrap_or_else(PoisonError::into_inner),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(GdbmError::new("timed out waiting for a pooled handle"));
+                    }
+                    self.returned
+                        .wait_timeout(state, deadline - now)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0
+                }
+            };
+        }
+    }
+}
+
+impl<'a> Deref for PooledGdbm<'a> {
+    type Target = Gdbm;
+
+    fn deref(&self) -> &Gdbm {
+        self.db.as_ref().expect("pooled handle is present until drop")
+    }
+}
+
+impl<'a> Drop for PooledGdbm<'a> {
+    fn drop(&mut self) {
+        if let Some(db) = self.db.take() {
+            self.pool.lock().idle.push(db);
+            self.pool.returned.notify_one();
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use {Gdbm, GdbmError};
+
+impl Gdbm {
+    /// Number of keys starting with `prefix`. gdbm has no ordered index,
+    /// so this walks every key.
+    pub fn count_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Result<usize, GdbmError> {
+        let prefix = prefix.as_ref();
+        let mut count = 0;
+        for key in self.keys() {
+            if key?.starts_with(prefix) {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Delete every record whose key starts with `prefix`, syncing once at
+    /// the end. Returns how many records were deleted. Like `clear`, this
+    /// keeps the crate's bookkeeping records, whatever `prefix` is.
+    ///
+    /// The matching keys are collected in a full pass before anything is
+    /// deleted, since deleting during a traversal can make gdbm skip keys.
+    pub fn delete_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> Result<usize, GdbmError> {
+        let prefix = prefix.as_ref();
+        let mut matching = Vec::new();
+        for key in self.keys() {
+            let key = key?;
+            if key.starts_with(prefix) {
+                matching.push(key);
+            }
+        }
+        self.delete_many(matching)
+    }
+}
@@ -0,0 +1,14 @@
+/// How far a long-running operation has got, as passed to the callback
+/// of the `*_with_progress` methods.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// Records processed so far. Dumps streamed through gdbm only know
+    /// this at the end and report 0 until then.
+    pub records: u64,
+    /// Bytes of dump data read or written so far, 0 for operations that
+    /// do not stream a dump
+    pub bytes: u64,
+}
+
+/// Records between two progress reports of a bulk operation
+pub const PROGRESS_INTERVAL: u64 = 1000;
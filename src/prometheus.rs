@@ -0,0 +1,90 @@
+//! Statistics of open handles in the Prometheus text exposition format,
+//! for serving from a `/metrics` endpoint:
+//!
+//! ```no_run
+//! # let db = gdbm::Gdbm::new(std::path::Path::new("db"), 0, gdbm::Open::READER, 0).unwrap();
+//! let text = gdbm::prometheus::collect(&[&db]).unwrap();
+//! ```
+//!
+//! Every sample is labelled with the handle's database path as `db`.
+
+use std::fmt::Write;
+use std::io::Error;
+use std::mem;
+use std::os::unix::io::AsRawFd;
+
+use libc;
+
+use {Gdbm, GdbmError};
+
+/// The content type to serve `collect`'s output with
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// The metric families `collect` reports: name, type and help text
+const FAMILIES: &[(&str, &str, &str)] = &[("gdbm_records", "gauge", "Number of records in the database."),
+                                          ("gdbm_file_size_bytes", "gauge", "Size of the database file."),
+                                          ("gdbm_block_size_bytes", "gauge", "Block size the database was created with."),
+                                          ("gdbm_cache_size_buckets", "gauge", "Number of buckets the bucket cache holds."),
+                                          ("gdbm_fetches_total", "counter", "Records looked up by the handle."),
+                                          ("gdbm_stores_total", "counter", "Records stored by the handle."),
+                                          ("gdbm_deletes_total", "counter", "Records deleted by the handle."),
+                                          ("gdbm_misses_total", "counter", "Fetches and deletes that found no record."),
+                                          ("gdbm_errors_total", "counter", "Fetches, stores and deletes that failed.")];
+
+/// What `collect` reports about one handle: its path and a value per
+/// family, in the order of `FAMILIES`
+struct Sample {
+    db: String,
+    values: Vec<u64>,
+}
+
+fn sample(db: &Gdbm) -> Result<Sample, GdbmError> {
+    let info = db.info()?;
+    let ops = db.op_stats();
+    let mut stat: libc::stat = unsafe { mem::zeroed() };
+    if unsafe { libc::fstat(db.as_raw_fd(), &mut stat) } != 0 {
+        return Err(Error::last_os_error().into());
+    }
+    Ok(Sample {
+        db: info.name.to_string_lossy().into_owned(),
+        values: vec![db.count()?,
+                     stat.st_size as u64,
+                     info.block_size as u64,
+                     info.cache_size as u64,
+                     ops.fetches,
+                     ops.stores,
+                     ops.deletes,
+                     ops.misses,
+                     ops.errors],
+    })
+}
+
+/// Escape a label value: backslash, double quote and newline
+fn write_label(out: &mut String, value: &str) {
+    for c in value.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+}
+
+/// Render the statistics of `dbs` in the Prometheus text format. Counting
+/// the records walks each database, so keep the scrape interval in
+/// proportion to their size.
+pub fn collect(dbs: &[&Gdbm]) -> Result<String, GdbmError> {
+    let samples = dbs.iter().map(|db| sample(db)).collect::<Result<Vec<_>, _>>()?;
+    let mut out = String::new();
+    for (i, &(name, kind, help)) in FAMILIES.iter().enumerate() {
+        // Writing to a String cannot fail
+        let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}", name, help, name, kind);
+        for sample in &samples {
+            let _ = write!(out, "{}{{db=\"", name);
+            write_label(&mut out, &sample.db);
+            let _ = writeln!(out, "\"}} {}", sample.values[i]);
+        }
+    }
+    Ok(out)
+}
@@ -0,0 +1,52 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use GdbmError;
+
+/// Canonical paths of the databases open for writing through
+/// `OpenOptions::exclusive_in_process`
+static WRITERS: Mutex<Option<HashSet<PathBuf>>> = Mutex::new(None);
+
+/// A path claimed in the writer registry, released on drop
+#[derive(Debug)]
+pub struct WriterClaim {
+    path: PathBuf,
+}
+
+/// The path `path` will resolve to, whether or not it exists yet
+fn canonical(path: &Path) -> io::Result<PathBuf> {
+    match fs::canonicalize(path) {
+        Ok(path) => Ok(path),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            let name = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no file name"))?;
+            let parent = match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            };
+            Ok(fs::canonicalize(parent)?.join(name))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Claim `path` for a writer, failing if another claim on it is live.
+pub fn claim(path: &Path) -> Result<WriterClaim, GdbmError> {
+    let path = canonical(path)?;
+    let mut writers = WRITERS.lock().unwrap_or_else(|e| e.into_inner());
+    if !writers.get_or_insert_with(HashSet::new).insert(path.clone()) {
+        return Err(GdbmError::new(format!("{} is already open for writing in this process", path.display())));
+    }
+    Ok(WriterClaim { path })
+}
+
+impl Drop for WriterClaim {
+    fn drop(&mut self) {
+        let mut writers = WRITERS.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(ref mut writers) = *writers {
+            writers.remove(&self.path);
+        }
+    }
+}
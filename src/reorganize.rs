@@ -0,0 +1,125 @@
+use std::cell::Cell;
+use std::fmt;
+use std::sync::Arc;
+
+use foreign::ForeignGdbmFile;
+use {Gdbm, GdbmError};
+
+type VetoFn = dyn Fn(f64) -> bool + Send + Sync;
+
+/// The function of `AutoReorganize::veto`
+#[derive(Clone)]
+struct Veto(Arc<VetoFn>);
+
+impl fmt::Debug for Veto {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Veto")
+    }
+}
+
+/// When a handle reorganizes itself, set with `Gdbm::set_auto_reorganize`
+#[derive(Debug, Clone)]
+pub struct AutoReorganize {
+    threshold: f64,
+    check_every: u64,
+    veto: Option<Veto>,
+}
+
+impl AutoReorganize {
+    /// Reorganize once `fragmentation` exceeds `threshold`, a fraction
+    /// between 0 and 1, checked every 1000 writes
+    pub fn new(threshold: f64) -> AutoReorganize {
+        AutoReorganize {
+            threshold,
+            check_every: 1000,
+            veto: None,
+        }
+    }
+
+    /// Check the fragmentation every `writes` stores and deletes. Each
+    /// check reads the whole bucket directory, so keep this well above 1
+    /// for large databases.
+    pub fn check_every(&mut self, writes: u64) -> &mut AutoReorganize {
+        self.check_every = writes.max(1);
+        self
+    }
+
+    /// Call `allow` with the fragmentation before reorganizing, which
+    /// only goes ahead if it returns true; to skip peak hours, say. A
+    /// vetoed reorganize is considered again at the next check.
+    pub fn veto<F>(&mut self, allow: F) -> &mut AutoReorganize
+        where F: Fn(f64) -> bool + Send + Sync + 'static
+    {
+        self.veto = Some(Veto(Arc::new(allow)));
+        self
+    }
+}
+
+/// An `AutoReorganize` and the writes made since its last check
+#[derive(Debug)]
+pub(crate) struct AutoReorganizeState {
+    policy: AutoReorganize,
+    writes: Cell<u64>,
+}
+
+impl Gdbm {
+    /// The share of the file on gdbm's free lists, from 0 to 1: space
+    /// left by deleted and replaced records that `reorganize` would give
+    /// back. Syncs first and reads the free lists from the file.
+    pub fn fragmentation(&self) -> Result<f64, GdbmError> {
+        self.sync()?;
+        let file = ForeignGdbmFile::open(&self.name()?)?;
+        if file.len() == 0 {
+            return Ok(0.0);
+        }
+        Ok(file.free_bytes()? as f64 / file.len() as f64)
+    }
+
+    /// Reorganize if the fragmentation exceeds `threshold`. Returns
+    /// whether it did.
+    pub fn reorganize_if_fragmented(&self, threshold: f64) -> Result<bool, GdbmError> {
+        if self.fragmentation()? <= threshold {
+            return Ok(false);
+        }
+        self.reorganize()?;
+        Ok(true)
+    }
+
+    /// Reorganize automatically as `policy` says, or stop doing so with
+    /// None. The check runs as part of the store or delete that reaches
+    /// the count; its errors are not reported, since that write has
+    /// already succeeded.
+    pub fn set_auto_reorganize(&mut self, policy: Option<AutoReorganize>) {
+        self.auto_reorganize = policy.map(|policy| AutoReorganizeState {
+            policy,
+            writes: Cell::new(0),
+        });
+    }
+
+    /// Count a successful write towards the `AutoReorganize` check
+    pub(crate) fn wrote(&self) {
+        let state = match self.auto_reorganize {
+            Some(ref state) => state,
+            None => return,
+        };
+        let writes = state.writes.get() + 1;
+        if writes < state.policy.check_every {
+            state.writes.set(writes);
+            return;
+        }
+        state.writes.set(0);
+        let fragmentation = match self.fragmentation() {
+            Ok(fragmentation) => fragmentation,
+            Err(_) => return,
+        };
+        if fragmentation <= state.policy.threshold {
+            return;
+        }
+        if let Some(Veto(ref allow)) = state.policy.veto {
+            if !allow(fragmentation) {
+                return;
+            }
+        }
+        let _ = self.reorganize();
+    }
+}
@@ -0,0 +1,131 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, Write};
+use std::str;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::ser::{Error, SerializeMap};
+use serde::{Serialize, Serializer};
+use serde_json;
+
+use {Gdbm, GdbmError};
+
+/// How `Serializable` renders keys and values, which JSON strings cannot
+/// hold as raw bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BytesFormat {
+    /// As UTF-8 text, with invalid sequences replaced by U+FFFD. Readable,
+    /// but binary data does not survive, and distinct keys can collide.
+    Lossy,
+    /// As standard base64, which round-trips any bytes
+    Base64,
+}
+
+/// The whole database as one map from key to value, returned by
+/// `Gdbm::as_serializable`, so that
+/// `serde_json::to_string(&db.as_serializable(BytesFormat::Base64))`
+/// dumps it.
+///
+/// Records are read one at a time while the serializer writes them,
+/// never collected in memory, so the map's length is not given up front.
+/// A failed read becomes a serializer error; `write_to` writes JSON and
+/// returns the read error itself. `Display` writes JSON too, for a quick
+/// look.
+#[derive(Debug, Clone, Copy)]
+pub struct Serializable<'a> {
+    db: &'a Gdbm,
+    format: BytesFormat,
+}
+
+impl Gdbm {
+    /// An adapter that serializes every record as an entry of a map, for
+    /// debugging
+    pub fn as_serializable(&self, format: BytesFormat) -> Serializable<'_> {
+        Serializable { db: self, format }
+    }
+}
+
+impl<'a> Serializable<'a> {
+    fn render<'b>(&self, bytes: &'b [u8]) -> Cow<'b, str> {
+        match self.format {
+            BytesFormat::Lossy => String::from_utf8_lossy(bytes),
+            BytesFormat::Base64 => Cow::Owned(BASE64.encode(bytes)),
+        }
+    }
+
+    /// Serialize the map, keeping a failed read in `read_error` as well
+    /// as reporting it to the serializer
+    fn serialize_map<S: Serializer>(&self,
+                                    serializer: S,
+                                    read_error: &RefCell<Option<GdbmError>>)
+                                    -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        for record in self.db.iter() {
+            let (key, value) = match record {
+                Ok(record) => record,
+                Err(err) => {
+                    let error = S::Error::custom(&err);
+                    *read_error.borrow_mut() = Some(err);
+                    return Err(error);
+                }
+            };
+            map.serialize_entry(&self.render(&key), &self.render(&value))?;
+        }
+        map.end()
+    }
+
+    /// Write the map to `out` as a JSON object
+    pub fn write_to<W: Write>(&self, out: &mut W) -> Result<(), GdbmError> {
+        let json = Json {
+            inner: self,
+            read_error: RefCell::new(None),
+        };
+        if let Err(err) = serde_json::to_writer(&mut *out, &json) {
+            return Err(json.read_error.into_inner().unwrap_or_else(|| io::Error::from(err).into()));
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+impl<'a> Serialize for Serializable<'a> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.serialize_map(serializer, &RefCell::new(None))
+    }
+}
+
+/// `Serializable` as `write_to` serializes it, holding on to a read error
+struct Json<'a, 'b: 'a> {
+    inner: &'a Serializable<'b>,
+    read_error: RefCell<Option<GdbmError>>,
+}
+
+impl<'a, 'b> Serialize for Json<'a, 'b> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_map(serializer, &self.read_error)
+    }
+}
+
+/// Passes serde_json's output on to a `fmt::Formatter`. serde_json only
+/// writes whole UTF-8 sequences, splitting strings at escapes.
+struct FmtWriter<'a, 'b: 'a>(&'a mut fmt::Formatter<'b>);
+
+impl<'a, 'b> Write for FmtWriter<'a, 'b> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let text = str::from_utf8(buf).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        self.0.write_str(text).map_err(|_| io::Error::other("formatter error"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> fmt::Display for Serializable<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        serde_json::to_writer(FmtWriter(f), self).map_err(|_| fmt::Error)
+    }
+}
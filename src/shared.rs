@@ -0,0 +1,290 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+use std::thread;
+use std::time::Duration;
+
+use {Gdbm, GdbmError, StoreOutcome, StoreStats};
+
+/// When a `SharedGdbm` syncs on its own, for databases opened without
+/// `SYNC`. Errors from these syncs are not reported; the next explicit
+/// `sync` returns them.
+#[derive(Debug, Clone, Default)]
+pub struct SyncPolicy {
+    interval: Option<Duration>,
+    writes: Option<u64>,
+}
+
+impl SyncPolicy {
+    /// Never sync automatically
+    pub fn new() -> SyncPolicy {
+        SyncPolicy::default()
+    }
+
+    /// Sync from a background thread every `interval`. The thread stops
+    /// when the last clone of the handle is dropped.
+    pub fn every(&mut self, interval: Duration) -> &mut SyncPolicy {
+        self.interval = Some(interval);
+        self
+    }
+
+    /// Sync after every `writes` writes made through the mirrored
+    /// methods, as part of the write that reaches the count. Writes made
+    /// inside `SharedGdbm::with` are not counted.
+    pub fn after_writes(&mut self, writes: u64) -> &mut SyncPolicy {
+        self.writes = Some(writes.max(1));
+        self
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    db: Mutex<Gdbm>,
+    sync_after_writes: Option<u64>,
+    unsynced_writes: AtomicU64,
+    /// Dropping this wakes the background sync thread so it can exit
+    _stop: Option<mpsc::Sender<()>>,
+}
+
+/// A `Gdbm` handle that can be cloned and shared between threads, for
+/// example as part of a web application's state.
+///
+/// Every call locks an internal mutex for its duration, so calls from
+/// different threads are serialized. gdbm does not allow concurrent
+/// calls on one handle, readers included, so a reader/writer lock would
+/// not help. Clones share the same handle, which is closed when the last
+/// clone is dropped.
+///
+/// A panic inside `with` does not poison the handle: every gdbm call
+/// either completes or fails before the closure regains control, so the
+/// database is left consistent.
+#[derive(Debug, Clone)]
+pub struct SharedGdbm {
+    inner: Arc<Inner>,
+}
+
+impl SharedGdbm {
+    /// Wrap `db` for sharing
+    pub fn new(db: Gdbm) -> SharedGdbm {
+        SharedGdbm {
+            inner: Arc::new(Inner {
+                db: Mutex::new(db),
+                sync_after_writes: None,
+                unsynced_writes: AtomicU64::new(0),
+                _stop: None,
+            }),
+        }
+    }
+
+    /// Wrap `db` for sharing and sync it according to `policy`.
+    pub fn with_sync_policy(db: Gdbm, policy: &SyncPolicy) -> Result<SharedGdbm, GdbmError> {
+        let (stop, stopped) = mpsc::channel();
+        let inner = Arc::new(Inner {
+            db: Mutex::new(db),
+            sync_after_writes: policy.writes,
+            unsynced_writes: AtomicU64::new(0),
+            _stop: policy.interval.map(|_| stop),
+        });
+        if let Some(interval) = policy.interval {
+            let weak = Arc::downgrade(&inner);
+            thread::Builder::new()
+                .name("gdbm-sync".to_string())
+                .spawn(move || background_sync(&weak, &stopped, interval))?;
+        }
+        Ok(SharedGdbm { inner })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Gdbm> {
+        self.inner.db.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Count `writes` towards the `SyncPolicy::after_writes` threshold,
+    /// syncing `db` if it is reached.
+    fn wrote(&self, db: &Gdbm, writes: u64) -> Result<(), GdbmError> {
+        if let Some(threshold) = self.inner.sync_after_writes {
+            let unsynced = self.inner.unsynced_writes.fetch_add(writes, Ordering::SeqCst) + writes;
+            if unsynced >= threshold {
+                self.inner.unsynced_writes.store(0, Ordering::SeqCst);
+                db.sync()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Run `f` with the handle locked, for anything not mirrored here and
+    /// for sequences of calls that must not interleave with other threads.
+    pub fn with<R, F: FnOnce(&mut Gdbm) -> R>(&self, f: F) -> R {
+        f(&mut self.lock())
+    }
+
+    /// See `Gdbm::fetch_data`
+    pub fn fetch_data<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Vec<u8>>, GdbmError> {
+        self.lock().fetch_data(key)
+    }
+
+    /// See `Gdbm::exists`
+    pub fn exists<K: AsRef<[u8]>>(&self, key: K) -> Result<bool, GdbmError> {
+        self.lock().contains(key.as_ref())
+    }
+
+    /// Delete the record under `key`, returning whether there was one.
+    /// Unlike `Gdbm::delete`, errors are reported rather than read as
+    /// "no such key".
+    pub fn delete<K: AsRef<[u8]>>(&self, key: K) -> Result<bool, GdbmError> {
+        let db = self.lock();
+        let deleted = db.delete_bytes(key.as_ref())?;
+        if deleted {
+            self.wrote(&db, 1)?;
+        }
+        Ok(deleted)
+    }
+
+    /// See `Gdbm::fetch_many`
+    pub fn fetch_many<I>(&self, keys: I) -> Result<Vec<Option<Vec<u8>>>, GdbmError>
+        where I: IntoIterator,
+              I::Item: AsRef<[u8]>
+    {
+        self.lock().fetch_many(keys)
+    }
+
+    /// See `Gdbm::store_checked`
+    pub fn store_checked<K, V>(&self, key: K, content: V, replace: bool) -> Result<StoreOutcome, GdbmError>
+        where K: AsRef<[u8]>,
+              V: AsRef<[u8]>
+    {
+        let db = self.lock();
+        let outcome = db.store_checked(key, content, replace)?;
+        self.wrote(&db, 1)?;
+        Ok(outcome)
+    }
+
+    /// See `Gdbm::store_many`
+    pub fn store_many<I, K, V>(&self, records: I, replace: bool) -> Result<StoreStats, GdbmError>
+        where I: IntoIterator<Item = (K, V)>,
+              K: AsRef<[u8]>,
+              V: AsRef<[u8]>
+    {
+        let db = self.lock();
+        let stats = db.store_many(records, replace)?;
+        // store_many has synced already
+        self.inner.unsynced_writes.store(0, Ordering::SeqCst);
+        Ok(stats)
+    }
+
+    /// See `Gdbm::insert`
+    pub fn insert<K, V>(&self, key: K, value: V) -> Result<Option<Vec<u8>>, GdbmError>
+        where K: AsRef<[u8]>,
+              V: AsRef<[u8]>
+    {
+        let db = self.lock();
+        let old = db.insert(key, value)?;
+        self.wrote(&db, 1)?;
+        Ok(old)
+    }
+
+    /// See `Gdbm::remove`
+    pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Vec<u8>>, GdbmError> {
+        let db = self.lock();
+        let old = db.remove(key)?;
+        self.wrote(&db, 1)?;
+        Ok(old)
+    }
+
+    /// See `Gdbm::delete_many`
+    pub fn delete_many<I>(&self, keys: I) -> Result<usize, GdbmError>
+        where I: IntoIterator,
+              I::Item: AsRef<[u8]>
+    {
+        let db = self.lock();
+        let deleted = db.delete_many(keys)?;
+        self.inner.unsynced_writes.store(0, Ordering::SeqCst);
+        Ok(deleted)
+    }
+
+    /// See `Gdbm::fetch_update`. The handle stays locked while `f` runs,
+    /// so the update is atomic with respect to other clones.
+    pub fn fetch_update<K, F>(&self, key: K, f: F) -> Result<Option<Vec<u8>>, GdbmError>
+        where K: AsRef<[u8]>,
+              F: FnOnce(Option<&[u8]>) -> Option<Vec<u8>>
+    {
+        let db = self.lock();
+        let old = db.fetch_update(key, f)?;
+        self.wrote(&db, 1)?;
+        Ok(old)
+    }
+
+    /// See `Gdbm::compare_and_swap`
+    pub fn compare_and_swap<K>(&self,
+                               key: K,
+                               expected: Option<&[u8]>,
+                               new: Option<&[u8]>)
+                               -> Result<Result<(), Option<Vec<u8>>>, GdbmError>
+        where K: AsRef<[u8]>
+    {
+        let db = self.lock();
+        let swapped = db.compare_and_swap(key, expected, new)?;
+        if swapped.is_ok() {
+            self.wrote(&db, 1)?;
+        }
+        Ok(swapped)
+    }
+
+    /// See `Gdbm::incr`
+    pub fn incr<K: AsRef<[u8]>>(&self, key: K, delta: i64) -> Result<i64, GdbmError> {
+        let db = self.lock();
+        let value = db.incr(key, delta)?;
+        self.wrote(&db, 1)?;
+        Ok(value)
+    }
+
+    /// See `Gdbm::decr`
+    pub fn decr<K: AsRef<[u8]>>(&self, key: K, delta: i64) -> Result<i64, GdbmError> {
+        let db = self.lock();
+        let value = db.decr(key, delta)?;
+        self.wrote(&db, 1)?;
+        Ok(value)
+    }
+
+    /// See `Gdbm::keys_stable`
+    pub fn keys(&self) -> Result<Vec<Vec<u8>>, GdbmError> {
+        Ok(self.lock().keys_stable()?.collect())
+    }
+
+    /// See `Gdbm::sync`
+    pub fn sync(&self) -> Result<(), GdbmError> {
+        let db = self.lock();
+        self.inner.unsynced_writes.store(0, Ordering::SeqCst);
+        db.sync()
+    }
+
+    /// Get the handle back, if this is the last clone. With a background
+    /// sync running this can also fail while a sync is in progress.
+    pub fn try_unwrap(self) -> Result<Gdbm, SharedGdbm> {
+        match Arc::try_unwrap(self.inner) {
+            Ok(inner) => Ok(inner.db.into_inner().unwrap_or_else(PoisonError::into_inner)),
+            Err(inner) => Err(SharedGdbm { inner }),
+        }
+    }
+}
+
+impl From<Gdbm> for SharedGdbm {
+    fn from(db: Gdbm) -> SharedGdbm {
+        SharedGdbm::new(db)
+    }
+}
+
+fn background_sync(inner: &Weak<Inner>, stopped: &mpsc::Receiver<()>, interval: Duration) {
+    loop {
+        match stopped.recv_timeout(interval) {
+            Err(RecvTimeoutError::Timeout) => {}
+            _ => return,
+        }
+        let inner = match inner.upgrade() {
+            Some(inner) => inner,
+            None => return,
+        };
+        let db = inner.db.lock().unwrap_or_else(PoisonError::into_inner);
+        inner.unsynced_writes.store(0, Ordering::SeqCst);
+        let _ = db.sync();
+    }
+}
@@ -0,0 +1,291 @@
+use std::cmp::Ordering;
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::Arc;
+use std::{iter, vec};
+
+use {Gdbm, GdbmError};
+
+type CompareFn = dyn Fn(&[u8], &[u8]) -> Ordering + Send + Sync;
+
+#[derive(Clone)]
+struct Compare(Arc<CompareFn>);
+
+impl fmt::Debug for Compare {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Compare")
+    }
+}
+
+/// Distinguishes the spill files of concurrent sorts in one process
+static SPILL_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Default for `SortOptions::max_open_runs`
+const MAX_OPEN_RUNS: usize = 64;
+
+/// How `Gdbm::entries_sorted_with` orders the keys and where it may put
+/// them while sorting.
+#[derive(Debug, Clone, Default)]
+pub struct SortOptions {
+    compare: Option<Compare>,
+    spill_after: Option<usize>,
+    spill_dir: Option<PathBuf>,
+    max_open_runs: Option<usize>,
+}
+
+impl SortOptions {
+    /// Byte-wise key order, everything sorted in memory.
+    pub fn new() -> SortOptions {
+        SortOptions::default()
+    }
+
+    /// Order keys with `compare` instead of byte-wise.
+    pub fn compare<F>(&mut self, compare: F) -> &mut SortOptions
+        where F: Fn(&[u8], &[u8]) -> Ordering + Send + Sync + 'static
+    {
+        self.compare = Some(Compare(Arc::new(compare)));
+        self
+    }
+
+    /// Hold at most `keys` keys in memory while collecting them, writing
+    /// each full batch out to a temporary file as a sorted run. The runs
+    /// are merged while iterating, which keeps one key per run in memory.
+    pub fn spill_after(&mut self, keys: usize) -> &mut SortOptions {
+        self.spill_after = Some(keys.max(1));
+        self
+    }
+
+    /// Directory for the spill files, the system temporary directory by
+    /// default. The files are unlinked as soon as they are created.
+    pub fn spill_dir(&mut self, dir: &Path) -> &mut SortOptions {
+        self.spill_dir = Some(dir.to_path_buf());
+        self
+    }
+
+    /// Keep at most `runs` spill files open, 64 by default. Whenever that
+    /// many runs have been written they are merged into a single run, so
+    /// a large sort stays within the process' file descriptor limit at the
+    /// cost of reading and writing the keys spilled so far once more.
+    pub fn max_open_runs(&mut self, runs: usize) -> &mut SortOptions {
+        self.max_open_runs = Some(runs.max(2));
+        self
+    }
+
+    fn cmp(&self, a: &[u8], b: &[u8]) -> Ordering {
+        match self.compare {
+            Some(Compare(ref compare)) => compare(a, b),
+            None => a.cmp(b),
+        }
+    }
+
+    /// Sort `keys` and write them to a new spill file, leaving `keys`
+    /// empty.
+    fn write_run(&self, keys: &mut Vec<Vec<u8>>) -> Result<Run, GdbmError> {
+        keys.sort_by(|a, b| self.cmp(a, b));
+        self.spill(keys.drain(..).map(Ok))
+    }
+
+    /// Add `run` to `runs`, merging them all into one run once there are
+    /// `max_open_runs` of them.
+    fn push_run(&self, runs: &mut Vec<Run>, run: Run) -> Result<(), GdbmError> {
+        runs.push(run);
+        if runs.len() >= self.max_open_runs.unwrap_or(MAX_OPEN_RUNS) {
+            let merged = self.spill(iter::from_fn(|| pop_smallest(runs, self).transpose()))?;
+            runs.clear();
+            runs.push(merged);
+        }
+        Ok(())
+    }
+
+    /// Write `keys`, which must already be in order, to a new spill file.
+    fn spill<I>(&self, keys: I) -> Result<Run, GdbmError>
+        where I: Iterator<Item = Result<Vec<u8>, GdbmError>>
+    {
+        let dir = match self.spill_dir {
+            Some(ref dir) => dir.clone(),
+            None => env::temp_dir(),
+        };
+        let path = dir.join(format!(".gdbm-sort-{}-{}",
+                                    process::id(),
+                                    SPILL_COUNTER.fetch_add(1, AtomicOrdering::SeqCst)));
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)?;
+        fs::remove_file(&path)?;
+        let mut writer = BufWriter::new(file);
+        for key in keys {
+            let key = key?;
+            // Keys fit in a datum, so their length fits in 32 bits
+            writer.write_all(&(key.len() as u32).to_le_bytes())?;
+            writer.write_all(&key)?;
+        }
+        let mut file = writer.into_inner().map_err(|e| e.into_error())?;
+        file.seek(SeekFrom::Start(0))?;
+        let mut run = Run {
+            reader: BufReader::new(file),
+            head: None,
+        };
+        run.advance()?;
+        Ok(run)
+    }
+}
+
+/// Take the smallest head among `runs`, advancing its run
+fn pop_smallest(runs: &mut [Run], options: &SortOptions) -> Result<Option<Vec<u8>>, GdbmError> {
+    // At most `max_open_runs` runs, so a linear scan for the smallest
+    // head is cheaper than keeping a heap.
+    let mut smallest: Option<usize> = None;
+    for (i, run) in runs.iter().enumerate() {
+        if let Some(ref head) = run.head {
+            let smaller = match smallest {
+                Some(j) => {
+                    let current = runs[j].head.as_ref().expect("smallest run has a head");
+                    options.cmp(head, current) == Ordering::Less
+                }
+                None => true,
+            };
+            if smaller {
+                smallest = Some(i);
+            }
+        }
+    }
+    match smallest {
+        Some(i) => {
+            let key = runs[i].head.take();
+            runs[i].advance()?;
+            Ok(key)
+        }
+        None => Ok(None),
+    }
+}
+
+/// One sorted spill file and its smallest unconsumed key
+#[derive(Debug)]
+struct Run {
+    reader: BufReader<File>,
+    head: Option<Vec<u8>>,
+}
+
+impl Run {
+    fn advance(&mut self) -> io::Result<()> {
+        self.head = if self.reader.fill_buf()?.is_empty() {
+            None
+        } else {
+            let mut len = [0; 4];
+            self.reader.read_exact(&mut len)?;
+            let mut key = vec![0; u32::from_le_bytes(len) as usize];
+            self.reader.read_exact(&mut key)?;
+            Some(key)
+        };
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+enum SortedKeys {
+    Memory(vec::IntoIter<Vec<u8>>),
+    Runs(Vec<Run>),
+    Done,
+}
+
+/// Iterator over the records of a database in key order, returned by
+/// `Gdbm::entries_sorted` and `Gdbm::entries_sorted_with`.
+///
+/// The keys are collected when the iterator is created and each value
+/// is fetched as its key comes up; records deleted in between are
+/// skipped. Iteration stops after the first error.
+#[derive(Debug)]
+pub struct SortedEntries<'a> {
+    db: &'a Gdbm,
+    options: SortOptions,
+    keys: SortedKeys,
+}
+
+impl Gdbm {
+    /// Iterate over the records in byte-wise key order. All keys are
+    /// held in memory; see `entries_sorted_with` for other orders and
+    /// for very large databases.
+    pub fn entries_sorted(&self) -> Result<SortedEntries<'_>, GdbmError> {
+        self.entries_sorted_with(&SortOptions::new())
+    }
+
+    /// Iterate over the records in the order given by `options`.
+    pub fn entries_sorted_with(&self, options: &SortOptions) -> Result<SortedEntries<'_>, GdbmError> {
+        let mut runs = Vec::new();
+        let mut batch = Vec::new();
+        for key in self.keys() {
+            batch.push(key?);
+            if options.spill_after.is_some_and(|limit| batch.len() >= limit) {
+                let run = options.write_run(&mut batch)?;
+                options.push_run(&mut runs, run)?;
+            }
+        }
+        let keys = if runs.is_empty() {
+            batch.sort_by(|a, b| options.cmp(a, b));
+            SortedKeys::Memory(batch.into_iter())
+        } else {
+            if !batch.is_empty() {
+                let run = options.write_run(&mut batch)?;
+                options.push_run(&mut runs, run)?;
+            }
+            SortedKeys::Runs(runs)
+        };
+        Ok(SortedEntries {
+            db: self,
+            options: options.clone(),
+            keys,
+        })
+    }
+}
+
+impl<'a> SortedEntries<'a> {
+    fn next_key(&mut self) -> Result<Option<Vec<u8>>, GdbmError> {
+        match self.keys {
+            SortedKeys::Memory(ref mut keys) => Ok(keys.next()),
+            SortedKeys::Runs(ref mut runs) => pop_smallest(runs, &self.options),
+            SortedKeys::Done => Ok(None),
+        }
+    }
+}
+
+impl<'a> Iterator for SortedEntries<'a> {
+    type Item = Result<(Vec<u8>, Vec<u8>), GdbmError>;
+
+    fn next(&mut self) -> Option<Result<(Vec<u8>, Vec<u8>), GdbmError>> {
+        loop {
+            let result = match self.next_key() {
+                Ok(Some(key)) => self.db.fetch_bytes(&key).map(|value| value.map(|value| (key, value))),
+                Ok(None) => {
+                    self.keys = SortedKeys::Done;
+                    return None;
+                }
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(Some(record)) => return Some(Ok(record)),
+                Ok(None) => continue,
+                Err(e) => {
+                    self.keys = SortedKeys::Done;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.keys {
+            SortedKeys::Memory(ref keys) => (0, Some(keys.len())),
+            SortedKeys::Runs(_) => (0, None),
+            SortedKeys::Done => (0, Some(0)),
+        }
+    }
+}
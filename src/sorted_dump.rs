@@ -0,0 +1,148 @@
+use std::io::{self, BufReader, BufWriter, Read, Write};
+
+#[cfg(feature = "hash")]
+use sha2::{Digest, Sha256};
+use {Gdbm, GdbmError, ImportFlag};
+
+/// First bytes of a dump written by `Gdbm::export_sorted`
+const SORTED_DUMP_MAGIC: &[u8; 12] = b"gdbm-sorted\n";
+
+/// Layout version of the dumps `Gdbm::export_sorted` writes, stored after
+/// the magic. Bumped whenever the layout changes, so that dumps of equal
+/// databases written by the same version are byte-identical.
+pub const SORTED_DUMP_VERSION: u32 = 1;
+
+/// Length field marking the end of the records, followed by their count
+const END_MARKER: u32 = u32::MAX;
+
+fn corrupt(message: impl Into<String>) -> GdbmError {
+    GdbmError::ImportError {
+        line: 0,
+        message: message.into(),
+    }
+}
+
+fn write_field<W: Write>(writer: &mut W, bytes: &[u8]) -> Result<(), GdbmError> {
+    if bytes.len() >= END_MARKER as usize {
+        return Err(GdbmError::TooLarge {
+            what: "record",
+            size: bytes.len(),
+            limit: END_MARKER as usize - 1,
+        });
+    }
+    writer.write_all(&(bytes.len() as u32).to_be_bytes())?;
+    writer.write_all(bytes)?;
+    Ok(())
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32, GdbmError> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf).map_err(truncated)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_field<R: Read>(reader: &mut R, len: u32) -> Result<Vec<u8>, GdbmError> {
+    let mut field = Vec::new();
+    reader.take(len as u64).read_to_end(&mut field)?;
+    if field.len() != len as usize {
+        return Err(corrupt("sorted dump is truncated"));
+    }
+    Ok(field)
+}
+
+fn truncated(err: io::Error) -> GdbmError {
+    if err.kind() == io::ErrorKind::UnexpectedEof {
+        corrupt("sorted dump is truncated")
+    } else {
+        err.into()
+    }
+}
+
+impl Gdbm {
+    /// Write the records to `writer` in byte-wise key order, in a layout
+    /// that depends on nothing but the records: two databases with the
+    /// same content always give the same bytes, whatever their block
+    /// size, history or gdbm version. Meant for backups that are
+    /// deduplicated or compared by hash.
+    ///
+    /// The layout is the magic `gdbm-sorted\n`, the big-endian u32
+    /// `SORTED_DUMP_VERSION`, then per record the big-endian u32 length
+    /// and bytes of the key and of the value, and finally the u32
+    /// `0xffffffff` and the record count as a big-endian u64. Returns the
+    /// number of records written. All keys are held in memory while
+    /// sorting, as `entries_sorted` does.
+    pub fn export_sorted<W: Write>(&self, writer: &mut W) -> Result<u64, GdbmError> {
+        let mut writer = BufWriter::new(writer);
+        writer.write_all(SORTED_DUMP_MAGIC)?;
+        writer.write_all(&SORTED_DUMP_VERSION.to_be_bytes())?;
+        let mut count = 0u64;
+        for record in self.entries_sorted()? {
+            let (key, value) = record?;
+            write_field(&mut writer, &key)?;
+            write_field(&mut writer, &value)?;
+            count += 1;
+        }
+        writer.write_all(&END_MARKER.to_be_bytes())?;
+        writer.write_all(&count.to_be_bytes())?;
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// SHA-256 of the records, to check that replicas or backups hold the
+    /// same data without comparing them record by record. It is the hash
+    /// of what `export_sorted` writes, so it equals the `sha256sum` of a
+    /// sorted dump, and it changes with `SORTED_DUMP_VERSION`. Needs the
+    /// `hash` feature.
+    #[cfg(feature = "hash")]
+    pub fn content_hash(&self) -> Result<[u8; 32], GdbmError> {
+        let mut hasher = Sha256::new();
+        self.export_sorted(&mut hasher)?;
+        Ok(hasher.finalize().into())
+    }
+
+    /// Load a dump written by `export_sorted` into this database, syncing
+    /// once at the end.
+    ///
+    /// Returns the number of records stored. With `ImportFlag::Insert` a
+    /// key the database already has fails the import. A malformed or
+    /// truncated dump, or one of an unknown version, is reported as
+    /// `GdbmError::ImportError` with line 0. The records loaded before
+    /// the error stay stored.
+    pub fn import_sorted<R: Read>(&self, reader: &mut R, flag: ImportFlag) -> Result<u64, GdbmError> {
+        let mut reader = BufReader::new(reader);
+        let mut magic = [0; 12];
+        reader.read_exact(&mut magic).map_err(truncated)?;
+        if magic != *SORTED_DUMP_MAGIC {
+            return Err(corrupt("not a sorted dump"));
+        }
+        let version = read_u32(&mut reader)?;
+        if version != SORTED_DUMP_VERSION {
+            return Err(corrupt(format!("unsupported sorted dump version {}", version)));
+        }
+        self.with_deferred_sync(|| {
+            let mut records = 0u64;
+            loop {
+                let len = read_u32(&mut reader)?;
+                if len == END_MARKER {
+                    break;
+                }
+                let key = read_field(&mut reader, len)?;
+                let len = read_u32(&mut reader)?;
+                if len == END_MARKER {
+                    return Err(corrupt("sorted dump ends in the middle of a record"));
+                }
+                let value = read_field(&mut reader, len)?;
+                if !self.store_bytes(&key, &value, flag.to_store())? {
+                    return Err(corrupt(format!("key {:?} already exists", String::from_utf8_lossy(&key))));
+                }
+                records += 1;
+            }
+            let mut count = [0; 8];
+            reader.read_exact(&mut count).map_err(truncated)?;
+            if u64::from_be_bytes(count) != records {
+                return Err(corrupt("sorted dump record count does not match"));
+            }
+            Ok(records)
+        })
+    }
+}
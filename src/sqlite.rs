@@ -0,0 +1,42 @@
+use std::path::Path;
+
+use rusqlite::Connection;
+
+use {Gdbm, GdbmError};
+
+fn sqlite_error(err: rusqlite::Error) -> GdbmError {
+    GdbmError::new(format!("sqlite: {}", err))
+}
+
+/// `name` quoted as an SQL identifier
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+impl Gdbm {
+    /// Copy every record into a new table `table` of the SQLite database
+    /// at `path`, which is created if needed. The table has a `key BLOB
+    /// PRIMARY KEY` and a `value BLOB` column, and is filled in one
+    /// transaction, so it is left out entirely if the export fails. Fails
+    /// if the table already exists. Returns the number of records copied.
+    pub fn export_sqlite(&self, path: &Path, table: &str) -> Result<u64, GdbmError> {
+        let mut connection = Connection::open(path).map_err(sqlite_error)?;
+        let table = quote_identifier(table);
+        // Dropping the transaction on an error rolls it back
+        let transaction = connection.transaction().map_err(sqlite_error)?;
+        transaction.execute(&format!("CREATE TABLE {} (key BLOB PRIMARY KEY, value BLOB)", table), [])
+            .map_err(sqlite_error)?;
+        let mut records = 0;
+        {
+            let mut insert = transaction.prepare(&format!("INSERT INTO {} (key, value) VALUES (?1, ?2)", table))
+                .map_err(sqlite_error)?;
+            for record in self.iter() {
+                let (key, value) = record?;
+                insert.execute((&key, &value)).map_err(sqlite_error)?;
+                records += 1;
+            }
+        }
+        transaction.commit().map_err(sqlite_error)?;
+        Ok(records)
+    }
+}
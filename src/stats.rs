@@ -0,0 +1,336 @@
+//! Reports on what a database holds, for capacity planning: how value
+//! sizes are distributed, which records are largest and how much of the
+//! file is dead space. Each report scans the whole database and can be
+//! printed for a log or, with the `json` feature, converted to JSON.
+//!
+//! `WriteAmplification` is the exception: rather than scanning, the
+//! layers that add bytes to what they store count their writes as they
+//! make them.
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[cfg(feature = "json")]
+use std::str;
+
+#[cfg(feature = "json")]
+use base64::engine::general_purpose::STANDARD as BASE64;
+#[cfg(feature = "json")]
+use base64::Engine;
+#[cfg(feature = "json")]
+use serde_json::Value;
+
+use foreign::ForeignGdbmFile;
+use {Gdbm, GdbmError};
+
+/// Value sizes in a range of a `ValueHistogram`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizeRange {
+    /// Smallest size in the range, in bytes
+    pub min: usize,
+    /// Largest size in the range, in bytes
+    pub max: usize,
+    /// Number of values with a size in the range
+    pub count: u64,
+}
+
+/// The distribution of value sizes, returned by `value_histogram`.
+/// Sizes are grouped in powers of two: 0, 1, 2 to 3, 4 to 7 and so on.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValueHistogram {
+    /// Number of records
+    pub records: u64,
+    /// Bytes of all values together
+    pub total_bytes: u64,
+    /// Smallest value size, 0 for an empty database
+    pub min: usize,
+    /// Largest value size, 0 for an empty database
+    pub max: usize,
+    /// The ranges holding at least one value, smallest first
+    pub ranges: Vec<SizeRange>,
+}
+
+/// A record's key and value size, one of the `LargestEntries`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizedKey {
+    pub key: Vec<u8>,
+    /// Size of the value in bytes
+    pub value_size: usize,
+}
+
+/// The records with the largest values, returned by `largest_entries`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LargestEntries {
+    /// Number of records scanned
+    pub records: u64,
+    /// The largest records, largest value first; records of equal size
+    /// are in key order
+    pub entries: Vec<SizedKey>,
+}
+
+/// How the database file is used, returned by `space`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SpaceStats {
+    /// Size of the file in bytes
+    pub file_size: u64,
+    /// Bytes of the keys and values of the records
+    pub live_bytes: u64,
+    /// Bytes on gdbm's free lists, left by deleted and replaced records
+    pub free_bytes: u64,
+    /// `free_bytes` as a share of `file_size`, from 0 to 1; see
+    /// `Gdbm::fragmentation`
+    pub fragmentation: f64,
+}
+
+/// Bytes a layer has stored in gdbm against the bytes it was given to
+/// write, returned by the `write_amplification` method of
+/// `ChecksummedGdbm`, `SecondaryIndex`, `CompressedGdbm` and
+/// `EncryptedGdbm`, to weigh what a layer costs before enabling it.
+///
+/// Both counts are of keys and values together. gdbm's own overhead,
+/// buckets and free lists, comes on top and is not counted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WriteAmplification {
+    /// Bytes of the keys and values passed to the layer's writes
+    pub logical_bytes: u64,
+    /// Bytes of the keys and values the layer stored in gdbm for them
+    pub stored_bytes: u64,
+}
+
+/// The counters behind `WriteAmplification`, relaxed atomics like
+/// `OpStats`'s
+#[derive(Debug, Default)]
+pub(crate) struct WriteCounters {
+    logical_bytes: AtomicU64,
+    stored_bytes: AtomicU64,
+}
+
+impl WriteCounters {
+    /// Count a write of `logical` bytes of user data that took `stored`
+    /// bytes in gdbm
+    pub(crate) fn add(&self, logical: usize, stored: usize) {
+        self.logical_bytes.fetch_add(logical as u64, Ordering::Relaxed);
+        self.stored_bytes.fetch_add(stored as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> WriteAmplification {
+        WriteAmplification {
+            logical_bytes: self.logical_bytes.load(Ordering::Relaxed),
+            stored_bytes: self.stored_bytes.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn reset(&self) {
+        self.logical_bytes.store(0, Ordering::Relaxed);
+        self.stored_bytes.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Index of the power of two range holding `size`
+fn range_index(size: usize) -> usize {
+    (usize::BITS - size.leading_zeros()) as usize
+}
+
+fn range_bounds(index: usize) -> (usize, usize) {
+    match index {
+        0 => (0, 0),
+        _ => (1 << (index - 1), usize::MAX >> (usize::BITS as usize - index)),
+    }
+}
+
+/// Scan the database and count its values by size
+pub fn value_histogram(db: &Gdbm) -> Result<ValueHistogram, GdbmError> {
+    let mut histogram = ValueHistogram::default();
+    let mut counts = vec![0u64; usize::BITS as usize + 1];
+    for record in db.iter() {
+        let (_, value) = record?;
+        let size = value.len();
+        if histogram.records == 0 || size < histogram.min {
+            histogram.min = size;
+        }
+        histogram.max = histogram.max.max(size);
+        histogram.records += 1;
+        histogram.total_bytes += size as u64;
+        counts[range_index(size)] += 1;
+    }
+    histogram.ranges = counts
+        .into_iter()
+        .enumerate()
+        .filter(|&(_, count)| count > 0)
+        .map(|(index, count)| {
+            let (min, max) = range_bounds(index);
+            SizeRange { min, max, count }
+        })
+        .collect();
+    Ok(histogram)
+}
+
+/// Scan the database for the `n` records with the largest values. Keeps
+/// only `n` keys in memory.
+pub fn largest_entries(db: &Gdbm, n: usize) -> Result<LargestEntries, GdbmError> {
+    let mut records = 0;
+    // Smallest of the largest on top, ties broken towards later keys
+    let mut heap = BinaryHeap::with_capacity(n + 1);
+    for record in db.iter() {
+        let (key, value) = record?;
+        records += 1;
+        if n == 0 {
+            continue;
+        }
+        heap.push((Reverse(value.len()), key));
+        if heap.len() > n {
+            heap.pop();
+        }
+    }
+    let entries = heap
+        .into_sorted_vec()
+        .into_iter()
+        .map(|(Reverse(value_size), key)| SizedKey { key, value_size })
+        .collect();
+    Ok(LargestEntries { records, entries })
+}
+
+/// Sync the database and read how its file is used. The rest of the
+/// file, neither live nor free, is gdbm's header, bucket directory and
+/// buckets. Reorganizing gives back about `free_bytes`.
+pub fn space(db: &Gdbm) -> Result<SpaceStats, GdbmError> {
+    db.sync()?;
+    let file = ForeignGdbmFile::open(&db.name()?)?;
+    let free_bytes = file.free_bytes()?;
+    Ok(SpaceStats {
+        file_size: file.len(),
+        live_bytes: file.live_bytes()?,
+        free_bytes,
+        fragmentation: if file.len() == 0 { 0.0 } else { free_bytes as f64 / file.len() as f64 },
+    })
+}
+
+impl ValueHistogram {
+    /// Mean value size, 0 for an empty database
+    pub fn mean(&self) -> f64 {
+        if self.records == 0 {
+            return 0.0;
+        }
+        self.total_bytes as f64 / self.records as f64
+    }
+
+    /// The JSON form of the histogram
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> Value {
+        let ranges: Vec<Value> = self
+            .ranges
+            .iter()
+            .map(|range| json!({"min": range.min, "max": range.max, "count": range.count}))
+            .collect();
+        json!({
+            "records": self.records,
+            "total_bytes": self.total_bytes,
+            "min": self.min,
+            "max": self.max,
+            "ranges": ranges,
+        })
+    }
+}
+
+impl LargestEntries {
+    /// The JSON form of the report. A key that is not UTF-8 is written
+    /// base64 encoded, as `key_base64` instead of `key`, as in
+    /// `Gdbm::export_jsonl`.
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> Value {
+        let entries: Vec<Value> = self
+            .entries
+            .iter()
+            .map(|entry| match str::from_utf8(&entry.key) {
+                Ok(key) => json!({"key": key, "value_size": entry.value_size}),
+                Err(_) => json!({"key_base64": BASE64.encode(&entry.key), "value_size": entry.value_size}),
+            })
+            .collect();
+        json!({"records": self.records, "entries": entries})
+    }
+}
+
+impl SpaceStats {
+    /// The JSON form of the report
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> Value {
+        json!({
+            "file_size": self.file_size,
+            "live_bytes": self.live_bytes,
+            "free_bytes": self.free_bytes,
+            "fragmentation": self.fragmentation,
+        })
+    }
+}
+
+impl WriteAmplification {
+    /// Stored bytes per logical byte, 1 before anything was written.
+    /// Below 1 when the layer shrinks what it stores, as compression does.
+    pub fn ratio(&self) -> f64 {
+        if self.logical_bytes == 0 {
+            return 1.0;
+        }
+        self.stored_bytes as f64 / self.logical_bytes as f64
+    }
+
+    /// The JSON form of the counts
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> Value {
+        json!({
+            "logical_bytes": self.logical_bytes,
+            "stored_bytes": self.stored_bytes,
+            "ratio": self.ratio(),
+        })
+    }
+}
+
+impl fmt::Display for ValueHistogram {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} value(s), {} bytes", self.records, self.total_bytes)?;
+        if self.records == 0 {
+            return Ok(());
+        }
+        write!(f, ", {} to {} bytes each, {:.1} on average:", self.min, self.max, self.mean())?;
+        for range in &self.ranges {
+            write!(f, "\n  {} to {} bytes: {}", range.min, range.max, range.count)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for SpaceStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "{} bytes in the file, {} live, {} free ({:.1}% fragmented)",
+               self.file_size,
+               self.live_bytes,
+               self.free_bytes,
+               self.fragmentation * 100.0)
+    }
+}
+
+impl fmt::Display for LargestEntries {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "largest {} of {} value(s):", self.entries.len(), self.records)?;
+        for entry in &self.entries {
+            write!(f, "\n  {} bytes \"", entry.value_size)?;
+            for byte in entry.key.iter().flat_map(|b| ::std::ascii::escape_default(*b)) {
+                write!(f, "{}", byte as char)?;
+            }
+            write!(f, "\"")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for WriteAmplification {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "{} bytes stored for {} bytes written ({:.2}x)",
+               self.stored_bytes,
+               self.logical_bytes,
+               self.ratio())
+    }
+}
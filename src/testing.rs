@@ -0,0 +1,81 @@
+//! Stand-ins for a database in unit tests.
+//!
+//! Write application code against `KvStore`, pass it a `Gdbm` in
+//! production and a `MemStore` in tests:
+//!
+//! ```
+//! use gdbm::KvStore;
+//! use gdbm::testing::MemStore;
+//!
+//! fn remember(store: &dyn KvStore, name: &str) -> Result<(), gdbm::GdbmError> {
+//!     store.insert(name.as_bytes(), b"seen")?;
+//!     Ok(())
+//! }
+//!
+//! let store = MemStore::new();
+//! remember(&store, "alice").unwrap();
+//! assert_eq!(store.get(b"alice").unwrap(), Some(b"seen".to_vec()));
+//! ```
+
+use std::collections::BTreeMap;
+use std::sync::{Mutex, MutexGuard};
+
+use kv::{KvIter, KvStore};
+use GdbmError;
+
+type Map = BTreeMap<Vec<u8>, Vec<u8>>;
+
+/// A `KvStore` held in memory, safe to share between threads
+#[derive(Debug, Default)]
+pub struct MemStore {
+    records: Mutex<Map>,
+}
+
+impl MemStore {
+    /// An empty store
+    pub fn new() -> MemStore {
+        MemStore::default()
+    }
+
+    fn records(&self) -> MutexGuard<'_, Map> {
+        // A panic elsewhere cannot leave the map half updated
+        self.records.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<K, V> ::std::iter::FromIterator<(K, V)> for MemStore
+    where K: AsRef<[u8]>,
+          V: AsRef<[u8]>
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(records: I) -> MemStore {
+        let records = records
+            .into_iter()
+            .map(|(key, value)| (key.as_ref().to_vec(), value.as_ref().to_vec()))
+            .collect();
+        MemStore { records: Mutex::new(records) }
+    }
+}
+
+impl KvStore for MemStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, GdbmError> {
+        Ok(self.records().get(key).cloned())
+    }
+
+    fn insert(&self, key: &[u8], value: &[u8]) -> Result<Option<Vec<u8>>, GdbmError> {
+        Ok(self.records().insert(key.to_vec(), value.to_vec()))
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<Option<Vec<u8>>, GdbmError> {
+        Ok(self.records().remove(key))
+    }
+
+    /// Iterates over a snapshot taken by this call, ordered by key
+    fn iter(&self) -> KvIter<'_> {
+        let snapshot: Vec<_> = self.records().clone().into_iter().map(Ok).collect();
+        Box::new(snapshot.into_iter())
+    }
+
+    fn len(&self) -> Result<u64, GdbmError> {
+        Ok(self.records().len() as u64)
+    }
+}
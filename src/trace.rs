@@ -0,0 +1,41 @@
+use std::cell::RefCell;
+use std::sync::Arc;
+
+thread_local! {
+    static TRACE_ID: RefCell<Option<Arc<str>>> = const { RefCell::new(None) };
+}
+
+/// Puts back the trace id a scope replaced, even if the scope panics
+struct Restore(Option<Arc<str>>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        TRACE_ID.with(|id| *id.borrow_mut() = previous);
+    }
+}
+
+/// Run `f` with `id` as the trace id of every operation it makes on this
+/// thread, restoring the previous id afterwards.
+///
+/// The id is opaque to gdbm: typically the id of the application request
+/// being served, so that what it did can be picked out of the logs. It
+/// is passed to `AuditSink`s in `AuditRecord::trace_id`, and follows
+/// commands sent to a `GdbmWriterActor` or an `AsyncGdbm` onto their
+/// threads. Wrappers such as `EncryptedGdbm` work on the calling thread,
+/// so operations through them carry it as well.
+pub fn with_trace_id<T, F: FnOnce() -> T>(id: &str, f: F) -> T {
+    in_trace_scope(Some(Arc::from(id)), f)
+}
+
+/// The trace id set by the innermost `with_trace_id` on this thread
+pub fn current_trace_id() -> Option<Arc<str>> {
+    TRACE_ID.with(|id| id.borrow().clone())
+}
+
+/// Run `f` with trace id `id`, which may be none, as a command carried to
+/// another thread does
+pub(crate) fn in_trace_scope<T, F: FnOnce() -> T>(id: Option<Arc<str>>, f: F) -> T {
+    let _restore = Restore(TRACE_ID.with(|current| current.replace(id)));
+    f()
+}
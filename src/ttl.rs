@@ -0,0 +1,185 @@
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use {Gdbm, GdbmError, SharedGdbm};
+
+/// Bytes of expiry time in front of every value
+const EXPIRY_LEN: usize = 8;
+
+/// Stored in place of an expiry time for records that never expire
+const NEVER: u64 = 0;
+
+/// A database whose records can expire, for sessions and caches.
+///
+/// Every value is stored behind an 8 byte little endian expiry time, in
+/// milliseconds since the Unix epoch, or 0 for records that never
+/// expire. Expired records read as absent but stay in the file until
+/// `purge_expired` runs, by hand or from a sweeper thread. Since the
+/// layout differs from plain records, only write to the database through
+/// this wrapper.
+///
+/// The handle is a `SharedGdbm`, so a `TtlGdbm` can be cloned and used
+/// from several threads.
+#[derive(Debug, Clone)]
+pub struct TtlGdbm {
+    db: SharedGdbm,
+}
+
+/// A background thread purging expired records, returned by
+/// `TtlGdbm::spawn_sweeper`. Dropping it stops the thread and waits for
+/// it to finish.
+#[derive(Debug)]
+pub struct Sweeper {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+fn encode(value: &[u8], expires: u64) -> Vec<u8> {
+    let mut stored = Vec::with_capacity(EXPIRY_LEN + value.len());
+    stored.extend_from_slice(&expires.to_le_bytes());
+    stored.extend_from_slice(value);
+    stored
+}
+
+/// Split a stored record into its expiry time and value
+fn decode(stored: &[u8]) -> Result<(u64, &[u8]), GdbmError> {
+    if stored.len() < EXPIRY_LEN {
+        return Err(GdbmError::new("record is too short to hold an expiry time"));
+    }
+    let mut expires = [0; EXPIRY_LEN];
+    expires.copy_from_slice(&stored[..EXPIRY_LEN]);
+    Ok((u64::from_le_bytes(expires), &stored[EXPIRY_LEN..]))
+}
+
+fn is_expired(expires: u64, now: u64) -> bool {
+    expires != NEVER && expires <= now
+}
+
+/// The live value of a stored record, None if it has expired
+fn live(stored: Option<Vec<u8>>) -> Result<Option<Vec<u8>>, GdbmError> {
+    match stored {
+        Some(stored) => {
+            let (expires, value) = decode(&stored)?;
+            if is_expired(expires, now_millis()) {
+                Ok(None)
+            } else {
+                Ok(Some(value.to_vec()))
+            }
+        }
+        None => Ok(None),
+    }
+}
+
+impl TtlGdbm {
+    /// Wrap `db`
+    pub fn new(db: Gdbm) -> TtlGdbm {
+        TtlGdbm { db: SharedGdbm::new(db) }
+    }
+
+    /// Wrap a handle that is already shared
+    pub fn from_shared(db: SharedGdbm) -> TtlGdbm {
+        TtlGdbm { db }
+    }
+
+    /// The shared handle, for operations on the stored records
+    pub fn as_shared(&self) -> &SharedGdbm {
+        &self.db
+    }
+
+    /// Look up `key`, treating an expired record as absent
+    pub fn get<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Vec<u8>>, GdbmError> {
+        live(self.db.fetch_data(key)?)
+    }
+
+    /// Store `value` under `key` with no expiry, returning the live value
+    /// it replaced.
+    pub fn insert<K, V>(&self, key: K, value: V) -> Result<Option<Vec<u8>>, GdbmError>
+        where K: AsRef<[u8]>,
+              V: AsRef<[u8]>
+    {
+        live(self.db.insert(key, encode(value.as_ref(), NEVER))?)
+    }
+
+    /// Store `value` under `key`, expiring `ttl` from now, returning the
+    /// live value it replaced.
+    pub fn insert_with_ttl<K, V>(&self, key: K, value: V, ttl: Duration) -> Result<Option<Vec<u8>>, GdbmError>
+        where K: AsRef<[u8]>,
+              V: AsRef<[u8]>
+    {
+        let expires = now_millis().saturating_add(ttl.as_millis() as u64).max(1);
+        live(self.db.insert(key, encode(value.as_ref(), expires))?)
+    }
+
+    /// Delete `key`, returning its value if it had not expired
+    pub fn remove<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Vec<u8>>, GdbmError> {
+        live(self.db.remove(key)?)
+    }
+
+    /// Time left before `key` expires. None if it has no live record,
+    /// `Some(None)` if it never expires.
+    pub fn ttl<K: AsRef<[u8]>>(&self, key: K) -> Result<Option<Option<Duration>>, GdbmError> {
+        let stored = match self.db.fetch_data(key)? {
+            Some(stored) => stored,
+            None => return Ok(None),
+        };
+        let (expires, _) = decode(&stored)?;
+        let now = now_millis();
+        if expires == NEVER {
+            Ok(Some(None))
+        } else if is_expired(expires, now) {
+            Ok(None)
+        } else {
+            Ok(Some(Some(Duration::from_millis(expires - now))))
+        }
+    }
+
+    /// Delete every expired record, returning how many there were. The
+    /// handle stays locked for the whole scan.
+    pub fn purge_expired(&self) -> Result<usize, GdbmError> {
+        self.db.with(|db| {
+            let now = now_millis();
+            let mut expired = Vec::new();
+            for record in db.iter() {
+                let (key, stored) = record?;
+                if is_expired(decode(&stored)?.0, now) {
+                    expired.push(key);
+                }
+            }
+            db.delete_many(expired)
+        })
+    }
+
+    /// Purge expired records every `interval` from a background thread,
+    /// until the returned `Sweeper` is dropped. Errors are ignored; the
+    /// next sweep tries again.
+    pub fn spawn_sweeper(&self, interval: Duration) -> Result<Sweeper, GdbmError> {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let ttl = self.clone();
+        let thread = thread::Builder::new()
+            .name("gdbm-ttl-sweeper".to_string())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    let _ = ttl.purge_expired();
+                }
+            })?;
+        Ok(Sweeper {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for Sweeper {
+    fn drop(&mut self) {
+        // Disconnecting the channel wakes the thread up
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
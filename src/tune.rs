@@ -0,0 +1,93 @@
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+/// Smallest block size gdbm accepts
+const MIN_BLOCK_SIZE: u32 = 512;
+
+/// Largest block size `recommended_block_size` picks. Larger blocks make
+/// every bucket read and write slower without saving much space.
+const MAX_BLOCK_SIZE: u32 = 64 * 1024;
+
+/// Records a block should hold at the 90th percentile record size
+const RECORDS_PER_BLOCK: usize = 8;
+
+/// Bucket header and element sizes of a 64-bit gdbm file
+const BUCKET_HEADER_LEN: usize = 112;
+const BUCKET_ELEMENT_LEN: usize = 24;
+
+/// Bounds for the cache size `OpenOptions::tune_for` sets, in buckets
+const MIN_CACHE_SIZE: usize = 100;
+const MAX_CACHE_SIZE: usize = 16384;
+
+/// The records a database is expected to hold, for
+/// `OpenOptions::tune_for`
+#[derive(Debug, Clone)]
+pub struct ValueProfile {
+    sizes: Vec<usize>,
+    records: Option<u64>,
+}
+
+impl ValueProfile {
+    /// Records of about `size` bytes, key and value together
+    pub fn new(size: usize) -> ValueProfile {
+        ValueProfile::from_samples(vec![size])
+    }
+
+    /// Records whose sizes, key and value together, are distributed like
+    /// `sizes`, a sample of existing records say
+    pub fn from_samples(sizes: Vec<usize>) -> ValueProfile {
+        ValueProfile { sizes, records: None }
+    }
+
+    /// About `records` records are expected, which sizes the bucket cache
+    pub fn records(&mut self, records: u64) -> &mut ValueProfile {
+        self.records = Some(records);
+        self
+    }
+
+    /// The block size for a database created at `path`
+    pub(crate) fn block_size(&self, path: &Path) -> u32 {
+        recommended_block_size(&self.sizes, fs_block_size(path))
+    }
+
+    /// The cache size for `block_size`: enough buckets to hold the
+    /// expected records, with buckets about half full after splits
+    pub(crate) fn cache_size(&self, block_size: u32) -> Option<usize> {
+        let elements = (block_size as usize).saturating_sub(BUCKET_HEADER_LEN) / BUCKET_ELEMENT_LEN;
+        let buckets = self.records? as usize / (elements / 2).max(1);
+        Some(buckets.clamp(MIN_CACHE_SIZE, MAX_CACHE_SIZE))
+    }
+}
+
+/// A gdbm block size for records, key and value together, of the sizes in
+/// `sample_value_sizes`, on a file system with blocks of `fs_block` bytes.
+///
+/// The pick is a power of two and a multiple of `fs_block` that holds
+/// eight records of the 90th percentile size, between `fs_block` and
+/// 64 KiB: large enough that most records do not span blocks, small
+/// enough that bucket I/O stays cheap. Without samples it is `fs_block`.
+pub fn recommended_block_size(sample_value_sizes: &[usize], fs_block: u32) -> u32 {
+    let fs_block = fs_block.max(MIN_BLOCK_SIZE);
+    let mut sizes = sample_value_sizes.to_vec();
+    sizes.sort_unstable();
+    let p90 = match sizes.len() {
+        0 => 0,
+        n => sizes[(n * 9 / 10).min(n - 1)],
+    };
+    let target = p90.saturating_mul(RECORDS_PER_BLOCK).min(MAX_BLOCK_SIZE as usize) as u32;
+    let block = target.max(fs_block).next_power_of_two().min(MAX_BLOCK_SIZE.max(fs_block));
+    block.div_ceil(fs_block) * fs_block
+}
+
+/// The block size of the file system `path` is, or would be created, on
+fn fs_block_size(path: &Path) -> u32 {
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    fs::metadata(path)
+        .or_else(|_| fs::metadata(dir))
+        .map(|metadata| metadata.blksize() as u32)
+        .unwrap_or(4096)
+}
@@ -0,0 +1,110 @@
+use std::marker::PhantomData;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use codec::{Bincode, Codec};
+use {Gdbm, GdbmError, Iter};
+
+/// Marks the key, value and codec types without owning any of them
+type Types<K, V, C> = PhantomData<fn() -> (K, V, C)>;
+
+/// A database whose keys and values are Rust types rather than bytes,
+/// converted with serde and the codec `C`, `Bincode` unless chosen
+/// otherwise. Any type implementing `Serialize` and `DeserializeOwned`,
+/// such as one with `#[derive(Serialize, Deserialize)]`, can be stored.
+///
+/// Records that fail to decode, for example ones written by other code,
+/// are reported as errors rather than skipped.
+#[derive(Debug)]
+pub struct TypedGdbm<K, V, C = Bincode> {
+    db: Gdbm,
+    _types: Types<K, V, C>,
+}
+
+/// Iterator over the records of a `TypedGdbm`, returned by
+/// `TypedGdbm::iter`
+#[derive(Debug)]
+pub struct TypedIter<'a, K, V, C = Bincode> {
+    inner: Iter<'a>,
+    _types: Types<K, V, C>,
+}
+
+impl<K, V, C> TypedGdbm<K, V, C>
+    where K: Serialize + DeserializeOwned,
+          V: Serialize + DeserializeOwned,
+          C: Codec<K> + Codec<V>
+{
+    /// Wrap `db`
+    pub fn new(db: Gdbm) -> TypedGdbm<K, V, C> {
+        TypedGdbm {
+            db,
+            _types: PhantomData,
+        }
+    }
+
+    /// The underlying handle, for operations on raw bytes
+    pub fn as_gdbm(&self) -> &Gdbm {
+        &self.db
+    }
+
+    /// Unwrap the underlying handle
+    pub fn into_inner(self) -> Gdbm {
+        self.db
+    }
+
+    /// Look up `key`
+    pub fn get(&self, key: &K) -> Result<Option<V>, GdbmError> {
+        match self.db.fetch_data(<C as Codec<K>>::encode(key)?)? {
+            Some(value) => Ok(Some(<C as Codec<V>>::decode(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Store `value` under `key`, returning the value it replaced
+    pub fn insert(&self, key: &K, value: &V) -> Result<Option<V>, GdbmError> {
+        match self.db.insert(<C as Codec<K>>::encode(key)?, <C as Codec<V>>::encode(value)?)? {
+            Some(old) => Ok(Some(<C as Codec<V>>::decode(&old)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Delete `key`, returning its value
+    pub fn remove(&self, key: &K) -> Result<Option<V>, GdbmError> {
+        match self.db.remove(<C as Codec<K>>::encode(key)?)? {
+            Some(old) => Ok(Some(<C as Codec<V>>::decode(&old)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// True if `key` has a record
+    pub fn contains_key(&self, key: &K) -> Result<bool, GdbmError> {
+        Ok(self.db.fetch_data(<C as Codec<K>>::encode(key)?)?.is_some())
+    }
+
+    /// Iterate over the records, in gdbm's traversal order
+    pub fn iter(&self) -> TypedIter<'_, K, V, C> {
+        TypedIter {
+            inner: self.db.iter(),
+            _types: PhantomData,
+        }
+    }
+}
+
+impl<'a, K, V, C> Iterator for TypedIter<'a, K, V, C>
+    where K: Serialize + DeserializeOwned,
+          V: Serialize + DeserializeOwned,
+          C: Codec<K> + Codec<V>
+{
+    type Item = Result<(K, V), GdbmError>;
+
+    fn next(&mut self) -> Option<Result<(K, V), GdbmError>> {
+        Some(self.inner.next()?.and_then(|(key, value)| {
+            Ok((<C as Codec<K>>::decode(&key)?, <C as Codec<V>>::decode(&value)?))
+        }))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
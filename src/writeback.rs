@@ -0,0 +1,201 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use {Gdbm, GdbmError, Store};
+
+/// When a `WriteBackGdbm` flushes its buffer to the database
+#[derive(Debug, Clone)]
+pub struct FlushPolicy {
+    max_records: usize,
+    max_bytes: usize,
+    max_age: Option<Duration>,
+    write_through: bool,
+}
+
+impl Default for FlushPolicy {
+    fn default() -> FlushPolicy {
+        FlushPolicy {
+            max_records: 1024,
+            max_bytes: 4 << 20,
+            max_age: None,
+            write_through: false,
+        }
+    }
+}
+
+impl FlushPolicy {
+    /// Flush at 1024 buffered records or 4 MiB of buffered keys and
+    /// values, whichever comes first, and never on age
+    pub fn new() -> FlushPolicy {
+        FlushPolicy::default()
+    }
+
+    /// Buffer nothing: every write goes straight to the database, which
+    /// syncs as its open flags say. For switching the buffering off
+    /// without changing the code using the wrapper.
+    pub fn write_through() -> FlushPolicy {
+        FlushPolicy {
+            write_through: true,
+            ..FlushPolicy::default()
+        }
+    }
+
+    /// Flush once `records` writes are buffered
+    pub fn max_records(&mut self, records: usize) -> &mut FlushPolicy {
+        self.max_records = records.max(1);
+        self
+    }
+
+    /// Flush once the buffered keys and values take `bytes`
+    pub fn max_bytes(&mut self, bytes: usize) -> &mut FlushPolicy {
+        self.max_bytes = bytes;
+        self
+    }
+
+    /// Flush once the oldest buffered write is `age` old. The age is
+    /// checked on every call on the wrapper, not from a timer.
+    pub fn max_age(&mut self, age: Duration) -> &mut FlushPolicy {
+        self.max_age = Some(age);
+        self
+    }
+}
+
+/// A write-back layer over a `Gdbm` handle for bursty writes: stores and
+/// deletes are buffered in memory and written out in one batch, with one
+/// sync, when the `FlushPolicy` says so, on `flush`, and on drop.
+///
+/// Buffered writes are lost if the process dies before they are flushed.
+/// The loss window is therefore everything written since the last flush:
+/// at most `max_records` writes and `max_bytes` bytes, and, with
+/// `max_age` set, writes older than that only when no call on the wrapper
+/// came after the deadline. Reads through the wrapper see buffered
+/// writes; reads on the database by other means do not.
+#[derive(Debug)]
+pub struct WriteBackGdbm {
+    db: Option<Gdbm>,
+    policy: FlushPolicy,
+    /// Buffered writes by key; None is a delete
+    pending: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+    pending_bytes: usize,
+    oldest: Option<Instant>,
+}
+
+impl WriteBackGdbm {
+    /// Wrap `db`, flushing as `policy` says
+    pub fn new(db: Gdbm, policy: &FlushPolicy) -> WriteBackGdbm {
+        WriteBackGdbm {
+            db: Some(db),
+            policy: policy.clone(),
+            pending: BTreeMap::new(),
+            pending_bytes: 0,
+            oldest: None,
+        }
+    }
+
+    fn db(&self) -> &Gdbm {
+        self.db.as_ref().expect("database is present until dropped")
+    }
+
+    /// The record under `key`, buffered or stored
+    pub fn fetch_data<K: AsRef<[u8]>>(&mut self, key: K) -> Result<Option<Vec<u8>>, GdbmError> {
+        self.flush_if_due()?;
+        match self.pending.get(key.as_ref()) {
+            Some(value) => Ok(value.clone()),
+            None => self.db().fetch_bytes(key.as_ref()),
+        }
+    }
+
+    /// Store `value` under `key`, replacing any record. Returns an error
+    /// if this write triggers a flush that fails; the write itself stays
+    /// buffered either way.
+    pub fn put<K, V>(&mut self, key: K, value: V) -> Result<(), GdbmError>
+        where K: AsRef<[u8]>,
+              V: AsRef<[u8]>
+    {
+        if self.policy.write_through {
+            return self.db().store_bytes(key.as_ref(), value.as_ref(), Store::REPLACE).map(|_| ());
+        }
+        self.buffer(key.as_ref(), Some(value.as_ref().to_vec()));
+        self.flush_if_due()
+    }
+
+    /// Delete the record under `key`, if there is one. Errors as `put`.
+    pub fn delete<K: AsRef<[u8]>>(&mut self, key: K) -> Result<(), GdbmError> {
+        if self.policy.write_through {
+            return self.db().delete_bytes(key.as_ref()).map(|_| ());
+        }
+        self.buffer(key.as_ref(), None);
+        self.flush_if_due()
+    }
+
+    fn buffer(&mut self, key: &[u8], value: Option<Vec<u8>>) {
+        let size = key.len() + value.as_ref().map_or(0, Vec::len);
+        if let Some(old) = self.pending.insert(key.to_vec(), value) {
+            self.pending_bytes -= key.len() + old.map_or(0, |old| old.len());
+        }
+        self.pending_bytes += size;
+        self.oldest.get_or_insert_with(Instant::now);
+    }
+
+    /// Number of buffered writes, one per key
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Flush if the policy's record, byte or age limit is reached
+    pub fn flush_if_due(&mut self) -> Result<(), GdbmError> {
+        let aged = match (self.oldest, self.policy.max_age) {
+            (Some(oldest), Some(max_age)) => oldest.elapsed() >= max_age,
+            _ => false,
+        };
+        if aged || self.pending.len() >= self.policy.max_records || self.pending_bytes >= self.policy.max_bytes {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Write all buffered writes to the database in key order and sync
+    /// once. Returns how many were written. On error the writes not yet
+    /// written stay buffered, for the next flush to retry.
+    pub fn flush(&mut self) -> Result<usize, GdbmError> {
+        if self.pending.is_empty() {
+            return Ok(0);
+        }
+        let db = self.db.as_ref().expect("database is present until dropped");
+        let pending = &mut self.pending;
+        let pending_bytes = &mut self.pending_bytes;
+        let written = db.with_deferred_sync(|| {
+            let mut written = 0;
+            while let Some((key, value)) = pending.pop_first() {
+                let stored = match value {
+                    Some(ref value) => db.store_bytes(&key, value, Store::REPLACE),
+                    None => db.delete_bytes(&key),
+                };
+                if let Err(err) = stored {
+                    pending.insert(key, value);
+                    return Err(err);
+                }
+                *pending_bytes -= key.len() + value.map_or(0, |value| value.len());
+                written += 1;
+            }
+            Ok(written)
+        })?;
+        self.oldest = None;
+        Ok(written)
+    }
+
+    /// Flush and get the handle back
+    pub fn into_inner(mut self) -> Result<Gdbm, GdbmError> {
+        self.flush()?;
+        Ok(self.db.take().expect("database is present until dropped"))
+    }
+}
+
+/// Flushes the buffer, ignoring errors; call `flush` first to see them.
+impl Drop for WriteBackGdbm {
+    fn drop(&mut self) {
+        if self.db.is_some() {
+            let _ = self.flush();
+        }
+    }
+}
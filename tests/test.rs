@@ -1,11 +1,41 @@
+// create_test predates the lint and compares with true and false
+#![allow(clippy::bool_assert_comparison)]
+
+#[cfg(feature = "async")]
+extern crate futures_util;
 extern crate gdbm;
 extern crate libc;
+#[cfg(feature = "metrics")]
+extern crate metrics;
+#[cfg(feature = "metrics")]
+extern crate metrics_util;
+#[cfg(feature = "typed")]
+#[macro_use]
+extern crate serde;
+#[cfg(feature = "rayon")]
+extern crate rayon;
+#[cfg(feature = "sqlite")]
+extern crate rusqlite;
+#[cfg(feature = "json")]
+extern crate serde_json;
+#[cfg(feature = "hash")]
+extern crate sha2;
 
 use std::path::Path;
 use std::fs::remove_file;
 
 use libc::{S_IRUSR, S_IWUSR};
 
+/// Create a fresh database at `path`, replacing any leftover file.
+fn new_db(path: &str) -> gdbm::Gdbm {
+    let _ = remove_file(path);
+    gdbm::Gdbm::new(Path::new(path),
+                    0,
+                    gdbm::Open::NEWDB,
+                    (S_IRUSR | S_IWUSR) as i32)
+        .expect("Gdbm::new")
+}
+
 #[test]
 fn create_test() {
     // Should create a dbm
@@ -26,3 +56,2922 @@ fn create_test() {
     drop(db);
     remove_file("test.db").expect("remove_file");
 }
+
+#[test]
+fn sync_test() {
+    let db = new_db("sync_test.db");
+    db.insert("foo", "bar").expect("insert");
+    db.sync().expect("sync");
+    drop(db);
+    remove_file("sync_test.db").expect("remove_file");
+}
+
+#[test]
+fn info_test() {
+    let _ = remove_file("info_test.db");
+    let db = gdbm::Gdbm::new(Path::new("info_test.db"),
+                             512,
+                             gdbm::Open::NEWDB,
+                             (S_IRUSR | S_IWUSR) as i32)
+        .expect("Gdbm::new");
+    let info = db.info().expect("info");
+    assert_eq!(info.name, Path::new("info_test.db"));
+    assert_eq!(info.block_size, 512);
+    assert!(info.flags.contains(gdbm::Open::WRITER));
+    drop(db);
+    remove_file("info_test.db").expect("remove_file");
+}
+
+#[test]
+fn version_test() {
+    use std::cmp::Ordering;
+
+    let version = gdbm::version();
+    assert!(version >= (1, 14, 0));
+    assert_eq!(gdbm::version_cmp(version, version), Ordering::Equal);
+    assert_eq!(gdbm::version_cmp((1, 14, 0), (1, 9, 2)), Ordering::Greater);
+    assert_eq!(gdbm::version_cmp((1, 14, 0), (1, 14, 1)), Ordering::Less);
+}
+
+#[test]
+fn fatal_handler_test() {
+    use std::convert::TryInto;
+    use std::fs::{read, write};
+    use std::sync::{Arc, Mutex};
+
+    let path = "fatal_handler_test.db";
+    let _ = remove_file(path);
+    let db = gdbm::Gdbm::new(Path::new(path), 512, gdbm::Open::NEWDB, (S_IRUSR | S_IWUSR) as i32).expect("Gdbm::new");
+    db.store("key", &"value".to_string(), true).expect("store");
+    drop(db);
+
+    // Claim the bucket holds far more elements than fit, which gdbm
+    // treats as fatal. The header gives the directory's address and the
+    // directory the bucket's; the count follows 104 bytes of bucket header.
+    let mut bytes = read(path).expect("read");
+    let bucket = {
+        let word = |at: usize| u64::from_ne_bytes(bytes[at..at + 8].try_into().unwrap()) as usize;
+        word(word(8))
+    };
+    bytes[bucket + 108..bucket + 112].copy_from_slice(&i32::MAX.to_ne_bytes());
+    write(path, &bytes).expect("write");
+
+    let messages = Arc::new(Mutex::new(Vec::new()));
+    let seen = messages.clone();
+    gdbm::set_fatal_handler(move |message| seen.lock().unwrap().push(message.to_string()));
+    let db = gdbm::Gdbm::new(Path::new(path), 0, gdbm::Open::READER, 0).expect("Gdbm::new");
+    // gdbm reports the error instead of exiting the process
+    assert!(db.fetch_data("key").is_err());
+    gdbm::clear_fatal_handler();
+    assert_eq!(messages.lock().unwrap().len(), 1);
+    drop(db);
+    remove_file(path).expect("remove_file");
+}
+
+#[test]
+fn export_test() {
+    use std::fs::read;
+
+    let db = new_db("export_test.db");
+    db.store("foo", &"bar".to_string(), true).expect("store");
+    db.export_to_path(Path::new("export_test.ascii"),
+                      gdbm::DumpFormat::Ascii,
+                      (S_IRUSR | S_IWUSR) as i32)
+        .expect("export ascii");
+    db.export_to_path(Path::new("export_test.bin"),
+                      gdbm::DumpFormat::Binary,
+                      (S_IRUSR | S_IWUSR) as i32)
+        .expect("export binary");
+    assert!(read("export_test.ascii").expect("read").starts_with(b"# GDBM dump file"));
+    assert!(read("export_test.bin").expect("read").starts_with(b"!\r\n"));
+    drop(db);
+    remove_file("export_test.db").expect("remove_file");
+    remove_file("export_test.ascii").expect("remove_file");
+    remove_file("export_test.bin").expect("remove_file");
+}
+
+#[test]
+fn open_checks_test() {
+    use std::fs::{create_dir_all, remove_dir};
+
+    let db = new_db("open_checks_test.db");
+    db.store("foo", &"bar".to_string(), true).expect("store");
+    drop(db);
+
+    let mut options = gdbm::OpenOptions::new();
+    options.flags(gdbm::Open::NEWDB).refuse_if_exists(true);
+    assert!(options.open(Path::new("open_checks_test.db")).is_err());
+
+    let _ = create_dir_all("open_checks_test.dir");
+    let mut options = gdbm::OpenOptions::new();
+    options.flags(gdbm::Open::WRCREAT).require_regular_file(true);
+    assert!(options.open(Path::new("open_checks_test.dir")).is_err());
+    remove_dir("open_checks_test.dir").expect("remove_dir");
+
+    let mut options = gdbm::OpenOptions::new();
+    options.max_existing_size(1);
+    assert!(options.open(Path::new("open_checks_test.db")).is_err());
+
+    let uid = unsafe { libc::getuid() };
+    let mut options = gdbm::OpenOptions::new();
+    options.require_regular_file(true).require_owner(uid);
+    let db = options.open(Path::new("open_checks_test.db")).expect("open");
+    assert_eq!(db.fetch("foo").expect("fetch"), "bar");
+    drop(db);
+    remove_file("open_checks_test.db").expect("remove_file");
+}
+
+#[test]
+fn import_test() {
+    use std::fs::write;
+
+    let db = new_db("import_test.db");
+    db.store("foo", &"bar".to_string(), true).expect("store");
+    db.store("baz", &"qux".to_string(), true).expect("store");
+    db.export_to_path(Path::new("import_test.ascii"),
+                      gdbm::DumpFormat::Ascii,
+                      (S_IRUSR | S_IWUSR) as i32)
+        .expect("export");
+    drop(db);
+
+    let db = new_db("import_test.db");
+    db.store("foo", &"old".to_string(), true).expect("store");
+    let loaded = db.import_from_path(Path::new("import_test.ascii"), gdbm::ImportFlag::Replace)
+        .expect("import");
+    assert_eq!(loaded, 1);
+    assert_eq!(db.fetch("foo").expect("fetch"), "bar");
+    assert_eq!(db.fetch("baz").expect("fetch"), "qux");
+    assert!(db.import_from_path(Path::new("import_test.ascii"), gdbm::ImportFlag::Insert)
+        .is_err());
+
+    write("import_test.ascii", "# GDBM dump file\n#:version=1.1\n# End of header\n#:len=x\n")
+        .expect("write");
+    match db.import_from_path(Path::new("import_test.ascii"), gdbm::ImportFlag::Replace) {
+        Err(gdbm::GdbmError::ImportError { line, .. }) => assert_eq!(line, 4),
+        other => panic!("expected an import error, got {:?}", other),
+    }
+    drop(db);
+    remove_file("import_test.db").expect("remove_file");
+    remove_file("import_test.ascii").expect("remove_file");
+}
+
+#[test]
+fn stream_dump_test() {
+    let db = new_db("stream_dump_test.db");
+    for i in 0..1000 {
+        db.store(&format!("key{}", i), &format!("value{}", i), true).expect("store");
+    }
+    let mut ascii = Vec::new();
+    db.export_to_writer(&mut ascii, gdbm::DumpFormat::Ascii).expect("export ascii");
+    let mut binary = Vec::new();
+    db.export_to_writer(&mut binary, gdbm::DumpFormat::Binary).expect("export binary");
+    drop(db);
+
+    for dump in &[ascii, binary] {
+        let db = new_db("stream_dump_test.db");
+        let loaded = db.import_from_reader(&mut &dump[..], gdbm::ImportFlag::Insert)
+            .expect("import");
+        assert_eq!(loaded, 1000);
+        assert_eq!(db.fetch("key999").expect("fetch"), "value999");
+    }
+    remove_file("stream_dump_test.db").expect("remove_file");
+}
+
+#[test]
+fn export_mapped_test() {
+    use gdbm::{CsvOptions, DumpFormat, ExportMap, ImportFlag};
+
+    let _ = remove_file("export_mapped_test.db");
+    let db = new_db("export_mapped_test.db");
+    for i in 0..100u32 {
+        db.insert(format!("v1/{}", i), format!("{}\0", i).repeat(20)).expect("insert");
+    }
+    let mut map = ExportMap::new();
+    map.map_key(|key| [&b"v2/"[..], &key[3..]].concat())
+        .map_value(|mut value| {
+            value.retain(|&c| c != 0);
+            value
+        });
+    for &format in &[DumpFormat::Ascii, DumpFormat::Binary] {
+        let mut dump = Vec::new();
+        assert_eq!(db.export_to_writer_mapped(&mut dump, format, &mut map).expect("export"), 100);
+        let copy = new_db("export_mapped_test_copy.db");
+        assert_eq!(copy.import_from_reader(&mut &dump[..], ImportFlag::Insert).expect("import"), 100);
+        assert_eq!(copy.fetch("v2/42").expect("fetch"), "42".repeat(20));
+        assert!(!copy.exists("v1/42").expect("exists"));
+    }
+
+    let mut csv = Vec::new();
+    let mut upper = ExportMap::new();
+    upper.map_value(|value| value[..2].to_ascii_uppercase());
+    for i in 0..100 {
+        db.remove(format!("v1/{}", i)).expect("remove");
+    }
+    db.insert("k", "ab\0").expect("insert");
+    assert_eq!(db.export_csv_mapped(&mut csv, &CsvOptions::new(), &mut upper).expect("export_csv_mapped"), 1);
+    assert_eq!(csv, b"k,AB\n");
+    drop(db);
+    remove_file("export_mapped_test.db").expect("remove_file");
+    remove_file("export_mapped_test_copy.db").expect("remove_file");
+}
+
+#[test]
+fn store_checked_test() {
+    use gdbm::StoreOutcome;
+
+    let db = new_db("store_checked_test.db");
+    assert!(!db.exists("foo").expect("exists"));
+    assert_eq!(db.store_checked("foo", "bar", false).expect("store"), StoreOutcome::Inserted);
+    assert!(db.exists("foo").expect("exists"));
+    assert_eq!(db.store_checked("foo", "baz", false).expect("store"), StoreOutcome::AlreadyExists);
+    assert_eq!(db.fetch("foo").expect("fetch"), "bar");
+    assert_eq!(db.store_checked("foo", "baz", true).expect("store"), StoreOutcome::Replaced);
+    assert_eq!(db.fetch("foo").expect("fetch"), "baz");
+    assert_eq!(db.store_checked(b"new", b"value", true).expect("store"), StoreOutcome::Inserted);
+    drop(db);
+    remove_file("store_checked_test.db").expect("remove_file");
+}
+
+#[test]
+fn model_test() {
+    use std::collections::HashMap;
+
+    let db = new_db("model_test.db");
+    let mut model = HashMap::new();
+    for i in 0..100 {
+        let key = format!("key{}", i).into_bytes();
+        let value = format!("value{}", i).into_bytes();
+        db.store_checked(&key, &value, true).expect("store");
+        model.insert(key, value);
+    }
+    db.assert_matches_model(&model);
+
+    db.store_checked("extra", "x", true).expect("store");
+    model.insert(b"missing".to_vec(), b"y".to_vec());
+    model.insert(b"key1".to_vec(), b"changed".to_vec());
+    let report = db.compare_to_model(&model).expect("compare");
+    assert!(!report.is_match());
+    assert_eq!(report.only_in_db_keys, vec![b"extra".to_vec()]);
+    assert_eq!(report.only_in_model_keys, vec![b"missing".to_vec()]);
+    assert_eq!(report.different_keys, vec![b"key1".to_vec()]);
+    drop(db);
+    remove_file("model_test.db").expect("remove_file");
+}
+
+#[test]
+fn insert_test() {
+    let db = new_db("insert_test.db");
+    assert_eq!(db.insert("foo", "bar").expect("insert"), None);
+    assert_eq!(db.insert("foo", "baz").expect("insert"), Some(b"bar".to_vec()));
+    assert_eq!(db.fetch_data("foo").expect("fetch_data"), Some(b"baz".to_vec()));
+    assert_eq!(db.fetch_data("nope").expect("fetch_data"), None);
+    drop(db);
+    remove_file("insert_test.db").expect("remove_file");
+}
+
+#[test]
+fn entry_test() {
+    use gdbm::Entry;
+
+    let db = new_db("entry_test.db");
+    let value = db.entry("count").expect("entry").or_insert("1").expect("or_insert");
+    assert_eq!(value, b"1");
+    let value = db.entry("count")
+        .expect("entry")
+        .and_modify(|v| v.push(b'0'))
+        .expect("and_modify")
+        .or_insert_with(|| "unused")
+        .expect("or_insert_with");
+    assert_eq!(value, b"10");
+    assert_eq!(db.fetch_data("count").expect("fetch_data"), Some(b"10".to_vec()));
+    match db.entry("count").expect("entry") {
+        Entry::Occupied(entry) => assert_eq!(entry.remove().expect("remove"), b"10"),
+        Entry::Vacant(_) => panic!("expected an occupied entry"),
+    }
+    assert!(!db.exists("count").expect("exists"));
+    drop(db);
+    remove_file("entry_test.db").expect("remove_file");
+}
+
+#[test]
+fn max_value_size_test() {
+    let mut db = new_db("max_value_size_test.db");
+    assert_eq!(db.max_value_size(), gdbm::MAX_DATUM_SIZE);
+    db.set_max_value_size(4);
+    db.store_checked("foo", "1234", true).expect("store");
+    match db.store_checked("foo", "12345", true) {
+        Err(gdbm::GdbmError::TooLarge { size, limit, .. }) => assert_eq!((size, limit), (5, 4)),
+        other => panic!("expected TooLarge, got {:?}", other),
+    }
+    assert_eq!(db.fetch_data("foo").expect("fetch_data"), Some(b"1234".to_vec()));
+    drop(db);
+    remove_file("max_value_size_test.db").expect("remove_file");
+}
+
+#[test]
+fn fetch_update_test() {
+    let db = new_db("fetch_update_test.db");
+    let bump = |old: Option<&[u8]>| {
+        let n = old.map(|v| v[0]).unwrap_or(0);
+        Some(vec![n + 1])
+    };
+    assert_eq!(db.fetch_update("n", bump).expect("fetch_update"), None);
+    assert_eq!(db.fetch_update("n", bump).expect("fetch_update"), Some(vec![1]));
+    assert_eq!(db.fetch_data("n").expect("fetch_data"), Some(vec![2]));
+    assert_eq!(db.fetch_update("n", |_| None).expect("fetch_update"), Some(vec![2]));
+    assert!(!db.exists("n").expect("exists"));
+    drop(db);
+    remove_file("fetch_update_test.db").expect("remove_file");
+}
+
+#[test]
+fn seed_test() {
+    let _ = remove_file("seed_test.db");
+    let mut options = gdbm::OpenOptions::new();
+    options.flags(gdbm::Open::WRCREAT)
+        .seed_if_empty(|db| db.store_checked("default", "1", false).map(|_| ()));
+    let db = options.open(Path::new("seed_test.db")).expect("open");
+    assert_eq!(db.fetch_data("default").expect("fetch_data"), Some(b"1".to_vec()));
+    assert!(db.exists("\0gdbm-rs:seeded").expect("exists"));
+    // The marker is bookkeeping, not a record of the database
+    assert_eq!(db.keys().len(), 1);
+    assert_eq!(db.keys().collect::<Result<Vec<_>, _>>().expect("keys"), vec![b"default".to_vec()]);
+    assert_eq!(db.iter().count(), 1);
+    assert_eq!(db.first_key().expect("first_key"), Some(b"default".to_vec()));
+    db.delete("default");
+    drop(db);
+
+    // Already seeded, so the default must not come back
+    let db = options.open(Path::new("seed_test.db")).expect("open");
+    assert_eq!(db.fetch_data("default").expect("fetch_data"), None);
+    db.insert("other", "2").expect("insert");
+    db.clear().expect("clear");
+    drop(db);
+    let db = options.open(Path::new("seed_test.db")).expect("open");
+    assert_eq!(db.fetch_data("default").expect("fetch_data"), None);
+    drop(db);
+    remove_file("seed_test.db").expect("remove_file");
+
+    // A schema version alone does not make the database non-empty
+    let db = new_db("seed_test.db");
+    db.insert(gdbm::SCHEMA_VERSION_KEY, "0").expect("insert");
+    drop(db);
+    let db = options.open(Path::new("seed_test.db")).expect("open");
+    assert_eq!(db.fetch_data("default").expect("fetch_data"), Some(b"1".to_vec()));
+    drop(db);
+    remove_file("seed_test.db").expect("remove_file");
+
+    // A seed that fails part way leaves nothing behind, and is run again
+    let mut failing = gdbm::OpenOptions::new();
+    failing.flags(gdbm::Open::WRCREAT).seed_if_empty(|db| {
+        db.store_checked("first", "1", false)?;
+        Err(gdbm::GdbmError::Error("seed failed".to_string()))
+    });
+    assert!(failing.open(Path::new("seed_test.db")).is_err());
+    let db = gdbm::Gdbm::new(Path::new("seed_test.db"), 0, gdbm::Open::READER, 0).expect("open");
+    assert_eq!(db.iter().count(), 0);
+    drop(db);
+    let db = options.open(Path::new("seed_test.db")).expect("open");
+    assert_eq!(db.fetch_data("first").expect("fetch_data"), None);
+    assert_eq!(db.fetch_data("default").expect("fetch_data"), Some(b"1".to_vec()));
+    drop(db);
+    remove_file("seed_test.db").expect("remove_file");
+}
+
+#[test]
+fn compare_and_swap_test() {
+    let db = new_db("compare_and_swap_test.db");
+    assert_eq!(db.compare_and_swap("lock", None, Some(b"a")).expect("cas"), Ok(()));
+    assert_eq!(db.compare_and_swap("lock", None, Some(b"b")).expect("cas"),
+               Err(Some(b"a".to_vec())));
+    assert_eq!(db.compare_and_swap("lock", Some(b"a"), Some(b"b")).expect("cas"), Ok(()));
+    assert_eq!(db.compare_and_swap("lock", Some(b"a"), None).expect("cas"),
+               Err(Some(b"b".to_vec())));
+    assert_eq!(db.compare_and_swap("lock", Some(b"b"), None).expect("cas"), Ok(()));
+    assert_eq!(db.compare_and_swap("lock", Some(b"b"), None).expect("cas"), Err(None));
+    drop(db);
+    remove_file("compare_and_swap_test.db").expect("remove_file");
+}
+
+#[test]
+fn legacy_test() {
+    let _ = remove_file("legacy_test.db");
+    let db = gdbm::legacy::Gdbm::new(Path::new("legacy_test.db"),
+                                     0,
+                                     gdbm::Open::NEWDB,
+                                     (S_IRUSR | S_IWUSR) as i32)
+        .expect("Gdbm::new");
+    assert!(db.store("foo", &"bar".to_string(), true).expect("store"));
+    assert_eq!(db.fetch("foo").expect("fetch"), "bar");
+    assert!(db.exists("foo").expect("exists"));
+    db.sync();
+    // The current API is available through Deref
+    assert_eq!(db.insert("foo", "baz").expect("insert"), Some(b"bar".to_vec()));
+    assert!(db.delete("foo"));
+    drop(db);
+    remove_file("legacy_test.db").expect("remove_file");
+}
+
+#[test]
+fn append_test() {
+    let db = new_db("append_test.db");
+    assert_eq!(db.append("log", "one;").expect("append"), 4);
+    assert_eq!(db.append("log", "two;").expect("append"), 8);
+    assert_eq!(db.fetch_data("log").expect("fetch_data"), Some(b"one;two;".to_vec()));
+    drop(db);
+    remove_file("append_test.db").expect("remove_file");
+}
+
+#[test]
+fn counter_test() {
+    let db = new_db("counter_test.db");
+    assert_eq!(db.incr("hits", 5).expect("incr"), 5);
+    assert_eq!(db.incr("hits", 2).expect("incr"), 7);
+    assert_eq!(db.decr("hits", 10).expect("decr"), -3);
+    assert_eq!(db.fetch_data("hits").expect("fetch_data"), Some((-3i64).to_le_bytes().to_vec()));
+    db.store_checked("text", "abc", true).expect("store");
+    assert!(db.incr("text", 1).is_err());
+    db.insert("max", i64::MAX.to_le_bytes()).expect("insert");
+    assert!(db.incr("max", 1).is_err());
+    drop(db);
+    remove_file("counter_test.db").expect("remove_file");
+}
+
+#[test]
+fn rename_copy_test() {
+    let db = new_db("rename_copy_test.db");
+    db.insert("a", "1").expect("insert");
+    db.insert("b", "2").expect("insert");
+    assert!(db.copy_key("a", "c", false).expect("copy_key"));
+    assert!(!db.copy_key("a", "b", false).expect("copy_key"));
+    assert_eq!(db.fetch_data("b").expect("fetch_data"), Some(b"2".to_vec()));
+    assert!(!db.rename_key("a", "b", false).expect("rename_key"));
+    assert!(db.rename_key("a", "b", true).expect("rename_key"));
+    assert_eq!(db.fetch_data("a").expect("fetch_data"), None);
+    assert_eq!(db.fetch_data("b").expect("fetch_data"), Some(b"1".to_vec()));
+    assert!(db.rename_key("missing", "d", true).is_err());
+    assert!(!db.rename_key("b", "b", false).expect("rename_key"));
+    assert!(db.rename_key("b", "b", true).expect("rename_key"));
+    assert_eq!(db.fetch_data("b").expect("fetch_data"), Some(b"1".to_vec()));
+    assert!(db.rename_key("missing", "missing", false).is_err());
+    drop(db);
+    remove_file("rename_copy_test.db").expect("remove_file");
+}
+
+#[test]
+fn remove_test() {
+    let db = new_db("remove_test.db");
+    db.insert("foo", "bar").expect("insert");
+    assert_eq!(db.remove("foo").expect("remove"), Some(b"bar".to_vec()));
+    assert_eq!(db.remove("foo").expect("remove"), None);
+    assert!(!db.exists("foo").expect("exists"));
+    drop(db);
+    remove_file("remove_test.db").expect("remove_file");
+}
+
+#[test]
+fn fetch_many_test() {
+    let db = new_db("fetch_many_test.db");
+    db.insert("a", "1").expect("insert");
+    db.insert("c", "3").expect("insert");
+    let values = db.fetch_many(&["a", "b", "c"]).expect("fetch_many");
+    assert_eq!(values, vec![Some(b"1".to_vec()), None, Some(b"3".to_vec())]);
+    drop(db);
+    remove_file("fetch_many_test.db").expect("remove_file");
+}
+
+#[test]
+fn store_many_test() {
+    let _ = remove_file("store_many_test.db");
+    let db = gdbm::Gdbm::new(Path::new("store_many_test.db"),
+                             0,
+                             gdbm::Open::NEWDB | gdbm::Open::SYNC,
+                             (S_IRUSR | S_IWUSR) as i32)
+        .expect("Gdbm::new");
+    db.insert("key0", "old").expect("insert");
+    let records = (0..100).map(|i| (format!("key{}", i), format!("value{}", i)));
+    let stats = db.store_many(records, false).expect("store_many");
+    assert_eq!(stats, gdbm::StoreStats { inserted: 99, replaced: 0, skipped: 1 });
+    let stats = db.store_many(vec![("key0", "new"), ("key100", "x")], true).expect("store_many");
+    assert_eq!(stats, gdbm::StoreStats { inserted: 1, replaced: 1, skipped: 0 });
+    assert_eq!(db.fetch_data("key0").expect("fetch_data"), Some(b"new".to_vec()));
+    assert!(db.info().expect("info").flags.contains(gdbm::Open::SYNC));
+    drop(db);
+    remove_file("store_many_test.db").expect("remove_file");
+}
+
+#[test]
+fn delete_many_test() {
+    let _ = remove_file("delete_many_test.db");
+    let db = new_db("delete_many_test.db");
+    db.store_many((0..10).map(|i| (format!("key{}", i), "value")), true).expect("store_many");
+    let deleted = db.delete_many(vec!["key0", "key1", "key1", "missing"]).expect("delete_many");
+    assert_eq!(deleted, 2);
+    assert_eq!(db.fetch_data("key1").expect("fetch_data"), None);
+    assert_eq!(db.fetch_data("key2").expect("fetch_data"), Some(b"value".to_vec()));
+    drop(db);
+    remove_file("delete_many_test.db").expect("remove_file");
+}
+
+#[test]
+fn clear_test() {
+    let _ = remove_file("clear_test.db");
+    let db = new_db("clear_test.db");
+    db.store_many((0..3000).map(|i| (format!("key{}", i), "value")), true).expect("store_many");
+    db.insert(gdbm::SCHEMA_VERSION_KEY, "3").expect("insert");
+    db.insert(gdbm::SEED_MARKER_KEY, "").expect("insert");
+    db.clear().expect("clear");
+    assert_eq!(db.fetch_data("key0").expect("fetch_data"), None);
+    // The crate's bookkeeping records survive
+    assert_eq!(db.schema_version().expect("schema_version"), 3);
+    assert_eq!(db.fetch_data(gdbm::SEED_MARKER_KEY).expect("fetch_data"), Some(Vec::new()));
+    assert!(db.compare_to_model(&Default::default()).expect("compare_to_model").is_match());
+    db.clear().expect("clear");
+    drop(db);
+    remove_file("clear_test.db").expect("remove_file");
+}
+
+#[test]
+fn extend_test() {
+    let _ = remove_file("extend_test.db");
+    let mut db = gdbm::Gdbm::create_from_iter(Path::new("extend_test.db"),
+                                              vec![("a", "1"), ("b", "2")],
+                                              gdbm::OpenOptions::new().flags(gdbm::Open::NEWDB))
+        .expect("create_from_iter");
+    db.extend(vec![("b".to_string(), "3".to_string()), ("c".to_string(), "4".to_string())]);
+    assert_eq!(db.fetch_many(&["a", "b", "c"]).expect("fetch_many"),
+               vec![Some(b"1".to_vec()), Some(b"3".to_vec()), Some(b"4".to_vec())]);
+    drop(db);
+    remove_file("extend_test.db").expect("remove_file");
+}
+
+#[test]
+fn iter_test() {
+    use std::collections::HashMap;
+    let _ = remove_file("iter_test.db");
+    let db = new_db("iter_test.db");
+    let mut model = HashMap::new();
+    for i in 0..50 {
+        model.insert(format!("key{}", i).into_bytes(), format!("value{}", i).into_bytes());
+    }
+    db.store_many(&model, true).expect("store_many");
+    let mut seen = HashMap::new();
+    for record in &db {
+        let (key, value) = record.expect("record");
+        seen.insert(key, value);
+    }
+    assert_eq!(seen, model);
+    let keys = db.keys().collect::<Result<Vec<_>, _>>().expect("keys");
+    assert_eq!(keys.len(), 50);
+    let mut iter = db.iter();
+    assert_eq!(iter.len(), 50);
+    iter.next().expect("next").expect("record");
+    assert_eq!(iter.len(), 49);
+    assert_eq!(iter.by_ref().count(), 49);
+    assert_eq!(iter.len(), 0);
+    drop(db);
+    remove_file("iter_test.db").expect("remove_file");
+}
+
+#[test]
+fn prefix_test() {
+    let _ = remove_file("prefix_test.db");
+    let db = new_db("prefix_test.db");
+    db.store_many((0..40).map(|i| (format!("session:{}", i), "s")), true).expect("store_many");
+    db.store_many((0..10).map(|i| (format!("user:{}", i), "u")), true).expect("store_many");
+    assert_eq!(db.count_prefix("session:").expect("count_prefix"), 40);
+    assert_eq!(db.count_prefix("").expect("count_prefix"), 50);
+    assert_eq!(db.delete_prefix("session:").expect("delete_prefix"), 40);
+    assert_eq!(db.count_prefix("session:").expect("count_prefix"), 0);
+    assert_eq!(db.count_prefix("user:").expect("count_prefix"), 10);
+    drop(db);
+    remove_file("prefix_test.db").expect("remove_file");
+}
+
+#[test]
+fn glob_test() {
+    let glob = gdbm::Glob::new("session:*:expired").expect("Glob::new");
+    assert!(glob.matches(b"session:42:expired"));
+    assert!(glob.matches(b"session::expired"));
+    assert!(!glob.matches(b"session:42:active"));
+    let glob = gdbm::Glob::new("user[0-9]?[!x]\\*").expect("Glob::new");
+    assert!(glob.matches(b"user1ab*"));
+    assert!(!glob.matches(b"user1abx"));
+    assert!(!glob.matches(b"userxab*"));
+    assert!(!glob.matches(b"user1ax*"));
+    assert!(gdbm::Glob::new("*a*b*").expect("Glob::new").matches(b"xxaxxbxx"));
+    assert!(gdbm::Glob::new("[]]").expect("Glob::new").matches(b"]"));
+    assert!(gdbm::Glob::new("[a-").is_err());
+    assert!(gdbm::Glob::new("a\\").is_err());
+    assert!(gdbm::Glob::new("a?c").expect("Glob::new").matches(b"a\xffc"));
+    let glob = gdbm::Glob::new("{user,session}:*").expect("Glob::new");
+    assert!(glob.matches(b"user:1") && glob.matches(b"session:/1") && !glob.matches(b"admin:1"));
+    assert!(gdbm::Glob::new("{a,b").is_err());
+
+    let _ = remove_file("glob_test.db");
+    let db = new_db("glob_test.db");
+    db.store_many((0..20).map(|i| (format!("session:{}:{}", i, if i % 4 == 0 { "expired" } else { "active" }), "")),
+                  true)
+        .expect("store_many");
+    let matching = db.keys_matching("session:*:expired")
+        .expect("keys_matching")
+        .collect::<Result<Vec<_>, _>>()
+        .expect("keys");
+    assert_eq!(matching.len(), 5);
+    drop(db);
+    remove_file("glob_test.db").expect("remove_file");
+}
+
+#[cfg(feature = "regex")]
+#[test]
+fn keys_matching_regex_test() {
+    let db = new_db("keys_matching_regex_test.db");
+    db.store_many((0..20).map(|i| (format!("session:{}:{}", i, if i % 4 == 0 { "expired" } else { "active" }), "")),
+                  true)
+        .expect("store_many");
+    db.insert(b"raw\xff", "").expect("insert");
+    let matching = db.keys_matching_regex(r"^session:1\d:")
+        .expect("keys_matching_regex")
+        .collect::<Result<Vec<_>, _>>()
+        .expect("keys");
+    assert_eq!(matching.len(), 10);
+    assert!(matching.iter().all(|key| key.starts_with(b"session:1") && key.len() > b"session:1:".len()));
+    assert_eq!(db.keys_matching_regex("expired$").expect("keys_matching_regex").count(), 5);
+    assert_eq!(db.keys_matching_regex(r"(?-u)\xff").expect("keys_matching_regex").count(), 1);
+    assert!(db.keys_matching_regex("(").is_err());
+    drop(db);
+    remove_file("keys_matching_regex_test.db").expect("remove_file");
+}
+
+#[test]
+fn sorted_test() {
+    let _ = remove_file("sorted_test.db");
+    let db = new_db("sorted_test.db");
+    db.store_many((0..100).map(|i| (format!("{:03}", i), format!("{}", i))), true).expect("store_many");
+    let keys = db.entries_sorted()
+        .expect("entries_sorted")
+        .map(|record| record.expect("record").0)
+        .collect::<Vec<_>>();
+    let expected = (0..100).map(|i| format!("{:03}", i).into_bytes()).collect::<Vec<_>>();
+    assert_eq!(keys, expected);
+
+    let mut options = gdbm::SortOptions::new();
+    options.compare(|a, b| b.cmp(a)).spill_after(7).spill_dir(Path::new("."));
+    let records = db.entries_sorted_with(&options)
+        .expect("entries_sorted_with")
+        .collect::<Result<Vec<_>, _>>()
+        .expect("records");
+    assert_eq!(records.len(), 100);
+    assert_eq!(records[0], (b"099".to_vec(), b"99".to_vec()));
+    assert!(records.windows(2).all(|pair| pair[0].0 > pair[1].0));
+
+    // 100 runs of one key, merged every 3 runs
+    let mut options = gdbm::SortOptions::new();
+    options.spill_after(1).max_open_runs(3).spill_dir(Path::new("."));
+    let keys = db.entries_sorted_with(&options)
+        .expect("entries_sorted_with")
+        .map(|record| record.expect("record").0)
+        .collect::<Vec<_>>();
+    assert_eq!(keys, expected);
+    drop(db);
+    remove_file("sorted_test.db").expect("remove_file");
+}
+
+#[test]
+fn cursor_test() {
+    let _ = remove_file("cursor_test.db");
+    let db = new_db("cursor_test.db");
+    db.store_many((0..30).map(|i| (format!("key{}", i), "value")), true).expect("store_many");
+    let mut iter = db.iter();
+    assert_eq!(iter.cursor(), None);
+    let mut seen = iter.by_ref().take(10).map(|record| record.expect("record").0).collect::<Vec<_>>();
+    let token = iter.cursor().expect("cursor").to_token();
+
+    let cursor = gdbm::Cursor::from_token(&token).expect("from_token");
+    assert_eq!(cursor.key(), &seen[9][..]);
+    for record in db.iter_from(&cursor).expect("iter_from") {
+        seen.push(record.expect("record").0);
+    }
+    seen.sort();
+    seen.dedup();
+    assert_eq!(seen.len(), 30);
+
+    assert!(gdbm::Cursor::from_token("abc").is_err());
+    assert!(gdbm::Cursor::from_token("zz").is_err());
+    assert!(db.iter_from(&gdbm::Cursor::new("missing")).is_err());
+    drop(db);
+    remove_file("cursor_test.db").expect("remove_file");
+}
+
+#[test]
+fn page_test() {
+    let _ = remove_file("page_test.db");
+    let db = new_db("page_test.db");
+    db.store_many((0..25).map(|i| (format!("key{}", i), "value")), true).expect("store_many");
+    let mut seen = Vec::new();
+    let mut after: Option<gdbm::Cursor> = None;
+    let mut pages = 0;
+    loop {
+        let page = db.page(after.as_ref().map(|cursor| cursor.key()), 10).expect("page");
+        pages += 1;
+        seen.extend(page.entries.into_iter().map(|(key, _)| key));
+        match page.next {
+            Some(next) => after = Some(next),
+            None => break,
+        }
+    }
+    assert_eq!(pages, 3);
+    seen.sort();
+    seen.dedup();
+    assert_eq!(seen.len(), 25);
+
+    let page = db.page(None, 25).expect("page");
+    assert_eq!((page.entries.len(), page.next), (25, None));
+    assert!(db.page(None, 0).is_err());
+    drop(db);
+    remove_file("page_test.db").expect("remove_file");
+}
+
+#[test]
+fn keys_stable_test() {
+    let _ = remove_file("keys_stable_test.db");
+    let db = new_db("keys_stable_test.db");
+    db.store_many((0..200).map(|i| (format!("key{}", i), "value")), true).expect("store_many");
+    let keys = db.keys_stable().expect("keys_stable");
+    assert_eq!(keys.len(), 200);
+    for key in keys {
+        db.remove(&key).expect("remove").expect("record");
+    }
+    assert_eq!(db.keys().len(), 0);
+    drop(db);
+    remove_file("keys_stable_test.db").expect("remove_file");
+}
+
+#[test]
+fn first_next_key_test() {
+    let _ = remove_file("first_next_key_test.db");
+    let db = new_db("first_next_key_test.db");
+    assert_eq!(db.first_key().expect("first_key"), None);
+    db.store_many((0..10).map(|i| (format!("key{}", i), "value")), true).expect("store_many");
+    let mut keys = Vec::new();
+    let mut next = db.first_key().expect("first_key");
+    while let Some(key) = next {
+        next = db.next_key(&key).expect("next_key");
+        keys.push(key);
+    }
+    assert_eq!(keys, db.keys().collect::<Result<Vec<_>, _>>().expect("keys"));
+    assert_eq!(db.next_key("missing").expect("next_key"), None);
+    drop(db);
+    remove_file("first_next_key_test.db").expect("remove_file");
+}
+
+#[test]
+fn shared_test() {
+    use std::thread;
+    let _ = remove_file("shared_test.db");
+    let db = gdbm::SharedGdbm::new(new_db("shared_test.db"));
+    let threads = (0..4)
+        .map(|t| {
+            let db = db.clone();
+            thread::spawn(move || {
+                for i in 0..50 {
+                    db.insert(format!("key{}-{}", t, i), "value").expect("insert");
+                    db.incr("counter", 1).expect("incr");
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    for thread in threads {
+        thread.join().expect("join");
+    }
+    assert_eq!(db.incr("counter", 0).expect("incr"), 200);
+    assert_eq!(db.keys().expect("keys").len(), 201);
+    assert!(db.exists("key0-0").expect("exists"));
+    assert!(db.delete("key0-0").expect("delete"));
+    assert!(!db.exists("key0-0").expect("exists"));
+    assert!(!db.delete("key0-0").expect("delete"));
+    let db = db.try_unwrap().expect("try_unwrap");
+    drop(db);
+    remove_file("shared_test.db").expect("remove_file");
+}
+
+#[cfg(feature = "async")]
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake};
+    use std::thread::{self, Thread};
+
+    struct Unpark(Thread);
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Arc::new(Unpark(thread::current())).into();
+    let mut cx = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn async_test() {
+    let _ = remove_file("async_test.db");
+    let db = gdbm::AsyncGdbm::from_gdbm(new_db("async_test.db")).expect("AsyncGdbm");
+    let stored = db.store("key", "value", false);
+    let fetched = db.get("key");
+    assert_eq!(block_on(stored).expect("store"), gdbm::StoreOutcome::Inserted);
+    assert_eq!(block_on(fetched).expect("get"), Some(b"value".to_vec()));
+    assert_eq!(block_on(db.len()).expect("len"), 1);
+    assert!(block_on(db.delete("key")).expect("delete"));
+    assert!(!block_on(db.delete("key")).expect("delete"));
+    block_on(db.sync()).expect("sync");
+    assert!(block_on(db.call(|_| -> Result<(), gdbm::GdbmError> { panic!("boom") })).is_err());
+    assert_eq!(block_on(db.len()).expect("len"), 0);
+    drop(db);
+    remove_file("async_test.db").expect("remove_file");
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn entries_stream_test() {
+    let _ = remove_file("entries_stream_test.db");
+    let db = new_db("entries_stream_test.db");
+    db.store_many((0..250).map(|i| (format!("key{}", i), format!("value{}", i))), true).expect("store_many");
+    let db = gdbm::AsyncGdbm::from_gdbm(db).expect("AsyncGdbm");
+    let mut stream = db.entries_stream();
+    stream.batch_size(64);
+    let mut keys = Vec::new();
+    while let Some(record) = block_on(stream.next_entry()) {
+        keys.push(record.expect("record").0);
+    }
+    keys.sort();
+    keys.dedup();
+    assert_eq!(keys.len(), 250);
+    assert!(block_on(stream.next_entry()).is_none());
+    drop(stream);
+
+    use futures_util::StreamExt;
+    let mut stream = db.entries_stream();
+    let mut count = 0;
+    while let Some(record) = block_on(stream.next()) {
+        assert!(record.expect("record").1.starts_with(b"value"));
+        count += 1;
+    }
+    assert_eq!(count, 250);
+    let mut small = db.entries_stream();
+    small.batch_size(7);
+    assert_eq!(block_on(small.filter_map(|record| std::future::ready(record.ok())).count()), 250);
+    drop(stream);
+    drop(db);
+    remove_file("entries_stream_test.db").expect("remove_file");
+}
+
+#[test]
+fn writer_actor_test() {
+    use std::thread;
+    let _ = remove_file("writer_actor_test.db");
+    let writer = gdbm::GdbmWriterActor::spawn(new_db("writer_actor_test.db")).expect("spawn");
+    let threads = (0..4)
+        .map(|t| {
+            let writer = writer.clone();
+            thread::spawn(move || {
+                for i in 0..25 {
+                    writer.store(format!("key{}-{}", t, i), "value", false);
+                }
+                writer.sync().wait().expect("sync");
+            })
+        })
+        .collect::<Vec<_>>();
+    for thread in threads {
+        thread.join().expect("join");
+    }
+    assert_eq!(writer.store("key0-0", "other", false).wait().expect("store"),
+               gdbm::StoreOutcome::AlreadyExists);
+    assert_eq!(writer.remove("key0-0").wait().expect("remove"), Some(b"value".to_vec()));
+    let count = writer.call(|db| Ok(db.keys().len())).wait().expect("call");
+    assert_eq!(count, 99);
+    assert!(writer.call(|_| -> Result<(), gdbm::GdbmError> { panic!("boom") }).wait().is_err());
+    writer.sync().wait().expect("sync");
+    drop(writer);
+    remove_file("writer_actor_test.db").expect("remove_file");
+}
+
+#[test]
+fn pool_test() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    let _ = remove_file("pool_test.db");
+    let db = new_db("pool_test.db");
+    db.insert("key", "value").expect("insert");
+    drop(db);
+
+    let healthy = Arc::new(AtomicBool::new(true));
+    let mut pool = gdbm::GdbmPool::new(Path::new("pool_test.db"), &gdbm::OpenOptions::new(), 2);
+    let check = healthy.clone();
+    pool.health_check(move |_| check.load(Ordering::SeqCst));
+    {
+        let a = pool.get().expect("get");
+        let b = pool.get().expect("get");
+        assert_eq!(a.fetch_data("key").expect("fetch_data"), Some(b"value".to_vec()));
+        assert_eq!(b.fetch_data("key").expect("fetch_data"), Some(b"value".to_vec()));
+        assert_eq!(pool.open_count(), 2);
+        assert!(pool.get_timeout(Duration::from_millis(10)).is_err());
+    }
+    assert_eq!(pool.idle_count(), 2);
+    healthy.store(false, Ordering::SeqCst);
+    let c = pool.get().expect("get");
+    assert_eq!(pool.open_count(), 1);
+    assert_eq!(pool.idle_count(), 0);
+    drop(c);
+    remove_file("pool_test.db").expect("remove_file");
+}
+
+#[test]
+fn file_lock_test() {
+    use std::time::Duration;
+    let _ = remove_file("file_lock_test.db");
+    let flags = gdbm::Open::NEWDB | gdbm::Open::NOLOCK;
+    let a = gdbm::Gdbm::new(Path::new("file_lock_test.db"), 0, flags, (S_IRUSR | S_IWUSR) as i32)
+        .expect("Gdbm::new");
+    let b = gdbm::Gdbm::new(Path::new("file_lock_test.db"), 0, gdbm::Open::WRITER | gdbm::Open::NOLOCK, 0)
+        .expect("Gdbm::new");
+    {
+        let locked = a.lock_exclusive(Duration::from_secs(1)).expect("lock_exclusive");
+        assert!(locked.is_exclusive());
+        locked.insert("key", "value").expect("insert");
+        assert!(b.lock_shared(Duration::from_millis(20)).is_err());
+    }
+    let shared_a = a.lock_shared(Duration::from_secs(1)).expect("lock_shared");
+    let shared_b = b.lock_shared(Duration::from_secs(1)).expect("lock_shared");
+    assert!(!shared_b.is_exclusive());
+    drop((shared_a, shared_b));
+    drop((a, b));
+
+    let locked = new_db("file_lock_test.db");
+    assert!(locked.lock_exclusive(Duration::from_secs(1)).is_err());
+    drop(locked);
+    remove_file("file_lock_test.db").expect("remove_file");
+}
+
+#[test]
+fn open_retry_test() {
+    use std::thread;
+    use std::time::Duration;
+    let _ = remove_file("open_retry_test.db");
+    let db = new_db("open_retry_test.db");
+    let mut options = gdbm::OpenOptions::new();
+    options.flags(gdbm::Open::WRITER).retry(2, Duration::from_millis(1));
+    match options.open(Path::new("open_retry_test.db")) {
+        Err(gdbm::GdbmError::LockContended { attempts, .. }) => assert_eq!(attempts, 3),
+        other => panic!("expected LockContended, got {:?}", other.map(|_| ())),
+    }
+
+    let holder = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        drop(db);
+    });
+    options.retry(20, Duration::from_millis(5));
+    let db = options.open(Path::new("open_retry_test.db")).expect("open");
+    holder.join().expect("join");
+    drop(db);
+    remove_file("open_retry_test.db").expect("remove_file");
+}
+
+#[test]
+fn exclusive_in_process_test() {
+    let _ = remove_file("exclusive_in_process_test.db");
+    let mut options = gdbm::OpenOptions::new();
+    options.flags(gdbm::Open::NEWDB | gdbm::Open::NOLOCK).exclusive_in_process(true);
+    let db = options.open(Path::new("exclusive_in_process_test.db")).expect("open");
+    options.flags(gdbm::Open::WRITER | gdbm::Open::NOLOCK);
+    assert!(options.open(Path::new("./exclusive_in_process_test.db")).is_err());
+    let reader = gdbm::OpenOptions::new()
+        .flags(gdbm::Open::READER | gdbm::Open::NOLOCK)
+        .exclusive_in_process(true)
+        .open(Path::new("exclusive_in_process_test.db"))
+        .expect("open reader");
+    drop(reader);
+    drop(db);
+    let db = options.open(Path::new("exclusive_in_process_test.db")).expect("open");
+    drop(db);
+    remove_file("exclusive_in_process_test.db").expect("remove_file");
+}
+
+#[test]
+fn sync_policy_test() {
+    use std::thread;
+    use std::time::Duration;
+    let _ = remove_file("sync_policy_test.db");
+    let mut policy = gdbm::SyncPolicy::new();
+    policy.every(Duration::from_millis(5)).after_writes(10);
+    let db = gdbm::SharedGdbm::with_sync_policy(new_db("sync_policy_test.db"), &policy)
+        .expect("with_sync_policy");
+    for i in 0..25 {
+        db.insert(format!("key{}", i), "value").expect("insert");
+    }
+    thread::sleep(Duration::from_millis(20));
+    drop(db);
+    // The sync thread may still be finishing with the handle
+    let db = gdbm::OpenOptions::new()
+        .retry(10, Duration::from_millis(1))
+        .open(Path::new("sync_policy_test.db"))
+        .expect("open");
+    assert_eq!(db.keys().len(), 25);
+    drop(db);
+    remove_file("sync_policy_test.db").expect("remove_file");
+}
+
+#[test]
+fn is_stale_test() {
+    let _ = remove_file("is_stale_test.db");
+    let _ = remove_file("is_stale_test.db.new");
+    let db = new_db("is_stale_test.db");
+    assert!(!db.is_stale().expect("is_stale"));
+    drop(new_db("is_stale_test.db.new"));
+    std::fs::rename("is_stale_test.db.new", "is_stale_test.db").expect("rename");
+    assert!(db.is_stale().expect("is_stale"));
+    remove_file("is_stale_test.db").expect("remove_file");
+    assert!(db.is_stale().expect("is_stale"));
+    drop(db);
+}
+
+#[test]
+fn reopen_test() {
+    let _ = remove_file("reopen_test.db");
+    let _ = remove_file("reopen_test.db.new");
+    let mut db = new_db("reopen_test.db");
+    db.set_max_value_size(16);
+    db.insert("key", "old").expect("insert");
+    db.reopen().expect("reopen");
+    assert_eq!(db.fetch_data("key").expect("fetch_data"), Some(b"old".to_vec()));
+    assert_eq!(db.max_value_size(), 16);
+
+    let new = new_db("reopen_test.db.new");
+    new.insert("key", "new").expect("insert");
+    drop(new);
+    std::fs::rename("reopen_test.db.new", "reopen_test.db").expect("rename");
+    assert!(db.is_stale().expect("is_stale"));
+    db.reopen().expect("reopen");
+    assert!(!db.is_stale().expect("is_stale"));
+    assert_eq!(db.fetch_data("key").expect("fetch_data"), Some(b"new".to_vec()));
+
+    // A replaced file another handle has open is not ours to unlock
+    std::fs::rename("reopen_test.db", "reopen_test.db.old").expect("rename");
+    let other = new_db("reopen_test.db");
+    match db.reopen() {
+        Err(gdbm::GdbmError::LockContended { .. }) => {}
+        result => panic!("expected LockContended, got {:?}", result),
+    }
+    assert_eq!(db.fetch_data("key").expect("fetch_data"), Some(b"new".to_vec()));
+    drop(other);
+    remove_file("reopen_test.db.old").expect("remove_file");
+
+    // A failed reopen of a replaced file keeps the old handle
+    remove_file("reopen_test.db").expect("remove_file");
+    assert!(db.reopen().is_err());
+    assert_eq!(db.fetch_data("key").expect("fetch_data"), Some(b"new".to_vec()));
+    drop(db);
+}
+
+#[test]
+fn par_for_each_test() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    let _ = remove_file("par_for_each_test.db");
+    let db = new_db("par_for_each_test.db");
+    db.store_many((0..1000).map(|i| (format!("key{}", i), format!("{}", i))), true).expect("store_many");
+    let count = AtomicUsize::new(0);
+    let sum = AtomicUsize::new(0);
+    db.par_for_each(64, 4, |_, value| {
+        let value: usize = std::str::from_utf8(value).expect("utf8").parse().expect("number");
+        count.fetch_add(1, Ordering::SeqCst);
+        sum.fetch_add(value, Ordering::SeqCst);
+    }).expect("par_for_each");
+    assert_eq!(count.load(Ordering::SeqCst), 1000);
+    assert_eq!(sum.load(Ordering::SeqCst), (0..1000).sum::<usize>());
+    drop(db);
+    remove_file("par_for_each_test.db").expect("remove_file");
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn par_entries_test() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    let db = new_db("par_entries_test.db");
+    db.store_many((0..1000).map(|i| (format!("key{}", i), format!("{}", i))), true).expect("store_many");
+    let sum = AtomicUsize::new(0);
+    let add = |_: &[u8], value: &[u8]| {
+        let value: usize = std::str::from_utf8(value).expect("utf8").parse().expect("number");
+        sum.fetch_add(value, Ordering::SeqCst);
+    };
+    db.par_entries(64, add).expect("par_entries");
+    assert_eq!(sum.load(Ordering::SeqCst), (0..1000).sum::<usize>());
+    // Reading on the only thread of the pool, which then processes the chunks too
+    sum.store(0, Ordering::SeqCst);
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(1).build().expect("pool");
+    let db = pool.install(move || {
+        db.par_entries(7, add).expect("par_entries");
+        db
+    });
+    assert_eq!(sum.load(Ordering::SeqCst), (0..1000).sum::<usize>());
+    drop(db);
+    remove_file("par_entries_test.db").expect("remove_file");
+}
+
+#[test]
+fn par_for_each_panic_test() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    let db = new_db("par_for_each_panic_test.db");
+    db.store_many((0..20).map(|i| (format!("key{}", i), "value")), true).expect("store_many");
+    // Run it elsewhere so that a hang fails the test instead of blocking it
+    let (done, finished) = mpsc::channel();
+    thread::spawn(move || {
+        let result = catch_unwind(AssertUnwindSafe(|| db.par_for_each(1, 1, |_, _| panic!("worker panic"))));
+        let _ = done.send(result.is_err());
+    });
+    let panicked = finished.recv_timeout(Duration::from_secs(10));
+    // Remove the file before asserting, so that a failure does not leave it behind
+    let _ = remove_file("par_for_each_panic_test.db");
+    let panicked = panicked.expect("par_for_each hung after its workers panicked");
+    assert!(panicked, "the worker panic did not reach the caller");
+}
+
+#[test]
+fn cancellation_test() {
+    let _ = remove_file("cancellation_test.db");
+    let db = new_db("cancellation_test.db");
+    db.store_many((0..100).map(|i| (format!("key{}", i), vec![b'x'; 1000])), true).expect("store_many");
+    let cancel = gdbm::CancellationToken::new();
+    let mut keys = db.keys().with_cancellation(&cancel);
+    keys.next().expect("next").expect("key");
+    cancel.clone().cancel();
+    match keys.next() {
+        Some(Err(gdbm::GdbmError::Cancelled)) => {}
+        other => panic!("expected Cancelled, got {:?}", other),
+    }
+    assert!(keys.next().is_none());
+
+    let mut dump = Vec::new();
+    match db.export_to_writer_cancellable(&mut dump, gdbm::DumpFormat::Ascii, &cancel) {
+        Err(gdbm::GdbmError::Cancelled) => {}
+        other => panic!("expected Cancelled, got {:?}", other),
+    }
+    db.export_to_writer(&mut dump, gdbm::DumpFormat::Ascii).expect("export_to_writer");
+    db.clear().expect("clear");
+    match db.import_from_reader_cancellable(&mut &dump[..], gdbm::ImportFlag::Replace, &cancel) {
+        Err(gdbm::GdbmError::Cancelled) => {}
+        other => panic!("expected Cancelled, got {:?}", other),
+    }
+    let uncancelled = gdbm::CancellationToken::new();
+    assert_eq!(db.import_from_reader_cancellable(&mut &dump[..], gdbm::ImportFlag::Replace, &uncancelled)
+                   .expect("import"),
+               100);
+    drop(db);
+    remove_file("cancellation_test.db").expect("remove_file");
+}
+
+#[test]
+fn progress_test() {
+    let _ = remove_file("progress_test.db");
+    let db = new_db("progress_test.db");
+    let mut reports = Vec::new();
+    db.store_many_with_progress((0..2500).map(|i| (format!("key{}", i), vec![b'x'; 100])),
+                                true,
+                                |progress| reports.push(progress.records))
+        .expect("store_many_with_progress");
+    assert_eq!(reports, vec![1000, 2000, 2500]);
+
+    let mut dump = Vec::new();
+    let mut last = gdbm::Progress::default();
+    db.export_to_writer_with_progress(&mut dump, gdbm::DumpFormat::Binary, |progress| last = progress)
+        .expect("export_to_writer_with_progress");
+    assert_eq!(last, gdbm::Progress { records: 2500, bytes: dump.len() as u64 });
+
+    db.clear().expect("clear");
+    let mut updates = 0;
+    let mut last = gdbm::Progress::default();
+    db.import_from_reader_with_progress(&mut &dump[..], gdbm::ImportFlag::Replace, |progress| {
+        updates += 1;
+        last = progress;
+    }).expect("import_from_reader_with_progress");
+    assert!(updates > 2);
+    assert_eq!(last, gdbm::Progress { records: 2500, bytes: dump.len() as u64 });
+
+    let path = Path::new("progress_test.dump");
+    let mut last = gdbm::Progress::default();
+    db.export_to_path_with_progress(path, gdbm::DumpFormat::Binary, 0o600, |progress| last = progress)
+        .expect("export_to_path_with_progress");
+    assert_eq!(last, gdbm::Progress { records: 2500, bytes: dump.len() as u64 });
+    db.export_to_path(Path::new("progress_test_direct.dump"), gdbm::DumpFormat::Binary, 0o600)
+        .expect("export_to_path");
+    assert_eq!(std::fs::read(path).expect("read"), std::fs::read("progress_test_direct.dump").expect("read"));
+    db.clear().expect("clear");
+    let mut last = gdbm::Progress::default();
+    assert_eq!(db.import_from_path_with_progress(path, gdbm::ImportFlag::Insert, |progress| last = progress)
+                   .expect("import_from_path_with_progress"),
+               2500);
+    assert_eq!(last, gdbm::Progress { records: 2500, bytes: dump.len() as u64 });
+    remove_file("progress_test.dump").expect("remove_file");
+    remove_file("progress_test_direct.dump").expect("remove_file");
+
+    let mut db = db;
+    for i in 0..500 {
+        db.remove(format!("key{}", i)).expect("remove");
+    }
+    db.insert(gdbm::SCHEMA_VERSION_KEY, "4").expect("insert");
+    let before = std::fs::metadata("progress_test.db").expect("metadata").len();
+    let mut reports = Vec::new();
+    db.reorganize_with_progress(|progress| reports.push(progress.records)).expect("reorganize_with_progress");
+    assert_eq!(reports, vec![1000, 2000, 2000]);
+    assert!(std::fs::metadata("progress_test.db").expect("metadata").len() < before);
+    assert_eq!(db.iter().count(), 2000);
+    assert_eq!(db.fetch_data(gdbm::SCHEMA_VERSION_KEY).expect("fetch_data"), Some(b"4".to_vec()));
+    db.insert("after", "reorganize").expect("insert");
+    assert!(!Path::new("progress_test.db.reorganize").exists());
+    std::fs::write("progress_test.db.reorganize", "").expect("write");
+    assert!(db.reorganize_with_progress(|_| {}).is_err());
+    remove_file("progress_test.db.reorganize").expect("remove_file");
+    assert_eq!(db.iter().count(), 2001);
+    drop(db);
+    remove_file("progress_test.db").expect("remove_file");
+}
+
+#[cfg(feature = "typed")]
+#[test]
+fn typed_test() {
+    let _ = remove_file("typed_test.db");
+    let db: gdbm::TypedGdbm<(String, u32), Vec<Option<i64>>> = gdbm::TypedGdbm::new(new_db("typed_test.db"));
+    let key = ("alice".to_string(), 7);
+    assert_eq!(db.insert(&key, &vec![Some(-1), None]).expect("insert"), None);
+    assert_eq!(db.get(&key).expect("get"), Some(vec![Some(-1), None]));
+    assert_eq!(db.insert(&key, &vec![]).expect("insert"), Some(vec![Some(-1), None]));
+    assert!(db.contains_key(&key).expect("contains_key"));
+    assert_eq!(db.get(&("bob".to_string(), 7)).expect("get"), None);
+
+    // Bincode layout: u64 length, bytes, then the u32
+    let raw_key = [&5u64.to_le_bytes()[..], b"alice", &7u32.to_le_bytes()].concat();
+    assert_eq!(db.as_gdbm().fetch_data(&raw_key).expect("fetch_data"), Some(0u64.to_le_bytes().to_vec()));
+
+    db.as_gdbm().store_checked("junk", "x", true).expect("store_checked");
+    let records: Vec<_> = db.iter().collect();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records.iter().filter(|r| r.is_err()).count(), 1);
+    assert_eq!(db.remove(&key).expect("remove"), Some(vec![]));
+
+    // Derived types need no glue
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Role {
+        Admin,
+        Member { since: u32 },
+    }
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct User {
+        name: String,
+        roles: Vec<Role>,
+    }
+    let users: gdbm::TypedGdbm<u64, User> = gdbm::TypedGdbm::new(db.into_inner());
+    let user = User {
+        name: "carol".to_string(),
+        roles: vec![Role::Admin, Role::Member { since: 2020 }],
+    };
+    users.insert(&1, &user).expect("insert");
+    assert_eq!(users.get(&1).expect("get"), Some(user));
+    drop(users);
+    remove_file("typed_test.db").expect("remove_file");
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn json_codec_test() {
+    use gdbm::{Codec, Json};
+
+    let _ = remove_file("json_codec_test.db");
+    let db: gdbm::TypedGdbm<String, (u64, Vec<Option<String>>, f64), Json> =
+        gdbm::TypedGdbm::new(new_db("json_codec_test.db"));
+    let value = (u64::MAX, vec![Some("tab\there \"quoted\" \u{1F600}".to_string()), None], 1.5);
+    db.insert(&"k".to_string(), &value).expect("insert");
+    assert_eq!(db.get(&"k".to_string()).expect("get"), Some(value));
+    assert_eq!(db.as_gdbm().fetch_data("\"k\"").expect("fetch_data"),
+               Some(b"[18446744073709551615,[\"tab\\there \\\"quoted\\\" \xF0\x9F\x98\x80\",null],1.5]".to_vec()));
+
+    assert!(<Json as Codec<Vec<u8>>>::decode(b"[1,]").is_err());
+    assert!(<Json as Codec<u8>>::decode(b"1 2").is_err());
+    assert!(<Json as Codec<u8>>::decode(b"256").is_err());
+    let nested = format!("{}{}", "[".repeat(100_000), "]".repeat(100_000));
+    assert!(<Json as Codec<Vec<()>>>::decode(nested.as_bytes()).is_err());
+    drop(db);
+    remove_file("json_codec_test.db").expect("remove_file");
+}
+
+#[cfg(feature = "cbor")]
+#[test]
+fn cbor_codec_test() {
+    use gdbm::{Cbor, Codec};
+
+    let _ = remove_file("cbor_codec_test.db");
+    type Value = (u64, i64, Vec<Option<String>>, f64);
+    let db: gdbm::TypedGdbm<String, Value, Cbor> =
+        gdbm::TypedGdbm::new(new_db("cbor_codec_test.db"));
+    let value = (u64::MAX, -1000, vec![Some("a".to_string()), None], 1.1);
+    db.insert(&"k".to_string(), &value).expect("insert");
+    assert_eq!(db.get(&"k".to_string()).expect("get"), Some(value));
+    // Examples from RFC 8949 appendix A
+    assert_eq!(db.as_gdbm().fetch_data(b"\x61k").expect("fetch_data"),
+               Some(b"\x84\x1b\xff\xff\xff\xff\xff\xff\xff\xff\x39\x03\xe7\x82\x61a\xf6\xfb\x3f\xf1\x99\x99\x99\x99\x99\x9a".to_vec()));
+    assert_eq!(<Cbor as Codec<f64>>::decode(b"\xf9\x3e\x00").expect("decode"), 1.5);
+    assert_eq!(<Cbor as Codec<Vec<u8>>>::decode(b"\x9f\x01\x02\xff").expect("decode"), vec![1, 2]);
+
+    assert!(<Cbor as Codec<Vec<u8>>>::decode(b"\x82\x01").is_err());
+    assert!(<Cbor as Codec<u8>>::decode(b"\x01\x02").is_err());
+    assert!(<Cbor as Codec<String>>::decode(b"\x41a").is_err());
+    assert!(<Cbor as Codec<Vec<u8>>>::decode(b"\x9b\xff\xff\xff\xff\xff\xff\xff\xff").is_err());
+    assert!(<Cbor as Codec<Vec<()>>>::decode(&[0x81; 100_000]).is_err());
+    drop(db);
+    remove_file("cbor_codec_test.db").expect("remove_file");
+}
+
+#[cfg(feature = "msgpack")]
+#[test]
+fn msgpack_codec_test() {
+    use gdbm::{Codec, MessagePack};
+
+    let _ = remove_file("msgpack_codec_test.db");
+    type Value = (u64, i64, Vec<Option<String>>, f64);
+    let db: gdbm::TypedGdbm<String, Value, MessagePack> =
+        gdbm::TypedGdbm::new(new_db("msgpack_codec_test.db"));
+    let value = (u64::MAX, -129, vec![Some("a".to_string()), None], 1.5);
+    db.insert(&"k".to_string(), &value).expect("insert");
+    assert_eq!(db.get(&"k".to_string()).expect("get"), Some(value));
+    assert_eq!(db.as_gdbm().fetch_data(b"\xa1k").expect("fetch_data"),
+               Some(b"\x94\xcf\xff\xff\xff\xff\xff\xff\xff\xff\xd1\xff\x7f\x92\xa1a\xc0\xcb\x3f\xf8\0\0\0\0\0\0".to_vec()));
+
+    // Structs are maps keyed by field name
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Point {
+        x: i8,
+        y: i8,
+    }
+    let packed = b"\x82\xa1x\x01\xa1y\xff".to_vec();
+    assert_eq!(<MessagePack as Codec<Point>>::encode(&Point { x: 1, y: -1 }).expect("encode"), packed);
+    assert_eq!(<MessagePack as Codec<Point>>::decode(&packed).expect("decode"), Point { x: 1, y: -1 });
+    assert_eq!(<MessagePack as Codec<f64>>::decode(b"\xca\x3f\xc0\0\0").expect("decode"), 1.5);
+
+    assert!(<MessagePack as Codec<Vec<u8>>>::decode(b"\x92\x01").is_err());
+    assert!(<MessagePack as Codec<u8>>::decode(b"\x01\x02").is_err());
+    assert!(<MessagePack as Codec<Vec<u8>>>::decode(b"\xdd\xff\xff\xff\xff").is_err());
+    assert!(<MessagePack as Codec<Vec<()>>>::decode(&[0x91; 100_000]).is_err());
+    drop(db);
+    remove_file("msgpack_codec_test.db").expect("remove_file");
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn serializable_test() {
+    use gdbm::BytesFormat;
+
+    let _ = remove_file("serializable_test.db");
+    let db = new_db("serializable_test.db");
+    assert_eq!(db.as_serializable(BytesFormat::Lossy).to_string(), "{}");
+    db.insert("k\"ey", b"\xffa\n").expect("insert");
+    assert_eq!(db.as_serializable(BytesFormat::Lossy).to_string(), "{\"k\\\"ey\":\"\u{FFFD}a\\n\"}");
+    let mut out = Vec::new();
+    db.as_serializable(BytesFormat::Base64).write_to(&mut out).expect("write_to");
+    assert_eq!(out, b"{\"ayJleQ==\":\"/2EK\"}".to_vec());
+    db.insert("k2", "v2").expect("insert");
+    let json = serde_json::to_string(&db.as_serializable(BytesFormat::Lossy)).expect("to_string");
+    match serde_json::from_str(&json).expect("from_str") {
+        serde_json::Value::Object(members) => assert_eq!(members.len(), 2),
+        other => panic!("not an object: {}", other),
+    }
+    drop(db);
+    remove_file("serializable_test.db").expect("remove_file");
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn jsonl_test() {
+    use gdbm::{GdbmError, ImportFlag};
+
+    let _ = remove_file("jsonl_test.db");
+    let db = new_db("jsonl_test.db");
+    db.insert("k\"1", "line\nbreak").expect("insert");
+    db.insert(b"\xff", "binary key").expect("insert");
+    let mut jsonl = Vec::new();
+    assert_eq!(db.export_jsonl(&mut jsonl).expect("export_jsonl"), 2);
+    let text = String::from_utf8(jsonl.clone()).expect("utf-8");
+    assert!(text.contains("{\"key\":\"k\\\"1\",\"value\":\"line\\nbreak\"}\n"));
+    assert!(text.contains("{\"key_base64\":\"/w==\",\"value\":\"binary key\"}\n"));
+
+    let copy = new_db("jsonl_test_copy.db");
+    assert_eq!(copy.import_jsonl(&mut &jsonl[..], ImportFlag::Insert).expect("import_jsonl"), 2);
+    assert_eq!(copy.to_map().expect("to_map"), db.to_map().expect("to_map"));
+    match copy.import_jsonl(&mut &jsonl[..], ImportFlag::Insert) {
+        Err(GdbmError::ImportError { line: 1, .. }) => {}
+        other => panic!("expected an import error on line 1, got {:?}", other),
+    }
+    let extra = b"\n{\"value\": \"v\", \"key\": \"k\", \"ts\": 1}\n{\"key\": 1, \"value\": \"v\"}\n";
+    match copy.import_jsonl(&mut &extra[..], ImportFlag::Replace) {
+        Err(GdbmError::ImportError { line: 3, .. }) => {}
+        other => panic!("expected an import error on line 3, got {:?}", other),
+    }
+    assert_eq!(copy.fetch_data("k").expect("fetch_data"), Some(b"v".to_vec()));
+
+    let mut ascii = gdbm::ExportMap::new();
+    ascii.map_key(|key| key.into_iter().filter(u8::is_ascii).collect());
+    let mut mapped = Vec::new();
+    assert_eq!(db.export_jsonl_mapped(&mut mapped, &mut ascii).expect("export_jsonl_mapped"), 2);
+    assert!(String::from_utf8(mapped).expect("utf-8").contains("{\"key\":\"\",\"value\":\"binary key\"}\n"));
+    drop(db);
+    drop(copy);
+    remove_file("jsonl_test.db").expect("remove_file");
+    remove_file("jsonl_test_copy.db").expect("remove_file");
+}
+
+#[test]
+fn bucket_test() {
+    let _ = remove_file("bucket_test.db");
+    let db = new_db("bucket_test.db");
+    let users = db.bucket("users").expect("bucket");
+    let user = db.bucket("user").expect("bucket");
+    assert!(db.bucket("bad\0name").is_err());
+    assert_eq!(users.name(), "users");
+
+    users.insert("alice", "1").expect("insert");
+    users.insert("bob", "2").expect("insert");
+    user.insert("alice", "other").expect("insert");
+    db.store_checked("users-unrelated", "x", true).expect("store_checked");
+
+    assert_eq!(users.fetch_data("alice").expect("fetch_data"), Some(b"1".to_vec()));
+    assert_eq!(user.fetch_data("alice").expect("fetch_data"), Some(b"other".to_vec()));
+    assert_eq!(db.fetch_data(users.full_key("bob")).expect("fetch_data"), Some(b"2".to_vec()));
+    assert_eq!(users.len().expect("len"), 2);
+
+    let mut records = users.iter().collect::<Result<Vec<_>, _>>().expect("iter");
+    records.sort();
+    assert_eq!(records,
+               vec![(b"alice".to_vec(), b"1".to_vec()), (b"bob".to_vec(), b"2".to_vec())]);
+
+    assert_eq!(users.clear().expect("clear"), 2);
+    assert!(users.is_empty().expect("is_empty"));
+    assert_eq!(user.keys().count(), 1);
+    assert!(db.fetch_data("users-unrelated").expect("fetch_data").is_some());
+    drop(db);
+    remove_file("bucket_test.db").expect("remove_file");
+}
+
+#[test]
+fn migrations_test() {
+    let _ = remove_file("migrations_test.db");
+    let db = new_db("migrations_test.db");
+    for i in 0..10 {
+        db.store_checked(format!("k{}", i), i.to_string(), true).expect("store_checked");
+    }
+    assert_eq!(db.schema_version().expect("schema_version"), 0);
+    drop(db);
+
+    let mut migrations = gdbm::Migrations::new();
+    // v1: values become "n=<old>"
+    migrations.add(|_key, value| Ok(Some([&b"n="[..], &value].concat())));
+    // v2: drop odd values, leave the rest
+    migrations.add(|_key, value| {
+        let n: u32 = String::from_utf8(value[2..].to_vec())?.parse().expect("number");
+        Ok(if n % 2 == 1 { None } else { Some(value) })
+    });
+    assert_eq!(migrations.latest(), 2);
+
+    let mut options = gdbm::OpenOptions::new();
+    options.flags(gdbm::Open::WRCREAT);
+    let db = options.open(Path::new("migrations_test.db")).expect("open");
+    let report = migrations.dry_run(&db).expect("dry_run");
+    assert_eq!(report,
+               gdbm::MigrationReport { from: 0, to: 2, changed: 5, deleted: 5, unchanged: 0 });
+    assert_eq!(db.fetch_data("k3").expect("fetch_data"), Some(b"3".to_vec()));
+    drop(db);
+
+    // A reader refuses to open an unmigrated database
+    options.flags(gdbm::Open::READER).migrations(&migrations);
+    assert!(options.open(Path::new("migrations_test.db")).is_err());
+
+    options.flags(gdbm::Open::WRCREAT);
+    let db = options.open(Path::new("migrations_test.db")).expect("open");
+    assert_eq!(db.schema_version().expect("schema_version"), 2);
+    assert_eq!(db.fetch_data("k4").expect("fetch_data"), Some(b"n=4".to_vec()));
+    assert_eq!(db.fetch_data("k3").expect("fetch_data"), None);
+    let mut reports = Vec::new();
+    let report = migrations.run_with_progress(&db, false, |p| reports.push(p.records)).expect("run");
+    assert_eq!((report.from, report.to, report.changed), (2, 2, 0));
+    assert!(reports.is_empty());
+
+    let mut newer = migrations.clone();
+    newer.add(|_key, value| Ok(Some(value)));
+    drop(db);
+    let db = gdbm::OpenOptions::new().flags(gdbm::Open::WRCREAT).open(Path::new("migrations_test.db")).expect("open");
+    assert_eq!(newer.run(&db).expect("run").unchanged, 5);
+    assert!(migrations.run(&db).is_err());
+    drop(db);
+
+    // Emptying the database keeps its schema version, so records stored
+    // afterwards in the latest format are not migrated again
+    options.migrations(&newer);
+    let db = options.open(Path::new("migrations_test.db")).expect("open");
+    db.clear().expect("clear");
+    db.insert("k1", "n=1").expect("insert");
+    drop(db);
+    let db = options.open(Path::new("migrations_test.db")).expect("open");
+    assert_eq!(db.schema_version().expect("schema_version"), 3);
+    assert_eq!(db.fetch_data("k1").expect("fetch_data"), Some(b"n=1".to_vec()));
+    assert_eq!(db.delete_prefix("").expect("delete_prefix"), 1);
+    db.insert("k2", "n=2").expect("insert");
+    drop(db);
+    let db = options.open(Path::new("migrations_test.db")).expect("open");
+    assert_eq!(db.schema_version().expect("schema_version"), 3);
+    assert_eq!(db.fetch_data("k2").expect("fetch_data"), Some(b"n=2".to_vec()));
+    drop(db);
+    remove_file("migrations_test.db").expect("remove_file");
+}
+
+#[test]
+fn secondary_index_test() {
+    let _ = remove_file("secondary_index_test.db");
+    let _ = remove_file("secondary_index_test.idx");
+    let db = new_db("secondary_index_test.db");
+    // Values are "city,name"; index by city
+    let index = gdbm::SecondaryIndex::new(new_db("secondary_index_test.idx"), |_key, value| {
+        value.split(|&b| b == b',').next().map(|city| city.to_vec()).into_iter().collect()
+    });
+    index.store(&db, "1", "paris,ann").expect("store");
+    index.store(&db, "2", "oslo,bob").expect("store");
+    index.store(&db, "3", "paris,cid").expect("store");
+    assert_eq!(index.lookup("paris").expect("lookup"), vec![b"1".to_vec(), b"3".to_vec()]);
+
+    // Moving a record updates both entries
+    assert_eq!(index.store(&db, "1", "oslo,ann").expect("store"), Some(b"paris,ann".to_vec()));
+    assert_eq!(index.lookup("paris").expect("lookup"), vec![b"3".to_vec()]);
+    assert_eq!(index.get(&db, "oslo").expect("get"),
+               vec![(b"1".to_vec(), b"oslo,ann".to_vec()), (b"2".to_vec(), b"oslo,bob".to_vec())]);
+
+    index.remove(&db, "3").expect("remove");
+    assert!(index.lookup("paris").expect("lookup").is_empty());
+    assert_eq!(index.as_gdbm().fetch_data("paris").expect("fetch_data"), None);
+
+    // Writes that bypass the index are picked up by a rebuild
+    db.store_checked("4", "rome,dan", true).expect("store_checked");
+    assert!(index.lookup("rome").expect("lookup").is_empty());
+    assert_eq!(index.rebuild(&db).expect("rebuild"), 3);
+    assert_eq!(index.lookup("rome").expect("lookup"), vec![b"4".to_vec()]);
+    assert_eq!(index.lookup("oslo").expect("lookup").len(), 2);
+    drop(index);
+    drop(db);
+    remove_file("secondary_index_test.db").expect("remove_file");
+    remove_file("secondary_index_test.idx").expect("remove_file");
+}
+
+#[test]
+fn ttl_test() {
+    use std::thread;
+    use std::time::Duration;
+
+    let _ = remove_file("ttl_test.db");
+    let db = gdbm::TtlGdbm::new(new_db("ttl_test.db"));
+    db.insert("forever", "a").expect("insert");
+    db.insert_with_ttl("short", "b", Duration::from_millis(30)).expect("insert_with_ttl");
+    db.insert_with_ttl("long", "c", Duration::from_secs(3600)).expect("insert_with_ttl");
+    assert_eq!(db.get("short").expect("get"), Some(b"b".to_vec()));
+    assert_eq!(db.ttl("forever").expect("ttl"), Some(None));
+    assert!(db.ttl("long").expect("ttl").expect("live").expect("expires") > Duration::from_secs(3500));
+
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(db.get("short").expect("get"), None);
+    assert_eq!(db.ttl("short").expect("ttl"), None);
+    // Still in the file until purged
+    assert!(db.as_shared().fetch_data("short").expect("fetch_data").is_some());
+    assert_eq!(db.purge_expired().expect("purge_expired"), 1);
+    assert!(db.as_shared().fetch_data("short").expect("fetch_data").is_none());
+
+    let sweeper = db.spawn_sweeper(Duration::from_millis(10)).expect("spawn_sweeper");
+    db.insert_with_ttl("swept", "d", Duration::from_millis(1)).expect("insert_with_ttl");
+    thread::sleep(Duration::from_millis(100));
+    drop(sweeper);
+    assert!(db.as_shared().fetch_data("swept").expect("fetch_data").is_none());
+    assert_eq!(db.as_shared().keys().expect("keys").len(), 2);
+    drop(db);
+    remove_file("ttl_test.db").expect("remove_file");
+}
+
+#[cfg(feature = "compression")]
+#[test]
+fn compression_test() {
+    let _ = remove_file("compression_test.db");
+    let db = new_db("compression_test.db");
+    db.store_checked("legacy", "written before compression", true).expect("store_checked");
+    let db = gdbm::CompressedGdbm::new(db);
+
+    let json: String = (0..200).map(|i| format!("{{\"id\":{},\"name\":\"user\",\"active\":true}},", i)).collect();
+    let run = vec![b'a'; 100_000];
+    let mut state = 12345u32;
+    let noise: Vec<u8> = (0..5000).map(|_| {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        (state >> 16) as u8
+    }).collect();
+    let magic = b"\0lz4 but not compressed".to_vec();
+    let values: Vec<(&str, Vec<u8>)> =
+        vec![("json", json.into_bytes()), ("run", run), ("noise", noise), ("magic", magic), ("short", b"x".to_vec()), ("empty", Vec::new())];
+    for &(key, ref value) in &values {
+        db.insert(key, value).expect("insert");
+        assert_eq!(db.fetch_data(key).expect("fetch_data").as_ref(), Some(value), "{}", key);
+    }
+    let stored = |key: &str| db.as_gdbm().fetch_data(key).expect("fetch_data").expect("stored");
+    assert!(stored("json").len() * 5 < values[0].1.len());
+    assert!(stored("run").len() < 1000);
+    assert_eq!(stored("short"), b"x".to_vec());
+    assert_eq!(db.fetch_data("legacy").expect("fetch_data"), Some(b"written before compression".to_vec()));
+    assert_eq!(db.iter().filter(|r| r.is_ok()).count(), 7);
+
+    // A block from another LZ4 encoder: "abc", a 27 byte match at offset 3
+    // that overlaps its own output, and "xyzzy"
+    let mut foreign = b"\0lz4\x01".to_vec();
+    foreign.extend_from_slice(&35u32.to_le_bytes());
+    foreign.extend_from_slice(b"\x3fabc\x03\x00\x08\x50xyzzy");
+    db.as_gdbm().insert("foreign", &foreign).expect("insert");
+    assert_eq!(db.fetch_data("foreign").expect("fetch_data"), Some(b"abcabcabcabcabcabcabcabcabcabcxyzzy".to_vec()));
+
+    // A truncated block is reported, not misread
+    let mut broken = stored("json");
+    broken.truncate(broken.len() / 2);
+    db.as_gdbm().insert("broken", &broken).expect("insert");
+    assert!(db.fetch_data("broken").is_err());
+    drop(db);
+    remove_file("compression_test.db").expect("remove_file");
+}
+
+#[cfg(feature = "encryption")]
+#[test]
+fn encryption_test() {
+    use gdbm::{EncryptedGdbm, GdbmError, KeyProvider, StaticKey, Zeroizing};
+
+    struct Rotated;
+
+    impl KeyProvider for Rotated {
+        fn current_key_id(&self) -> u32 {
+            2
+        }
+
+        fn key(&self, id: u32) -> Result<Zeroizing<[u8; 32]>, GdbmError> {
+            match id {
+                0 => Ok(Zeroizing::new([7; 32])),
+                2 => Ok(Zeroizing::new([9; 32])),
+                _ => Err(GdbmError::Error("no such key".to_string())),
+            }
+        }
+    }
+
+    let _ = remove_file("encryption_test.db");
+    let db = EncryptedGdbm::new(new_db("encryption_test.db"), StaticKey::new([7; 32]));
+    let secret = b"hunter2 hunter2 hunter2".to_vec();
+    db.insert("password", &secret).expect("insert");
+    db.store_checked("empty", "", true).expect("store_checked");
+    assert_eq!(db.fetch_data("password").expect("fetch_data"), Some(secret.clone()));
+    assert_eq!(db.fetch_data("empty").expect("fetch_data"), Some(Vec::new()));
+
+    let stored = db.as_gdbm().fetch_data("password").expect("fetch_data").expect("stored");
+    assert_eq!(stored.len(), 1 + 4 + 24 + secret.len() + 16);
+    assert!(stored.windows(7).all(|w| w != b"hunter2"));
+    // The same value encrypts differently every time
+    db.insert("password", &secret).expect("insert");
+    assert!(db.as_gdbm().fetch_data("password").expect("fetch_data") != Some(stored.clone()));
+
+    // Tampering with any field, truncating, or moving a value to another
+    // key fails authentication
+    let fields = [("format", 0), ("key id", 1), ("nonce", 5), ("ciphertext", 29), ("tag", stored.len() - 1)];
+    for &(field, offset) in &fields {
+        let mut tampered = stored.clone();
+        tampered[offset] ^= 1;
+        db.as_gdbm().insert("tampered", &tampered).expect("insert");
+        assert!(db.fetch_data("tampered").is_err(), "tampered {}", field);
+    }
+    db.as_gdbm().insert("tampered", &stored[..stored.len() - 1]).expect("insert");
+    assert!(db.fetch_data("tampered").is_err());
+    db.as_gdbm().insert("tampered", &stored[..1 + 4 + 24 + 15]).expect("insert");
+    assert!(db.fetch_data("tampered").is_err());
+    db.as_gdbm().insert("moved", &stored).expect("insert");
+    assert!(db.fetch_data("moved").is_err());
+    db.as_gdbm().remove("tampered").expect("remove");
+    db.as_gdbm().remove("moved").expect("remove");
+
+    let db = EncryptedGdbm::new(db.into_inner(), StaticKey::new([8; 32]));
+    assert!(db.fetch_data("password").is_err());
+
+    // Rotation: values under key 0 are rewritten under key 2
+    let db = EncryptedGdbm::new(db.into_inner(), Rotated);
+    assert_eq!(db.fetch_data("password").expect("fetch_data"), Some(secret.clone()));
+    assert_eq!(db.reencrypt().expect("reencrypt"), 2);
+    assert_eq!(db.reencrypt().expect("reencrypt"), 0);
+    assert_eq!(db.iter().collect::<Result<Vec<_>, _>>().expect("iter").len(), 2);
+    assert_eq!(db.remove("password").expect("remove"), Some(secret));
+    drop(db);
+    remove_file("encryption_test.db").expect("remove_file");
+}
+
+#[test]
+fn checksum_test() {
+    let _ = remove_file("checksum_test.db");
+    let db = gdbm::ChecksummedGdbm::new(new_db("checksum_test.db"));
+    db.insert("greeting", "123456789").expect("insert");
+    db.store_checked("empty", "", true).expect("store_checked");
+    assert_eq!(db.fetch_data("greeting").expect("fetch_data"), Some(b"123456789".to_vec()));
+    assert_eq!(db.fetch_data("empty").expect("fetch_data"), Some(Vec::new()));
+    // CRC-32 check value of "123456789"
+    let stored = db.as_gdbm().fetch_data("greeting").expect("fetch_data").expect("stored");
+    assert_eq!(&stored[9..], &0xcbf4_3926u32.to_le_bytes());
+
+    let mut rotten = stored.clone();
+    rotten[3] ^= 0x10;
+    db.as_gdbm().insert("greeting", &rotten).expect("insert");
+    match db.fetch_data("greeting") {
+        Err(gdbm::GdbmError::ChecksumMismatch { key, expected, actual }) => {
+            assert_eq!(key, b"greeting".to_vec());
+            assert_eq!(expected, 0xcbf4_3926);
+            assert!(actual != expected);
+        }
+        other => panic!("expected a checksum mismatch, got {:?}", other),
+    }
+    assert_eq!(db.scrub().expect("scrub"), vec![b"greeting".to_vec()]);
+    assert_eq!(db.iter().filter(|r| r.is_err()).count(), 1);
+    assert!(db.remove("greeting").is_err());
+    assert_eq!(db.fetch_data("greeting").expect("fetch_data"), None);
+    assert!(db.scrub().expect("scrub").is_empty());
+    drop(db);
+    remove_file("checksum_test.db").expect("remove_file");
+}
+
+#[test]
+fn blob_test() {
+    use std::io::{Read, Write};
+
+    let _ = remove_file("blob_test.db");
+    let db = new_db("blob_test.db");
+    let data: Vec<u8> = (0..gdbm::BLOB_CHUNK_SIZE * 2 + 12345).map(|i| (i % 251) as u8).collect();
+    let mut writer = db.blob_writer("big").expect("blob_writer");
+    for piece in data.chunks(100_000) {
+        writer.write_all(piece).expect("write_all");
+    }
+    // Nothing is visible until the writer finishes
+    assert_eq!(db.fetch_data("big").expect("fetch_data"), None);
+    assert_eq!(writer.finish().expect("finish"), data.len() as u64);
+    // Manifest plus three chunks
+    assert_eq!(db.keys().count(), 4);
+
+    let mut reader = db.blob_reader("big").expect("blob_reader").expect("exists");
+    assert_eq!(reader.len(), data.len() as u64);
+    let mut read = Vec::new();
+    reader.read_to_end(&mut read).expect("read_to_end");
+    assert!(read == data);
+
+    // A writer dropped unfinished leaves the old value and no chunks
+    {
+        let mut writer = db.blob_writer("big").expect("blob_writer");
+        writer.write_all(&data[..gdbm::BLOB_CHUNK_SIZE + 10]).expect("write_all");
+    }
+    let mut read = Vec::new();
+    db.blob_reader("big").expect("blob_reader").expect("exists").read_to_end(&mut read).expect("read_to_end");
+    assert!(read == data);
+    assert_eq!(db.keys().count(), 4);
+    let mut writer = db.blob_writer("big").expect("blob_writer");
+    writer.write_all(&data[..gdbm::BLOB_CHUNK_SIZE + 10]).expect("write_all");
+    writer.discard().expect("discard");
+    assert_eq!(db.keys().count(), 4);
+
+    // Rewriting replaces the old chunks
+    let mut writer = db.blob_writer("big").expect("blob_writer");
+    writer.write_all(b"small now").expect("write_all");
+    writer.finish().expect("finish");
+    let mut read = Vec::new();
+    db.blob_reader("big").expect("blob_reader").expect("exists").read_to_end(&mut read).expect("read_to_end");
+    assert_eq!(read, b"small now".to_vec());
+    assert_eq!(db.keys().count(), 2);
+
+    db.blob_writer("empty").expect("blob_writer").finish().expect("finish");
+    assert!(db.blob_reader("empty").expect("blob_reader").expect("exists").is_empty());
+    assert!(db.blob_reader("missing").expect("blob_reader").is_none());
+    db.store_checked("plain", "x", true).expect("store_checked");
+    assert!(db.blob_reader("plain").is_err());
+
+    assert!(db.remove_blob("big").expect("remove_blob"));
+    assert!(db.remove_blob("empty").expect("remove_blob"));
+    assert_eq!(db.keys().count(), 1);
+    drop(db);
+    remove_file("blob_test.db").expect("remove_file");
+}
+
+#[test]
+fn map_test() {
+    use std::collections::{BTreeMap, HashMap};
+
+    let _ = remove_file("map_test.db");
+    let mut map = BTreeMap::new();
+    for i in 0..50 {
+        map.insert(format!("key{:02}", i).into_bytes(), vec![i as u8; i]);
+    }
+    let mut options = gdbm::OpenOptions::new();
+    options.flags(gdbm::Open::WRCREAT);
+    let db = new_db("map_test.db");
+    db.store_checked("stale", "gone after from_map", true).expect("store_checked");
+    drop(db);
+
+    let db = gdbm::Gdbm::from_map(Path::new("map_test.db"), &map, &options).expect("from_map");
+    assert_eq!(db.to_map().expect("to_map"), map);
+    let hash_map: HashMap<Vec<u8>, Vec<u8>> = map.clone().into_iter().collect();
+    assert_eq!(db.to_hash_map().expect("to_hash_map"), hash_map);
+    drop(db);
+
+    let db = gdbm::Gdbm::from_map(Path::new("map_test.db"), &hash_map, &options).expect("from_map");
+    assert_eq!(db.to_map().expect("to_map"), map);
+    drop(db);
+    remove_file("map_test.db").expect("remove_file");
+}
+
+#[test]
+fn convert_test() {
+    use gdbm::DbFormat;
+
+    if gdbm::version_cmp(gdbm::version(), (1, 21, 0)) == std::cmp::Ordering::Less {
+        return;
+    }
+    let _ = remove_file("convert_test.db");
+    let db = new_db("convert_test.db");
+    db.insert("k", "v").expect("insert");
+    assert_eq!(db.format().expect("format"), DbFormat::Standard);
+    db.convert(DbFormat::Numsync).expect("convert");
+    assert_eq!(db.format().expect("format"), DbFormat::Numsync);
+    db.convert(DbFormat::Numsync).expect("convert");
+    db.convert(DbFormat::Standard).expect("convert");
+    assert_eq!(db.format().expect("format"), DbFormat::Standard);
+    assert_eq!(db.fetch_data("k").expect("fetch_data"), Some(b"v".to_vec()));
+    drop(db);
+    remove_file("convert_test.db").expect("remove_file");
+}
+
+#[test]
+fn csv_test() {
+    use gdbm::{CsvOptions, GdbmError, ImportFlag, Quoting};
+
+    let _ = remove_file("csv_test.db");
+    let db = new_db("csv_test.db");
+    db.insert("plain", "value").expect("insert");
+    db.insert("with,comma", "say \"hi\"\nbye").expect("insert");
+    db.insert("binary", b"\x00\xff").expect("insert");
+
+    for options in &[CsvOptions::new(), CsvOptions::new().quoting(Quoting::Always).delimiter(b';').clone()] {
+        let mut csv = Vec::new();
+        assert_eq!(db.export_csv(&mut csv, options).expect("export_csv"), 3);
+        let copy = new_db("csv_test_copy.db");
+        assert_eq!(copy.import_csv(&mut &csv[..], options).expect("import_csv"), 3);
+        assert_eq!(copy.to_map().expect("to_map"), db.to_map().expect("to_map"));
+    }
+
+    let mut options = CsvOptions::new();
+    options.base64_values(true).header(true);
+    let mut csv = Vec::new();
+    db.export_csv(&mut csv, &options).expect("export_csv");
+    assert!(csv.starts_with(b"key,value\n"));
+    let copy = new_db("csv_test_copy.db");
+    copy.import_csv(&mut &csv[..], &options).expect("import_csv");
+    assert_eq!(copy.to_map().expect("to_map"), db.to_map().expect("to_map"));
+    match copy.import_csv(&mut &csv[..], &options) {
+        Err(GdbmError::ImportError { line: 2, .. }) => {}
+        other => panic!("expected an import error on line 2, got {:?}", other),
+    }
+    options.import_flag(ImportFlag::Replace);
+    copy.import_csv(&mut &csv[..], &options).expect("import_csv");
+
+    let tsv = b"a\t\"1\"\r\n\nb\t2\n";
+    assert_eq!(copy.import_csv(&mut &tsv[..], CsvOptions::tsv().import_flag(ImportFlag::Replace)).expect("import_csv"), 2);
+    assert_eq!(copy.fetch_data("a").expect("fetch_data"), Some(b"\"1\"".to_vec()));
+    assert!(db.export_csv(&mut Vec::new(), &CsvOptions::tsv()).is_err());
+    match copy.import_csv(&mut &b"x,1\ny,\"2\n"[..], &CsvOptions::new()) {
+        Err(GdbmError::ImportError { line: 2, .. }) => {}
+        other => panic!("expected an import error on line 2, got {:?}", other),
+    }
+    match copy.import_csv(&mut &b"c,1,2\n"[..], &CsvOptions::new()) {
+        Err(GdbmError::ImportError { line: 1, .. }) => {}
+        other => panic!("expected an import error on line 1, got {:?}", other),
+    }
+    drop(db);
+    drop(copy);
+    remove_file("csv_test.db").expect("remove_file");
+    remove_file("csv_test_copy.db").expect("remove_file");
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn sqlite_test() {
+    let _ = remove_file("sqlite_test.db");
+    let _ = remove_file("sqlite_test.sqlite");
+    let db = new_db("sqlite_test.db");
+    for i in 0..100 {
+        db.insert(format!("key{}", i), vec![i as u8; i]).expect("insert");
+    }
+    let path = Path::new("sqlite_test.sqlite");
+    assert_eq!(db.export_sqlite(path, "my \"records\"").expect("export_sqlite"), 100);
+    assert!(db.export_sqlite(path, "my \"records\"").is_err());
+    assert_eq!(db.export_sqlite(path, "second").expect("export_sqlite"), 100);
+    let sqlite = rusqlite::Connection::open(path).expect("open");
+    let (count, total): (i64, i64) = sqlite.query_row("SELECT count(*), sum(length(value)) FROM \"my \"\"records\"\"\"",
+                   [],
+                   |row| Ok((row.get(0)?, row.get(1)?)))
+        .expect("query_row");
+    assert_eq!((count, total), (100, 4950));
+    let value: Vec<u8> = sqlite.query_row("SELECT value FROM second WHERE key = ?1", [b"key7"], |row| row.get(0))
+        .expect("query_row");
+    assert_eq!(value, vec![7; 7]);
+    drop(sqlite);
+    drop(db);
+    remove_file("sqlite_test.db").expect("remove_file");
+    remove_file("sqlite_test.sqlite").expect("remove_file");
+}
+
+#[cfg(feature = "bdb")]
+#[test]
+fn bdb_test() {
+    use std::collections::BTreeMap;
+
+    use gdbm::{BdbHashFile, OnConflict};
+
+    // tests/data/bdb_hash.db was written by Berkeley DB 5.3 through its
+    // ndbm interface, with these records
+    let mut expected = BTreeMap::new();
+    for i in 0..40 {
+        expected.insert(format!("key{:02}", i).into_bytes(), format!("value {} ", i).repeat(i % 7).into_bytes());
+    }
+    expected.insert(b"big".to_vec(), (0..=255u8).collect::<Vec<u8>>().repeat(40));
+    expected.insert(b"\x00\xff".to_vec(), Vec::new());
+
+    let path = Path::new("tests/data/bdb_hash.db");
+    let source = BdbHashFile::open(path).expect("BdbHashFile::open");
+    let records: BTreeMap<_, _> = source.iter().collect::<Result<_, _>>().expect("iter");
+    assert_eq!(records, expected);
+
+    let _ = remove_file("bdb_test.db");
+    let db = new_db("bdb_test.db");
+    db.insert("key00", "mine").expect("insert");
+    let stats = db.import_bdb_hash(path, OnConflict::Skip).expect("import_bdb_hash");
+    assert_eq!((stats.inserted, stats.replaced, stats.skipped), (41, 0, 1));
+    assert_eq!(db.fetch_data("key00").expect("fetch_data"), Some(b"mine".to_vec()));
+    assert!(db.import_bdb_hash(path, OnConflict::Fail).is_err());
+    let stats = db.import_bdb_hash(path, OnConflict::Replace).expect("import_bdb_hash");
+    assert_eq!(stats.replaced, 42);
+    assert_eq!(db.to_map().expect("to_map"), expected);
+    assert!(BdbHashFile::open(Path::new("bdb_test.db")).is_err());
+    drop(db);
+    remove_file("bdb_test.db").expect("remove_file");
+}
+
+#[test]
+fn detect_format_test() {
+    use std::fs::write;
+
+    use gdbm::{detect_format, DbFormat, FileFormat};
+
+    let _ = remove_file("detect_format_test.db");
+    let db = new_db("detect_format_test.db");
+    let path = Path::new("detect_format_test.db");
+    assert_eq!(detect_format(path).expect("detect_format"), FileFormat::Gdbm(DbFormat::Standard));
+    if gdbm::version_cmp(gdbm::version(), (1, 21, 0)) != std::cmp::Ordering::Less {
+        db.convert(DbFormat::Numsync).expect("convert");
+        assert_eq!(detect_format(path).expect("detect_format"), FileFormat::Gdbm(DbFormat::Numsync));
+    }
+    drop(db);
+    assert_eq!(detect_format(Path::new("tests/data/bdb_hash.db")).expect("detect_format"), FileFormat::BerkeleyDb);
+    assert_eq!(detect_format(Path::new("Cargo.toml")).expect("detect_format"), FileFormat::Unknown);
+
+    // gdbm's 32 bit offset magic, in both byte orders, as if from another host
+    write(path, 0x1357_9acdu32.to_ne_bytes()).expect("write");
+    assert_eq!(detect_format(path).expect("detect_format"), FileFormat::ForeignGdbm);
+    write(path, 0x1357_9acfu32.swap_bytes().to_ne_bytes()).expect("write");
+    assert_eq!(detect_format(path).expect("detect_format"), FileFormat::ForeignGdbm);
+    write(path, b"").expect("write");
+    assert_eq!(detect_format(path).expect("detect_format"), FileFormat::Unknown);
+    remove_file(path).expect("remove_file");
+    assert!(detect_format(path).is_err());
+
+    write("detect_format_test.dir", b"").expect("write");
+    write("detect_format_test.pag", b"").expect("write");
+    assert_eq!(detect_format(Path::new("detect_format_test")).expect("detect_format"), FileFormat::Ndbm);
+    remove_file("detect_format_test.dir").expect("remove_file");
+    remove_file("detect_format_test.pag").expect("remove_file");
+}
+
+#[test]
+fn diff_test() {
+    use gdbm::Difference;
+
+    let _ = remove_file("diff_test_a.db");
+    let _ = remove_file("diff_test_b.db");
+    let a = new_db("diff_test_a.db");
+    let b = new_db("diff_test_b.db");
+    for i in 0..100 {
+        a.insert(format!("key{}", i), "same").expect("insert");
+        b.insert(format!("key{}", i), "same").expect("insert");
+    }
+    assert_eq!(gdbm::diff(&a, &b).count(), 0);
+    a.insert("mine", "1").expect("insert");
+    b.insert("theirs", "2").expect("insert");
+    b.insert("key7", "changed").expect("insert");
+    let mut differences: Vec<Difference> = gdbm::diff(&a, &b).collect::<Result<_, _>>().expect("diff");
+    differences.sort_by(|x, y| x.key().cmp(y.key()));
+    assert_eq!(differences,
+               vec![Difference::Changed {
+                        key: b"key7".to_vec(),
+                        a: b"same".to_vec(),
+                        b: b"changed".to_vec(),
+                    },
+                    Difference::OnlyInA {
+                        key: b"mine".to_vec(),
+                        value: b"1".to_vec(),
+                    },
+                    Difference::OnlyInB {
+                        key: b"theirs".to_vec(),
+                        value: b"2".to_vec(),
+                    }]);
+    drop(a);
+    drop(b);
+    remove_file("diff_test_a.db").expect("remove_file");
+    remove_file("diff_test_b.db").expect("remove_file");
+}
+
+#[test]
+fn merge_test() {
+    use gdbm::ConflictPolicy;
+
+    let _ = remove_file("merge_test_mine.db");
+    let _ = remove_file("merge_test_theirs.db");
+    let mine = new_db("merge_test_mine.db");
+    let theirs = new_db("merge_test_theirs.db");
+    mine.insert("same", "1").expect("insert");
+    mine.insert("conflict", "mine").expect("insert");
+    theirs.insert("same", "1").expect("insert");
+    theirs.insert("conflict", "theirs").expect("insert");
+    theirs.insert("new", "2").expect("insert");
+
+    let stats = mine.merge_from(&theirs, ConflictPolicy::KeepMine).expect("merge_from");
+    assert_eq!((stats.inserted, stats.replaced, stats.skipped), (1, 0, 2));
+    assert_eq!(mine.fetch_data("conflict").expect("fetch_data"), Some(b"mine".to_vec()));
+    assert_eq!(mine.fetch_data("new").expect("fetch_data"), Some(b"2".to_vec()));
+
+    let policy = ConflictPolicy::callback(|key, mine, theirs| {
+        assert_eq!(key, b"conflict");
+        Some([mine, b"+", theirs].concat())
+    });
+    let stats = mine.merge_from(&theirs, policy).expect("merge_from");
+    assert_eq!((stats.inserted, stats.replaced, stats.skipped), (0, 1, 2));
+    assert_eq!(mine.fetch_data("conflict").expect("fetch_data"), Some(b"mine+theirs".to_vec()));
+
+    let stats = mine.merge_from(&theirs, ConflictPolicy::TakeTheirs).expect("merge_from");
+    assert_eq!((stats.inserted, stats.replaced, stats.skipped), (0, 1, 2));
+    assert_eq!(gdbm::diff(&mine, &theirs).count(), 0);
+    drop(mine);
+    drop(theirs);
+    remove_file("merge_test_mine.db").expect("remove_file");
+    remove_file("merge_test_theirs.db").expect("remove_file");
+}
+
+#[test]
+fn copy_compacted_test() {
+    use std::fs::metadata;
+
+    let _ = remove_file("copy_compacted_test.db");
+    let _ = remove_file("copy_compacted_test_copy.db");
+    let db = new_db("copy_compacted_test.db");
+    for i in 0..2000 {
+        db.insert(format!("key{}", i), vec![b'x'; 200]).expect("insert");
+    }
+    for i in 0..1900 {
+        db.remove(format!("key{}", i)).expect("remove");
+    }
+    db.insert(gdbm::SCHEMA_VERSION_KEY, "2").expect("insert");
+    let dest = Path::new("copy_compacted_test_copy.db");
+    let copy = db.copy_compacted(dest).expect("copy_compacted");
+    assert_eq!(gdbm::diff(&db, &copy).count(), 0);
+    assert_eq!(copy.fetch_data(gdbm::SCHEMA_VERSION_KEY).expect("fetch_data"), Some(b"2".to_vec()));
+    copy.insert("writable", "yes").expect("insert");
+    assert!(metadata(dest).expect("metadata").len() < metadata("copy_compacted_test.db").expect("metadata").len());
+    assert!(db.copy_compacted(dest).is_err());
+    drop(db);
+    drop(copy);
+    remove_file("copy_compacted_test.db").expect("remove_file");
+    remove_file("copy_compacted_test_copy.db").expect("remove_file");
+}
+
+#[test]
+fn export_sorted_test() {
+    let _ = remove_file("export_sorted_test_a.db");
+    let _ = remove_file("export_sorted_test_b.db");
+    let a = new_db("export_sorted_test_a.db");
+    let b = new_db("export_sorted_test_b.db");
+    for i in 0..100 {
+        a.insert(format!("key{}", i), format!("value{}", i)).expect("insert");
+        b.insert(format!("key{}", 99 - i), format!("value{}", 99 - i)).expect("insert");
+    }
+    b.insert("gone", "soon").expect("insert");
+    b.remove("gone").expect("remove");
+
+    let mut dump_a = Vec::new();
+    let mut dump_b = Vec::new();
+    assert_eq!(a.export_sorted(&mut dump_a).expect("export_sorted"), 100);
+    assert_eq!(b.export_sorted(&mut dump_b).expect("export_sorted"), 100);
+    assert_eq!(dump_a, dump_b);
+    assert!(dump_a.starts_with(b"gdbm-sorted\n\0\0\0\x01"));
+
+    b.insert("key5", "changed").expect("insert");
+    assert_eq!(b.import_sorted(&mut &dump_a[..], gdbm::ImportFlag::Replace).expect("import_sorted"), 100);
+    assert_eq!(b.fetch_data("key5").expect("fetch_data"), Some(b"value5".to_vec()));
+    assert!(b.import_sorted(&mut &dump_a[..], gdbm::ImportFlag::Insert).is_err());
+    assert!(b.import_sorted(&mut &dump_a[..dump_a.len() - 3], gdbm::ImportFlag::Replace).is_err());
+    drop(a);
+    drop(b);
+    remove_file("export_sorted_test_a.db").expect("remove_file");
+    remove_file("export_sorted_test_b.db").expect("remove_file");
+}
+
+#[cfg(feature = "hash")]
+#[test]
+fn content_hash_test() {
+    use sha2::{Digest, Sha256};
+
+    let _ = remove_file("content_hash_test_a.db");
+    let _ = remove_file("content_hash_test_b.db");
+    let a = new_db("content_hash_test_a.db");
+    let b = new_db("content_hash_test_b.db");
+    let empty = a.content_hash().expect("content_hash");
+    // sha256sum of the 28 byte dump of an empty database
+    let hex: String = empty.iter().map(|byte| format!("{:02x}", byte)).collect();
+    assert_eq!(hex, "7071d241f33a760676c3cbe182456fb1d7343de2aac61a32cc0cff4dc680e347");
+    for i in 0..500 {
+        a.insert(format!("key{}", i), vec![i as u8; i]).expect("insert");
+        b.insert(format!("key{}", 499 - i), vec![(499 - i) as u8; 499 - i]).expect("insert");
+    }
+    let hash = a.content_hash().expect("content_hash");
+    assert_ne!(hash, empty);
+    assert_eq!(hash, b.content_hash().expect("content_hash"));
+    b.insert("key7", "changed").expect("insert");
+    assert_ne!(hash, b.content_hash().expect("content_hash"));
+
+    // The SHA-256 of the sorted dump
+    let mut dump = Vec::new();
+    a.export_sorted(&mut dump).expect("export_sorted");
+    assert_eq!(hash, <[u8; 32]>::from(Sha256::digest(&dump)));
+    drop(a);
+    drop(b);
+    remove_file("content_hash_test_a.db").expect("remove_file");
+    remove_file("content_hash_test_b.db").expect("remove_file");
+}
+
+#[test]
+fn foreign_format_test() {
+    use std::collections::BTreeMap;
+    use gdbm::{ForeignGdbmFile, Gdbm, GdbmError};
+
+    let _ = remove_file("foreign_format_test.db");
+    let _ = remove_file("foreign_format_test_native.db");
+    let _ = remove_file("foreign_format_test_copy.db");
+
+    // A native database reads back the same through ForeignGdbmFile
+    let native = new_db("foreign_format_test_native.db");
+    for i in 0..300 {
+        native.insert(format!("key{}", i), format!("value{}", i)).expect("insert");
+    }
+    native.sync().expect("sync");
+    let source = ForeignGdbmFile::open(Path::new("foreign_format_test_native.db")).expect("open");
+    let records: BTreeMap<_, _> = source.iter().collect::<Result<_, _>>().expect("iter");
+    assert_eq!(records.len(), 300);
+    assert_eq!(records[&b"key42"[..]], b"value42".to_vec());
+
+    // A big-endian file with 32-bit offsets: header, directory of one
+    // bucket with four elements, then the two records
+    let be = |n: u32| n.to_be_bytes().to_vec();
+    let mut file = vec![0u8; 2048];
+    let header = [be(0x1357_9acd), be(512), be(512), be(4), be(0), be(140), be(4), be(2048)].concat();
+    file[..header.len()].copy_from_slice(&header);
+    file[512..516].copy_from_slice(&be(1024));
+    let mut bucket = vec![0u8; 60];
+    bucket[56..60].copy_from_slice(&be(2));
+    bucket.extend([be(1), be(0), be(2048), be(3), be(5)].concat());
+    bucket.extend([be(2), be(0), be(2056), be(4), be(2)].concat());
+    for _ in 0..2 {
+        bucket.extend([be(u32::MAX), be(0), be(0), be(0), be(0)].concat());
+    }
+    file[1024..1024 + bucket.len()].copy_from_slice(&bucket);
+    file.extend_from_slice(b"onefirst");
+    file.extend_from_slice(b"two2nd");
+    std::fs::write("foreign_format_test.db", &file).expect("write");
+
+    let path = Path::new("foreign_format_test.db");
+    match Gdbm::new(path, 0, gdbm::Open::READER, 0o644) {
+        Err(GdbmError::ForeignFormat { path: ref reported }) => assert_eq!(reported, path),
+        other => panic!("expected ForeignFormat, got {:?}", other.map(|_| ())),
+    }
+    let copy = Gdbm::convert_offsets(path, Path::new("foreign_format_test_copy.db")).expect("convert_offsets");
+    assert_eq!(copy.fetch_data("one").expect("fetch_data"), Some(b"first".to_vec()));
+    assert_eq!(copy.fetch_data("two2").expect("fetch_data"), Some(b"nd".to_vec()));
+    assert_eq!(copy.iter().count(), 2);
+    assert!(Gdbm::convert_offsets(path, Path::new("foreign_format_test_copy.db")).is_err());
+    drop(native);
+    drop(copy);
+    remove_file("foreign_format_test.db").expect("remove_file");
+    remove_file("foreign_format_test_native.db").expect("remove_file");
+    remove_file("foreign_format_test_copy.db").expect("remove_file");
+}
+
+#[test]
+fn bulk_load_test() {
+    use std::fs::metadata;
+    use gdbm::BulkLoadOptions;
+
+    let _ = remove_file("bulk_load_test.db");
+    let db = new_db("bulk_load_test.db");
+    let cache_size = db.info().expect("info").cache_size;
+    {
+        let load = db.bulk_load_with(BulkLoadOptions::new()
+                .cache_size(8192)
+                .preallocate(4 << 20)
+                .reorganize(true))
+            .expect("bulk_load_with");
+        assert_eq!(load.info().expect("info").cache_size, 8192);
+        for i in 0..5000 {
+            load.insert(format!("key{}", i), vec![b'x'; 100]).expect("insert");
+        }
+        for i in 0..4000 {
+            load.remove(format!("key{}", i)).expect("remove");
+        }
+        load.finish().expect("finish");
+    }
+    assert_eq!(db.info().expect("info").cache_size, cache_size);
+    assert_eq!(db.iter().count(), 1000);
+    // Preallocating keeps the file size gdbm sees
+    assert!(metadata("bulk_load_test.db").expect("metadata").len() < 1 << 20);
+
+    // Dropping the guard ends the load too
+    db.bulk_load().expect("bulk_load").insert("dropped", "guard").expect("insert");
+    assert_eq!(db.info().expect("info").cache_size, cache_size);
+    assert_eq!(db.fetch_data("dropped").expect("fetch_data"), Some(b"guard".to_vec()));
+    drop(db);
+    remove_file("bulk_load_test.db").expect("remove_file");
+}
+
+#[test]
+fn write_batch_test() {
+    let _ = remove_file("write_batch_test.db");
+    let db = new_db("write_batch_test.db");
+    db.insert("old", "value").expect("insert");
+
+    let mut batch = db.write_batch();
+    batch.put("a", "1").put("b", "2").delete("old").delete("missing");
+    batch.put("a", "3");
+    assert_eq!(batch.len(), 5);
+    assert_eq!(db.fetch_data("a").expect("fetch_data"), None);
+    batch.commit().expect("commit");
+    assert_eq!(db.fetch_data("a").expect("fetch_data"), Some(b"3".to_vec()));
+    assert_eq!(db.fetch_data("b").expect("fetch_data"), Some(b"2".to_vec()));
+    assert_eq!(db.fetch_data("old").expect("fetch_data"), None);
+
+    let mut batch = db.write_batch();
+    batch.put("c", "4").delete("a");
+    batch.discard();
+    assert_eq!(db.fetch_data("c").expect("fetch_data"), None);
+    assert_eq!(db.fetch_data("a").expect("fetch_data"), Some(b"3".to_vec()));
+    assert!(db.write_batch().is_empty());
+    drop(db);
+    remove_file("write_batch_test.db").expect("remove_file");
+}
+
+#[test]
+fn write_back_test() {
+    use std::thread::sleep;
+    use std::time::Duration;
+    use gdbm::{FlushPolicy, WriteBackGdbm};
+
+    let _ = remove_file("write_back_test.db");
+    let db = new_db("write_back_test.db");
+    db.insert("gone", "soon").expect("insert");
+    let mut cache = WriteBackGdbm::new(db, FlushPolicy::new().max_records(3).max_age(Duration::from_millis(50)));
+    cache.put("a", "1").expect("put");
+    cache.put("a", "2").expect("put");
+    cache.delete("gone").expect("delete");
+    assert_eq!(cache.pending(), 2);
+    assert_eq!(cache.fetch_data("a").expect("fetch_data"), Some(b"2".to_vec()));
+    assert_eq!(cache.fetch_data("gone").expect("fetch_data"), None);
+
+    // The third buffered key reaches max_records
+    cache.put("b", "3").expect("put");
+    assert_eq!(cache.pending(), 0);
+    cache.put("c", "4").expect("put");
+    sleep(Duration::from_millis(60));
+    cache.flush_if_due().expect("flush_if_due");
+    assert_eq!(cache.pending(), 0);
+    cache.put("d", "5").expect("put");
+    assert_eq!(cache.flush().expect("flush"), 1);
+
+    cache.put("e", "6").expect("put");
+    let db = cache.into_inner().expect("into_inner");
+    for &(key, value) in &[("a", "2"), ("b", "3"), ("c", "4"), ("d", "5"), ("e", "6")] {
+        assert_eq!(db.fetch_data(key).expect("fetch_data"), Some(value.as_bytes().to_vec()));
+    }
+    assert_eq!(db.fetch_data("gone").expect("fetch_data"), None);
+
+    let mut cache = WriteBackGdbm::new(db, &FlushPolicy::write_through());
+    cache.put("f", "7").expect("put");
+    assert_eq!(cache.pending(), 0);
+    let db = cache.into_inner().expect("into_inner");
+    assert_eq!(db.fetch_data("f").expect("fetch_data"), Some(b"7".to_vec()));
+    drop(db);
+    remove_file("write_back_test.db").expect("remove_file");
+}
+
+#[test]
+fn auto_reorganize_test() {
+    use std::fs::metadata;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use gdbm::AutoReorganize;
+
+    let _ = remove_file("auto_reorganize_test.db");
+    let mut db = new_db("auto_reorganize_test.db");
+    for i in 0..2000 {
+        db.insert(format!("key{}", i), vec![b'x'; 500]).expect("insert");
+    }
+    assert!(db.fragmentation().expect("fragmentation") < 0.1);
+    for i in 0..1800 {
+        db.remove(format!("key{}", i)).expect("remove");
+    }
+    let fragmented = db.fragmentation().expect("fragmentation");
+    assert!(fragmented > 0.5, "fragmentation {}", fragmented);
+    assert!(!db.reorganize_if_fragmented(0.99).expect("reorganize_if_fragmented"));
+
+    // A vetoed check leaves the file alone
+    let vetoes = Arc::new(AtomicUsize::new(0));
+    let counted = vetoes.clone();
+    db.set_auto_reorganize(Some(AutoReorganize::new(0.5).check_every(10).veto(move |_| {
+        counted.fetch_add(1, Ordering::SeqCst);
+        false
+    }).clone()));
+    for i in 0..10 {
+        db.insert(format!("new{}", i), "value").expect("insert");
+    }
+    assert_eq!(vetoes.load(Ordering::SeqCst), 1);
+    assert!(db.fragmentation().expect("fragmentation") > 0.5);
+
+    let size = metadata("auto_reorganize_test.db").expect("metadata").len();
+    db.set_auto_reorganize(Some(AutoReorganize::new(0.5).check_every(5).clone()));
+    for i in 0..5 {
+        db.remove(format!("new{}", i)).expect("remove");
+    }
+    assert!(db.fragmentation().expect("fragmentation") < 0.1);
+    assert!(metadata("auto_reorganize_test.db").expect("metadata").len() < size);
+    assert_eq!(db.iter().count(), 205);
+    drop(db);
+    remove_file("auto_reorganize_test.db").expect("remove_file");
+}
+
+#[test]
+fn preallocate_test() {
+    use std::fs::metadata;
+    use std::os::unix::fs::MetadataExt;
+
+    let _ = remove_file("preallocate_test.db");
+    let db = new_db("preallocate_test.db");
+    let before = metadata("preallocate_test.db").expect("metadata");
+    // Some file systems cannot preallocate; nothing to check there
+    if db.preallocate(8 << 20).is_ok() {
+        let after = metadata("preallocate_test.db").expect("metadata");
+        assert_eq!(after.len(), before.len());
+        assert!(after.blocks() * 512 >= 8 << 20);
+        db.insert("still", "works").expect("insert");
+        assert_eq!(db.fetch_data("still").expect("fetch_data"), Some(b"works".to_vec()));
+    }
+    drop(db);
+    remove_file("preallocate_test.db").expect("remove_file");
+}
+
+#[test]
+fn for_each_key_test() {
+    use std::collections::BTreeSet;
+
+    let _ = remove_file("for_each_key_test.db");
+    let db = new_db("for_each_key_test.db");
+    let mut visited = 0;
+    db.for_each_key(|_| visited += 1).expect("for_each_key");
+    assert_eq!(visited, 0);
+    for i in 0..1000 {
+        db.insert(format!("key{}", i), "value").expect("insert");
+    }
+    let mut keys = BTreeSet::new();
+    db.for_each_key(|key| {
+        keys.insert(key.to_vec());
+    }).expect("for_each_key");
+    assert_eq!(keys, db.keys().collect::<Result<BTreeSet<_>, _>>().expect("keys"));
+    assert_eq!(keys.len(), 1000);
+    drop(db);
+    remove_file("for_each_key_test.db").expect("remove_file");
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn pure_reader_test() {
+    use std::collections::BTreeMap;
+    use gdbm::GdbmReaderPure;
+
+    let _ = remove_file("pure_reader_test.db");
+    let db = new_db("pure_reader_test.db");
+    let mut expected = BTreeMap::new();
+    for i in 0..3000u32 {
+        let key = if i % 3 == 0 { i.to_be_bytes().to_vec() } else { format!("key{}", i).into_bytes() };
+        let value = vec![b'v'; (i % 50) as usize];
+        db.insert(&key, &value).expect("insert");
+        expected.insert(key, value);
+    }
+    db.insert([0xff, 0x80, 0xc3], "high bytes").expect("insert");
+    expected.insert(vec![0xff, 0x80, 0xc3], b"high bytes".to_vec());
+    for i in 0..100 {
+        db.remove(format!("key{}", i * 3 + 1)).expect("remove");
+        expected.remove(format!("key{}", i * 3 + 1).as_bytes());
+    }
+    drop(db);
+
+    let reader = GdbmReaderPure::open(Path::new("pure_reader_test.db")).expect("open");
+    for (key, value) in &expected {
+        assert_eq!(reader.get(key).expect("get"), Some(&value[..]));
+    }
+    assert_eq!(reader.get("key1").expect("get"), None);
+    assert_eq!(reader.get("absent").expect("get"), None);
+    let entries: BTreeMap<_, _> = reader.entries()
+        .map(|entry| entry.map(|(key, value)| (key.to_vec(), value.to_vec())))
+        .collect::<Result<_, _>>()
+        .expect("entries");
+    assert_eq!(entries, expected);
+    assert_eq!(reader.keys().count(), expected.len());
+
+    // The shared lock keeps gdbm writers out
+    assert!(gdbm::Gdbm::new(Path::new("pure_reader_test.db"), 0, gdbm::Open::WRITER, 0o644).is_err());
+    drop(reader);
+    remove_file("pure_reader_test.db").expect("remove_file");
+}
+
+#[test]
+fn tune_for_test() {
+    use gdbm::{recommended_block_size, ValueProfile};
+    assert_eq!(recommended_block_size(&[], 4096), 4096);
+    assert_eq!(recommended_block_size(&[100; 10], 4096), 4096);
+    assert_eq!(recommended_block_size(&[3000; 10], 4096), 32768);
+    assert_eq!(recommended_block_size(&[1 << 20], 4096), 65536);
+    assert_eq!(recommended_block_size(&[100], 100), 1024);
+
+    let path = Path::new("/tmp/tune_for.db");
+    let _ = remove_file(path);
+    let mut profile = ValueProfile::from_samples(vec![3000; 10]);
+    profile.records(1_000_000);
+    let db = gdbm::OpenOptions::new().flags(gdbm::Open::NEWDB).tune_for(&profile).open(path).unwrap();
+    let info = db.info().unwrap();
+    assert_eq!(info.block_size, 32768);
+    assert!(info.cache_size > 100);
+    db.store("key", &"v".repeat(3000), true).unwrap();
+    drop(db);
+    remove_file(path).unwrap();
+}
+
+#[test]
+fn value_stats_test() {
+    use gdbm::stats;
+    let db = new_db("value_stats_test.db");
+    for (key, size) in &[("empty", 0), ("one", 1), ("three", 3), ("small", 5), ("big", 100), ("bigger", 300)] {
+        db.store(key, &"x".repeat(*size), true).unwrap();
+    }
+
+    let histogram = stats::value_histogram(&db).unwrap();
+    assert_eq!(histogram.records, 6);
+    assert_eq!(histogram.total_bytes, 409);
+    assert_eq!((histogram.min, histogram.max), (0, 300));
+    let ranges: Vec<_> = histogram.ranges.iter().map(|r| (r.min, r.max, r.count)).collect();
+    assert_eq!(ranges, vec![(0, 0, 1), (1, 1, 1), (2, 3, 1), (4, 7, 1), (64, 127, 1), (256, 511, 1)]);
+    assert!(histogram.to_string().contains("256 to 511 bytes: 1"));
+
+    let largest = stats::largest_entries(&db, 2).unwrap();
+    assert_eq!(largest.records, 6);
+    let entries: Vec<_> = largest.entries.iter().map(|e| (e.key.clone(), e.value_size)).collect();
+    assert_eq!(entries, vec![(b"bigger".to_vec(), 300), (b"big".to_vec(), 100)]);
+    assert!(largest.to_string().contains("300 bytes \"bigger\""));
+    #[cfg(feature = "json")]
+    assert_eq!(largest.to_json(),
+               serde_json::from_str::<serde_json::Value>(r#"{"records":6,"entries":[{"key":"bigger","value_size":300},{"key":"big","value_size":100}]}"#).unwrap());
+    drop(db);
+    remove_file("value_stats_test.db").unwrap();
+}
+
+#[test]
+fn space_stats_test() {
+    use std::fs::metadata;
+    use gdbm::stats;
+
+    let db = new_db("space_stats_test.db");
+    for i in 0..1000 {
+        db.store(&format!("key{:04}", i), &"x".repeat(500), true).unwrap();
+    }
+    let space = stats::space(&db).unwrap();
+    assert_eq!(space.file_size, metadata("space_stats_test.db").unwrap().len());
+    assert_eq!(space.live_bytes, 1000 * (7 + 500));
+    assert!(space.fragmentation < 0.1, "{}", space);
+
+    for i in 0..900 {
+        assert!(db.delete(&format!("key{:04}", i)));
+    }
+    let space = stats::space(&db).unwrap();
+    assert_eq!(space.live_bytes, 100 * (7 + 500));
+    assert!(space.free_bytes >= 900 * (7 + 500), "{}", space);
+    assert!(space.live_bytes + space.free_bytes <= space.file_size);
+    assert_eq!(space.fragmentation, space.free_bytes as f64 / space.file_size as f64);
+    assert!(space.to_string().contains("% fragmented"));
+    #[cfg(feature = "json")]
+    assert_eq!(space.to_json()["free_bytes"], space.free_bytes);
+    drop(db);
+    remove_file("space_stats_test.db").unwrap();
+}
+
+#[test]
+fn write_amplification_test() {
+    use gdbm::stats::WriteAmplification;
+    use gdbm::{ChecksummedGdbm, SecondaryIndex};
+
+    let db = ChecksummedGdbm::new(new_db("write_amplification_test.db"));
+    assert_eq!(db.write_amplification().ratio(), 1.0);
+    db.insert("key", "value").unwrap();
+    assert!(db.store_checked("key", "other", false).is_ok());
+    assert_eq!(db.write_amplification(),
+               WriteAmplification {
+                   logical_bytes: 8,
+                   stored_bytes: 12,
+               });
+    assert_eq!(db.write_amplification().ratio(), 1.5);
+    assert!(db.write_amplification().to_string().contains("(1.50x)"));
+    db.reset_write_amplification();
+    assert_eq!(db.write_amplification(), WriteAmplification::default());
+    let db = db.into_inner();
+    db.clear().unwrap();
+
+    // Each index key costs its entry: the key, a u32 length and the
+    // primary key
+    let index = SecondaryIndex::new(new_db("write_amplification_test.idx"), |_key, value| {
+        value.split(|&b| b == b',').map(|tag| tag.to_vec()).collect()
+    });
+    index.store(&db, "k1", "a,b").unwrap();
+    assert_eq!(index.write_amplification(),
+               WriteAmplification {
+                   logical_bytes: 5,
+                   stored_bytes: 5 + 2 * (1 + 4 + 2),
+               });
+    index.store(&db, "k2", "a").unwrap();
+    index.remove(&db, "k1").unwrap();
+    assert_eq!(index.write_amplification(),
+               WriteAmplification {
+                   logical_bytes: 8,
+                   stored_bytes: 19 + 3 + (1 + 12) + (1 + 6),
+               });
+    #[cfg(feature = "json")]
+    assert_eq!(index.write_amplification().to_json()["stored_bytes"], 42);
+
+    #[cfg(feature = "compression")]
+    {
+        let db = gdbm::CompressedGdbm::new(db);
+        db.insert("big", "x".repeat(10_000)).unwrap();
+        assert!(db.write_amplification().ratio() < 0.1, "{}", db.write_amplification());
+    }
+    remove_file("write_amplification_test.db").unwrap();
+    remove_file("write_amplification_test.idx").unwrap();
+}
+
+#[cfg(feature = "prometheus")]
+#[test]
+fn prometheus_collect_test() {
+    let db = new_db("prometheus_test.db");
+    db.store("a", &"1".to_string(), true).unwrap();
+    db.store("b", &"2".to_string(), true).unwrap();
+    let text = gdbm::prometheus::collect(&[&db]).unwrap();
+    assert!(text.contains("# TYPE gdbm_records gauge\n"), "{}", text);
+    assert!(text.lines().any(|line| line.starts_with("gdbm_records{db=\"") && line.ends_with("prometheus_test.db\"} 2")),
+            "{}",
+            text);
+    assert!(text.contains("gdbm_file_size_bytes{"));
+    assert!(text.contains("gdbm_cache_size_buckets{"));
+    assert!(text.contains("# TYPE gdbm_stores_total counter\n"));
+    drop(db);
+    remove_file("prometheus_test.db").unwrap();
+}
+
+#[test]
+fn op_stats_test() {
+    use gdbm::OpStats;
+    let db = new_db("op_stats_test.db");
+    db.store("a", &"1".to_string(), true).unwrap();
+    db.store("b", &"2".to_string(), true).unwrap();
+    assert!(!db.store("a", &"3".to_string(), false).unwrap());
+    assert_eq!(db.fetch("a").unwrap(), "1");
+    assert!(db.fetch("missing").is_err());
+    assert_eq!(db.fetch_data("b").unwrap(), Some(b"2".to_vec()));
+    assert!(db.delete("b"));
+    assert!(!db.delete("b"));
+    assert!(db.store_checked("big", vec![0; db.max_value_size() + 1], true).is_err());
+    assert_eq!(db.op_stats(),
+               OpStats {
+                   fetches: 3,
+                   stores: 4,
+                   deletes: 2,
+                   misses: 2,
+                   errors: 1,
+               });
+    db.reset_op_stats();
+    assert_eq!(db.op_stats(), OpStats::default());
+    drop(db);
+    remove_file("op_stats_test.db").unwrap();
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn metrics_test() {
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+    metrics::with_local_recorder(&recorder, || {
+        let db = new_db("metrics_test.db");
+        db.store("a", &"1".to_string(), true).unwrap();
+        db.store("b", &"22".to_string(), true).unwrap();
+        assert_eq!(db.fetch("b").unwrap(), "22");
+        assert!(db.fetch("missing").is_err());
+        assert!(db.delete("a"));
+        assert!(db.store_checked("big", vec![0; db.max_value_size() + 1], true).is_err());
+    });
+    let metrics: Vec<_> = snapshotter.snapshot()
+        .into_vec()
+        .into_iter()
+        .map(|(key, _, _, value)| {
+            let key = key.key();
+            let labels: Vec<_> = key.labels().map(|label| format!("{}={}", label.key(), label.value())).collect();
+            (key.name().to_string(), labels.join(","), value)
+        })
+        .collect();
+    let find = |name: &str, labels: &str| {
+        metrics.iter()
+            .find(|&(n, l, _)| n == name && l == labels)
+            .map(|(_, _, value)| value)
+            .unwrap_or_else(|| panic!("no {} {{{}}} in {:?}", name, labels, metrics))
+    };
+    assert_eq!(find("gdbm_fetches_total", ""), &DebugValue::Counter(2));
+    assert_eq!(find("gdbm_stores_total", ""), &DebugValue::Counter(3));
+    assert_eq!(find("gdbm_deletes_total", ""), &DebugValue::Counter(1));
+    assert_eq!(find("gdbm_misses_total", ""), &DebugValue::Counter(1));
+    assert_eq!(find("gdbm_errors_total", "op=store"), &DebugValue::Counter(1));
+    let sizes = |op: &str| match find("gdbm_value_size_bytes", &format!("op={}", op)) {
+        DebugValue::Histogram(values) => values.iter().map(|value| value.into_inner()).collect::<Vec<f64>>(),
+        other => panic!("{:?}", other),
+    };
+    assert_eq!(sizes("store"), vec![1.0, 2.0]);
+    assert_eq!(sizes("fetch"), vec![2.0]);
+    match find("gdbm_fetch_duration_seconds", "") {
+        DebugValue::Histogram(values) => assert_eq!(values.len(), 2),
+        other => panic!("{:?}", other),
+    }
+    remove_file("metrics_test.db").unwrap();
+}
+
+#[test]
+fn audit_sink_test() {
+    use std::fs::read_to_string;
+    use std::sync::{Arc, Mutex};
+    use gdbm::{AuditOp, AuditRecord, AuditSink, GdbmError};
+
+    let mut db = new_db("audit_sink_test.db");
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let log = seen.clone();
+    let sink: Arc<dyn AuditSink> = Arc::new(move |record: &AuditRecord| -> Result<(), GdbmError> {
+        log.lock().unwrap().push((record.op, record.key.to_vec(), record.value_size));
+        Ok(())
+    });
+    db.set_audit_sink(Some(sink));
+    db.store("a", &"one".to_string(), true).unwrap();
+    assert!(!db.store("a", &"two".to_string(), false).unwrap());
+    assert!(db.delete("a"));
+    assert!(!db.delete("a"));
+    assert_eq!(*seen.lock().unwrap(),
+               vec![(AuditOp::Store, b"a".to_vec(), 3), (AuditOp::Delete, b"a".to_vec(), 0)]);
+
+    // A failing sink fails the write, which has been made
+    db.set_audit_sink(Some(Arc::new(|_: &AuditRecord| -> Result<(), GdbmError> { Err(GdbmError::Error("audit failed".to_string())) })));
+    assert!(db.store("b", &"x".to_string(), true).is_err());
+    assert!(db.exists("b").unwrap());
+
+    let _ = remove_file("audit_sink_test.db.audit");
+    let sidecar = db.audit_to_sidecar().unwrap();
+    assert!(sidecar.ends_with("audit_sink_test.db.audit"));
+    db.store("key\t1", &"value".to_string(), true).unwrap();
+    assert!(db.delete("b"));
+    let text = read_to_string(&sidecar).unwrap();
+    let lines: Vec<Vec<&str>> = text.lines().map(|line| line.split('\t').collect()).collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(&lines[0][1..], &["store", "key\\t1", "5"]);
+    assert_eq!(&lines[1][1..], &["delete", "b", "0"]);
+    assert!(lines[0][0].parse::<f64>().unwrap() > 1.5e9);
+    drop(db);
+    remove_file("audit_sink_test.db").unwrap();
+    remove_file(sidecar).unwrap();
+}
+
+#[test]
+fn trace_id_test() {
+    use std::fs::read_to_string;
+    use std::sync::{Arc, Mutex};
+    use gdbm::{current_trace_id, with_trace_id, AuditRecord, AuditSink, GdbmError, GdbmWriterActor};
+
+    let mut db = new_db("trace_id_test.db");
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let log = seen.clone();
+    let sink: Arc<dyn AuditSink> = Arc::new(move |record: &AuditRecord| -> Result<(), GdbmError> {
+        log.lock().unwrap().push((record.key.to_vec(), record.trace_id.map(str::to_string)));
+        Ok(())
+    });
+    db.set_audit_sink(Some(sink));
+    db.insert("a", "1").unwrap();
+    with_trace_id("req-1", || {
+        db.insert("b", "2").unwrap();
+        with_trace_id("req-2", || db.insert("c", "3").unwrap());
+        assert_eq!(current_trace_id().as_deref(), Some("req-1"));
+    });
+    assert_eq!(current_trace_id(), None);
+
+    // Commands carry the id of the thread that sent them
+    let actor = GdbmWriterActor::spawn(db).unwrap();
+    let pending = with_trace_id("req-3", || actor.store("d", "4", true));
+    pending.wait().unwrap();
+    actor.store("e", "5", true).wait().unwrap();
+    assert_eq!(*seen.lock().unwrap(),
+               vec![(b"a".to_vec(), None),
+                    (b"b".to_vec(), Some("req-1".to_string())),
+                    (b"c".to_vec(), Some("req-2".to_string())),
+                    (b"d".to_vec(), Some("req-3".to_string())),
+                    (b"e".to_vec(), None)]);
+
+    let sidecar = actor.call(|db| {
+            let _ = remove_file("trace_id_test.db.audit");
+            db.audit_to_sidecar()
+        })
+        .wait()
+        .unwrap();
+    with_trace_id("req\t4", || actor.remove("a")).wait().unwrap();
+    actor.remove("b").wait().unwrap();
+    drop(actor);
+    let text = read_to_string(&sidecar).unwrap();
+    let lines: Vec<Vec<&str>> = text.lines().map(|line| line.split('\t').collect()).collect();
+    assert_eq!(&lines[0][1..], &["delete", "a", "0", "req\\t4"]);
+    assert_eq!(&lines[1][1..], &["delete", "b", "0"]);
+    remove_file("trace_id_test.db").unwrap();
+    remove_file(sidecar).unwrap();
+}
+
+#[test]
+fn kv_store_test() {
+    use gdbm::testing::MemStore;
+    use gdbm::KvStore;
+
+    fn exercise(store: &dyn KvStore) {
+        assert!(store.is_empty().expect("is_empty"));
+        assert_eq!(store.insert(b"a", b"1").expect("insert"), None);
+        assert_eq!(store.insert(b"a", b"2").expect("insert"), Some(b"1".to_vec()));
+        store.insert(b"b", b"3").expect("insert");
+        assert_eq!(store.get(b"a").expect("get"), Some(b"2".to_vec()));
+        assert_eq!(store.len().expect("len"), 2);
+        let mut records: Vec<_> = store.iter().collect::<Result<_, _>>().expect("iter");
+        records.sort();
+        assert_eq!(records, vec![(b"a".to_vec(), b"2".to_vec()), (b"b".to_vec(), b"3".to_vec())]);
+        assert_eq!(store.remove(b"b").expect("remove"), Some(b"3".to_vec()));
+        assert_eq!(store.remove(b"b").expect("remove"), None);
+        assert_eq!(store.get(b"b").expect("get"), None);
+    }
+
+    let _ = remove_file("kv_store_test.db");
+    exercise(&new_db("kv_store_test.db"));
+    exercise(&MemStore::new());
+    let store: MemStore = vec![("k", "v")].into_iter().collect();
+    assert_eq!(store.len().expect("len"), 1);
+    remove_file("kv_store_test.db").expect("remove_file");
+}
+
+#[cfg(feature = "cli")]
+#[test]
+fn cli_test() {
+    use std::convert::TryInto;
+    use std::process::Command;
+
+    let tool = |args: &[&str]| Command::new(env!("CARGO_BIN_EXE_gdbm-tool")).args(args).output().expect("gdbm-tool");
+    let _ = remove_file("cli_test.db");
+    assert!(tool(&["put", "cli_test.db", "a", "1"]).status.success());
+    assert!(tool(&["put", "cli_test.db", "b", "2"]).status.success());
+    assert_eq!(tool(&["get", "cli_test.db", "a"]).stdout, b"1\n".to_vec());
+    let mut keys = tool(&["keys", "cli_test.db"]).stdout;
+    keys.sort();
+    assert_eq!(keys, b"\n\nab".to_vec());
+    assert_eq!(tool(&["count", "cli_test.db"]).stdout, b"2\n".to_vec());
+    assert!(tool(&["del", "cli_test.db", "a"]).status.success());
+    assert_eq!(tool(&["get", "cli_test.db", "a"]).status.code(), Some(1));
+    assert_eq!(tool(&["put", "cli_test.db"]).status.code(), Some(2));
+    // The options come after the last command, not among the commands
+    let help = String::from_utf8(tool(&["help"]).stdout).expect("utf-8");
+    let (commands, options) = help.split_at(help.find("\n\nCSV and TSV options").expect("options"));
+    assert!(commands.contains("\n    diff ") && commands.contains("\n    convert "));
+    assert!(!options.contains("\n    diff ") && !options.contains("\n    convert "));
+    let check = tool(&["check", "cli_test.db"]);
+    assert!(check.status.success());
+    assert_eq!(check.stdout, b"1 records checked, 0 problems\n".to_vec());
+    assert_eq!(tool(&["check", "cli_test_missing.db"]).status.code(), Some(1));
+
+    // Enough records to split buckets and grow the directory
+    let db = new_db("cli_test_damaged.db");
+    db.store_many((0..2000).map(|i| (format!("key{}", i), format!("{}", i))), true).expect("store_many");
+    drop(db);
+    assert!(tool(&["check", "cli_test_damaged.db"]).status.success());
+    // Miscount the elements of the first bucket, 108 bytes in
+    let mut bytes = std::fs::read("cli_test_damaged.db").expect("read");
+    let bucket = {
+        let word = |at: usize| u64::from_ne_bytes(bytes[at..at + 8].try_into().unwrap()) as usize;
+        word(word(8))
+    };
+    let count = i32::from_ne_bytes(bytes[bucket + 108..bucket + 112].try_into().unwrap());
+    bytes[bucket + 108..bucket + 112].copy_from_slice(&(count + 1).to_ne_bytes());
+    std::fs::write("cli_test_damaged.db", &bytes).expect("write");
+    let check = tool(&["check", "cli_test_damaged.db"]);
+    assert_eq!(check.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&check.stdout).contains(&format!("bucket {} counts {} elements", bucket, count + 1)));
+    // Cut the file off in the middle of the bucket
+    bytes.truncate(bucket + 64);
+    std::fs::write("cli_test_damaged.db", &bytes).expect("write");
+    assert_eq!(tool(&["check", "cli_test_damaged.db"]).status.code(), Some(1));
+    remove_file("cli_test_damaged.db").expect("remove_file");
+
+    let _ = remove_file("cli_test_loaded.db");
+    for format in &["ascii", "binary"] {
+        assert!(tool(&["dump", "cli_test.db", "--format", format, "cli_test.dump"]).status.success());
+        assert!(tool(&["load", "cli_test_loaded.db", "--replace", "cli_test.dump"]).status.success());
+        assert_eq!(tool(&["get", "cli_test_loaded.db", "b"]).stdout, b"2\n".to_vec());
+    }
+    assert_eq!(tool(&["load", "cli_test_loaded.db", "cli_test.dump"]).status.code(), Some(1));
+    assert_eq!(tool(&["dump", "cli_test.db", "--format", "xml", "-"]).status.code(), Some(2));
+    remove_file("cli_test.dump").expect("remove_file");
+    remove_file("cli_test_loaded.db").expect("remove_file");
+
+    if gdbm::version_cmp(gdbm::version(), (1, 21, 0)) != std::cmp::Ordering::Less {
+        assert!(tool(&["convert", "--to", "numsync", "cli_test.db"]).status.success());
+        assert!(tool(&["convert", "--to", "standard", "cli_test.db"]).status.success());
+        assert_eq!(tool(&["get", "cli_test.db", "b"]).stdout, b"2\n".to_vec());
+    }
+    assert_eq!(tool(&["convert", "cli_test.db"]).status.code(), Some(2));
+
+    let csv = tool(&["dump", "cli_test.db", "--format", "csv", "--header", "--base64", "-"]);
+    assert_eq!(csv.stdout, b"key,value\nb,Mg==\n".to_vec());
+    assert!(tool(&["dump", "cli_test.db", "--format", "tsv", "cli_test.tsv"]).status.success());
+    assert!(tool(&["load", "cli_test_loaded.db", "--format", "tsv", "cli_test.tsv"]).status.success());
+    assert_eq!(tool(&["get", "cli_test_loaded.db", "b"]).stdout, b"2\n".to_vec());
+    assert_eq!(tool(&["dump", "cli_test.db", "--format", "csv", "--quote", "sometimes", "-"]).status.code(), Some(2));
+    assert_eq!(tool(&["diff", "cli_test.db", "cli_test_loaded.db"]).status.code(), Some(0));
+    assert!(tool(&["put", "cli_test_loaded.db", "b", "3"]).status.success());
+    assert!(tool(&["put", "cli_test_loaded.db", "c", "4"]).status.success());
+    let diff = tool(&["diff", "cli_test.db", "cli_test_loaded.db"]);
+    assert_eq!(diff.status.code(), Some(1));
+    assert_eq!(diff.stdout, b"~ b\n+ c\n".to_vec());
+    remove_file("cli_test.tsv").expect("remove_file");
+    remove_file("cli_test_loaded.db").expect("remove_file");
+
+    assert!(tool(&["reorganize", "cli_test.db"]).status.success());
+    assert_eq!(tool(&["get", "cli_test.db", "b"]).stdout, b"2\n".to_vec());
+    remove_file("cli_test.db").expect("remove_file");
+
+    let _ = remove_file("cli_test_sums.db");
+    let db = gdbm::ChecksummedGdbm::new(new_db("cli_test_sums.db"));
+    db.insert("a", "1").expect("insert");
+    db.insert("b", "2").expect("insert");
+    drop(db);
+    let scrub = tool(&["scrub", "cli_test_sums.db"]);
+    assert!(scrub.status.success());
+    assert_eq!(scrub.stdout, b"0 damaged values\n".to_vec());
+    assert!(tool(&["put", "cli_test_sums.db", "b", "garbage"]).status.success());
+    let scrub = tool(&["scrub", "cli_test_sums.db"]);
+    assert_eq!(scrub.status.code(), Some(1));
+    assert_eq!(scrub.stdout, b"value of \"b\" does not match its checksum\n1 damaged values\n".to_vec());
+    remove_file("cli_test_sums.db").expect("remove_file");
+}